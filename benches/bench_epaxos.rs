@@ -0,0 +1,160 @@
+use criterion::Throughput;
+use criterion::{BenchmarkId, Criterion, black_box, criterion_group, criterion_main};
+use epaxos::bench::{BenchCluster, BenchDeps, BenchProcessor};
+
+fn bench_get_interfs(c: &mut Criterion) {
+    let mut group = c.benchmark_group("get_interfs");
+    for n_instances in [100, 1_000, 10_000] {
+        let p = BenchProcessor::new_populated(3, n_instances);
+        group.bench_with_input(BenchmarkId::from_parameter(n_instances), &p, |b, p| {
+            b.iter(|| p.get_interfs(black_box(7)))
+        });
+    }
+    group.finish();
+}
+
+fn bench_tarjan_scc(c: &mut Criterion) {
+    let mut group = c.benchmark_group("tarjan_scc");
+    for (depth, scc_size) in [(10, 1), (100, 1), (10, 10), (100, 10), (10, 100)] {
+        let p = BenchProcessor::new_scc_chain(depth, scc_size);
+        let id = BenchmarkId::new(format!("depth_{}", depth), format!("scc_{}", scc_size));
+        group.bench_with_input(id, &p, |b, p| b.iter(|| black_box(p.tarjan_scc())));
+    }
+    group.finish();
+}
+
+fn bench_execute_cmd(c: &mut Criterion) {
+    let mut group = c.benchmark_group("execute_cmd");
+    for n_instances in [100, 1_000] {
+        group.bench_function(BenchmarkId::from_parameter(n_instances), |b| {
+            b.iter_batched(
+                || BenchProcessor::new_populated(3, n_instances),
+                |mut p| black_box(p.execute_newest()),
+                criterion::BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+fn bench_end_to_end(c: &mut Criterion) {
+    let mut group = c.benchmark_group("end_to_end");
+    for (n_clients, n_replicas) in [(1, 3), (10, 3), (10, 5)] {
+        let id = BenchmarkId::new(format!("{}_clients", n_clients), n_replicas);
+        group.bench_function(id, |b| {
+            b.iter_batched(
+                || BenchCluster::new(n_replicas),
+                |mut cluster| black_box(cluster.run(n_clients, 10)),
+                criterion::BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+/// Requests per second through a cluster whose leaders pipeline 1 or 8 instances.
+/// The in-memory network has no latency, so this shows the cost of queueing, not the
+/// overlap pipelining buys over a real network
+fn bench_pipeline_depth(c: &mut Criterion) {
+    let mut group = c.benchmark_group("pipeline_depth");
+    let (n_clients, per_client) = (10, 10);
+    group.throughput(Throughput::Elements((n_clients * per_client) as u64));
+    for depth in [1, 8] {
+        group.bench_function(BenchmarkId::from_parameter(depth), |b| {
+            b.iter_batched(
+                || BenchCluster::with_pipeline_depth(3, Some(depth)),
+                |mut cluster| black_box(cluster.run(n_clients, per_client)),
+                criterion::BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+/// A pipelined workload through leaders sending the PreAccepts of requests that start
+/// together one by one or as a PreAcceptBatch. The parameter is the number of messages
+/// between replicas each needs
+fn bench_pre_accept_batching(c: &mut Criterion) {
+    let mut group = c.benchmark_group("pre_accept_batching");
+    let (n_clients, per_client, depth) = (10, 20, 4);
+    for (name, batched) in [("unbatched", false), ("batched", true)] {
+        let messages = BenchCluster::with_pre_accept_batching(3, depth, batched)
+            .run_counting_messages(n_clients, per_client);
+        group.bench_function(BenchmarkId::new(name, messages), |b| {
+            b.iter_batched(
+                || BenchCluster::with_pre_accept_batching(3, depth, batched),
+                |mut cluster| black_box(cluster.run(n_clients, per_client)),
+                criterion::BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+/// A write-heavy mix, one Get in 10, through leaders that execute their Sets at commit
+/// (eager) or leave them to the Gets (lazy). Under lazy the Sets are acked sooner and the
+/// Gets pay for executing them
+fn bench_lazy_execution(c: &mut Criterion) {
+    let mut group = c.benchmark_group("lazy_execution");
+    let (n_clients, per_client) = (10, 20);
+    group.throughput(Throughput::Elements((n_clients * per_client) as u64));
+    for (name, lazy) in [("eager", false), ("lazy", true)] {
+        group.bench_function(name, |b| {
+            b.iter_batched(
+                || BenchCluster::with_lazy_execution(3, lazy),
+                |mut cluster| black_box(cluster.run_mixed(n_clients, per_client, 10)),
+                criterion::BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+/// Clients on every replica all hitting one key. Gets never depend on each other, so they
+/// all commit on the fast path, where Sets conflict and pay for an Accept round
+fn bench_one_key(c: &mut Criterion) {
+    let mut group = c.benchmark_group("one_key");
+    let (n_clients, per_client) = (10, 20);
+    group.throughput(Throughput::Elements((n_clients * per_client) as u64));
+    for (name, reads) in [("get", true), ("set", false)] {
+        group.bench_function(name, |b| {
+            b.iter_batched(
+                || BenchCluster::new(3),
+                |mut cluster| black_box(cluster.run_one_key(n_clients, per_client, reads)),
+                criterion::BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+/// Throughput is in encoded bytes, so the report shows the size of each encoding
+fn bench_decode_deps(c: &mut Criterion) {
+    let mut group = c.benchmark_group("decode_deps");
+    for n_deps in [10, 100, 1_000] {
+        let deps = BenchDeps::new(5, n_deps);
+        group.throughput(Throughput::Bytes(deps.full.len() as u64));
+        group.bench_with_input(BenchmarkId::new("instance", n_deps), &deps, |b, d| {
+            b.iter(|| black_box(d.decode_full()))
+        });
+        group.throughput(Throughput::Bytes(deps.compact.len() as u64));
+        group.bench_with_input(BenchmarkId::new("compact", n_deps), &deps, |b, d| {
+            b.iter(|| black_box(d.decode_compact()))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_get_interfs,
+    bench_tarjan_scc,
+    bench_execute_cmd,
+    bench_end_to_end,
+    bench_pipeline_depth,
+    bench_pre_accept_batching,
+    bench_lazy_execution,
+    bench_one_key,
+    bench_decode_deps
+);
+criterion_main!(benches);
@@ -0,0 +1,241 @@
+use crate::common::{
+    ClientRequest, Command, CommandResult, EMsg, Envelope, Variable, Versioned, fmt_opt_value,
+};
+use reactor_actor::codec::BincodeCodec;
+use reactor_actor::{BehaviourBuilder, RouteTo, RuntimeCtx, SendErrAction};
+
+use std::io::BufRead;
+use tokio::sync::mpsc::{self, UnboundedReceiver, unbounded_channel};
+use tokio::task;
+
+const USAGE: &str = "usage: get <key> | set <key> <value> | del <key> | dump";
+
+// //////////////////////////////////////////////////////////////////////////////
+//                                  Generator
+// //////////////////////////////////////////////////////////////////////////////
+
+/// A line typed into the cli
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum CliInput {
+    Request(Command),
+    Dump,
+}
+
+/// Parses `get k`, `set k v` (the value runs to the end of the line), `del k` or `dump`.
+/// There is no delete command, `del` writes an empty value that expires at once, which
+/// reads treat as missing
+fn parse_line(line: &str) -> Result<CliInput, String> {
+    let mut words = line.trim().splitn(3, char::is_whitespace);
+    let op = words.next().unwrap_or_default().to_ascii_lowercase();
+    let key = words.next().map(Variable::new);
+    let arg = words.next().map(str::trim).filter(|arg| !arg.is_empty());
+    match (op.as_str(), key, arg) {
+        ("get", Some(key), None) => Ok(CliInput::Request(Command::Get { key })),
+        ("set", Some(key), Some(val)) => Ok(CliInput::Request(Command::Set {
+            key,
+            val: val.into(),
+            ttl_ms: None,
+        })),
+        ("del", Some(key), None) => Ok(CliInput::Request(Command::set_ex(key, "", 0))),
+        ("dump", None, None) => Ok(CliInput::Dump),
+        ("get" | "set" | "del" | "dump", ..) => Err(format!("bad arguments for {}", op)),
+        _ => Err(format!("unknown command {:?}", op)),
+    }
+}
+
+/// Iterator over the lines typed into the cli, read on a blocking task and passed through
+/// a channel. Prints usage for lines it can't parse and waits for the next one. While the
+/// user is idle it waits in block_in_place, as PacedWorkload does, so the runtime moves its
+/// other tasks off this worker
+struct StdinReqGenerator {
+    lines: UnboundedReceiver<String>,
+    count: usize,
+    addr: String,
+}
+
+impl Iterator for StdinReqGenerator {
+    type Item = EMsg;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let line = match self.lines.try_recv() {
+                Ok(line) => line,
+                Err(mpsc::error::TryRecvError::Empty) => {
+                    task::block_in_place(|| self.lines.blocking_recv())? // Stdin closed
+                }
+                Err(mpsc::error::TryRecvError::Disconnected) => return None,
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            match parse_line(&line) {
+                Ok(CliInput::Request(cmd)) => {
+                    self.count += 1;
+                    return Some(EMsg::ClientRequest(ClientRequest {
+                        client_id: self.addr.clone(),
+                        msg_id: format!("{}_cli_{}", self.addr, self.count),
+                        cmd,
+                        timestamp_us: None,
+                    }));
+                }
+                Ok(CliInput::Dump) => return Some(EMsg::DumpState),
+                Err(e) => println!("{}\n{}", e, USAGE),
+            }
+        }
+    }
+}
+
+// //////////////////////////////////////////////////////////////////////////////
+//                                  Processor
+// //////////////////////////////////////////////////////////////////////////////
+
+/// Prints the responses of the server as they arrive
+struct Processor;
+
+impl reactor_actor::ActorProcess for Processor {
+    type IMsg = EMsg;
+    type OMsg = EMsg;
+
+    fn process(&mut self, input: Self::IMsg) -> Vec<Self::OMsg> {
+        match &input {
+            EMsg::ClientRequest(_) | EMsg::DumpState => vec![input],
+            EMsg::ClientResponse(resp) => {
+                match &resp.cmd_result {
+                    CommandResult::Get { key, val, version } => {
+                        println!("{} = {} (version {})", key, fmt_opt_value(val), version)
+                    }
+                    CommandResult::Set { key, version } => {
+                        println!("OK {} (version {})", key, version)
+                    }
+                    other => println!("{:?}", other),
+                }
+                vec![]
+            }
+            EMsg::ClientError(err) => {
+                println!("Error for {}: {}", err.msg_id, err.reason);
+                vec![]
+            }
+            EMsg::DumpStateResponse(state) => {
+                println!("{}", state);
+                vec![]
+            }
+            _ => {
+                panic!("Cli got unexpected message")
+            }
+        }
+    }
+}
+
+// //////////////////////////////////////////////////////////////////////////////
+//                                  Sender
+// //////////////////////////////////////////////////////////////////////////////
+
+struct Sender {
+    server: String,
+}
+
+impl reactor_actor::ActorSend for Sender {
+    type OMsg = Envelope;
+
+    async fn before_send<'a>(&'a mut self, output: &Self::OMsg) -> RouteTo<'a> {
+        match &output.msg {
+            EMsg::ClientRequest(_) | EMsg::DumpState => RouteTo::from(self.server.as_str()),
+            _ => panic!("Cli tried to send non ClientRequest"),
+        }
+    }
+}
+
+// //////////////////////////////////////////////////////////////////////////////
+//                                  ACTORS
+// //////////////////////////////////////////////////////////////////////////////
+
+/// Cli actor: sends the commands typed on stdin to `server` and prints what comes back
+pub async fn cli(ctx: RuntimeCtx, server: String) {
+    let (tx, rx) = unbounded_channel();
+    // Reading stdin blocks, keep it off the reactor loop
+    task::spawn_blocking(move || {
+        for line in std::io::stdin().lock().lines() {
+            let Ok(line) = line else { break };
+            if tx.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    println!("{}", USAGE);
+    BehaviourBuilder::new(Versioned(Processor), BincodeCodec::default())
+        .send(Sender { server })
+        .generator_if(true, || {
+            StdinReqGenerator {
+                lines: rx,
+                count: 0,
+                addr: ctx.addr.to_string(),
+            }
+            .map(Envelope::new)
+        })
+        .on_send_failure(SendErrAction::Drop)
+        .build()
+        .run(ctx)
+        .await
+        .unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set(key: &str, val: &str, ttl_ms: Option<u64>) -> CliInput {
+        CliInput::Request(Command::Set {
+            key: Variable::new(key),
+            val: val.into(),
+            ttl_ms,
+        })
+    }
+
+    // --- Tests ---
+    #[test]
+    fn test_parse_line() {
+        assert_eq!(
+            parse_line("get k"),
+            Ok(CliInput::Request(Command::Get {
+                key: Variable::new("k")
+            }))
+        );
+        assert_eq!(
+            parse_line("SET k two words "),
+            Ok(set("k", "two words", None))
+        );
+        assert_eq!(parse_line("del k"), Ok(set("k", "", Some(0))));
+        assert_eq!(parse_line(" dump"), Ok(CliInput::Dump));
+        assert!(parse_line("get").is_err());
+        assert!(parse_line("set k").is_err());
+        assert!(parse_line("dump k").is_err());
+        assert!(parse_line("put k v").is_err());
+    }
+
+    #[test]
+    fn test_malformed_lines_are_skipped() {
+        let (tx, rx) = unbounded_channel();
+        for line in ["get", "", "bogus k", "set k v", "dump"] {
+            tx.send(line.to_string()).unwrap();
+        }
+        drop(tx);
+        let msgs: Vec<EMsg> = StdinReqGenerator {
+            lines: rx,
+            count: 0,
+            addr: "cli1".to_string(),
+        }
+        .collect();
+        let [EMsg::ClientRequest(req), EMsg::DumpState] = &msgs[..] else {
+            panic!("Unexpected {:?}", msgs)
+        };
+        assert_eq!(req.msg_id, "cli1_cli_1");
+        assert_eq!(
+            req.cmd,
+            Command::Set {
+                key: Variable::new("k"),
+                val: "v".into(),
+                ttl_ms: None,
+            }
+        );
+    }
+}
@@ -1,19 +1,35 @@
-use crate::common::{ClientRequest, Command, CommandResult, EMsg, Variable};
+#[cfg(feature = "verbose")]
+use crate::common::fmt_opt_value;
+use crate::common::{
+    ClientRequest, ClientResponse, Command, CommandResult, EMsg, Envelope, ErrorCode, FNV_OFFSET,
+    Value, Variable, Versioned, fnv1a, now_us,
+};
+use crate::metrics::{MetricsConfig, MetricsReporter, metrics_route};
 use rand::prelude::*;
 use rand::rngs::StdRng;
 use rand_distr::{Distribution, Exp, Zipf}; // Exp for Time, Zipf for key selection
 use reactor_actor::codec::BincodeCodec;
 use reactor_actor::{ActorAddr, BehaviourBuilder, RouteTo, RuntimeCtx, SendErrAction};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, oneshot};
 use tokio::task;
-#[cfg(feature = "verbose")]
-use tracing::info;
+use tracing::{error, info, warn};
 
 // //////////////////////////////////////////////////////////////////////////////
 //                                  Configuration
 // //////////////////////////////////////////////////////////////////////////////
 
+/// Given in the workload as e.g. `{"type": "Hotspot", "hot_fraction": 0.1, "hot_access_prob": 0.9}`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
 pub enum KeyDistribution {
     /// Keys are chosen uniformly at random
     Uniform,
@@ -21,6 +37,55 @@ pub enum KeyDistribution {
     /// few keys are "hot" and are chosen more frequently
     /// skew = 0.99 is the YCSB default
     Zipfian { skew: f64 },
+    /// The first `hot_fraction` of the keys get `hot_access_prob` of the accesses, uniformly
+    /// within the hot and the cold keys. 0.1 / 0.9 sends 90% of requests to 10% of the keys
+    Hotspot {
+        hot_fraction: f64,
+        hot_access_prob: f64,
+    },
+    /// Each client walks key_0, key_1, ... in order, wrapping at key_space_size. Under
+    /// per-client prefixes this gives conflict-free write streams, e.g. for bulk loads
+    Sequential,
+}
+
+/// How request send times are paced
+pub enum RateLimiterKind {
+    /// Exponential inter-arrival times averaging `rps`. Catches up in bursts when behind
+    Poisson { rps: f64 },
+    /// Steady `rps`, with at most `burst` requests sent back to back
+    TokenBucket { rps: f64, burst: usize },
+}
+
+/// How a client decides when to send its next request
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WorkloadMode {
+    /// Sends at `target_rps` whether or not responses come back
+    #[default]
+    Open,
+    /// Keeps `concurrency` requests outstanding, sending the next one as soon as one
+    /// is answered or times out. `target_rps` is ignored
+    Closed,
+}
+
+/// How the Sender picks the server for a request
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum RoutingMode {
+    /// Any server, chosen at random per request
+    #[default]
+    #[serde(alias = "random")]
+    Random,
+    /// The server owning the request's first key, see key_to_replica. Commands on a key
+    /// then all start at the replica whose log already holds the earlier ones, so they
+    /// rarely conflict
+    KeyAffinity,
+    /// Every request to one server: `fixed_server`, or else the one the client's addr
+    /// hashes to. Keeps command-leader locality out of the measurements
+    #[serde(alias = "fixed")]
+    Fixed,
+    /// The servers in turn, starting from the one Fixed would pick
+    #[serde(alias = "round_robin")]
+    RoundRobin,
 }
 
 #[derive(Clone, Deserialize)]
@@ -30,56 +95,321 @@ pub struct Workload {
     #[serde(default)]
     pub key_space_size: usize, // Number of unique keys
     #[serde(default)]
-    pub zipf_skew: f64, // Zipfian skew parameter (0.0 for uniform)
+    pub zipf_skew: f64, // Zipfian skew parameter (0.0 for uniform), unless distribution is set
+    #[serde(default)]
+    pub distribution: Option<KeyDistribution>, // Overrides zipf_skew
     #[serde(default)]
     pub read_ratio: f64, // Ratio of read operations (0.0 - all writes, 1.0 - all reads)
     #[serde(default)]
     pub run_duration: u64, // Duration to run the workload in seconds
+    #[serde(default)]
+    pub incr_ratio: f64, // Ratio of Incr operations, taken out of the writes
+    #[serde(default)]
+    pub burst: Option<usize>, // Token bucket size. Poisson arrivals if unset
+    #[serde(default)]
+    pub namespace: String, // Namespace of every generated key, "" for the default one
+    #[serde(default)]
+    pub report_interval: u64, // Seconds between throughput logs, 0 to disable
+    #[serde(default)]
+    pub output_path: Option<String>, // Results file written at the end of the run, if set
+    #[serde(default)]
+    pub mode: WorkloadMode, // open (default) or closed
+    #[serde(default)]
+    pub concurrency: usize, // Requests kept in flight in closed mode, at least 1
+    #[serde(default)]
+    pub routing: RoutingMode, // Random (default), KeyAffinity, Fixed or RoundRobin
+    #[serde(default)]
+    pub fixed_server: Option<String>, // Server of Fixed routing, default by hashing the addr
+    #[serde(default)]
+    pub max_outstanding: Option<usize>, // Open mode pauses at this many unanswered requests
+    #[serde(default)]
+    pub timeout_ms: u64, // Re-send a request unanswered for this long, 0 for the default 3s
+    #[serde(default)]
+    pub max_retries: Option<u32>, // Re-sends before a request counts as failed, default 3
+    #[serde(default)]
+    pub trace_path: Option<String>, // JSONL file recording every generated request
+    #[serde(default)]
+    pub replay_path: Option<String>, // Trace to re-send instead of generating requests
+    #[serde(default)]
+    pub ops_path: Option<String>, // Text file of operations to send instead, see parse_ops
+    #[serde(default)]
+    pub history_path: Option<String>, // JSONL history for a linearizability checker, if set
+    #[serde(default)]
+    pub warmup_secs: u64, // Leading part of run_duration kept out of the statistics
+    #[serde(default)]
+    pub seed: Option<u64>, // Reproducible requests, combined with each client's addr
+    // Keys are "{key_prefix}key_{i}". Clients sharing a prefix (by default all of them, with
+    // the empty one) draw from the same key_space_size keys and conflict. Giving each client
+    // its own prefix, or setting disjoint_keyspace, removes conflicts between clients, so
+    // mixing both kinds of clients sweeps the conflict rate
+    #[serde(default)]
+    pub key_prefix: String,
+    #[serde(default)]
+    pub disjoint_keyspace: bool, // Use "{addr}_" as the prefix, overriding key_prefix
+    // Fraction of requests on HOT_KEY, shared by every client. The rest go to the client's
+    // own keys as with disjoint_keyspace. Unset, keys are drawn as above
+    #[serde(default)]
+    pub conflict_ratio: Option<f64>,
+    #[serde(default)]
+    pub value_size_bytes: Option<usize>, // Exact size of Set values. Unset, short "value_{addr}_{n}" strings
+    // (offset seconds, rps) steps replacing target_rps from each offset on, 0 rps pausing.
+    // Latency is also reported per step
+    #[serde(default)]
+    pub rps_schedule: Vec<(u64, f64)>,
+    #[serde(default)]
+    pub load_phase: bool, // Set every key once, unmeasured, before the run starts. Open mode only
+    #[serde(default)]
+    pub load_ops: usize, // Keys the load phase sets, 0 for the whole key_space_size
+    pub drain_secs: Option<u64>, // Wait for late responses after the run, None for the default 2s
+    #[serde(default)]
+    pub max_ops: usize, // End the run after this many requests, unless run_duration ends it first. 0 for no limit
 }
 
 pub struct WorkloadConfig {
-    pub target_rps: f64,               // Target requests per second
+    pub rate_limiter: RateLimiterKind, // Request pacing, at the target requests per second
     pub key_space_size: usize,         // Number of unique keys
     pub distribution: KeyDistribution, // Key selection distribution
     pub read_ratio: f64, // Ratio of read operations (0.0 - all writes, 1.0 - all reads)
     pub run_duration: Duration, // Duration to run the workload
+    pub seed: Option<u64>, // RNG seed, mixed with the addr. None to seed from the OS
+    pub incr_ratio: f64, // Ratio of Incr operations (read_ratio + incr_ratio <= 1.0)
+    pub namespace: String, // Namespace of every generated key
+    pub report_interval: Option<Duration>, // Throughput log period, None to disable
+    pub output_path: Option<PathBuf>, // Where to write the JSON results file, if anywhere
+    pub mode: WorkloadMode, // Open-loop pacing or closed-loop concurrency
+    pub concurrency: usize, // Requests kept in flight in closed mode
+    pub routing: RoutingMode, // Server choice per request
+    pub fixed_server: Option<String>, // Server all requests go to under Fixed routing
+    pub max_outstanding: Option<usize>, // Cap on unanswered requests in open mode, None for no cap
+    pub request_timeout: Duration, // Unanswered requests are re-sent after this long
+    pub max_retries: u32, // Re-sends after a timeout before giving up on a request
+    pub trace_path: Option<PathBuf>, // Where to record generated requests, needs the serde feature
+    pub replay_path: Option<PathBuf>, // Trace replayed by cp_client, needs the serde feature
+    pub ops_path: Option<PathBuf>, // Operations sent by cp_client instead of generated ones
+    pub history_path: Option<PathBuf>, // Where to write the Porcupine history, if anywhere
+    pub warmup: Duration, // Requests sent this early in the run don't count in the statistics
+    pub key_prefix: String, // Prepended to every generated key name
+    pub disjoint_keyspace: bool, // Prefix keys with the client's addr instead of key_prefix
+    pub conflict_ratio: Option<f64>, // Chance a request targets HOT_KEY, the rest stay disjoint
+    pub value_size: Option<usize>, // Bytes in every Set value, None for the short default values
+    pub rps_schedule: Vec<(Duration, f64)>, // Rate from each offset into the run on, sorted
+    pub load_ops: Option<usize>, // Keys set by a load phase before the run, None for no load phase
+    pub drain: Duration, // Longest wait for outstanding responses after the run, before reporting
+    pub max_ops: Option<usize>, // Requests after which the run ends, None to run for run_duration
 }
 
 impl Default for WorkloadConfig {
     fn default() -> Self {
         WorkloadConfig {
-            target_rps: 10.0,
+            rate_limiter: RateLimiterKind::Poisson { rps: 10.0 },
             key_space_size: 10,
             distribution: KeyDistribution::Zipfian { skew: 0.99 },
             read_ratio: 0.5,
             run_duration: Duration::from_secs(60),
+            seed: None,
+            incr_ratio: 0.0,
+            namespace: String::new(),
+            report_interval: None,
+            output_path: None,
+            mode: WorkloadMode::Open,
+            concurrency: 1,
+            routing: RoutingMode::Random,
+            fixed_server: None,
+            max_outstanding: None,
+            request_timeout: DEFAULT_REQUEST_TIMEOUT,
+            max_retries: DEFAULT_MAX_RETRIES,
+            trace_path: None,
+            replay_path: None,
+            ops_path: None,
+            history_path: None,
+            warmup: Duration::ZERO,
+            key_prefix: String::new(),
+            disjoint_keyspace: false,
+            conflict_ratio: None,
+            value_size: None,
+            rps_schedule: vec![],
+            load_ops: None,
+            drain: DEFAULT_DRAIN,
+            max_ops: None,
         }
     }
 }
 
 impl WorkloadConfig {
     fn new(workload: Workload) -> Self {
-        let distribution = if workload.zipf_skew == 0.0 {
-            KeyDistribution::Uniform
-        } else {
-            KeyDistribution::Zipfian {
+        let distribution = match workload.distribution {
+            Some(distribution) => distribution,
+            None if workload.zipf_skew == 0.0 => KeyDistribution::Uniform,
+            None => KeyDistribution::Zipfian {
                 skew: workload.zipf_skew,
-            }
+            },
+        };
+        let rate_limiter = match workload.burst {
+            Some(burst) => RateLimiterKind::TokenBucket {
+                rps: workload.target_rps,
+                burst,
+            },
+            None => RateLimiterKind::Poisson {
+                rps: workload.target_rps,
+            },
         };
         WorkloadConfig {
-            target_rps: workload.target_rps,
+            rate_limiter,
             key_space_size: workload.key_space_size,
             distribution,
             read_ratio: workload.read_ratio,
             run_duration: Duration::from_secs(workload.run_duration),
+            seed: workload.seed,
+            incr_ratio: workload.incr_ratio,
+            namespace: workload.namespace,
+            report_interval: (workload.report_interval > 0)
+                .then(|| Duration::from_secs(workload.report_interval)),
+            output_path: workload.output_path.map(PathBuf::from),
+            mode: workload.mode,
+            concurrency: workload.concurrency.max(1),
+            routing: workload.routing,
+            fixed_server: workload.fixed_server,
+            max_outstanding: workload.max_outstanding.map(|cap| cap.max(1)),
+            request_timeout: match workload.timeout_ms {
+                0 => DEFAULT_REQUEST_TIMEOUT,
+                ms => Duration::from_millis(ms),
+            },
+            max_retries: workload.max_retries.unwrap_or(DEFAULT_MAX_RETRIES),
+            trace_path: workload.trace_path.map(PathBuf::from),
+            replay_path: workload.replay_path.map(PathBuf::from),
+            ops_path: workload.ops_path.map(PathBuf::from),
+            history_path: workload.history_path.map(PathBuf::from),
+            warmup: Duration::from_secs(workload.warmup_secs),
+            key_prefix: workload.key_prefix,
+            disjoint_keyspace: workload.disjoint_keyspace,
+            conflict_ratio: workload.conflict_ratio,
+            value_size: workload.value_size_bytes,
+            rps_schedule: {
+                let mut steps: Vec<_> = workload
+                    .rps_schedule
+                    .into_iter()
+                    .map(|(offset, rps)| (Duration::from_secs(offset), rps))
+                    .collect();
+                steps.sort_by_key(|(offset, _)| *offset);
+                steps
+            },
+            load_ops: workload.load_phase.then_some(match workload.load_ops {
+                0 => workload.key_space_size,
+                n => n,
+            }),
+            drain: workload
+                .drain_secs
+                .map_or(DEFAULT_DRAIN, Duration::from_secs),
+            max_ops: (workload.max_ops > 0).then_some(workload.max_ops),
         }
     }
+
+    /// The settings a run actually used, defaults filled in, for the results file
+    fn to_json(&self) -> serde_json::Value {
+        let (rate_limiter, target_rps, burst) = match self.rate_limiter {
+            RateLimiterKind::Poisson { rps } => ("Poisson", rps, None),
+            RateLimiterKind::TokenBucket { rps, burst } => ("TokenBucket", rps, Some(burst)),
+        };
+        let zipf_skew = match self.distribution {
+            KeyDistribution::Zipfian { skew } => skew,
+            _ => 0.0,
+        };
+        serde_json::json!({
+            "rate_limiter": rate_limiter,
+            "target_rps": target_rps,
+            "burst": burst,
+            "key_space_size": self.key_space_size,
+            "zipf_skew": zipf_skew,
+            "distribution": self.distribution,
+            "read_ratio": self.read_ratio,
+            "incr_ratio": self.incr_ratio,
+            "run_duration_s": self.run_duration.as_secs_f64(),
+            "warmup_s": self.warmup.as_secs_f64(),
+            "seed": self.seed,
+            "namespace": self.namespace,
+            "key_prefix": self.key_prefix,
+            "disjoint_keyspace": self.disjoint_keyspace,
+            "conflict_ratio": self.conflict_ratio,
+            "value_size_bytes": self.value_size,
+            "load_ops": self.load_ops,
+            "drain_secs": self.drain.as_secs_f64(),
+            "max_ops": self.max_ops,
+            "rps_schedule": self
+                .rps_schedule
+                .iter()
+                .map(|(offset, rps)| (offset.as_secs(), *rps))
+                .collect::<Vec<_>>(),
+            "mode": self.mode,
+            "concurrency": self.concurrency,
+            "routing": self.routing,
+            "fixed_server": self.fixed_server,
+            "max_outstanding": self.max_outstanding,
+            "timeout_ms": self.request_timeout.as_millis() as u64,
+            "max_retries": self.max_retries,
+        })
+    }
 }
 
 // //////////////////////////////////////////////////////////////////////////////
 //                                  Generator
 // //////////////////////////////////////////////////////////////////////////////
 
+/// Holds up to `burst` tokens, refilled at `rps` per second. Each request takes one
+pub struct TokenBucket {
+    rps: f64,
+    burst: usize,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(rps: f64, burst: usize, now: Instant) -> Self {
+        assert!(
+            rps > 0.0 && burst > 0,
+            "Token bucket needs positive rps and burst"
+        );
+        TokenBucket {
+            rps,
+            burst,
+            tokens: burst as f64,
+            last_refill: now,
+        }
+    }
+
+    /// Adds the tokens earned since the last refill, up to `burst`
+    pub fn refill(&mut self, now: Instant) {
+        let elapsed = now
+            .saturating_duration_since(self.last_refill)
+            .as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rps).min(self.burst as f64);
+        self.last_refill = now;
+    }
+
+    /// Takes a token if one is available, otherwise returns how long until there is one
+    pub fn try_acquire(&mut self, now: Instant) -> Result<(), Duration> {
+        self.refill(now);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - self.tokens) / self.rps))
+        }
+    }
+}
+
+/// KeyDistribution ready to sample from
+enum KeySampler {
+    Uniform,
+    Zipfian(Zipf<f64>),
+    Hotspot {
+        hot_keys: usize, // key_0..key_{hot_keys - 1}
+        hot_access_prob: f64,
+    },
+    Sequential {
+        next: usize,
+    },
+}
+
 pub struct WorkloadIterator {
     // Identifies the client
     addr: ActorAddr,
@@ -88,241 +418,3813 @@ pub struct WorkloadIterator {
     // Lifecycle
     start_time: Instant,
     run_duration: Duration,
+    max_ops: Option<usize>, // Ends the run at this request_count, if run_duration didn't yet
 
     // Timing (Poisson Process)
     exp_dist: Exp<f64>,
     next_arrival: Instant,
+    token_bucket: Option<TokenBucket>, // Replaces the Poisson process when set
+    rps: f64,                          // Rate in force, target_rps or the current schedule step's
+    base_rps: f64,                     // Rate before the first schedule step
+    rps_schedule: Vec<(Duration, f64)>,
 
     // Key Selection
     rng: StdRng,
-    key_dist: Option<Zipf<f64>>, // None if uniform distribution
+    key_dist: KeySampler,
     key_space_size: usize,
+    namespace: String,
+    key_prefix: String, // Resolved, the addr's under disjoint_keyspace or conflict_ratio
+    conflict_ratio: Option<f64>,
 
-    read_ratio: f64, // Ratio of read operations
+    read_ratio: f64,                // Ratio of read operations
+    incr_ratio: f64,                // Ratio of Incr operations
+    value_size: Option<usize>,      // Bytes per Set value, see generate_value
+    value_fill: Vec<u8>,            // value_size bytes of padding, filled once
+    paced: bool,                    // False in closed mode, where responses set the pace
+    done: bool,                     // WorkloadDone was yielded
+    trace: Option<BufWriter<File>>, // One JSON line per generated request
+    key_stats: SharedKeyStats,      // Ops generated per key, conflicts added by the Processor
 }
 
 impl WorkloadIterator {
     pub fn new(addr: ActorAddr, config: WorkloadConfig) -> Self {
-        let exp_dist = Exp::new(config.target_rps).expect("RPS must be positive");
+        let (rps, token_bucket) = match config.rate_limiter {
+            RateLimiterKind::Poisson { rps } => (rps, None),
+            RateLimiterKind::TokenBucket { rps, burst } => {
+                (rps, Some(TokenBucket::new(rps, burst, Instant::now())))
+            }
+        };
+        let exp_dist = Exp::new(rps).expect("RPS must be positive");
 
         let key_dist = match config.distribution {
-            KeyDistribution::Uniform => None,
-            KeyDistribution::Zipfian { skew } => Some(
+            KeyDistribution::Uniform => KeySampler::Uniform,
+            KeyDistribution::Zipfian { skew } => KeySampler::Zipfian(
                 Zipf::new(config.key_space_size as f64, skew).expect("Invalid Zipf parameters"),
             ),
+            KeyDistribution::Hotspot {
+                hot_fraction,
+                hot_access_prob,
+            } => KeySampler::Hotspot {
+                // At least one hot key, and all of them at most
+                hot_keys: ((config.key_space_size as f64 * hot_fraction).round() as usize)
+                    .clamp(1, config.key_space_size),
+                hot_access_prob: hot_access_prob.clamp(0.0, 1.0),
+            },
+            KeyDistribution::Sequential => KeySampler::Sequential { next: 0 },
         };
 
+        let seed = match config.seed {
+            Some(seed) => client_seed(seed, &addr),
+            None => rand_seed_from_os(),
+        };
+        let key_prefix = if config.disjoint_keyspace || config.conflict_ratio.is_some() {
+            format!("{}_", addr)
+        } else {
+            config.key_prefix
+        };
         Self {
             addr,
             request_count: 0,
             start_time: Instant::now(),
             run_duration: config.run_duration,
+            max_ops: config.max_ops,
             exp_dist,
             next_arrival: Instant::now(),
-            rng: StdRng::from_rng(&mut rand::rng()),
+            token_bucket,
+            rps,
+            base_rps: rps,
+            rps_schedule: config.rps_schedule,
+            rng: StdRng::seed_from_u64(seed),
             key_dist,
             key_space_size: config.key_space_size,
+            namespace: config.namespace,
+            key_prefix,
+            conflict_ratio: config.conflict_ratio,
             read_ratio: config.read_ratio,
+            incr_ratio: config.incr_ratio,
+            value_size: config.value_size,
+            value_fill: (0..config.value_size.unwrap_or(0))
+                .map(|i| b'a' + (i % 26) as u8)
+                .collect(),
+            paced: config.mode == WorkloadMode::Open,
+            done: false,
+            trace: open_trace(config.trace_path.as_deref()),
+            key_stats: SharedKeyStats::default(),
         }
     }
 
+    /// Counts ops into `key_stats`, the Processor's, instead of a private one
+    pub fn with_key_stats(mut self, key_stats: SharedKeyStats) -> Self {
+        self.key_stats = key_stats;
+        self
+    }
+
+    /// Counts a conflict on `key`, reported by conflict_analysis
+    #[allow(dead_code)]
+    pub fn record_conflict(&self, key: &str) {
+        self.key_stats.lock().unwrap().record_conflict(key);
+    }
+
+    /// Conflict rate per key and overall, for the ops generated so far
+    #[allow(dead_code)]
+    pub fn conflict_analysis(&self) -> ConflictAnalysis {
+        self.key_stats.lock().unwrap().conflict_analysis()
+    }
+
+    /// Same as `new`, but with a fixed RNG seed so the generated stream is reproducible
+    #[allow(dead_code)]
+    pub fn with_seed(addr: ActorAddr, config: WorkloadConfig, seed: u64) -> Self {
+        Self::new(
+            addr,
+            WorkloadConfig {
+                seed: Some(seed),
+                ..config
+            },
+        )
+    }
+
     pub fn generate_key(&mut self) -> String {
-        let key_index = match &self.key_dist {
-            Some(zipf) => (zipf.sample(&mut self.rng)) as usize,
-            None => self.rng.random_range(0..self.key_space_size),
+        if let Some(ratio) = self.conflict_ratio
+            && self.rng.random_bool(ratio.clamp(0.0, 1.0))
+        {
+            return HOT_KEY.to_string();
+        }
+        let key_index = match &mut self.key_dist {
+            // Zipf samples 1..=key_space_size
+            KeySampler::Zipfian(zipf) => (zipf.sample(&mut self.rng)) as usize - 1,
+            KeySampler::Uniform => self.rng.random_range(0..self.key_space_size),
+            KeySampler::Hotspot {
+                hot_keys,
+                hot_access_prob,
+            } => {
+                let all_hot = *hot_keys == self.key_space_size;
+                if all_hot || self.rng.random_bool(*hot_access_prob) {
+                    self.rng.random_range(0..*hot_keys)
+                } else {
+                    self.rng.random_range(*hot_keys..self.key_space_size)
+                }
+            }
+            KeySampler::Sequential { next } => {
+                let key_index = *next;
+                *next = (key_index + 1) % self.key_space_size;
+                key_index
+            }
         };
 
-        format!("key_{}", key_index)
+        format!("{}key_{}", self.key_prefix, key_index)
     }
-}
 
-impl Iterator for WorkloadIterator {
-    type Item = EMsg;
+    /// Set value of exactly value_size bytes, starting with `tag` so each value can be
+    /// told apart, padded from value_fill. Without a value_size, only the tag
+    fn generate_value(&self, tag: String) -> Value {
+        let Some(size) = self.value_size else {
+            return tag.into();
+        };
+        let tag = &tag.as_bytes()[..tag.len().min(size)];
+        let mut val = Vec::with_capacity(size);
+        val.extend_from_slice(tag);
+        val.extend_from_slice(&self.value_fill[tag.len()..]);
+        Value(val)
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        // Check if run duration exceeded
-        let now = Instant::now();
-        if now.duration_since(self.start_time) >= self.run_duration {
-            return None;
+    /// One Set per key, key_0 to key_{n - 1} under the client's prefix and namespace, for
+    /// a load phase. msg_ids start with LOAD_PREFIX
+    fn load_requests(&self, n: usize) -> Vec<ClientRequest> {
+        (0..n)
+            .map(|i| ClientRequest {
+                msg_id: format!("{}{}", LOAD_PREFIX, i),
+                client_id: self.addr.to_string(),
+                cmd: Command::Set {
+                    key: Variable::in_namespace(
+                        self.namespace.clone(),
+                        format!("{}key_{}", self.key_prefix, i),
+                    ),
+                    val: self.generate_value(format!("load_{}_{}", self.addr, i)),
+                    ttl_ms: None,
+                },
+                timestamp_us: None,
+            })
+            .collect()
+    }
+
+    /// Restarts the run clock, so a load phase doesn't eat into run_duration
+    fn restart(&mut self) {
+        self.start_time = Instant::now();
+        self.next_arrival = self.start_time;
+    }
+
+    /// Samples the gap until the next request arrival
+    pub fn next_interval(&mut self) -> Duration {
+        Duration::from_secs_f64(self.exp_dist.sample(&mut self.rng))
+    }
+
+    /// Rate at `elapsed` into the run, and when its schedule step ends, if it does
+    fn rps_at(&self, elapsed: Duration) -> (f64, Option<Duration>) {
+        let step = self
+            .rps_schedule
+            .partition_point(|(offset, _)| *offset <= elapsed);
+        let rps = match step {
+            0 => self.base_rps,
+            _ => self.rps_schedule[step - 1].1,
+        };
+        (rps, self.rps_schedule.get(step).map(|(offset, _)| *offset))
+    }
+
+    /// Switches to the rate of the schedule step a request due at `now` falls in. Steps at
+    /// 0 rps send nothing, so returns the start of the next step that does, or the end of
+    /// the run
+    fn follow_rps_schedule(&mut self, now: Instant) -> Instant {
+        if self.rps_schedule.is_empty() {
+            return now;
+        }
+        let mut at = now.max(self.next_arrival);
+        loop {
+            let (rps, step_end) = self.rps_at(at.saturating_duration_since(self.start_time));
+            if rps > 0.0 {
+                if rps != self.rps {
+                    self.rps = rps;
+                    self.exp_dist = Exp::new(rps).expect("RPS must be positive");
+                    if let Some(bucket) = &mut self.token_bucket {
+                        bucket.rps = rps;
+                    }
+                }
+                break;
+            }
+            match step_end {
+                Some(end) => at = self.start_time + end,
+                None => {
+                    at = self.start_time + self.run_duration;
+                    break;
+                }
+            }
         }
+        self.next_arrival = self.next_arrival.max(at);
+        at
+    }
 
-        if self.next_arrival > now {
-            let sleep_time = self.next_arrival - now;
-            task::block_in_place(|| {
-                std::thread::sleep(sleep_time);
-            });
-        } else {
+    /// When the next request may be sent, booked against the rate limiter. Doesn't wait
+    fn schedule(&mut self, now: Instant) -> Instant {
+        let now = self.follow_rps_schedule(now);
+        if let Some(bucket) = &mut self.token_bucket {
+            let mut at = now;
+            while let Err(wait) = bucket.try_acquire(at) {
+                at += wait.max(Duration::from_nanos(1));
+            }
+            return at;
+        }
+
+        if self.next_arrival < now {
             // Reset arrival time if behind schedule to prevent burstiness
             self.next_arrival = now;
         }
+        let at = self.next_arrival;
 
         // Calculate next arrival time
-        let interval_secs = self.exp_dist.sample(&mut self.rng);
-        self.next_arrival += Duration::from_secs_f64(interval_secs);
+        let interval = self.next_interval();
+        self.next_arrival += interval;
+        at
+    }
+
+    /// Blocks until the next request may be sent
+    fn pace(&mut self) {
+        let wait = self
+            .schedule(Instant::now())
+            .saturating_duration_since(Instant::now());
+        if !wait.is_zero() {
+            task::block_in_place(|| {
+                std::thread::sleep(wait);
+            });
+        }
+    }
+
+    /// Whether the next request should be paced: open mode, and the run isn't over
+    fn needs_pacing(&self, now: Instant) -> bool {
+        self.paced && now.duration_since(self.start_time) < self.run_duration
+    }
+}
+
+fn rand_seed_from_os() -> u64 {
+    rand::rng().random()
+}
+
+/// FNV-1a of `addr`, starting from `seed`, so clients sharing a seed draw different streams
+fn client_seed(seed: u64, addr: &str) -> u64 {
+    fnv1a(FNV_OFFSET ^ seed, addr.as_bytes())
+}
+
+impl Iterator for WorkloadIterator {
+    type Item = EMsg;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.needs_pacing(Instant::now()) {
+            self.pace();
+        }
+        self.generate()
+    }
+}
+
+impl WorkloadIterator {
+    /// The next request, without pacing. WorkloadDone once the run is over, then None
+    fn generate(&mut self) -> Option<EMsg> {
+        // Check if run duration exceeded, or all ops sent
+        let now = Instant::now();
+        if now.duration_since(self.start_time) >= self.run_duration
+            || self.max_ops.is_some_and(|n| self.request_count >= n)
+        {
+            if self.done {
+                return None;
+            }
+            self.done = true;
+            if let Some(trace) = &mut self.trace
+                && let Err(e) = trace.flush()
+            {
+                warn!("{} Failed to flush the request trace: {}", self.addr, e);
+            }
+            return Some(EMsg::WorkloadDone);
+        }
 
-        // Decide if read or write
-        let is_write = !self.rng.random_bool(self.read_ratio);
+        // Decide if read, incr or plain write
+        let op: f64 = self.rng.random();
 
         // Generate request
         self.request_count += 1;
-        let key = self.generate_key();
+        let key_name = self.generate_key();
+        self.key_stats.lock().unwrap().record_op(&key_name);
+        let key = Variable::in_namespace(self.namespace.clone(), key_name);
 
         let msg_id = self.request_count.to_string(); // Unique message ID
 
-        if is_write {
-            Some(EMsg::ClientRequest(ClientRequest {
-                msg_id,
-                client_id: self.addr.to_string(),
-                cmd: Command::Set {
-                    key: Variable { name: key },
-                    val: format!("value_{}_{}", self.addr, self.request_count),
-                },
-            }))
+        let cmd = if op < self.read_ratio {
+            Command::Get { key }
+        } else if op < self.read_ratio + self.incr_ratio {
+            Command::Incr { key, delta: 1 }
         } else {
-            Some(EMsg::ClientRequest(ClientRequest {
-                msg_id,
-                client_id: self.addr.to_string(),
-                cmd: Command::Get {
-                    key: Variable { name: key },
-                },
-            }))
-        }
+            Command::Set {
+                key,
+                val: self.generate_value(format!("value_{}_{}", self.addr, self.request_count)),
+                ttl_ms: None,
+            }
+        };
+
+        let req = ClientRequest {
+            msg_id,
+            client_id: self.addr.to_string(),
+            cmd,
+            timestamp_us: Some(now_us()),
+        };
+        self.trace(&req);
+        Some(EMsg::ClientRequest(req))
     }
 }
 
-// //////////////////////////////////////////////////////////////////////////////
-//                                  Processor
-// //////////////////////////////////////////////////////////////////////////////
-struct Processor {
-    #[cfg(feature = "verbose")]
-    store: std::collections::HashMap<String, (String, String)>, // Storing msg-id to key-value pairs at client for lchecker
-}
+/// Requests a PacedWorkload may generate ahead of the actor sending them
+const PACER_QUEUE: usize = 64;
 
-impl reactor_actor::ActorProcess for Processor {
-    type IMsg = EMsg;
-    type OMsg = EMsg;
+/// Runs an open-mode workload as a task on the tokio runtime, which sleeps on timers until
+/// each send time and queues the requests. The generator handed to the actor only waits
+/// on the queue, instead of sleeping through the gaps itself.
+/// The actor's generator is a plain Iterator, so an empty queue still parks a thread in
+/// block_in_place. Tokio moves the worker's other tasks to a new thread meanwhile, so the
+/// runtime stays responsive, but every waiting client holds one thread
+pub struct PacedWorkload {
+    rx: mpsc::Receiver<EMsg>,
+}
 
-    fn process(&mut self, input: Self::IMsg) -> Vec<Self::OMsg> {
-        match &input {
-            // For CP read client, it gets CPReadRequest messages from the generator
-            // and just directly sends to the Actor::Sender
-            EMsg::ClientRequest(req) => {
-                match &req.cmd {
-                    Command::Get { key } => {
-                        #[cfg(feature = "verbose")]
-                        {
-                            info!(
-                                "{} [Req: {}] Getting {}",
-                                req.client_id, req.msg_id, key.name
-                            );
-                        }
-                        vec![input]
-                    }
-                    Command::Set { key, val } => {
-                        #[cfg(feature = "verbose")]
-                        {
-                            // Store msg_id, key, and value for lchecker
-                            self.store
-                                .insert(req.msg_id.clone(), (key.name.clone(), val.clone()));
-                            info!(
-                                "{} [Req: {}] Setting {} = {}",
-                                req.client_id, req.msg_id, key.name, val
-                            );
-                        }
-                        vec![input]
-                    }
+impl PacedWorkload {
+    pub fn spawn(runtime: &tokio::runtime::Handle, mut workload: WorkloadIterator) -> Self {
+        let (tx, rx) = mpsc::channel(PACER_QUEUE);
+        runtime.spawn(async move {
+            loop {
+                if workload.needs_pacing(Instant::now()) {
+                    let at = workload.schedule(Instant::now());
+                    tokio::time::sleep_until(at.into()).await;
+                }
+                let Some(msg) = workload.generate() else {
+                    break;
+                };
+                if tx.send(msg).await.is_err() {
+                    break; // The actor stopped
                 }
             }
+        });
+        PacedWorkload { rx }
+    }
+}
 
-            EMsg::ClientResponse(resp) => {
-                match &resp.cmd_result {
-                    CommandResult::Get { key, val } => {
-                        #[cfg(feature = "verbose")]
-                        info!(
-                            "{} [Req: {}] Get {} = {}",
-                            resp.client_id,
-                            resp.msg_id,
-                            key.name,
-                            val.as_deref().unwrap_or("NONE")
-                        );
-                        vec![]
-                    }
-                    CommandResult::Set { key, status: _ } => {
-                        #[cfg(feature = "verbose")]
-                        info!(
-                            "{} [Req: {}] Set {} = {}",
-                            resp.client_id,
-                            resp.msg_id,
-                            key.name,
-                            self.store.get(&resp.msg_id).unwrap().1
-                        ); // Will exist
-                        vec![]
-                    }
-                }
+impl Iterator for PacedWorkload {
+    type Item = EMsg;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.rx.try_recv() {
+            Ok(msg) => Some(msg),
+            Err(mpsc::error::TryRecvError::Empty) => {
+                task::block_in_place(|| self.rx.blocking_recv())
             }
+            Err(mpsc::error::TryRecvError::Disconnected) => None,
+        }
+    }
+}
 
-            _ => {
-                panic!("Client got an unexpected message")
+/// Creates the request trace file at `path`, if any. Tracing needs the serde feature
+fn open_trace(path: Option<&Path>) -> Option<BufWriter<File>> {
+    let path = path?;
+    if cfg!(not(feature = "serde")) {
+        warn!("trace_path is set, but requests can only be traced with the serde feature");
+        return None;
+    }
+    match File::create(path) {
+        Ok(file) => Some(BufWriter::new(file)),
+        Err(e) => {
+            warn!("Can't create trace {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+impl WorkloadIterator {
+    /// Appends `req` to the trace as one JSON line. Stops tracing on the first error
+    #[cfg_attr(not(feature = "serde"), allow(unused_variables))]
+    fn trace(&mut self, req: &ClientRequest) {
+        #[cfg(feature = "serde")]
+        if let Some(trace) = &mut self.trace {
+            let written = serde_json::to_writer(&mut *trace, req)
+                .map_err(std::io::Error::from)
+                .and_then(|()| trace.write_all(b"\n"));
+            if let Err(e) = written {
+                warn!("{} Stopped tracing requests: {}", self.addr, e);
+                self.trace = None;
             }
         }
     }
 }
 
-// //////////////////////////////////////////////////////////////////////////////
-//                                  Sender
-// //////////////////////////////////////////////////////////////////////////////
-struct Sender {
-    servers: Vec<String>,
+/// Re-sends the requests of a trace written through `trace_path`, as `addr`: same
+/// commands, same msg_ids, same gaps between them. Yields WorkloadDone after the last one
+#[cfg(feature = "serde")]
+pub struct RequestReplayer {
+    addr: ActorAddr,
+    lines: std::io::Lines<std::io::BufReader<File>>,
+    last_traced_us: Option<u64>, // Traced send time of the previous request
+    done: bool,
 }
 
-impl reactor_actor::ActorSend for Sender {
-    type OMsg = EMsg;
+#[cfg(feature = "serde")]
+impl RequestReplayer {
+    pub fn open(addr: ActorAddr, path: &Path) -> std::io::Result<Self> {
+        Ok(RequestReplayer {
+            addr,
+            lines: std::io::BufRead::lines(std::io::BufReader::new(File::open(path)?)),
+            last_traced_us: None,
+            done: false,
+        })
+    }
+}
 
-    async fn before_send<'a>(&'a mut self, _output: &Self::OMsg) -> RouteTo<'a> {
-        match &_output {
-            EMsg::ClientRequest(_) => {
-                // Send randomly to any server in the list
-                let mut rng = rand::rng();
-                let choice = self.servers.choose(&mut rng).unwrap();
+#[cfg(feature = "serde")]
+impl Iterator for RequestReplayer {
+    type Item = EMsg;
 
-                RouteTo::from(choice.as_str())
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let req = match self.lines.next() {
+            Some(Ok(line)) => serde_json::from_str::<ClientRequest>(&line)
+                .map_err(|e| warn!("{} Skipping the rest of a bad trace: {}", self.addr, e))
+                .ok(),
+            Some(Err(e)) => {
+                warn!("{} Can't read the trace: {}", self.addr, e);
+                None
             }
+            None => None,
+        };
+        let Some(req) = req else {
+            self.done = true;
+            return Some(EMsg::WorkloadDone);
+        };
 
-            _ => {
-                panic!("Reader tried to send non ReadRequest")
-            }
+        if let (Some(prev), Some(sent)) = (self.last_traced_us, req.timestamp_us) {
+            let gap = Duration::from_micros(sent.saturating_sub(prev));
+            task::block_in_place(|| {
+                std::thread::sleep(gap);
+            });
         }
+        self.last_traced_us = req.timestamp_us.or(self.last_traced_us);
+        Some(EMsg::ClientRequest(ClientRequest {
+            client_id: self.addr.clone(),
+            timestamp_us: Some(now_us()),
+            ..req
+        }))
     }
 }
 
-impl Sender {
-    fn new(servers: Vec<String>) -> Self {
-        Sender { servers }
-    }
+/// One line of an ops file, see parse_ops
+#[derive(Debug, Clone, PartialEq)]
+pub struct TracedOp {
+    pub at: Option<Duration>, // Send time from the start of the replay, None for right away
+    pub cmd: Command,
 }
 
-// //////////////////////////////////////////////////////////////////////////////
-//                                  ACTORS
-// //////////////////////////////////////////////////////////////////////////////
-
-pub async fn cp_client(ctx: RuntimeCtx, servers: Vec<String>, workload: Option<Workload>) {
-    let mut config = WorkloadConfig::default();
-    if workload.is_some() {
-        config = WorkloadConfig::new(workload.unwrap());
+/// Parses an ops file, one operation per line: `GET key`, `SET key value` or
+/// `INCR key [delta]`, optionally after a send time in milliseconds from the start, as in
+/// `250 GET key`. Values run to the end of the line. Blank lines and lines starting with
+/// `#` are skipped. Keys go to `namespace`. Errors name the first bad line
+pub fn parse_ops(text: &str, namespace: &str) -> Result<Vec<TracedOp>, String> {
+    let mut ops = vec![];
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let op = parse_op(line, namespace).map_err(|e| format!("line {}: {}", i + 1, e))?;
+        ops.push(op);
     }
+    Ok(ops)
+}
 
-    BehaviourBuilder::new(
-        Processor {
-            #[cfg(feature = "verbose")]
-            store: std::collections::HashMap::new(),
+fn parse_op(line: &str, namespace: &str) -> Result<TracedOp, String> {
+    let (at, rest) = match line.split_once(char::is_whitespace) {
+        Some((first, rest)) if first.bytes().all(|b| b.is_ascii_digit()) => {
+            let ms = first
+                .parse()
+                .map_err(|e| format!("bad time {}: {}", first, e))?;
+            (Some(Duration::from_millis(ms)), rest.trim_start())
+        }
+        _ => (None, line),
+    };
+    let mut words = rest.splitn(3, char::is_whitespace);
+    let op = words.next().unwrap_or_default().to_ascii_uppercase();
+    let key = match words.next() {
+        Some(name) => Variable::in_namespace(namespace, name),
+        None => return Err(format!("{} without a key", op)),
+    };
+    let arg = words.next().map(str::trim).filter(|arg| !arg.is_empty());
+    let cmd = match (op.as_str(), arg) {
+        ("GET", None) => Command::Get { key },
+        ("SET", Some(val)) => Command::Set {
+            key,
+            val: val.into(),
+            ttl_ms: None,
+        },
+        ("SET", None) => return Err("SET without a value".to_string()),
+        ("INCR", delta) => Command::Incr {
+            key,
+            delta: match delta {
+                Some(delta) => delta
+                    .parse()
+                    .map_err(|e| format!("bad delta {}: {}", delta, e))?,
+                None => 1,
+            },
         },
-        BincodeCodec::default(),
-    )
-    .send(Sender::new(servers))
-    .generator_if(true, || WorkloadIterator::new(ctx.addr.to_string(), config))
-    .on_send_failure(SendErrAction::Drop)
-    .build()
-    .run(ctx)
-    .await
-    .unwrap();
+        ("GET", Some(_)) => return Err("GET takes only a key".to_string()),
+        _ => return Err(format!("unknown op {}", op)),
+    };
+    Ok(TracedOp { at, cmd })
+}
+
+/// Sends the operations of an ops file in order, as `addr`, with msg_ids counting from 1.
+/// Ops with a send time wait for it, the others go right after the previous one. Yields
+/// WorkloadDone after the last one
+pub struct OpsReplayer {
+    addr: ActorAddr,
+    ops: std::vec::IntoIter<TracedOp>,
+    start: Option<Instant>, // Set by the first op
+    sent: usize,
+    done: bool,
+}
+
+impl OpsReplayer {
+    pub fn new(addr: ActorAddr, ops: Vec<TracedOp>) -> Self {
+        OpsReplayer {
+            addr,
+            ops: ops.into_iter(),
+            start: None,
+            sent: 0,
+            done: false,
+        }
+    }
+
+    /// Reads and parses the ops file at `path`
+    pub fn open(addr: ActorAddr, path: &Path, namespace: &str) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        Ok(OpsReplayer::new(addr, parse_ops(&text, namespace)?))
+    }
+}
+
+impl Iterator for OpsReplayer {
+    type Item = EMsg;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let Some(op) = self.ops.next() else {
+            self.done = true;
+            return Some(EMsg::WorkloadDone);
+        };
+        let start = *self.start.get_or_insert_with(Instant::now);
+        if let Some(wait) = op
+            .at
+            .and_then(|at| (start + at).checked_duration_since(Instant::now()))
+        {
+            task::block_in_place(|| {
+                std::thread::sleep(wait);
+            });
+        }
+        self.sent += 1;
+        Some(EMsg::ClientRequest(ClientRequest {
+            client_id: self.addr.clone(),
+            msg_id: self.sent.to_string(),
+            cmd: op.cmd,
+            timestamp_us: Some(now_us()),
+        }))
+    }
+}
+
+/// Generator of cp_client: the workload, or a replayed trace when `replay_path` or
+/// `ops_path` is set
+fn workload_generator(
+    addr: ActorAddr,
+    config: WorkloadConfig,
+    key_stats: SharedKeyStats,
+    load_acks: Option<LoadAcks>,
+) -> Box<dyn Iterator<Item = EMsg> + Send> {
+    if let Some(path) = &config.ops_path {
+        // Better no run at all than one sending part of the ops
+        match OpsReplayer::open(addr.clone(), path, &config.namespace) {
+            Ok(replayer) => return Box::new(replayer),
+            Err(e) => panic!("Can't replay ops {}: {}", path.display(), e),
+        }
+    }
+    #[cfg(feature = "serde")]
+    if let Some(path) = &config.replay_path {
+        match RequestReplayer::open(addr.clone(), path) {
+            Ok(replayer) => return Box::new(replayer),
+            Err(e) => warn!("Can't open trace {}: {}", path.display(), e),
+        }
+    }
+    #[cfg(not(feature = "serde"))]
+    if config.replay_path.is_some() {
+        warn!("replay_path is set, but traces can only be replayed with the serde feature");
+    }
+    let load_ops = config.load_ops;
+    let workload = WorkloadIterator::new(addr, config).with_key_stats(key_stats);
+    match (load_ops, load_acks) {
+        (Some(n), Some(acks)) => Box::new(LoadThenRun::new(workload, n, acks)),
+        _ => pace_if_open(workload),
+    }
+}
+
+/// Runs an open-mode workload from a PacedWorkload when there's a tokio runtime to pace it
+fn pace_if_open(workload: WorkloadIterator) -> Box<dyn Iterator<Item = EMsg> + Send> {
+    match tokio::runtime::Handle::try_current() {
+        Ok(runtime) if workload.paced => Box::new(PacedWorkload::spawn(&runtime, workload)),
+        _ => Box::new(workload),
+    }
+}
+
+/// msg_id prefix of load phase requests, which the Processor keeps out of the statistics
+const LOAD_PREFIX: &str = "load_";
+
+/// Load phase Sets still awaiting a response. Counted up by LoadThenRun as it sends them,
+/// and down by the Processor as each is answered or given up on
+type LoadAcks = Arc<AtomicUsize>;
+
+/// How often LoadThenRun checks whether the load phase was acknowledged
+const LOAD_POLL: Duration = Duration::from_millis(10);
+
+/// YCSB-style load phase: one Set per key, sent back to back, then once all of them
+/// settled, the configured workload with its clock restarted
+pub struct LoadThenRun {
+    loads: std::vec::IntoIter<ClientRequest>,
+    acks: LoadAcks,
+    workload: Option<WorkloadIterator>, // Taken when the run phase starts
+    run: Option<Box<dyn Iterator<Item = EMsg> + Send>>,
+}
+
+impl LoadThenRun {
+    fn new(workload: WorkloadIterator, load_ops: usize, acks: LoadAcks) -> Self {
+        LoadThenRun {
+            loads: workload.load_requests(load_ops).into_iter(),
+            acks,
+            workload: Some(workload),
+            run: None,
+        }
+    }
+}
+
+impl Iterator for LoadThenRun {
+    type Item = EMsg;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(req) = self.loads.next() {
+            self.acks.fetch_add(1, Ordering::SeqCst);
+            return Some(EMsg::ClientRequest(req));
+        }
+        if let Some(mut workload) = self.workload.take() {
+            while self.acks.load(Ordering::SeqCst) > 0 {
+                task::block_in_place(|| std::thread::sleep(LOAD_POLL));
+            }
+            info!("{} Load phase done, starting the run", workload.addr);
+            workload.restart();
+            self.run = Some(pace_if_open(workload));
+        }
+        self.run.as_mut()?.next()
+    }
+}
+
+/// msg_ids of the requests awaiting a response. Shared between the ClosedLoopClient
+/// generator and the Processor, which clears answered and failed requests
+type Outstanding = Arc<Mutex<HashSet<String>>>;
+
+/// Ops generated and conflicts seen per key name. Shared between the WorkloadIterator,
+/// which counts the ops, and the Processor, which counts conflicts as responses arrive
+#[derive(Debug, Default)]
+pub struct KeyStats {
+    per_key_ops: HashMap<String, u64>,
+    per_key_conflicts: HashMap<String, u64>, // Cas requests answered with success: false
+}
+
+type SharedKeyStats = Arc<Mutex<KeyStats>>;
+
+/// Observed conflict rates, to hold against what the key distribution predicts
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ConflictAnalysis {
+    pub hot_keys: Vec<(String, f64)>, // Keys that saw conflicts and their rate, highest first
+    pub overall_conflict_rate: f64,   // Conflicts over all ops
+}
+
+impl KeyStats {
+    pub fn record_op(&mut self, key: &str) {
+        *self.per_key_ops.entry(key.to_string()).or_default() += 1;
+    }
+
+    pub fn record_conflict(&mut self, key: &str) {
+        *self.per_key_conflicts.entry(key.to_string()).or_default() += 1;
+    }
+
+    pub fn conflict_analysis(&self) -> ConflictAnalysis {
+        let rate = |key: &str, conflicts: u64| {
+            conflicts as f64 / self.per_key_ops.get(key).copied().unwrap_or(0).max(1) as f64
+        };
+        let mut hot_keys: Vec<_> = self
+            .per_key_conflicts
+            .iter()
+            .map(|(key, &conflicts)| (key.clone(), rate(key, conflicts)))
+            .collect();
+        hot_keys.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        let ops: u64 = self.per_key_ops.values().sum();
+        let conflicts: u64 = self.per_key_conflicts.values().sum();
+        ConflictAnalysis {
+            hot_keys,
+            overall_conflict_rate: conflicts as f64 / ops.max(1) as f64,
+        }
+    }
+}
+
+/// How long a client waits for late responses after its last request, unless the workload
+/// says otherwise
+const DEFAULT_DRAIN: Duration = Duration::from_secs(2);
+
+/// Gap between the WorkloadTicks a Draining generator yields
+const DRAIN_TICK: Duration = Duration::from_millis(50);
+
+/// Follows a generator's WorkloadDone with WorkloadTicks for the length of the drain, so
+/// the Processor can notice the drain ran out and stop the client even if no response
+/// ever arrives
+pub struct Draining<I> {
+    inner: I,
+    drain: Duration,
+    done_at: Option<Instant>, // When the inner generator yielded WorkloadDone
+}
+
+impl<I: Iterator<Item = EMsg>> Draining<I> {
+    pub fn new(inner: I, drain: Duration) -> Self {
+        Draining {
+            inner,
+            drain,
+            done_at: None,
+        }
+    }
+}
+
+impl<I: Iterator<Item = EMsg>> Iterator for Draining<I> {
+    type Item = EMsg;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let Some(done_at) = self.done_at else {
+            let msg = self.inner.next()?;
+            if matches!(msg, EMsg::WorkloadDone) {
+                self.done_at = Some(Instant::now());
+            }
+            return Some(msg);
+        };
+        // One tick past the end, which the Processor sees after its own drain deadline
+        if done_at.elapsed() > self.drain + DRAIN_TICK {
+            return None;
+        }
+        task::block_in_place(|| {
+            std::thread::sleep(DRAIN_TICK);
+        });
+        Some(EMsg::WorkloadTick)
+    }
+}
+
+/// Count, mean and nearest-rank percentiles of a set of latencies, in microseconds
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LatencySummary {
+    pub count: usize,
+    pub mean_us: f64,
+    pub p50_us: u64,
+    pub p95_us: u64,
+    pub p99_us: u64,
+    pub max_us: u64,
+}
+
+impl LatencySummary {
+    /// None without samples
+    pub fn new(samples: &[u64]) -> Option<Self> {
+        if samples.is_empty() {
+            return None;
+        }
+        let mut sorted = samples.to_vec();
+        sorted.sort_unstable();
+        let n = sorted.len();
+        let percentile = |p: usize| sorted[(p * n).div_ceil(100).max(1) - 1];
+        Some(LatencySummary {
+            count: n,
+            mean_us: sorted.iter().sum::<u64>() as f64 / n as f64,
+            p50_us: percentile(50),
+            p95_us: percentile(95),
+            p99_us: percentile(99),
+            max_us: sorted[n - 1],
+        })
+    }
+}
+
+/// Latencies of the requests sent during one rps_schedule step
+struct RpsStep {
+    offset: Duration, // Into the run
+    rps: f64,
+    start_us: u64,
+    latencies: Vec<u64>,
+}
+
+impl RpsStep {
+    /// A step per rps_schedule entry, preceded by the target_rps one if the schedule starts
+    /// later. Empty without a schedule
+    fn for_run(config: &WorkloadConfig, start_us: u64) -> Vec<RpsStep> {
+        if config.rps_schedule.is_empty() {
+            return vec![];
+        }
+        let base_rps = match config.rate_limiter {
+            RateLimiterKind::Poisson { rps } | RateLimiterKind::TokenBucket { rps, .. } => rps,
+        };
+        let mut steps = config.rps_schedule.clone();
+        if steps[0].0 > Duration::ZERO {
+            steps.insert(0, (Duration::ZERO, base_rps));
+        }
+        steps
+            .into_iter()
+            .map(|(offset, rps)| RpsStep {
+                offset,
+                rps,
+                start_us: start_us + offset.as_micros() as u64,
+                latencies: vec![],
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for LatencySummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "count={} mean={:.0}us p50={}us p95={}us p99={}us max={}us",
+            self.count, self.mean_us, self.p50_us, self.p95_us, self.p99_us, self.max_us
+        )
+    }
+}
+
+/// Completions within the last `window`, written by the client Processor and read by the
+/// throughput reporter
+pub struct ThroughputWindow {
+    window: Duration,
+    completions: VecDeque<Instant>, // Oldest first
+    in_flight: usize,
+    errors: u64,
+}
+
+type Throughput = Arc<Mutex<ThroughputWindow>>;
+
+impl ThroughputWindow {
+    pub fn new(window: Duration) -> Self {
+        ThroughputWindow {
+            window,
+            completions: VecDeque::new(),
+            in_flight: 0,
+            errors: 0,
+        }
+    }
+
+    /// Records a request completed at `now`
+    pub fn record(&mut self, now: Instant) {
+        self.completions.push_back(now);
+        self.prune(now);
+    }
+
+    /// Drops completions that fell out of the window ending at `now`
+    fn prune(&mut self, now: Instant) {
+        while let Some(oldest) = self.completions.front() {
+            if now.saturating_duration_since(*oldest) < self.window {
+                break;
+            }
+            self.completions.pop_front();
+        }
+    }
+
+    /// Completed requests per second over the window ending at `now`
+    pub fn rate(&mut self, now: Instant) -> f64 {
+        self.prune(now);
+        self.completions.len() as f64 / self.window.as_secs_f64()
+    }
+}
+
+/// Logs the throughput of `addr` every `every`, over the last `every`. Runs on its own
+/// thread so it never holds up the actor's message loop, and stops with the Processor
+fn spawn_throughput_reporter(addr: String, every: Duration) -> Throughput {
+    let throughput = Arc::new(Mutex::new(ThroughputWindow::new(every)));
+    let shared = throughput.clone();
+    std::thread::spawn(move || {
+        loop {
+            std::thread::sleep(every);
+            if Arc::strong_count(&shared) == 1 {
+                break;
+            }
+            let mut window = shared.lock().unwrap();
+            let rate = window.rate(Instant::now());
+            info!(
+                "{} Throughput {:.1} req/s, in flight {}, errors {}",
+                addr, rate, window.in_flight, window.errors
+            );
+        }
+    });
+    throughput
+}
+
+/// One issued request in the results file
+#[derive(Debug, Clone, Serialize)]
+struct RequestRecord {
+    msg_id: String,
+    op: &'static str,
+    key: Option<String>,       // First key the command names
+    sent_us: u64,              // First send, retries keep it
+    completed_us: Option<u64>, // None if never answered
+    outcome: String, // "ok", the ErrorCode of an error response, "rejected", "timed_out" or "incomplete"
+}
+
+/// Per-request records of a run, kept in memory and written out once the workload is
+/// done so the message loop never waits on the disk
+struct ResultsLog {
+    path: PathBuf,
+    config: serde_json::Value,
+    records: Vec<RequestRecord>,
+    by_msg_id: HashMap<String, usize>, // Index into records
+}
+
+impl ResultsLog {
+    fn new(path: PathBuf, config: &WorkloadConfig) -> Self {
+        ResultsLog {
+            path,
+            config: config.to_json(),
+            records: Vec::new(),
+            by_msg_id: HashMap::new(),
+        }
+    }
+
+    /// Records the first send of `req`, re-sends are ignored
+    fn sent(&mut self, req: &ClientRequest) {
+        if self.by_msg_id.contains_key(&req.msg_id) {
+            return;
+        }
+        self.by_msg_id
+            .insert(req.msg_id.clone(), self.records.len());
+        self.records.push(RequestRecord {
+            msg_id: req.msg_id.clone(),
+            op: req.cmd.op_name(),
+            key: req.cmd.keys().first().map(|k| k.to_string()),
+            sent_us: req.timestamp_us.unwrap_or_else(now_us),
+            completed_us: None,
+            outcome: "incomplete".to_string(),
+        });
+    }
+
+    /// Marks `msg_id` finished now, the first outcome wins
+    fn completed(&mut self, msg_id: &str, outcome: String) {
+        if let Some(&i) = self.by_msg_id.get(msg_id)
+            && self.records[i].completed_us.is_none()
+        {
+            self.records[i].completed_us = Some(now_us());
+            self.records[i].outcome = outcome;
+        }
+    }
+
+    fn write(&self, summary: serde_json::Value) -> std::io::Result<()> {
+        let results = serde_json::json!({
+            "config": self.config,
+            "requests": self.records,
+            "summary": summary,
+        });
+        let file = std::io::BufWriter::new(std::fs::File::create(&self.path)?);
+        serde_json::to_writer_pretty(file, &results)?;
+        Ok(())
+    }
+}
+
+/// One line of the history file, shaped like a porcupine.Event. A call's value is a
+/// KvInput (`op` 0 get, 1 put, 2 append), a return's a KvOutput
+#[derive(Debug, Clone, Serialize)]
+struct HistoryEvent {
+    client_id: String,
+    kind: &'static str, // "call" or "return"
+    id: usize,          // Pairs a return with its call
+    time_ns: u64,       // Monotonic, since the log was created
+    value: serde_json::Value,
+}
+
+/// Calls and returns of the Gets, Sets and Appends of a run, for Porcupine's KV model.
+/// Requests never answered, or answered with an error, have no return so the checker
+/// treats them as possibly applied. Other commands aren't in the model and are left out
+struct HistoryLog {
+    path: PathBuf,
+    start: Instant,
+    events: Vec<HistoryEvent>,
+    ids: HashMap<String, usize>, // Id of each called msg_id
+}
+
+impl HistoryLog {
+    fn new(path: PathBuf) -> Self {
+        HistoryLog {
+            path,
+            start: Instant::now(),
+            events: Vec::new(),
+            ids: HashMap::new(),
+        }
+    }
+
+    fn elapsed_ns(&self) -> u64 {
+        self.start.elapsed().as_nanos() as u64
+    }
+
+    /// Records the call of `req` on its first send
+    fn call(&mut self, req: &ClientRequest) {
+        let (op, key, value) = match &req.cmd {
+            Command::Get { key } => (0, key, String::new()),
+            Command::Set { key, val, .. } => (1, key, lossy(val)),
+            Command::Append { key, suffix } => (2, key, lossy(suffix)),
+            _ => return,
+        };
+        if self.ids.contains_key(&req.msg_id) {
+            return;
+        }
+        let id = self.ids.len();
+        self.ids.insert(req.msg_id.clone(), id);
+        self.events.push(HistoryEvent {
+            client_id: req.client_id.clone(),
+            kind: "call",
+            id,
+            time_ns: self.elapsed_ns(),
+            value: serde_json::json!({ "op": op, "key": key.to_string(), "value": value }),
+        });
+    }
+
+    /// Records the return of a successful response to a called request
+    fn returned(&mut self, resp: &ClientResponse) {
+        // Only Gets, Sets and Appends were called
+        let Some(&id) = self.ids.get(&resp.msg_id) else {
+            return;
+        };
+        if resp.is_error() {
+            return;
+        }
+        let value = match &resp.cmd_result {
+            CommandResult::Get { val, .. } => val.as_ref().map(lossy).unwrap_or_default(),
+            _ => String::new(),
+        };
+        self.events.push(HistoryEvent {
+            client_id: resp.client_id.clone(),
+            kind: "return",
+            id,
+            time_ns: self.elapsed_ns(),
+            value: serde_json::json!({ "value": value }),
+        });
+    }
+
+    fn write(&self) -> std::io::Result<()> {
+        let mut file = BufWriter::new(File::create(&self.path)?);
+        for event in &self.events {
+            serde_json::to_writer(&mut file, event)?;
+            writeln!(file)?;
+        }
+        file.flush()
+    }
+}
+
+fn lossy(val: &Value) -> String {
+    String::from_utf8_lossy(val.as_bytes()).into_owned()
+}
+
+/// The key every client shares under conflict_ratio
+const HOT_KEY: &str = "hot_key";
+
+/// How long a request may go unanswered before it is re-sent, unless the workload says
+const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Times a timed out request is re-sent before it counts as failed, unless the workload says
+const DEFAULT_MAX_RETRIES: u32 = 3;
+
+/// Times a request refused with a retryable ClientError is re-sent before giving up
+const MAX_ERROR_RETRIES: u32 = 3;
+
+/// How often a ClosedLoopClient at its limit checks for responses
+const CLOSED_LOOP_POLL: Duration = Duration::from_millis(1);
+
+/// How long a ClosedLoopClient waits before re-sending a request refused as retryable
+const ERROR_BACKOFF: Duration = CLOSED_LOOP_POLL.saturating_mul(2);
+
+/// Wraps a WorkloadIterator, keeping at most `max_outstanding` requests in flight.
+/// While at the limit it yields WorkloadTicks, letting the Processor time out requests
+/// whose response was lost
+pub struct ClosedLoopClient {
+    workload: WorkloadIterator,
+    outstanding: Outstanding,
+    max_outstanding: usize,
+}
+
+impl ClosedLoopClient {
+    pub fn new(
+        workload: WorkloadIterator,
+        outstanding: Outstanding,
+        max_outstanding: usize,
+    ) -> Self {
+        Self {
+            workload,
+            outstanding,
+            max_outstanding: max_outstanding.max(1),
+        }
+    }
+}
+
+impl Iterator for ClosedLoopClient {
+    type Item = EMsg;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let now = Instant::now();
+        if now.duration_since(self.workload.start_time) >= self.workload.run_duration {
+            // Lets the workload report once and stop
+            return self.workload.next();
+        }
+
+        if self.outstanding.lock().unwrap().len() < self.max_outstanding {
+            let msg = self.workload.next()?;
+            if let EMsg::ClientRequest(req) = &msg {
+                self.outstanding.lock().unwrap().insert(req.msg_id.clone());
+            }
+            return Some(msg);
+        }
+
+        // At the limit, wait for a response or a timeout to free a slot
+        task::block_in_place(|| {
+            std::thread::sleep(CLOSED_LOOP_POLL);
+        });
+        Some(EMsg::WorkloadTick)
+    }
+}
+
+// //////////////////////////////////////////////////////////////////////////////
+//                                  Processor
+// //////////////////////////////////////////////////////////////////////////////
+/// Requests listed by MissingResponses
+const MISSING_SAMPLE: usize = 10;
+
+/// Answered requests whose result is kept, to compare duplicate responses against
+const ANSWER_WINDOW: usize = 1024;
+
+/// Requests sent that never got any response, still waiting or given up on, as of the end
+/// of the run. Warm-up and load phase requests included, a lost response is lost either way
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MissingResponses {
+    pub count: usize,
+    pub sample: Vec<MissingRequest>, // Up to MISSING_SAMPLE of them, by msg_id
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MissingRequest {
+    pub msg_id: String,
+    pub op: &'static str,
+    pub keys: Vec<String>,
+}
+
+/// A sent request the client is still waiting on
+struct Unanswered {
+    req: ClientRequest,
+    error_retries: u32, // Re-sends after a retryable ClientError
+    timeouts: u32,      // Re-sends after the request timed out
+    deadline: Instant,
+    read_floor: u64, // For a Get, the version of this client's last acked Set to the key when sent
+    warmup: bool,    // Sent during warm-up, kept out of the statistics whenever it is answered
+    backing_off: bool, // Refused with a retryable ClientError, re-sent at the deadline
+}
+
+struct Processor {
+    #[cfg(feature = "verbose")]
+    store: std::collections::HashMap<String, (String, String)>, // Storing msg-id to key-value pairs at client for lchecker
+    sent_us: HashMap<String, (u64, &'static str)>, // Send time and op of each outstanding request, by msg_id
+    latencies: HashMap<&'static str, Vec<u64>>, // Round-trip latency of each answered request in microseconds, by op
+    outstanding: Option<Outstanding>, // Set for a ClosedLoopClient, answered requests are removed
+    errors: HashMap<ErrorCode, u64>,  // Error responses received, by code
+    unanswered: HashMap<String, Unanswered>, // Sent requests awaiting a response, by msg_id
+    deadlines: BTreeSet<(Instant, String)>, // When each unanswered request times out, soonest first
+    request_timeout: Duration,
+    max_retries: u32, // Re-sends after a timeout before the request counts as failed
+    rejected: u64,    // Requests given up on after a ClientError
+    retried: u64,     // Requests re-sent at least once after a timeout
+    failed: u64,      // Requests still unanswered after max_retries re-sends
+    given_up: HashMap<String, ClientRequest>, // Failed requests, until a late response lands
+    answers: HashMap<String, CommandResult>, // Results of the last ANSWER_WINDOW answered requests
+    answer_order: VecDeque<String>, // msg_ids in answers, oldest first
+    duplicate_responses: u64, // Responses to a request that was already answered
+    conflicting_responses: u64, // Duplicates whose result differs from the first, a server bug
+    acked_versions: HashMap<Variable, u64>, // Version of this client's latest acked Set, by key
+    anomalies: u64,   // Gets that missed a Set this client had already seen acked
+    throughput: Option<Throughput>, // Set when the workload asks for periodic throughput reports
+    results: Option<ResultsLog>, // Set when the workload asks for a results file
+    history: Option<HistoryLog>, // Set when the workload asks for a history file
+    warmup_until: Option<Instant>, // Requests sent before this are warm-up, None for no warm-up
+    warmup_requests: u64,
+    value_bytes: u64, // Bytes of every Set value sent, over sets_sent
+    sets_sent: u64,
+    rps_steps: Vec<RpsStep>, // Latencies by the rps_schedule step the request was sent in
+    key_stats: SharedKeyStats, // Shared with the WorkloadIterator, failed Cas count as conflicts
+    load_acks: Option<LoadAcks>, // Set during a load phase, counted down as load requests settle
+    warmup: Duration,        // Restarted with the rps_schedule steps once a load phase is over
+    sent: u64,               // Requests counting in the statistics, the first time they were sent
+    issued: usize,           // Requests of the run, warm-up included, the first time they were sent
+    max_ops: Option<usize>,  // The run ends once `issued` reaches it, see end_reason
+    ends_with_trace: bool,   // A replayed trace, not run_duration, ends the run otherwise
+    drain: Duration,         // Longest wait for outstanding responses after WorkloadDone
+    drain_until: Option<Instant>, // Set from WorkloadDone until the final report
+    shutdown: Option<oneshot::Sender<()>>, // Fired after the final report to stop the client
+    metrics: Option<MetricsReporter>, // Set when the payload names a metrics actor
+}
+
+impl Processor {
+    fn new() -> Self {
+        Processor {
+            #[cfg(feature = "verbose")]
+            store: HashMap::new(),
+            sent_us: HashMap::new(),
+            latencies: HashMap::new(),
+            outstanding: None,
+            errors: HashMap::new(),
+            unanswered: HashMap::new(),
+            deadlines: BTreeSet::new(),
+            request_timeout: DEFAULT_REQUEST_TIMEOUT,
+            max_retries: DEFAULT_MAX_RETRIES,
+            rejected: 0,
+            retried: 0,
+            failed: 0,
+            given_up: HashMap::new(),
+            answers: HashMap::new(),
+            answer_order: VecDeque::new(),
+            duplicate_responses: 0,
+            conflicting_responses: 0,
+            acked_versions: HashMap::new(),
+            anomalies: 0,
+            throughput: None,
+            results: None,
+            history: None,
+            warmup_until: None,
+            warmup_requests: 0,
+            value_bytes: 0,
+            sets_sent: 0,
+            rps_steps: vec![],
+            key_stats: SharedKeyStats::default(),
+            load_acks: None,
+            warmup: Duration::ZERO,
+            sent: 0,
+            issued: 0,
+            max_ops: None,
+            ends_with_trace: false,
+            drain: Duration::ZERO,
+            drain_until: None,
+            shutdown: None,
+            metrics: None,
+        }
+    }
+
+    fn with_outstanding(outstanding: Outstanding) -> Self {
+        Processor {
+            outstanding: Some(outstanding),
+            ..Processor::new()
+        }
+    }
+
+    /// Stops waiting on `msg_id`, freeing its ClosedLoopClient slot. None if it was
+    /// already answered or given up on
+    fn settle(&mut self, msg_id: &str) -> Option<Unanswered> {
+        let unanswered = self.unanswered.remove(msg_id)?;
+        self.deadlines
+            .remove(&(unanswered.deadline, msg_id.to_string()));
+        if let Some(outstanding) = &self.outstanding {
+            outstanding.lock().unwrap().remove(msg_id);
+        }
+        if let Some(acks) = &self.load_acks
+            && msg_id.starts_with(LOAD_PREFIX)
+        {
+            acks.fetch_sub(1, Ordering::SeqCst);
+        }
+        Some(unanswered)
+    }
+
+    /// Restarts the warm-up and the rps_schedule steps from `now`, as the first run request
+    /// after a load phase is sent
+    fn start_run(&mut self, now: Instant) {
+        self.warmup_until = (!self.warmup.is_zero()).then(|| now + self.warmup);
+        let start_us = now_us();
+        for step in &mut self.rps_steps {
+            step.start_us = start_us + step.offset.as_micros() as u64;
+        }
+    }
+
+    /// Moves the deadline of an unanswered request to `deadline`
+    fn rearm(&mut self, msg_id: &str, deadline: Instant) {
+        if let Some(unanswered) = self.unanswered.get_mut(msg_id) {
+            self.deadlines
+                .remove(&(unanswered.deadline, msg_id.to_string()));
+            unanswered.deadline = deadline;
+            self.deadlines
+                .insert((unanswered.deadline, msg_id.to_string()));
+        }
+    }
+
+    /// Re-sends the requests whose deadline passed, or gives up on those out of retries.
+    /// Each is handled once per call, even if its new deadline is already due
+    fn expire_requests(&mut self, now: Instant) -> Vec<EMsg> {
+        let due: Vec<String> = self
+            .deadlines
+            .iter()
+            .take_while(|(deadline, _)| *deadline <= now)
+            .map(|(_, msg_id)| msg_id.clone())
+            .collect();
+        let mut out = vec![];
+        for msg_id in due {
+            let unanswered = self.unanswered.get_mut(&msg_id).unwrap();
+            if unanswered.backing_off {
+                unanswered.backing_off = false;
+                out.push(EMsg::ClientRequest(unanswered.req.clone()));
+                self.rearm(&msg_id, now + self.request_timeout);
+                continue;
+            }
+            if unanswered.timeouts < self.max_retries {
+                if unanswered.timeouts == 0 {
+                    self.retried += 1;
+                }
+                unanswered.timeouts += 1;
+                #[cfg(feature = "verbose")]
+                info!(
+                    "{} [Req: {}] Timed out, re-sending",
+                    unanswered.req.client_id, msg_id
+                );
+                out.push(EMsg::ClientRequest(unanswered.req.clone()));
+                self.rearm(&msg_id, now + self.request_timeout);
+                continue;
+            }
+            warn!(
+                "{} [Req: {}] No response after {} retries, giving up",
+                unanswered.req.client_id, msg_id, self.max_retries
+            );
+            if let Some(unanswered) = self.settle(&msg_id) {
+                self.given_up.insert(msg_id.clone(), unanswered.req);
+            }
+            self.sent_us.remove(&msg_id);
+            self.failed += 1;
+            if let Some(results) = &mut self.results {
+                results.completed(&msg_id, "timed_out".to_string());
+            }
+        }
+        out
+    }
+
+    /// Records the round-trip latency of a response whose request carried a timestamp
+    fn record_latency(&mut self, resp: &ClientResponse) {
+        if let Some((sent, op)) = self.sent_us.remove(&resp.msg_id) {
+            let latency = now_us().saturating_sub(sent);
+            #[cfg(feature = "verbose")]
+            if let (Some(received), Some(responded)) =
+                (resp.server_received_us, resp.server_responded_us)
+            {
+                info!(
+                    "{} [Req: {}] Latency {}us (server {}us)",
+                    resp.client_id,
+                    resp.msg_id,
+                    latency,
+                    responded.saturating_sub(received)
+                );
+            }
+            self.latencies.entry(op).or_default().push(latency);
+            let step = self.rps_steps.partition_point(|s| s.start_us <= sent);
+            if let Some(step) = step.checked_sub(1) {
+                self.rps_steps[step].latencies.push(latency);
+            }
+        }
+    }
+
+    /// Latency summary of each rps_schedule step that got responses
+    fn step_report(&self) -> Vec<(&RpsStep, LatencySummary)> {
+        self.rps_steps
+            .iter()
+            .filter_map(|step| Some((step, LatencySummary::new(&step.latencies)?)))
+            .collect()
+    }
+
+    /// Mean round-trip latency so far, None before the first response
+    #[allow(dead_code)]
+    pub fn avg_latency_us(&self) -> Option<f64> {
+        let all: Vec<u64> = self.latencies.values().flatten().copied().collect();
+        LatencySummary::new(&all).map(|s| s.mean_us)
+    }
+
+    /// Latency summary per op, sorted by op name, then over all ops
+    fn latency_report(&self) -> Vec<(&'static str, LatencySummary)> {
+        let mut ops: Vec<_> = self.latencies.keys().copied().collect();
+        ops.sort();
+        let mut report: Vec<_> = ops
+            .into_iter()
+            .filter_map(|op| Some((op, LatencySummary::new(&self.latencies[op])?)))
+            .collect();
+        let all: Vec<u64> = self.latencies.values().flatten().copied().collect();
+        if let Some(total) = LatencySummary::new(&all) {
+            report.push(("ALL", total));
+        }
+        report
+    }
+
+    /// Keeps the result of a request's first response, forgetting the oldest one past
+    /// ANSWER_WINDOW
+    fn remember_answer(&mut self, resp: &ClientResponse) {
+        if self.answer_order.len() == ANSWER_WINDOW
+            && let Some(oldest) = self.answer_order.pop_front()
+        {
+            self.answers.remove(&oldest);
+        }
+        self.answer_order.push_back(resp.msg_id.clone());
+        self.answers
+            .insert(resp.msg_id.clone(), resp.cmd_result.clone());
+    }
+
+    /// Counts a response to an already answered request, flagging it if its result
+    /// differs from the first response's. Requests out of the window go unnoticed
+    fn check_duplicate(&mut self, resp: &ClientResponse) {
+        let Some(first) = self.answers.get(&resp.msg_id) else {
+            return;
+        };
+        self.duplicate_responses += 1;
+        if *first != resp.cmd_result {
+            self.conflicting_responses += 1;
+            error!(
+                "{} [Req: {}] Conflicting responses: {:?}, then {:?}",
+                resp.client_id, resp.msg_id, first, resp.cmd_result
+            );
+        } else {
+            #[cfg(feature = "verbose")]
+            info!(
+                "{} [Req: {}] Ignoring duplicate response",
+                resp.client_id, resp.msg_id
+            );
+        }
+    }
+
+    /// The requests no response arrived for so far, see MissingResponses
+    pub fn missing_responses(&self) -> MissingResponses {
+        let mut missing: Vec<&ClientRequest> = self
+            .unanswered
+            .values()
+            .map(|u| &u.req)
+            .chain(self.given_up.values())
+            .collect();
+        missing.sort_by(|a, b| a.msg_id.cmp(&b.msg_id));
+        MissingResponses {
+            count: missing.len(),
+            sample: missing
+                .into_iter()
+                .take(MISSING_SAMPLE)
+                .map(|req| MissingRequest {
+                    msg_id: req.msg_id.clone(),
+                    op: req.cmd.op_name(),
+                    keys: req.cmd.keys().iter().map(|k| k.to_string()).collect(),
+                })
+                .collect(),
+        }
+    }
+
+    /// Mean size of the Set values sent so far in bytes, None before the first Set
+    pub fn avg_value_bytes(&self) -> Option<f64> {
+        (self.sets_sent > 0).then(|| self.value_bytes as f64 / self.sets_sent as f64)
+    }
+
+    /// Total error responses so far
+    pub fn error_count(&self) -> u64 {
+        self.errors.values().sum()
+    }
+
+    /// Counters and gauges of my reports to the metrics actor
+    fn metric_values(&self) -> (HashMap<String, u64>, HashMap<String, f64>) {
+        let answered = self.latencies.values().map(|l| l.len() as u64).sum();
+        let counters = [
+            ("requests_sent", self.sent),
+            ("answered", answered),
+            ("errors", self.error_count()),
+            ("retried", self.retried),
+            ("rejected", self.rejected),
+            ("failed", self.failed),
+            ("duplicate_responses", self.duplicate_responses),
+            ("anomalies", self.anomalies),
+        ];
+        let counters = counters
+            .into_iter()
+            .map(|(name, val)| (name.to_string(), val))
+            .collect();
+        let gauges = HashMap::from([("in_flight".to_string(), self.unanswered.len() as f64)]);
+        (counters, gauges)
+    }
+
+    /// Writes the results file, if the workload asked for one
+    fn write_results(&self) {
+        let Some(results) = &self.results else {
+            return;
+        };
+        let latency: serde_json::Map<_, _> = self
+            .latency_report()
+            .into_iter()
+            .map(|(op, s)| (op.to_string(), serde_json::json!(s)))
+            .collect();
+        let errors: serde_json::Map<_, _> = self
+            .errors
+            .iter()
+            .map(|(code, n)| (format!("{:?}", code), serde_json::json!(n)))
+            .collect();
+        let summary = serde_json::json!({
+            "issued": results.records.len(),
+            "ended_by": self.end_reason(),
+            "sent": self.sent,
+            "latency": latency,
+            "errors": errors,
+            "rejected": self.rejected,
+            "retried": self.retried,
+            "failed": self.failed,
+            "anomalies": self.anomalies,
+            "duplicate_responses": self.duplicate_responses,
+            "conflicting_responses": self.conflicting_responses,
+            "warmup": self.warmup_requests,
+            "avg_value_bytes": self.avg_value_bytes(),
+            "conflicts": self.key_stats.lock().unwrap().conflict_analysis(),
+            "rps_steps": self
+                .step_report()
+                .into_iter()
+                .map(|(step, s)| serde_json::json!({
+                    "offset_s": step.offset.as_secs(),
+                    "rps": step.rps,
+                    "latency": s,
+                }))
+                .collect::<Vec<_>>(),
+            "incomplete": self.sent_us.len(),
+            "missing_responses": self.missing_responses(),
+        });
+        match results.write(summary) {
+            Ok(()) => info!("Results written to {}", results.path.display()),
+            Err(e) => warn!("Failed to write {}: {}", results.path.display(), e),
+        }
+    }
+
+    /// What ended the run: max_ops, the end of a replayed trace, or run_duration
+    fn end_reason(&self) -> &'static str {
+        if self.max_ops.is_some_and(|n| self.issued >= n) {
+            "max_ops"
+        } else if self.ends_with_trace {
+            "end of trace"
+        } else {
+            "run_duration"
+        }
+    }
+
+    /// Final report, once the run is over and the drain ended: latencies, errors and what
+    /// is still unanswered, logged and written to the results file. Then stops the client
+    fn finish(&mut self) {
+        self.drain_until = None;
+        for (op, summary) in self.latency_report() {
+            info!("Latency {}: {}", op, summary);
+        }
+        let conflicts = self.key_stats.lock().unwrap().conflict_analysis();
+        if !conflicts.hot_keys.is_empty() {
+            info!(
+                "Conflict rate: {:.4}, hottest keys: {:?}",
+                conflicts.overall_conflict_rate,
+                &conflicts.hot_keys[..conflicts.hot_keys.len().min(5)]
+            );
+        }
+        for (step, summary) in self.step_report() {
+            info!(
+                "Latency at {} rps from {}s: {}",
+                step.rps,
+                step.offset.as_secs(),
+                summary
+            );
+        }
+        info!("Run ended by {}", self.end_reason());
+        // Still unanswered after the drain, kept out of the numbers above
+        info!(
+            "Requests: {} sent, {} answered, {} incomplete",
+            self.sent,
+            self.latencies.values().map(Vec::len).sum::<usize>(),
+            self.sent_us.len()
+        );
+        if self.warmup_requests > 0 {
+            info!("Warm-up requests left out: {}", self.warmup_requests);
+        }
+        let missing = self.missing_responses();
+        if missing.count > 0 {
+            warn!(
+                "{} requests never got a response, e.g. {:?}",
+                missing.count, missing.sample
+            );
+        }
+        if !self.errors.is_empty() || self.rejected > 0 {
+            info!("Errors: {:?}, rejected: {}", self.errors, self.rejected);
+        }
+        info!(
+            "Retried requests: {}, failed after {} retries: {}",
+            self.retried, self.max_retries, self.failed
+        );
+        info!("Read-your-writes anomalies: {}", self.anomalies);
+        if self.duplicate_responses > 0 {
+            info!(
+                "Duplicate responses: {}, with a different result: {}",
+                self.duplicate_responses, self.conflicting_responses
+            );
+        }
+        if let Some(avg) = self.avg_value_bytes() {
+            info!(
+                "Value size: {:.0} bytes on average over {} Sets",
+                avg, self.sets_sent
+            );
+        }
+        self.write_results();
+        if let Some(history) = &self.history {
+            match history.write() {
+                Ok(()) => info!("History written to {}", history.path.display()),
+                Err(e) => warn!("Failed to write {}: {}", history.path.display(), e),
+            }
+        }
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+    }
+}
+
+impl reactor_actor::ActorProcess for Processor {
+    type IMsg = EMsg;
+    type OMsg = EMsg;
+
+    fn process(&mut self, input: Self::IMsg) -> Vec<Self::OMsg> {
+        // Late answers to a request that was re-sent, or given up on, don't count
+        let completed = matches!(&input, EMsg::ClientResponse(resp)
+            if self.unanswered.get(&resp.msg_id).is_some_and(|u| !u.warmup));
+        let mut out = self.handle(input);
+        let now = Instant::now();
+        out.append(&mut self.expire_requests(now));
+        if let Some(until) = self.drain_until
+            && (self.unanswered.is_empty() || now >= until)
+        {
+            self.finish();
+        }
+        if let Some(throughput) = &self.throughput {
+            let mut throughput = throughput.lock().unwrap();
+            if completed {
+                throughput.record(Instant::now());
+            }
+            throughput.in_flight = self.sent_us.len();
+            throughput.errors = self.error_count() + self.rejected + self.failed;
+        }
+        if let Some(mut reporter) = self.metrics.take() {
+            out.extend(reporter.report_if_due(now, || self.metric_values()));
+            self.metrics = Some(reporter);
+        }
+        out
+    }
+}
+
+impl Processor {
+    /// Most fields of the requests and responses are only read to log them
+    #[cfg_attr(not(feature = "verbose"), allow(unused_variables))]
+    fn handle(&mut self, input: EMsg) -> Vec<EMsg> {
+        match &input {
+            // For CP read client, it gets CPReadRequest messages from the generator
+            // and just directly sends to the Actor::Sender
+            EMsg::ClientRequest(req) => {
+                let now = Instant::now();
+                // Decided at send time, so a late answer to a warm-up request still doesn't count.
+                // Load phase requests never count either
+                let load = self.load_acks.is_some() && req.msg_id.starts_with(LOAD_PREFIX);
+                if !load && self.load_acks.take().is_some() {
+                    self.start_run(now);
+                }
+                let warmup = load || self.warmup_until.is_some_and(|end| now < end);
+                if !load && !self.unanswered.contains_key(&req.msg_id) {
+                    self.issued += 1;
+                }
+                if load {
+                    // Not part of the run
+                } else if warmup {
+                    self.warmup_requests += 1;
+                } else if let Some(sent) = req.timestamp_us
+                    && self
+                        .sent_us
+                        .insert(req.msg_id.clone(), (sent, req.cmd.op_name()))
+                        .is_none()
+                {
+                    self.sent += 1;
+                }
+                let deadline = now + self.request_timeout;
+                let read_floor = match &req.cmd {
+                    Command::Get { key } => self.acked_versions.get(key).copied().unwrap_or(0),
+                    _ => 0,
+                };
+                self.unanswered
+                    .entry(req.msg_id.clone())
+                    .or_insert_with(|| {
+                        self.deadlines.insert((deadline, req.msg_id.clone()));
+                        Unanswered {
+                            req: req.clone(),
+                            error_retries: 0,
+                            timeouts: 0,
+                            deadline,
+                            read_floor,
+                            warmup,
+                            backing_off: false,
+                        }
+                    });
+                if let Some(results) = &mut self.results
+                    && !warmup
+                {
+                    results.sent(req);
+                }
+                if let Some(history) = &mut self.history {
+                    history.call(req);
+                }
+                match &req.cmd {
+                    Command::Get { key } => {
+                        #[cfg(feature = "verbose")]
+                        {
+                            info!(
+                                "{} [Req: {}] Getting {}",
+                                req.client_id, req.msg_id, key.name
+                            );
+                        }
+                        vec![input]
+                    }
+                    Command::Set { key, val, .. } => {
+                        self.value_bytes += val.len() as u64;
+                        self.sets_sent += 1;
+                        #[cfg(feature = "verbose")]
+                        {
+                            // Store msg_id, key, and value for lchecker
+                            self.store
+                                .insert(req.msg_id.clone(), (key.name.clone(), val.to_string()));
+                            info!(
+                                "{} [Req: {}] Setting {} = {}",
+                                req.client_id, req.msg_id, key.name, val
+                            );
+                        }
+                        vec![input]
+                    }
+                    Command::Cas { key, expected, new } => {
+                        #[cfg(feature = "verbose")]
+                        info!(
+                            "{} [Req: {}] Swapping {} from {} to {}",
+                            req.client_id,
+                            req.msg_id,
+                            key.name,
+                            fmt_opt_value(expected),
+                            new
+                        );
+                        vec![input]
+                    }
+                    Command::Incr { key, delta } => {
+                        #[cfg(feature = "verbose")]
+                        info!(
+                            "{} [Req: {}] Incrementing {} by {}",
+                            req.client_id, req.msg_id, key.name, delta
+                        );
+                        vec![input]
+                    }
+                    Command::Append { key, suffix } => {
+                        #[cfg(feature = "verbose")]
+                        info!(
+                            "{} [Req: {}] Appending {} to {}",
+                            req.client_id, req.msg_id, suffix, key.name
+                        );
+                        vec![input]
+                    }
+                    Command::SetIfAbsent { key, val } => {
+                        #[cfg(feature = "verbose")]
+                        info!(
+                            "{} [Req: {}] SetIfAbsent {} = {}",
+                            req.client_id, req.msg_id, key.name, val
+                        );
+                        vec![input]
+                    }
+                    Command::MultiSet { .. }
+                    | Command::MultiGet { .. }
+                    | Command::Txn { .. }
+                    | Command::Scan { .. }
+                    | Command::Noop
+                    | Command::Reconfig { .. }
+                    | Command::Watch { .. }
+                    | Command::Unwatch { .. } => {
+                        #[cfg(feature = "verbose")]
+                        info!(
+                            "{} [Req: {}] Sending {}",
+                            req.client_id, req.msg_id, req.cmd
+                        );
+                        vec![input]
+                    }
+                }
+            }
+
+            EMsg::ClientResponse(resp) => {
+                let Some(unanswered) = self.settle(&resp.msg_id) else {
+                    if self.given_up.remove(&resp.msg_id).is_some() {
+                        // Still counted as failed, but the response wasn't lost
+                        self.remember_answer(resp);
+                        return vec![];
+                    }
+                    // The original and a re-send both got answered, the first one counted
+                    self.check_duplicate(resp);
+                    return vec![];
+                };
+                self.remember_answer(resp);
+                self.record_latency(resp);
+                if let Some(results) = &mut self.results {
+                    let outcome = match &resp.cmd_result {
+                        CommandResult::Error { code, .. } => format!("{:?}", code),
+                        _ => "ok".to_string(),
+                    };
+                    results.completed(&resp.msg_id, outcome);
+                }
+                if let Some(history) = &mut self.history {
+                    history.returned(resp);
+                }
+                match &resp.cmd_result {
+                    CommandResult::Get { key, val, version } => {
+                        #[cfg(feature = "verbose")]
+                        info!(
+                            "{} [Req: {}] Get {} = {} (v{})",
+                            resp.client_id,
+                            resp.msg_id,
+                            key.name,
+                            fmt_opt_value(val),
+                            version
+                        );
+                        // Read-your-writes: a Get sent after a Set's ack must see that Set
+                        if *version < unanswered.read_floor {
+                            self.anomalies += 1;
+                            warn!(
+                                "{} [Req: {}] Stale read of {}: v{}, but a Set was acked at v{}",
+                                resp.client_id,
+                                resp.msg_id,
+                                key.name,
+                                version,
+                                unanswered.read_floor
+                            );
+                        }
+                        vec![]
+                    }
+                    CommandResult::Set { key, version } => {
+                        let acked = self.acked_versions.entry(key.clone()).or_default();
+                        *acked = (*acked).max(*version);
+                        #[cfg(feature = "verbose")]
+                        info!(
+                            "{} [Req: {}] Set {} = {} (v{})",
+                            resp.client_id,
+                            resp.msg_id,
+                            key.name,
+                            self.store.get(&resp.msg_id).unwrap().1, // Will exist
+                            version
+                        );
+                        vec![]
+                    }
+                    CommandResult::Cas {
+                        key,
+                        success,
+                        actual,
+                    } => {
+                        if !success {
+                            self.key_stats.lock().unwrap().record_conflict(&key.name);
+                        }
+                        #[cfg(feature = "verbose")]
+                        info!(
+                            "{} [Req: {}] Cas {} success={} actual={}",
+                            resp.client_id,
+                            resp.msg_id,
+                            key.name,
+                            success,
+                            fmt_opt_value(actual)
+                        );
+                        vec![]
+                    }
+                    CommandResult::Incr { key, val } => {
+                        #[cfg(feature = "verbose")]
+                        info!(
+                            "{} [Req: {}] Incr {} = {}",
+                            resp.client_id, resp.msg_id, key.name, val
+                        );
+                        vec![]
+                    }
+                    CommandResult::Append { key, len } => {
+                        #[cfg(feature = "verbose")]
+                        info!(
+                            "{} [Req: {}] Append {} len={}",
+                            resp.client_id, resp.msg_id, key.name, len
+                        );
+                        vec![]
+                    }
+                    CommandResult::SetIfAbsent { key, success } => {
+                        #[cfg(feature = "verbose")]
+                        info!(
+                            "{} [Req: {}] SetIfAbsent {} success={}",
+                            resp.client_id, resp.msg_id, key.name, success
+                        );
+                        vec![]
+                    }
+                    CommandResult::MultiSet { keys, status: _ } => {
+                        #[cfg(feature = "verbose")]
+                        info!(
+                            "{} [Req: {}] MultiSet {} keys",
+                            resp.client_id,
+                            resp.msg_id,
+                            keys.len()
+                        );
+                        vec![]
+                    }
+                    CommandResult::Txn { reads } => {
+                        #[cfg(feature = "verbose")]
+                        info!(
+                            "{} [Req: {}] Txn read {} keys",
+                            resp.client_id,
+                            resp.msg_id,
+                            reads.len()
+                        );
+                        vec![]
+                    }
+                    CommandResult::MultiGet { vals } => {
+                        #[cfg(feature = "verbose")]
+                        for (key, val) in vals {
+                            info!(
+                                "{} [Req: {}] MultiGet {} = {}",
+                                resp.client_id,
+                                resp.msg_id,
+                                key.name,
+                                fmt_opt_value(val)
+                            );
+                        }
+                        vec![]
+                    }
+                    CommandResult::Scan { pairs } => {
+                        #[cfg(feature = "verbose")]
+                        info!(
+                            "{} [Req: {}] Scan returned {} pairs",
+                            resp.client_id,
+                            resp.msg_id,
+                            pairs.len()
+                        );
+                        vec![]
+                    }
+                    CommandResult::Noop => {
+                        // Recovery gave up on the command, it was never applied
+                        warn!(
+                            "{} [Req: {}] Replaced by a Noop",
+                            resp.client_id, resp.msg_id
+                        );
+                        vec![]
+                    }
+                    CommandResult::Error { key, code, detail } => {
+                        *self.errors.entry(*code).or_default() += 1;
+                        error!(
+                            "{} [Req: {}] {:?} on {}: {}",
+                            resp.client_id,
+                            resp.msg_id,
+                            code,
+                            key.as_ref().map_or("-".to_string(), |k| k.to_string()),
+                            detail
+                        );
+                        vec![]
+                    }
+                }
+            }
+
+            EMsg::WorkloadDone => {
+                // Reported once nothing is outstanding or the drain runs out, see process()
+                self.drain_until
+                    .get_or_insert_with(|| Instant::now() + self.drain);
+                vec![]
+            }
+
+            EMsg::WatchNotify(notify) => {
+                #[cfg(feature = "verbose")]
+                info!(
+                    "{} Watched {} changed to {}",
+                    notify.client_id,
+                    notify.key,
+                    fmt_opt_value(&notify.val)
+                );
+                vec![]
+            }
+
+            EMsg::WorkloadTick => vec![],
+
+            EMsg::ClientError(err) => {
+                if !self.unanswered.contains_key(&err.msg_id) {
+                    return vec![];
+                }
+                warn!(
+                    "{} [Req: {}] Rejected: {}",
+                    err.client_id, err.msg_id, err.reason
+                );
+                // The Sender picks a random server, so a retry likely lands on another replica
+                if err.retryable
+                    && let Some(unanswered) = self.unanswered.get_mut(&err.msg_id)
+                    && unanswered.error_retries < MAX_ERROR_RETRIES
+                {
+                    unanswered.error_retries += 1;
+                    // A closed loop would otherwise retry an overloaded cluster straight away
+                    if self.outstanding.is_some() {
+                        unanswered.backing_off = true;
+                        self.rearm(&err.msg_id, Instant::now() + ERROR_BACKOFF);
+                        return vec![];
+                    }
+                    let req = unanswered.req.clone();
+                    self.rearm(&err.msg_id, Instant::now() + self.request_timeout);
+                    return vec![EMsg::ClientRequest(req)];
+                }
+                self.settle(&err.msg_id);
+                self.sent_us.remove(&err.msg_id);
+                if let Some(results) = &mut self.results {
+                    results.completed(&err.msg_id, "rejected".to_string());
+                }
+                self.rejected += 1;
+                vec![]
+            }
+
+            _ => {
+                panic!("Client got an unexpected message")
+            }
+        }
+    }
+}
+
+// //////////////////////////////////////////////////////////////////////////////
+//                                  Sender
+// //////////////////////////////////////////////////////////////////////////////
+struct Sender {
+    servers: Vec<String>,
+    routing: RoutingMode,
+    pinned: usize,      // Index of the Fixed server, where RoundRobin starts
+    next_server: usize, // RoundRobin cursor
+    metrics_actor: Option<String>,
+}
+
+/// Replica owning `key`: the FNV-1a hash of its name modulo the number of replicas.
+/// Every client maps a key the same way given the same replica list
+pub fn key_to_replica<'a>(key: &Variable, replicas: &'a [String]) -> &'a str {
+    let hash = fnv1a(FNV_OFFSET, key.name.as_bytes());
+    &replicas[(hash % replicas.len() as u64) as usize]
+}
+
+impl reactor_actor::ActorSend for Sender {
+    type OMsg = Envelope;
+
+    async fn before_send<'a>(&'a mut self, output: &Self::OMsg) -> RouteTo<'a> {
+        match &output.msg {
+            EMsg::ClientRequest(req) => RouteTo::from(self.destination(req)),
+            EMsg::Metrics { .. } => metrics_route(self.metrics_actor.as_deref()),
+
+            _ => {
+                panic!("Reader tried to send non ReadRequest")
+            }
+        }
+    }
+}
+
+impl Sender {
+    /// `fixed_server` must be one of `servers`, otherwise Fixed falls back to hashing `addr`
+    fn new(
+        servers: Vec<String>,
+        routing: RoutingMode,
+        fixed_server: Option<&str>,
+        addr: &str,
+    ) -> Self {
+        let configured = fixed_server.and_then(|server| {
+            let index = servers.iter().position(|s| s == server);
+            if index.is_none() {
+                warn!("{} fixed_server {} isn't one of the servers", addr, server);
+            }
+            index
+        });
+        let pinned = configured.unwrap_or_else(|| {
+            (fnv1a(FNV_OFFSET, addr.as_bytes()) % servers.len() as u64) as usize
+        });
+        Sender {
+            servers,
+            routing,
+            pinned,
+            next_server: pinned,
+            metrics_actor: None,
+        }
+    }
+
+    /// Server `req` goes to under the routing mode
+    fn destination(&mut self, req: &ClientRequest) -> &str {
+        match self.routing {
+            RoutingMode::KeyAffinity => match req.cmd.keys().first() {
+                Some(key) => key_to_replica(key, &self.servers),
+                None => self.servers.choose(&mut rand::rng()).unwrap(),
+            },
+            RoutingMode::Fixed => &self.servers[self.pinned],
+            RoutingMode::RoundRobin => {
+                let index = self.next_server;
+                self.next_server = (index + 1) % self.servers.len();
+                &self.servers[index]
+            }
+            // Send randomly to any server in the list
+            RoutingMode::Random => self.servers.choose(&mut rand::rng()).unwrap(),
+        }
+    }
+}
+
+// //////////////////////////////////////////////////////////////////////////////
+//                                  ACTORS
+// //////////////////////////////////////////////////////////////////////////////
+
+pub async fn cp_client(
+    ctx: RuntimeCtx,
+    servers: Vec<String>,
+    workload: Option<Workload>,
+    metrics: Option<MetricsConfig>,
+) {
+    let mut config = WorkloadConfig::default();
+    if let Some(workload) = workload {
+        config = WorkloadConfig::new(workload);
+    }
+    // Closed mode and a capped open loop both hold requests back until responses free a slot
+    let cap = match config.mode {
+        WorkloadMode::Closed => Some(config.concurrency),
+        WorkloadMode::Open => config.max_outstanding,
+    };
+    if let Some(cap) = cap {
+        return run_closed_loop(ctx, servers, config, cap, metrics).await;
+    }
+
+    let mut processor = Processor::new();
+    processor.request_timeout = config.request_timeout;
+    processor.max_retries = config.max_retries;
+    processor.throughput = config
+        .report_interval
+        .map(|every| spawn_throughput_reporter(ctx.addr.to_string(), every));
+    processor.results = config
+        .output_path
+        .clone()
+        .map(|path| ResultsLog::new(path, &config));
+    processor.history = config.history_path.clone().map(HistoryLog::new);
+    processor.warmup_until = (!config.warmup.is_zero()).then(|| Instant::now() + config.warmup);
+    processor.rps_steps = RpsStep::for_run(&config, now_us());
+    processor.max_ops = config.max_ops;
+    processor.warmup = config.warmup;
+    processor.ends_with_trace = config.replay_path.is_some() || config.ops_path.is_some();
+    processor.load_acks = config.load_ops.map(|_| LoadAcks::default());
+    let key_stats = processor.key_stats.clone();
+    let load_acks = processor.load_acks.clone();
+    processor.drain = config.drain;
+    let (shutdown, stopped) = oneshot::channel();
+    processor.shutdown = Some(shutdown);
+    let addr = ctx.addr.to_string();
+    let drain = config.drain;
+    processor.metrics = metrics
+        .as_ref()
+        .map(|m| MetricsReporter::new(addr.clone(), m));
+    let mut sender = Sender::new(
+        servers,
+        config.routing,
+        config.fixed_server.as_deref(),
+        &ctx.addr,
+    );
+    sender.metrics_actor = metrics.map(|m| m.actor);
+
+    let behaviour = BehaviourBuilder::new(Versioned(processor), BincodeCodec::default())
+        .send(sender)
+        .generator_if(true, || {
+            let generator = workload_generator(ctx.addr.to_string(), config, key_stats, load_acks);
+            Draining::new(generator, drain).map(Envelope::new)
+        })
+        .on_send_failure(SendErrAction::Drop)
+        .build();
+    run_until_finished(addr, behaviour.run(ctx), stopped).await;
+}
+
+/// Runs a client's behaviour until its Processor sent the final report
+async fn run_until_finished(
+    addr: ActorAddr,
+    behaviour: impl std::future::Future<Output = Result<(), impl fmt::Debug>>,
+    stopped: oneshot::Receiver<()>,
+) {
+    tokio::select! {
+        res = behaviour => res.unwrap(),
+        _ = stopped => info!("{} Client finished", addr),
+    }
+}
+
+/// Like cp_client, but waits for responses once `max_outstanding` requests are in flight.
+/// `timeout` overrides the workload's request timeout
+pub async fn closed_loop_client(
+    ctx: RuntimeCtx,
+    servers: Vec<String>,
+    workload: Option<Workload>,
+    max_outstanding: usize,
+    timeout: Duration,
+    metrics: Option<MetricsConfig>,
+) {
+    let mut config = WorkloadConfig::default();
+    if let Some(workload) = workload {
+        config = WorkloadConfig::new(workload);
+    }
+    config.request_timeout = timeout;
+    run_closed_loop(ctx, servers, config, max_outstanding, metrics).await;
+}
+
+async fn run_closed_loop(
+    ctx: RuntimeCtx,
+    servers: Vec<String>,
+    config: WorkloadConfig,
+    max_outstanding: usize,
+    metrics: Option<MetricsConfig>,
+) {
+    let outstanding: Outstanding = Arc::new(Mutex::new(HashSet::new()));
+    let addr = ctx.addr.to_string();
+    let mut processor = Processor::with_outstanding(outstanding.clone());
+    processor.request_timeout = config.request_timeout;
+    processor.max_retries = config.max_retries;
+    processor.throughput = config
+        .report_interval
+        .map(|every| spawn_throughput_reporter(addr.clone(), every));
+    processor.results = config
+        .output_path
+        .clone()
+        .map(|path| ResultsLog::new(path, &config));
+    processor.history = config.history_path.clone().map(HistoryLog::new);
+    processor.warmup_until = (!config.warmup.is_zero()).then(|| Instant::now() + config.warmup);
+    processor.rps_steps = RpsStep::for_run(&config, now_us());
+    processor.max_ops = config.max_ops;
+    let key_stats = processor.key_stats.clone();
+    if config.load_ops.is_some() {
+        warn!("{} load_phase is ignored in closed-loop mode", addr);
+    }
+    processor.drain = config.drain;
+    let (shutdown, stopped) = oneshot::channel();
+    processor.shutdown = Some(shutdown);
+    let generator_addr = addr.clone();
+    processor.metrics = metrics
+        .as_ref()
+        .map(|m| MetricsReporter::new(addr.clone(), m));
+    let mut sender = Sender::new(
+        servers,
+        config.routing,
+        config.fixed_server.as_deref(),
+        &addr,
+    );
+    sender.metrics_actor = metrics.map(|m| m.actor);
+
+    let behaviour = BehaviourBuilder::new(Versioned(processor), BincodeCodec::default())
+        .send(sender)
+        .generator_if(true, move || {
+            let drain = config.drain;
+            let workload = WorkloadIterator::new(generator_addr, config).with_key_stats(key_stats);
+            Draining::new(
+                ClosedLoopClient::new(workload, outstanding, max_outstanding),
+                drain,
+            )
+            .map(Envelope::new)
+        })
+        .on_send_failure(SendErrAction::Drop)
+        .build();
+    run_until_finished(addr, behaviour.run(ctx), stopped).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reactor_actor::ActorProcess;
+
+    // --- Helpers ---
+
+    fn mock_config() -> WorkloadConfig {
+        WorkloadConfig {
+            rate_limiter: RateLimiterKind::Poisson { rps: 100.0 },
+            key_space_size: 50,
+            distribution: KeyDistribution::Zipfian { skew: 0.99 },
+            read_ratio: 0.5,
+            run_duration: Duration::from_secs(60),
+            seed: None,
+            incr_ratio: 0.0,
+            namespace: String::new(),
+            report_interval: None,
+            output_path: None,
+            mode: WorkloadMode::Open,
+            concurrency: 1,
+            routing: RoutingMode::Random,
+            fixed_server: None,
+            max_outstanding: None,
+            request_timeout: DEFAULT_REQUEST_TIMEOUT,
+            max_retries: DEFAULT_MAX_RETRIES,
+            trace_path: None,
+            replay_path: None,
+            ops_path: None,
+            history_path: None,
+            warmup: Duration::ZERO,
+            key_prefix: String::new(),
+            disjoint_keyspace: false,
+            conflict_ratio: None,
+            value_size: None,
+            rps_schedule: vec![],
+            load_ops: None,
+            drain: Duration::ZERO,
+            max_ops: None,
+        }
+    }
+
+    fn sample_stream(it: &mut WorkloadIterator, n: usize) -> Vec<(String, Duration)> {
+        (0..n)
+            .map(|_| (it.generate_key(), it.next_interval()))
+            .collect()
+    }
+
+    // --- Tests ---
+    #[test]
+    fn test_same_seed_same_stream() {
+        let mut a = WorkloadIterator::with_seed("c1".into(), mock_config(), 42);
+        let mut b = WorkloadIterator::with_seed("c1".into(), mock_config(), 42);
+
+        assert_eq!(sample_stream(&mut a, 200), sample_stream(&mut b, 200));
+    }
+
+    #[test]
+    fn test_config_seed_matches_with_seed() {
+        let mut a = WorkloadIterator::new(
+            "c1".into(),
+            WorkloadConfig {
+                seed: Some(7),
+                ..mock_config()
+            },
+        );
+        let mut b = WorkloadIterator::with_seed("c1".into(), mock_config(), 7);
+
+        assert_eq!(sample_stream(&mut a, 50), sample_stream(&mut b, 50));
+    }
+
+    #[test]
+    fn test_different_seed_different_stream() {
+        let mut a = WorkloadIterator::with_seed("c1".into(), mock_config(), 1);
+        let mut b = WorkloadIterator::with_seed("c1".into(), mock_config(), 2);
+
+        assert_ne!(sample_stream(&mut a, 200), sample_stream(&mut b, 200));
+    }
+
+    #[test]
+    fn test_same_seed_differs_per_client() {
+        let mut a = WorkloadIterator::with_seed("c1".into(), mock_config(), 42);
+        let mut b = WorkloadIterator::with_seed("c2".into(), mock_config(), 42);
+
+        assert_ne!(sample_stream(&mut a, 200), sample_stream(&mut b, 200));
+    }
+
+    #[test]
+    fn test_key_prefix_applied_to_generated_keys() {
+        let keys = |config: WorkloadConfig, addr: &str| -> HashSet<String> {
+            let mut it = WorkloadIterator::with_seed(addr.into(), config, 5);
+            (0..200).map(|_| it.generate_key()).collect()
+        };
+
+        let shared = keys(mock_config(), "c1");
+        assert!(shared.iter().all(|k| k.starts_with("key_")));
+        assert!(!shared.is_disjoint(&keys(mock_config(), "c2")));
+
+        let prefixed = WorkloadConfig {
+            key_prefix: "p/".into(),
+            ..mock_config()
+        };
+        assert!(keys(prefixed, "c1").iter().all(|k| k.starts_with("p/key_")));
+
+        // disjoint_keyspace wins over key_prefix
+        let disjoint = || WorkloadConfig {
+            key_prefix: "p/".into(),
+            disjoint_keyspace: true,
+            ..mock_config()
+        };
+        let c1 = keys(disjoint(), "c1");
+        let c2 = keys(disjoint(), "c2");
+        assert!(c1.iter().all(|k| k.starts_with("c1_key_")));
+        assert!(c2.iter().all(|k| k.starts_with("c2_key_")));
+        assert!(c1.is_disjoint(&c2));
+    }
+
+    #[test]
+    fn test_conflict_ratio_sends_share_to_hot_key() {
+        for (ratio, expected) in [(0.0, 0), (0.02, 200), (1.0, 10_000)] {
+            let config = WorkloadConfig {
+                conflict_ratio: Some(ratio),
+                ..mock_config()
+            };
+            let mut it = WorkloadIterator::with_seed("c1".into(), config, 9);
+            let keys: Vec<String> = (0..10_000).map(|_| it.generate_key()).collect();
+            let hot = keys.iter().filter(|k| *k == HOT_KEY).count();
+            // Binomial std dev at 2% is 14, allow over 3 of them
+            assert!(hot.abs_diff(expected) <= 50, "ratio {}: {} hot", ratio, hot);
+            assert!(
+                keys.iter()
+                    .all(|k| k == HOT_KEY || k.starts_with("c1_key_"))
+            );
+        }
+    }
+
+    #[test]
+    fn test_hotspot_splits_hot_and_cold_keys() {
+        let config = WorkloadConfig {
+            distribution: KeyDistribution::Hotspot {
+                hot_fraction: 0.1,
+                hot_access_prob: 0.9,
+            },
+            ..mock_config()
+        };
+        let mut it = WorkloadIterator::with_seed("c1".into(), config, 4);
+        let indices: Vec<usize> = (0..10_000)
+            .map(|_| it.generate_key()["key_".len()..].parse().unwrap())
+            .collect();
+
+        // 5 of the 50 keys are hot
+        let hot = indices.iter().filter(|i| **i < 5).count();
+        assert!((8_850..9_150).contains(&hot), "hot: {}", hot);
+        let distinct: HashSet<_> = indices.iter().collect();
+        assert_eq!(distinct.len(), 50);
+    }
+
+    #[test]
+    fn test_sequential_walks_keys_and_wraps() {
+        let config = WorkloadConfig {
+            distribution: KeyDistribution::Sequential,
+            key_space_size: 3,
+            key_prefix: "p/".into(),
+            ..mock_config()
+        };
+        let mut it = WorkloadIterator::with_seed("c1".into(), config, 4);
+        let keys: Vec<String> = (0..7).map(|_| it.generate_key()).collect();
+        assert_eq!(
+            keys,
+            [
+                "p/key_0", "p/key_1", "p/key_2", "p/key_0", "p/key_1", "p/key_2", "p/key_0"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_workload_distribution_parsing() {
+        let parse = |json: &str| WorkloadConfig::new(serde_json::from_str(json).unwrap());
+
+        let hotspot = parse(
+            r#"{"distribution": {"type": "Hotspot", "hot_fraction": 0.2, "hot_access_prob": 0.8}}"#,
+        );
+        assert_eq!(
+            hotspot.distribution,
+            KeyDistribution::Hotspot {
+                hot_fraction: 0.2,
+                hot_access_prob: 0.8
+            }
+        );
+        let uniform = parse(r#"{"zipf_skew": 0.5, "distribution": {"type": "Uniform"}}"#);
+        assert_eq!(uniform.distribution, KeyDistribution::Uniform);
+        let sequential = parse(r#"{"distribution": {"type": "Sequential"}}"#);
+        assert_eq!(sequential.distribution, KeyDistribution::Sequential);
+        // Without distribution, zipf_skew decides as before
+        let zipf = parse(r#"{"zipf_skew": 0.5}"#);
+        assert_eq!(zipf.distribution, KeyDistribution::Zipfian { skew: 0.5 });
+        assert_eq!(parse("{}").distribution, KeyDistribution::Uniform);
+    }
+
+    #[test]
+    fn test_zipf_skew_raises_conflict_rate() {
+        // A request conflicts when its key is among the 10 before it, as if they were in flight
+        let analyse = |skew| {
+            let config = WorkloadConfig {
+                rate_limiter: RateLimiterKind::Poisson { rps: 1_000_000.0 },
+                key_space_size: 1000,
+                distribution: KeyDistribution::Zipfian { skew },
+                ..mock_config()
+            };
+            let mut it = WorkloadIterator::with_seed("c1".into(), config, 9);
+            let mut in_flight = VecDeque::new();
+            for _ in 0..5000 {
+                let Some(EMsg::ClientRequest(req)) = it.next() else {
+                    panic!("Expected a ClientRequest");
+                };
+                let key = req.cmd.keys()[0].name.clone();
+                if in_flight.contains(&key) {
+                    it.record_conflict(&key);
+                }
+                in_flight.push_back(key);
+                if in_flight.len() > 10 {
+                    in_flight.pop_front();
+                }
+            }
+            it.conflict_analysis()
+        };
+
+        let high = analyse(1.5);
+        let low = analyse(0.1);
+        assert!(
+            high.overall_conflict_rate > 5.0 * low.overall_conflict_rate,
+            "{} vs {}",
+            high.overall_conflict_rate,
+            low.overall_conflict_rate
+        );
+        assert!(high.hot_keys.windows(2).all(|w| w[0].1 >= w[1].1));
+    }
+
+    #[test]
+    fn test_failed_cas_counts_as_conflict() {
+        let mut p = Processor::new();
+        for (msg_id, success) in [("1", false), ("2", true), ("3", false)] {
+            p.process(EMsg::ClientRequest(ClientRequest {
+                client_id: "c1".into(),
+                msg_id: msg_id.into(),
+                cmd: Command::Cas {
+                    key: Variable::new("k"),
+                    expected: None,
+                    new: "v".into(),
+                },
+                timestamp_us: Some(now_us()),
+            }));
+            p.process(EMsg::ClientResponse(ClientResponse {
+                msg_id: msg_id.into(),
+                client_id: "c1".into(),
+                cmd_result: CommandResult::Cas {
+                    key: Variable::new("k"),
+                    success,
+                    actual: None,
+                },
+                server_received_us: None,
+                server_responded_us: None,
+            }));
+        }
+        let mut stats = p.key_stats.lock().unwrap();
+        for _ in 0..4 {
+            stats.record_op("k");
+        }
+        let analysis = stats.conflict_analysis();
+        assert_eq!(analysis.hot_keys, [("k".to_string(), 0.5)]);
+        assert_eq!(analysis.overall_conflict_rate, 0.5);
+    }
+
+    #[test]
+    fn test_op_mix_follows_ratios() {
+        let config = WorkloadConfig {
+            rate_limiter: RateLimiterKind::Poisson { rps: 1_000_000.0 },
+            read_ratio: 0.2,
+            incr_ratio: 0.3,
+            ..mock_config()
+        };
+        let mut it = WorkloadIterator::with_seed("c1".into(), config, 3);
+
+        let (mut gets, mut incrs, mut sets) = (0, 0, 0);
+        for _ in 0..2000 {
+            match it.next() {
+                Some(EMsg::ClientRequest(req)) => match req.cmd {
+                    Command::Get { .. } => gets += 1,
+                    Command::Incr { .. } => incrs += 1,
+                    Command::Set { .. } => sets += 1,
+                    _ => panic!("Unexpected command generated"),
+                },
+                _ => panic!("Expected a ClientRequest"),
+            }
+        }
+
+        // 20% / 30% / 50% with generous tolerance
+        assert!((300..500).contains(&gets), "gets: {}", gets);
+        assert!((500..700).contains(&incrs), "incrs: {}", incrs);
+        assert!((900..1100).contains(&sets), "sets: {}", sets);
+    }
+
+    #[test]
+    fn test_load_phase_leaves_no_missing_keys() {
+        let config = WorkloadConfig {
+            rate_limiter: RateLimiterKind::Poisson { rps: 1_000_000.0 },
+            distribution: KeyDistribution::Uniform,
+            load_ops: Some(50),
+            ..mock_config()
+        };
+        let mut p = Processor::new();
+        p.load_acks = Some(LoadAcks::default());
+        let mut workload = workload_generator(
+            "c1".into(),
+            config,
+            p.key_stats.clone(),
+            p.load_acks.clone(),
+        );
+
+        // In-memory store answering every request as it's sent
+        let mut store: HashMap<Variable, Value> = HashMap::new();
+        let mut run_gets = 0;
+        for msg in workload.by_ref().take(50 + 500) {
+            let EMsg::ClientRequest(req) = msg else {
+                panic!("Expected a ClientRequest, got {:?}", msg);
+            };
+            let loading = req.msg_id.starts_with(LOAD_PREFIX);
+            let cmd_result = match req.cmd.clone() {
+                Command::Set { key, val, .. } => {
+                    store.insert(key.clone(), val);
+                    CommandResult::Set { key, version: 1 }
+                }
+                Command::Get { key } => {
+                    assert!(!loading);
+                    assert!(store.contains_key(&key), "{:?} read before being set", key);
+                    run_gets += 1;
+                    let val = store.get(&key).cloned();
+                    CommandResult::Get {
+                        key,
+                        val,
+                        version: 1,
+                    }
+                }
+                cmd => panic!("Unexpected {:?}", cmd),
+            };
+            let msg_id = req.msg_id.clone();
+            p.process(EMsg::ClientRequest(req));
+            p.process(EMsg::ClientResponse(ClientResponse {
+                msg_id,
+                client_id: "c1".into(),
+                cmd_result,
+                server_received_us: None,
+                server_responded_us: None,
+            }));
+            if loading {
+                assert!(p.sent_us.is_empty() && p.warmup_requests == 0);
+            }
+        }
+        assert_eq!(store.len(), 50);
+        assert!(run_gets > 0);
+        assert!(p.load_acks.is_none());
+        assert_eq!(p.latencies.values().map(Vec::len).sum::<usize>(), 500);
+    }
+
+    #[test]
+    fn test_values_have_exact_size() {
+        let set_values = |value_size| {
+            let config = WorkloadConfig {
+                rate_limiter: RateLimiterKind::Poisson { rps: 1_000_000.0 },
+                read_ratio: 0.0,
+                value_size,
+                ..mock_config()
+            };
+            let mut it = WorkloadIterator::with_seed("c1".into(), config, 3);
+            (0..3)
+                .map(|_| match it.next() {
+                    Some(EMsg::ClientRequest(ClientRequest {
+                        cmd: Command::Set { val, .. },
+                        ..
+                    })) => val,
+                    other => panic!("Expected a Set, got {:?}", other),
+                })
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(set_values(None)[0], Value::from("value_c1_1"));
+        assert!(set_values(Some(0)).iter().all(|val| val.is_empty()));
+        assert!(set_values(Some(1)).iter().all(|val| val.as_bytes() == b"v"));
+
+        const MIB: usize = 1 << 20;
+        let large = set_values(Some(MIB));
+        for (i, val) in large.iter().enumerate() {
+            assert_eq!(val.len(), MIB);
+            let tag = format!("value_c1_{}", i + 1);
+            assert!(val.as_bytes().starts_with(tag.as_bytes()));
+            assert_eq!(val.as_bytes()[MIB - 1], b'a' + ((MIB - 1) % 26) as u8);
+        }
+        assert_ne!(large[0], large[1]);
+    }
+
+    #[test]
+    fn test_avg_value_bytes_counts_sets() {
+        let mut p = Processor::new();
+        assert_eq!(p.avg_value_bytes(), None);
+        for (msg_id, size) in [("1", 10), ("2", 30)] {
+            p.process(EMsg::ClientRequest(ClientRequest {
+                client_id: "c1".into(),
+                msg_id: msg_id.into(),
+                cmd: Command::Set {
+                    key: Variable::new("k"),
+                    val: Value(vec![b'x'; size]),
+                    ttl_ms: None,
+                },
+                timestamp_us: Some(now_us()),
+            }));
+        }
+        assert_eq!(p.avg_value_bytes(), Some(20.0));
+    }
+
+    #[test]
+    fn test_round_trip_latency_recorded() {
+        let mut p = Processor::new();
+        assert_eq!(p.avg_latency_us(), None);
+
+        let key = Variable::new("k");
+        for (msg_id, age_us) in [("1", 2_000), ("2", 4_000)] {
+            p.process(EMsg::ClientRequest(ClientRequest {
+                client_id: "c1".into(),
+                msg_id: msg_id.into(),
+                cmd: Command::Get { key: key.clone() },
+                timestamp_us: Some(now_us() - age_us),
+            }));
+        }
+        for msg_id in ["1", "2", "unknown"] {
+            p.process(EMsg::ClientResponse(ClientResponse {
+                msg_id: msg_id.into(),
+                client_id: "c1".into(),
+                cmd_result: CommandResult::Get {
+                    key: key.clone(),
+                    val: None,
+                    version: 0,
+                },
+                server_received_us: None,
+                server_responded_us: None,
+            }));
+        }
+
+        let gets = &p.latencies["GET"];
+        assert_eq!(gets.len(), 2);
+        assert!(gets[0] >= 2_000 && gets[1] >= 4_000);
+        assert!(p.avg_latency_us().unwrap() >= 3_000.0);
+        assert!(p.sent_us.is_empty());
+    }
+
+    #[test]
+    fn test_throughput_rate_over_window() {
+        let start = Instant::now();
+        let mut window = ThroughputWindow::new(Duration::from_secs(2));
+        for ms in (0..4000).step_by(100) {
+            window.record(start + Duration::from_millis(ms));
+        }
+
+        // Only the last 2s of completions count, 10 per second
+        assert_eq!(window.rate(start + Duration::from_millis(3950)), 10.0);
+        assert_eq!(window.completions.len(), 20);
+        assert_eq!(window.rate(start + Duration::from_secs(10)), 0.0);
+    }
+
+    #[test]
+    fn test_processor_feeds_throughput_window() {
+        let mut p = Processor::new();
+        let throughput = Arc::new(Mutex::new(ThroughputWindow::new(Duration::from_secs(60))));
+        p.throughput = Some(throughput.clone());
+
+        for msg_id in ["1", "2"] {
+            p.process(EMsg::ClientRequest(ClientRequest {
+                client_id: "c1".into(),
+                msg_id: msg_id.into(),
+                cmd: Command::Get {
+                    key: Variable::new("k"),
+                },
+                timestamp_us: Some(now_us()),
+            }));
+        }
+        p.process(mock_response("1"));
+
+        let window = throughput.lock().unwrap();
+        assert_eq!(window.completions.len(), 1);
+        assert_eq!(window.in_flight, 1);
+        assert_eq!(window.errors, 0);
+    }
+
+    #[test]
+    fn test_latency_summary_percentiles() {
+        assert_eq!(LatencySummary::new(&[]), None);
+
+        let samples: Vec<u64> = (1..=100).rev().collect();
+        let s = LatencySummary::new(&samples).unwrap();
+        assert_eq!((s.count, s.mean_us), (100, 50.5));
+        assert_eq!((s.p50_us, s.p95_us, s.p99_us, s.max_us), (50, 95, 99, 100));
+
+        let s = LatencySummary::new(&[7]).unwrap();
+        assert_eq!((s.p50_us, s.p99_us, s.max_us), (7, 7, 7));
+    }
+
+    #[test]
+    fn test_latency_report_split_by_op() {
+        let mut p = Processor::new();
+        let key = Variable::new("k");
+        let cmds = [
+            Command::Get { key: key.clone() },
+            Command::Incr {
+                key: key.clone(),
+                delta: 1,
+            },
+            Command::Get { key: key.clone() },
+        ];
+        for (i, cmd) in cmds.into_iter().enumerate() {
+            p.process(EMsg::ClientRequest(ClientRequest {
+                client_id: "c1".into(),
+                msg_id: i.to_string(),
+                cmd,
+                timestamp_us: Some(now_us()),
+            }));
+        }
+        // "2" never gets an answer
+        for msg_id in ["0", "1"] {
+            p.process(mock_response(msg_id));
+        }
+
+        let report = p.latency_report();
+        let ops: Vec<_> = report.iter().map(|(op, s)| (*op, s.count)).collect();
+        assert_eq!(ops, vec![("GET", 1), ("INCR", 1), ("ALL", 2)]);
+        assert_eq!(p.sent_us.len(), 1);
+        assert!(p.process(EMsg::WorkloadDone).is_empty());
+    }
+
+    fn mock_closed_loop(max_outstanding: usize) -> (ClosedLoopClient, Processor) {
+        let outstanding: Outstanding = Arc::new(Mutex::new(HashSet::new()));
+        let workload = WorkloadIterator::with_seed("c1".into(), mock_config(), 7);
+        let client = ClosedLoopClient::new(workload, outstanding.clone(), max_outstanding);
+        (client, Processor::with_outstanding(outstanding))
+    }
+
+    fn msg_id(msg: Option<EMsg>) -> String {
+        match msg {
+            Some(EMsg::ClientRequest(req)) => req.msg_id,
+            _ => panic!("Expected a ClientRequest"),
+        }
+    }
+
+    // Generates the next request and hands it to the Processor, as the runtime does
+    fn send_next(client: &mut ClosedLoopClient, p: &mut Processor) -> String {
+        let msg = client.next().unwrap();
+        let out = p.process(msg.clone());
+        assert_eq!(out.len(), 1);
+        msg_id(Some(msg))
+    }
+
+    fn mock_response(msg_id: &str) -> EMsg {
+        EMsg::ClientResponse(ClientResponse {
+            msg_id: msg_id.into(),
+            client_id: "c1".into(),
+            cmd_result: CommandResult::Set {
+                key: Variable::new("k"),
+                version: 1,
+            },
+            server_received_us: None,
+            server_responded_us: None,
+        })
+    }
+
+    #[test]
+    fn test_closed_loop_frees_slot_on_response() {
+        let (mut client, mut p) = mock_closed_loop(2);
+        assert_eq!(send_next(&mut client, &mut p), "1");
+        assert_eq!(send_next(&mut client, &mut p), "2");
+        assert_eq!(client.outstanding.lock().unwrap().len(), 2);
+        // At the limit, only ticks come out
+        assert!(matches!(client.next(), Some(EMsg::WorkloadTick)));
+
+        p.process(mock_response("1"));
+        assert_eq!(client.outstanding.lock().unwrap().len(), 1);
+        assert_eq!(send_next(&mut client, &mut p), "3");
+    }
+
+    // A Processor waiting on request "1", which times out immediately
+    fn mock_timing_out(max_retries: u32) -> Processor {
+        let mut p = Processor::new();
+        p.request_timeout = Duration::ZERO;
+        p.max_retries = max_retries;
+        let out = p.process(EMsg::ClientRequest(ClientRequest {
+            client_id: "c1".into(),
+            msg_id: "1".into(),
+            cmd: Command::Get {
+                key: Variable::new("k"),
+            },
+            timestamp_us: Some(now_us()),
+        }));
+        // The request itself, then its first re-send as it's already overdue
+        assert_eq!(out.len(), 2);
+        p
+    }
+
+    #[test]
+    fn test_timed_out_request_resent_then_failed() {
+        let mut p = mock_timing_out(2);
+        let out = p.process(EMsg::WorkloadTick);
+        assert!(matches!(&out[..], [EMsg::ClientRequest(r)] if r.msg_id == "1"));
+        assert_eq!((p.retried, p.failed), (1, 0));
+
+        // Out of retries
+        assert!(p.process(EMsg::WorkloadTick).is_empty());
+        assert_eq!((p.retried, p.failed), (1, 1));
+        assert!(p.unanswered.is_empty() && p.deadlines.is_empty());
+
+        // A response after giving up changes nothing
+        p.process(mock_response("1"));
+        assert!(p.latencies.is_empty());
+    }
+
+    #[test]
+    fn test_lost_response_reported_as_missing() {
+        // "1" was re-sent, and the re-send gets answered
+        let mut p = mock_timing_out(1);
+        p.process(mock_response("1"));
+        for msg_id in ["2", "3"] {
+            p.process(EMsg::ClientRequest(ClientRequest {
+                client_id: "c1".into(),
+                msg_id: msg_id.into(),
+                cmd: Command::Get {
+                    key: Variable::new(format!("k{}", msg_id)),
+                },
+                timestamp_us: Some(now_us()),
+            }));
+        }
+        p.process(mock_response("3"));
+        // The response to "2" is lost, and the client gives up on it
+        p.process(EMsg::WorkloadTick);
+        assert_eq!(p.failed, 1);
+
+        let missing = p.missing_responses();
+        assert_eq!(missing.count, 1);
+        assert_eq!(
+            missing.sample,
+            [MissingRequest {
+                msg_id: "2".into(),
+                op: "GET",
+                keys: vec!["k2".into()],
+            }]
+        );
+
+        // Late, but not lost after all
+        p.process(mock_response("2"));
+        assert_eq!(p.missing_responses().count, 0);
+    }
+
+    #[test]
+    fn test_late_original_response_ignored() {
+        let mut p = mock_timing_out(5);
+        p.process(mock_response("1"));
+        assert_eq!(p.latencies["GET"].len(), 1);
+        assert!(p.deadlines.is_empty());
+
+        // The original request's response shows up after the re-send's
+        let err = EMsg::ClientResponse(ClientResponse {
+            msg_id: "1".into(),
+            client_id: "c1".into(),
+            cmd_result: CommandResult::Error {
+                key: None,
+                code: ErrorCode::InvalidKey,
+                detail: String::new(),
+            },
+            server_received_us: None,
+            server_responded_us: None,
+        });
+        assert!(p.process(err).is_empty());
+        assert_eq!(p.latencies["GET"].len(), 1);
+        assert_eq!(p.error_count(), 0);
+        assert_eq!((p.retried, p.failed), (1, 0));
+        assert_eq!((p.duplicate_responses, p.conflicting_responses), (1, 1));
+    }
+
+    #[test]
+    fn test_duplicate_responses_counted_once() {
+        let mut p = Processor::new();
+        p.process(EMsg::ClientRequest(ClientRequest {
+            client_id: "c1".into(),
+            msg_id: "1".into(),
+            cmd: Command::Set {
+                key: Variable::new("k"),
+                val: Value::from("v"),
+                ttl_ms: None,
+            },
+            timestamp_us: Some(now_us()),
+        }));
+        p.process(mock_response("1"));
+        assert!(p.process(mock_response("1")).is_empty());
+        assert_eq!(p.latencies["SET"].len(), 1);
+        assert_eq!((p.duplicate_responses, p.conflicting_responses), (1, 0));
+
+        // Same request, another outcome: the server executed it twice
+        let conflicting = EMsg::ClientResponse(ClientResponse {
+            msg_id: "1".into(),
+            client_id: "c1".into(),
+            cmd_result: CommandResult::Set {
+                key: Variable::new("k"),
+                version: 2,
+            },
+            server_received_us: None,
+            server_responded_us: None,
+        });
+        assert!(p.process(conflicting).is_empty());
+        assert_eq!(p.latencies["SET"].len(), 1);
+        assert_eq!((p.duplicate_responses, p.conflicting_responses), (2, 1));
+    }
+
+    #[test]
+    fn test_closed_mode_never_exceeds_concurrency() {
+        let concurrency = 3;
+        let config = WorkloadConfig {
+            mode: WorkloadMode::Closed,
+            concurrency,
+            ..mock_config()
+        };
+        let outstanding: Outstanding = Arc::new(Mutex::new(HashSet::new()));
+        let workload = WorkloadIterator::with_seed("c1".into(), config, 7);
+        let mut client = ClosedLoopClient::new(workload, outstanding.clone(), concurrency);
+        let mut p = Processor::with_outstanding(outstanding.clone());
+        let mut rng = StdRng::seed_from_u64(3);
+
+        let mut in_flight: Vec<String> = Vec::new();
+        let mut peak = 0;
+        // Unpaced, so this only terminates if every request waits for a free slot
+        for _ in 0..200 {
+            // Answer a random request when full, sometimes earlier
+            if in_flight.len() == concurrency || (!in_flight.is_empty() && rng.random_bool(0.3)) {
+                let i = rng.random_range(0..in_flight.len());
+                p.process(mock_response(&in_flight.swap_remove(i)));
+            }
+            in_flight.push(send_next(&mut client, &mut p));
+            let n = outstanding.lock().unwrap().len();
+            assert!(n <= concurrency, "{} requests in flight", n);
+            assert_eq!(n, in_flight.len());
+            peak = peak.max(n);
+        }
+        assert_eq!(peak, concurrency);
+    }
+
+    #[test]
+    fn test_open_loop_stalls_at_max_outstanding() {
+        let (client, mut p) = mock_closed_loop(5);
+        let outstanding = client.outstanding.clone();
+        let (tx, rx) = std::sync::mpsc::channel();
+        // Nobody answers, so the generator only ticks once 5 requests are out
+        std::thread::spawn(move || {
+            for msg in client {
+                if matches!(msg, EMsg::ClientRequest(_)) && tx.send(msg).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let wait = Duration::from_secs(5);
+        let sent: Vec<String> = (0..5)
+            .map(|_| {
+                let msg = rx.recv_timeout(wait).unwrap();
+                p.process(msg.clone());
+                msg_id(Some(msg))
+            })
+            .collect();
+        assert!(rx.recv_timeout(Duration::from_millis(200)).is_err());
+        assert_eq!(outstanding.lock().unwrap().len(), 5);
+
+        // Each response lets exactly one more through
+        p.process(mock_response(&sent[0]));
+        assert_eq!(msg_id(rx.recv_timeout(wait).ok()), "6");
+        assert!(rx.recv_timeout(Duration::from_millis(200)).is_err());
+    }
+
+    fn mock_rejection(msg_id: &str, retryable: bool) -> EMsg {
+        EMsg::ClientError(crate::common::ClientErrorMsg {
+            msg_id: msg_id.into(),
+            client_id: "c1".into(),
+            reason: "busy".into(),
+            retryable,
+        })
+    }
+
+    #[test]
+    fn test_retryable_error_resends_request() {
+        let mut p = Processor::new();
+        let req = EMsg::ClientRequest(ClientRequest {
+            client_id: "c1".into(),
+            msg_id: "1".into(),
+            cmd: Command::Get {
+                key: Variable::new("k"),
+            },
+            timestamp_us: None,
+        });
+        p.process(req);
+
+        for _ in 0..MAX_ERROR_RETRIES {
+            let out = p.process(mock_rejection("1", true));
+            assert!(matches!(&out[..], [EMsg::ClientRequest(r)] if r.msg_id == "1"));
+        }
+        assert!(p.process(mock_rejection("1", true)).is_empty());
+        assert_eq!(p.rejected, 1);
+        assert!(p.unanswered.is_empty());
+    }
+
+    #[test]
+    fn test_closed_loop_backs_off_after_retryable_error() {
+        let (mut client, mut p) = mock_closed_loop(1);
+        let msg_id = send_next(&mut client, &mut p);
+
+        // Nothing re-sent straight away, and the slot stays taken
+        assert!(p.process(mock_rejection(&msg_id, true)).is_empty());
+        assert_eq!(client.outstanding.lock().unwrap().len(), 1);
+
+        std::thread::sleep(ERROR_BACKOFF);
+        let out = p.process(EMsg::WorkloadTick);
+        assert!(matches!(&out[..], [EMsg::ClientRequest(r)] if r.msg_id == msg_id));
+        // Back on the normal timeout, not re-sent again
+        assert!(p.process(EMsg::WorkloadTick).is_empty());
+        assert_eq!(p.retried, 0);
+    }
+
+    #[test]
+    fn test_non_retryable_error_gives_up() {
+        let mut p = Processor::new();
+        p.process(EMsg::ClientRequest(ClientRequest {
+            client_id: "c1".into(),
+            msg_id: "1".into(),
+            cmd: Command::Get {
+                key: Variable::new("k"),
+            },
+            timestamp_us: None,
+        }));
+        assert!(p.process(mock_rejection("1", false)).is_empty());
+        assert_eq!(p.rejected, 1);
+    }
+
+    #[test]
+    fn test_error_responses_are_counted_by_code() {
+        let mut p = Processor::new();
+        let codes = [
+            ErrorCode::NotAnInteger,
+            ErrorCode::NotAnInteger,
+            ErrorCode::InvalidRange,
+        ];
+        for (i, code) in codes.into_iter().enumerate() {
+            p.process(EMsg::ClientRequest(ClientRequest {
+                client_id: "c1".into(),
+                msg_id: i.to_string(),
+                cmd: Command::Incr {
+                    key: Variable::new("k"),
+                    delta: 1,
+                },
+                timestamp_us: None,
+            }));
+            let out = p.process(EMsg::ClientResponse(ClientResponse {
+                msg_id: i.to_string(),
+                client_id: "c1".into(),
+                cmd_result: CommandResult::Error {
+                    key: None,
+                    code,
+                    detail: String::new(),
+                },
+                server_received_us: None,
+                server_responded_us: None,
+            }));
+            assert!(out.is_empty());
+        }
+
+        assert_eq!(p.error_count(), 3);
+        assert_eq!(p.errors[&ErrorCode::NotAnInteger], 2);
+        assert_eq!(p.errors[&ErrorCode::InvalidRange], 1);
+    }
+
+    fn send(p: &mut Processor, msg_id: &str, cmd: Command) {
+        p.process(EMsg::ClientRequest(ClientRequest {
+            client_id: "c1".into(),
+            msg_id: msg_id.into(),
+            cmd,
+            timestamp_us: None,
+        }));
+    }
+
+    fn mock_get_response(msg_id: &str, version: u64) -> EMsg {
+        EMsg::ClientResponse(ClientResponse {
+            msg_id: msg_id.into(),
+            client_id: "c1".into(),
+            cmd_result: CommandResult::Get {
+                key: Variable::new("k"),
+                val: None,
+                version,
+            },
+            server_received_us: None,
+            server_responded_us: None,
+        })
+    }
+
+    #[test]
+    fn test_stale_read_after_acked_write_counted() {
+        let mut p = Processor::new();
+        let key = Variable::new("k");
+        send(&mut p, "1", Command::Get { key: key.clone() });
+        send(
+            &mut p,
+            "2",
+            Command::Set {
+                key: key.clone(),
+                val: "v".into(),
+                ttl_ms: None,
+            },
+        );
+        p.process(mock_response("2")); // Acked at v1
+        send(&mut p, "3", Command::Get { key: key.clone() });
+
+        // Sent before the ack, so it may miss the Set
+        p.process(mock_get_response("1", 0));
+        assert_eq!(p.anomalies, 0);
+        p.process(mock_get_response("3", 0));
+        assert_eq!(p.anomalies, 1);
+
+        send(&mut p, "4", Command::Get { key });
+        p.process(mock_get_response("4", 1));
+        assert_eq!(p.anomalies, 1);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_trace_replays_same_requests() {
+        let path = std::env::temp_dir().join(format!("epaxos_trace_{}.jsonl", std::process::id()));
+        let config = WorkloadConfig {
+            rate_limiter: RateLimiterKind::Poisson { rps: 1_000_000.0 },
+            incr_ratio: 0.2,
+            trace_path: Some(path.clone()),
+            ..mock_config()
+        };
+        let generated: Vec<ClientRequest> = WorkloadIterator::with_seed("c1".into(), config, 7)
+            .take(1000)
+            .map(|msg| match msg {
+                EMsg::ClientRequest(req) => req,
+                _ => panic!("Expected a ClientRequest"),
+            })
+            .collect();
+
+        let replayed: Vec<ClientRequest> = RequestReplayer::open("c2".into(), &path)
+            .unwrap()
+            .take(1000)
+            .map(|msg| match msg {
+                EMsg::ClientRequest(req) => req,
+                _ => panic!("Expected a ClientRequest"),
+            })
+            .collect();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(replayed.len(), 1000);
+        for (original, replay) in generated.iter().zip(&replayed) {
+            assert_eq!(replay.msg_id, original.msg_id);
+            assert_eq!(replay.cmd, original.cmd);
+            // Answers must come back to the replaying client
+            assert_eq!(replay.client_id, "c2");
+        }
+    }
+
+    #[test]
+    fn test_ops_file_replays_in_order() {
+        let path = std::env::temp_dir().join(format!("epaxos_ops_{}.txt", std::process::id()));
+        let ops = "# Hand-written\nSET a hello world\n\nget a\n40 INCR n 5\nINCR n\n";
+        std::fs::write(&path, ops).unwrap();
+        let started = Instant::now();
+        let replayed: Vec<EMsg> = OpsReplayer::open("c1".into(), &path, "ns")
+            .unwrap()
+            .collect();
+        std::fs::remove_file(&path).unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(40));
+
+        let key = |name: &str| Variable::in_namespace("ns", name);
+        let expected = [
+            Command::Set {
+                key: key("a"),
+                val: "hello world".into(),
+                ttl_ms: None,
+            },
+            Command::Get { key: key("a") },
+            Command::Incr {
+                key: key("n"),
+                delta: 5,
+            },
+            Command::Incr {
+                key: key("n"),
+                delta: 1,
+            },
+        ];
+        assert_eq!(replayed.len(), expected.len() + 1);
+        for (i, (msg, cmd)) in replayed.iter().zip(&expected).enumerate() {
+            assert!(matches!(msg, EMsg::ClientRequest(req)
+                if req.cmd == *cmd && req.msg_id == (i + 1).to_string() && req.client_id == "c1"));
+        }
+        assert!(matches!(replayed.last(), Some(EMsg::WorkloadDone)));
+    }
+
+    #[test]
+    fn test_ops_parse_errors_name_the_line() {
+        let err = |text: &str| parse_ops(text, "").unwrap_err();
+        assert_eq!(err("GET a\nDEL a"), "line 2: unknown op DEL");
+        assert_eq!(err("# c\n\nSET a"), "line 3: SET without a value");
+        assert_eq!(err("10 GET"), "line 1: GET without a key");
+        assert!(err("GET a\nINCR a x").starts_with("line 2: bad delta x"));
+        assert_eq!(parse_ops("", "").unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_results_file_has_a_row_per_request() {
+        let path = std::env::temp_dir().join(format!("epaxos_results_{}.json", std::process::id()));
+        let config = WorkloadConfig {
+            output_path: Some(path.clone()),
+            ..mock_config()
+        };
+        let mut p = Processor::new();
+        p.results = Some(ResultsLog::new(path.clone(), &config));
+        let mut it = WorkloadIterator::with_seed("c1".into(), config, 7);
+
+        let mut issued = Vec::new();
+        for _ in 0..10 {
+            let req = it.next().unwrap();
+            if let EMsg::ClientRequest(r) = &req {
+                issued.push(r.msg_id.clone());
+            }
+            p.process(req);
+        }
+        // 8 answered, one rejected, one left hanging
+        for msg_id in &issued[..8] {
+            p.process(mock_response(msg_id));
+        }
+        p.process(mock_rejection(&issued[8], false));
+        p.process(EMsg::WorkloadDone);
+
+        let file = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let results: serde_json::Value = serde_json::from_str(&file).unwrap();
+        let requests = results["requests"].as_array().unwrap();
+        assert_eq!(requests.len(), issued.len());
+        assert_eq!(results["summary"]["issued"], 10);
+        let outcomes: Vec<_> = requests
+            .iter()
+            .map(|r| r["outcome"].as_str().unwrap())
+            .collect();
+        assert_eq!(outcomes.iter().filter(|o| **o == "ok").count(), 8);
+        assert_eq!(outcomes[8..], ["rejected", "incomplete"]);
+        assert!(requests[9]["completed_us"].is_null());
+        assert_eq!(results["summary"]["latency"]["ALL"]["count"], 8);
+        assert_eq!(results["config"]["key_space_size"], 50);
+    }
+
+    #[test]
+    fn test_max_ops_ends_run_after_exactly_n_requests() {
+        let config = WorkloadConfig {
+            rate_limiter: RateLimiterKind::Poisson { rps: 1_000_000.0 },
+            max_ops: Some(5),
+            ..mock_config()
+        };
+        let msgs: Vec<EMsg> = WorkloadIterator::with_seed("c1".into(), config, 7).collect();
+        assert_eq!(msgs.len(), 6);
+        assert!(matches!(msgs.last(), Some(EMsg::WorkloadDone)));
+
+        let mut p = Processor::new();
+        p.max_ops = Some(5);
+        for msg in msgs {
+            p.process(msg);
+        }
+        assert_eq!((p.issued, p.end_reason()), (5, "max_ops"));
+    }
+
+    #[test]
+    fn test_drain_reports_unanswered_as_incomplete() {
+        let path = std::env::temp_dir().join(format!("epaxos_drain_{}.json", std::process::id()));
+        let config = mock_config();
+        let mut p = Processor::new();
+        p.results = Some(ResultsLog::new(path.clone(), &config));
+        p.drain = Duration::from_millis(100);
+        let (shutdown, mut stopped) = oneshot::channel();
+        p.shutdown = Some(shutdown);
+        let mut it = Draining::new(
+            WorkloadIterator::with_seed("c1".into(), config, 7)
+                .take(3)
+                .chain([EMsg::WorkloadDone]),
+            p.drain,
+        );
+        let sent: Vec<String> = (0..3)
+            .map(|_| match p.process(it.next().unwrap()).remove(0) {
+                EMsg::ClientRequest(req) => req.msg_id,
+                other => panic!("Expected a ClientRequest, got {:?}", other),
+            })
+            .collect();
+        p.process(mock_response(&sent[0]));
+
+        // The workload ends with two requests outstanding, one answered while draining
+        let done = Instant::now();
+        p.process(it.next().unwrap());
+        p.process(mock_response(&sent[1]));
+        assert!(stopped.try_recv().is_err());
+        // The other never is: ticks carry the Processor past the drain, then stop
+        for msg in it.by_ref() {
+            assert!(matches!(msg, EMsg::WorkloadTick));
+            p.process(msg);
+        }
+        assert!(done.elapsed() >= Duration::from_millis(100));
+        assert!(stopped.try_recv().is_ok());
+
+        let file = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let results: serde_json::Value = serde_json::from_str(&file).unwrap();
+        assert_eq!(results["summary"]["sent"], 3);
+        assert_eq!(results["summary"]["latency"]["ALL"]["count"], 2);
+        assert_eq!(results["summary"]["incomplete"], 1);
+    }
+
+    #[test]
+    fn test_warmup_requests_left_out_of_stats() {
+        let path = std::env::temp_dir().join(format!("epaxos_warmup_{}.json", std::process::id()));
+        let config = mock_config();
+        let mut p = Processor::new();
+        p.results = Some(ResultsLog::new(path.clone(), &config));
+        let throughput = Arc::new(Mutex::new(ThroughputWindow::new(Duration::from_secs(60))));
+        p.throughput = Some(throughput.clone());
+        let get = || Command::Get {
+            key: Variable::new("k"),
+        };
+        let send_timed = |p: &mut Processor, msg_id: &str| {
+            p.process(EMsg::ClientRequest(ClientRequest {
+                client_id: "c1".into(),
+                msg_id: msg_id.into(),
+                cmd: get(),
+                timestamp_us: Some(now_us()),
+            }));
+        };
+
+        p.warmup_until = Some(Instant::now() + Duration::from_secs(3600));
+        send_timed(&mut p, "1");
+        send_timed(&mut p, "2");
+        p.process(mock_get_response("1", 0));
+        // Measurement starts with "2" still in flight
+        p.warmup_until = Some(Instant::now());
+        send_timed(&mut p, "3");
+        p.process(mock_get_response("2", 0));
+        p.process(mock_get_response("3", 0));
+
+        assert_eq!(p.latencies["GET"].len(), 1);
+        assert_eq!(throughput.lock().unwrap().completions.len(), 1);
+        p.process(EMsg::WorkloadDone);
+        let file = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let results: serde_json::Value = serde_json::from_str(&file).unwrap();
+        let requests = results["requests"].as_array().unwrap();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0]["msg_id"], "3");
+        assert_eq!(results["summary"]["warmup"], 2);
+    }
+
+    #[test]
+    fn test_history_file_pairs_calls_and_returns() {
+        let path =
+            std::env::temp_dir().join(format!("epaxos_history_{}.jsonl", std::process::id()));
+        let mut p = Processor::new();
+        p.history = Some(HistoryLog::new(path.clone()));
+        let key = Variable::new("k");
+        let set = |val: &str| Command::Set {
+            key: key.clone(),
+            val: val.into(),
+            ttl_ms: None,
+        };
+        send(&mut p, "1", set("a"));
+        send(&mut p, "2", Command::Get { key: key.clone() });
+        send(&mut p, "3", set("b")); // Never answered
+        let incr = Command::Incr {
+            key: key.clone(),
+            delta: 1,
+        };
+        send(&mut p, "4", incr); // Not in the KV model
+        p.process(mock_response("1"));
+        p.process(EMsg::ClientResponse(ClientResponse {
+            msg_id: "2".into(),
+            client_id: "c1".into(),
+            cmd_result: CommandResult::Get {
+                key: key.clone(),
+                val: Some("a".into()),
+                version: 1,
+            },
+            server_received_us: None,
+            server_responded_us: None,
+        }));
+        p.process(mock_response("1")); // Duplicate
+        p.process(EMsg::WorkloadDone);
+
+        let file = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let events: Vec<serde_json::Value> = file
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let kinds: Vec<_> = events
+            .iter()
+            .map(|e| (e["kind"].as_str().unwrap(), e["id"].as_u64().unwrap()))
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                ("call", 0),
+                ("call", 1),
+                ("call", 2),
+                ("return", 0),
+                ("return", 1)
+            ]
+        );
+        assert_eq!(events[0]["client_id"], "c1");
+        assert_eq!(
+            events[0]["value"],
+            serde_json::json!({ "op": 1, "key": key.to_string(), "value": "a" })
+        );
+        assert_eq!(events[1]["value"]["op"], 0);
+        assert_eq!(events[4]["value"], serde_json::json!({ "value": "a" }));
+        let times: Vec<_> = events
+            .iter()
+            .map(|e| e["time_ns"].as_u64().unwrap())
+            .collect();
+        assert!(times.is_sorted());
+    }
+
+    #[test]
+    fn test_token_bucket_caps_bursts() {
+        let (rps, burst) = (100.0, 5);
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(rps, burst, start);
+
+        // Hammer the bucket every 100us over a 50ms window
+        let window = Duration::from_millis(50);
+        let mut sent = 0;
+        let mut t = start;
+        while t < start + window {
+            if bucket.try_acquire(t).is_ok() {
+                sent += 1;
+            }
+            t += Duration::from_micros(100);
+        }
+
+        // Initial burst plus what refills during the window, with one token of slack
+        let max = burst as f64 + rps * window.as_secs_f64() + 1.0;
+        assert!(sent as f64 <= max, "sent {} > {}", sent, max);
+        assert!(sent >= burst);
+    }
+
+    #[test]
+    fn test_token_bucket_reports_wait() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(10.0, 1, start);
+        assert!(bucket.try_acquire(start).is_ok());
+
+        let wait = bucket.try_acquire(start).unwrap_err();
+        assert!((wait.as_secs_f64() - 0.1).abs() < 1e-6);
+        assert!(bucket.try_acquire(start + wait).is_ok());
+    }
+
+    #[test]
+    fn test_rps_schedule_steps_change_arrival_rate() {
+        let secs = Duration::from_secs;
+        let config = WorkloadConfig {
+            rate_limiter: RateLimiterKind::Poisson { rps: 100.0 },
+            run_duration: secs(40),
+            rps_schedule: vec![(secs(10), 1000.0), (secs(20), 0.0), (secs(30), 10.0)],
+            ..mock_config()
+        };
+        let mut it = WorkloadIterator::with_seed("c1".into(), config, 5);
+        let start = it.start_time;
+        let mut per_step = [0usize; 4];
+        let mut now = start;
+        loop {
+            now = it.schedule(now);
+            let offset = now.duration_since(start);
+            if offset >= secs(40) {
+                break;
+            }
+            per_step[(offset.as_secs() / 10) as usize] += 1;
+        }
+
+        // 10s at 100, 1000, 0 and 10 rps
+        assert!((900..1100).contains(&per_step[0]), "{:?}", per_step);
+        assert!((9500..10500).contains(&per_step[1]), "{:?}", per_step);
+        assert_eq!(per_step[2], 0);
+        assert!((60..140).contains(&per_step[3]), "{:?}", per_step);
+    }
+
+    #[test]
+    fn test_rps_schedule_pause_until_run_end() {
+        let config = WorkloadConfig {
+            run_duration: Duration::from_secs(5),
+            rps_schedule: vec![(Duration::ZERO, 0.0)],
+            ..mock_config()
+        };
+        let mut it = WorkloadIterator::with_seed("c1".into(), config, 5);
+        let start = it.start_time;
+        assert_eq!(it.schedule(start), start + Duration::from_secs(5));
+
+        // Without a schedule target_rps applies throughout
+        let it = WorkloadIterator::with_seed("c1".into(), mock_config(), 5);
+        assert_eq!(it.rps_at(Duration::from_secs(1000)), (100.0, None));
+    }
+
+    #[test]
+    fn test_latency_bucketed_by_rps_step() {
+        let config = WorkloadConfig {
+            rps_schedule: vec![(Duration::from_secs(10), 500.0)],
+            ..mock_config()
+        };
+        let start_us = now_us() - 20_000_000;
+        let mut p = Processor::new();
+        p.rps_steps = RpsStep::for_run(&config, start_us);
+        assert_eq!(p.rps_steps.len(), 2);
+
+        // Sent 5s and 15s into the run
+        for (msg_id, sent_us) in [("1", start_us + 5_000_000), ("2", start_us + 15_000_000)] {
+            p.sent_us.insert(msg_id.into(), (sent_us, "GET"));
+            let EMsg::ClientResponse(resp) = mock_get_response(msg_id, 0) else {
+                unreachable!()
+            };
+            p.record_latency(&resp);
+        }
+        let report = p.step_report();
+        assert_eq!(report.len(), 2);
+        assert_eq!((report[0].0.rps, report[0].1.count), (100.0, 1));
+        assert_eq!((report[1].0.rps, report[1].1.count), (500.0, 1));
+        assert!(report[0].1.mean_us > report[1].1.mean_us);
+    }
+
+    #[test]
+    fn test_schedule_books_send_times_without_waiting() {
+        let config = WorkloadConfig {
+            rate_limiter: RateLimiterKind::TokenBucket {
+                rps: 10.0,
+                burst: 1,
+            },
+            ..mock_config()
+        };
+        let mut it = WorkloadIterator::with_seed("c1".into(), config, 1);
+        // Each request is booked once the previous one went out
+        let start = Instant::now();
+        let mut now = start;
+        let times: Vec<Instant> = (0..3)
+            .map(|_| {
+                now = it.schedule(now);
+                now
+            })
+            .collect();
+        for (i, at) in times.iter().enumerate() {
+            let offset = at.duration_since(start).as_secs_f64();
+            assert!((offset - 0.1 * i as f64).abs() < 1e-3, "{}: {}", i, offset);
+        }
+        assert!(start.elapsed() < Duration::from_millis(100));
+    }
+
+    #[test]
+    fn test_paced_clients_keep_a_small_runtime_responsive() {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(8)
+            .enable_time()
+            .build()
+            .unwrap();
+        let config = || WorkloadConfig {
+            rate_limiter: RateLimiterKind::TokenBucket { rps: 1.0, burst: 1 },
+            ..mock_config()
+        };
+
+        // 50 clients sending one request per second, each as its generator would. Each one
+        // waiting parks a thread in block_in_place, so this doesn't bound the thread count,
+        // only checks the 8 workers keep running other tasks and the clients pace concurrently
+        let start = Instant::now();
+        let clients: Vec<_> = (0..50)
+            .map(|i| {
+                let workload = WorkloadIterator::with_seed(format!("c{}", i), config(), 1);
+                let mut paced = PacedWorkload::spawn(runtime.handle(), workload);
+                runtime.spawn(async move { [paced.next(), paced.next()] })
+            })
+            .collect();
+
+        // The runtime still has workers for other tasks while the clients wait
+        let probe = runtime.block_on(async {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            let sent = Instant::now();
+            tokio::spawn(async {}).await.unwrap();
+            sent.elapsed()
+        });
+        assert!(probe < Duration::from_millis(50), "probe took {:?}", probe);
+
+        for client in clients {
+            let sent = runtime.block_on(client).unwrap();
+            assert!(
+                sent.iter()
+                    .all(|msg| matches!(msg, Some(EMsg::ClientRequest(_))))
+            );
+        }
+        // The second requests waited out the 1s pacing, concurrently
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(900), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_secs(3), "{:?}", elapsed);
+    }
+
+    #[test]
+    fn test_sender_destinations_per_routing_mode() {
+        let servers: Vec<String> = (1..=3).map(|i| format!("epaxos{}", i)).collect();
+        let req = ClientRequest {
+            client_id: "c1".into(),
+            msg_id: "1".into(),
+            cmd: Command::Get {
+                key: Variable::new("k"),
+            },
+            timestamp_us: None,
+        };
+        let destinations = |routing, fixed_server: Option<&str>, addr: &str| {
+            let mut sender = Sender::new(servers.clone(), routing, fixed_server, addr);
+            (0..6)
+                .map(|_| sender.destination(&req).to_string())
+                .collect::<Vec<_>>()
+        };
+
+        let fixed = destinations(RoutingMode::Fixed, Some("epaxos2"), "c1");
+        assert_eq!(fixed, vec!["epaxos2"; 6]);
+
+        // Unconfigured, or not a server: pinned by the addr's hash, the same on every run
+        let hashed = destinations(RoutingMode::Fixed, None, "c1");
+        assert!(hashed.iter().all(|s| *s == hashed[0]));
+        assert_eq!(destinations(RoutingMode::Fixed, Some("nope"), "c1"), hashed);
+
+        let round_robin = destinations(RoutingMode::RoundRobin, Some("epaxos3"), "c1");
+        assert_eq!(
+            round_robin,
+            [
+                "epaxos3", "epaxos1", "epaxos2", "epaxos3", "epaxos1", "epaxos2"
+            ]
+        );
+        let start = servers.iter().position(|s| *s == hashed[0]).unwrap();
+        let round_robin = destinations(RoutingMode::RoundRobin, None, "c1");
+        for (i, server) in round_robin.iter().enumerate() {
+            assert_eq!(*server, servers[(start + i) % 3]);
+        }
+    }
+
+    #[test]
+    fn test_key_affinity_pins_keys_and_spreads_them() {
+        let servers: Vec<String> = (1..=3).map(|i| format!("epaxos{}", i)).collect();
+        let request = |key: &str| ClientRequest {
+            client_id: "c1".into(),
+            msg_id: "1".into(),
+            cmd: Command::Set {
+                key: Variable::new(key),
+                val: "v".into(),
+                ttl_ms: None,
+            },
+            timestamp_us: None,
+        };
+        // Different clients agree on a key's server as long as they list servers alike
+        let mut senders: Vec<_> = ["c1", "c2"]
+            .iter()
+            .map(|addr| Sender::new(servers.clone(), RoutingMode::KeyAffinity, None, addr))
+            .collect();
+
+        let mut per_server: HashMap<String, usize> = HashMap::new();
+        for i in 0..300 {
+            let req = request(&format!("key{}", i));
+            let first = senders[0].destination(&req).to_string();
+            assert_eq!(senders[0].destination(&req), first);
+            assert_eq!(senders[1].destination(&req), first);
+            *per_server.entry(first).or_default() += 1;
+        }
+        assert_eq!(per_server.len(), 3);
+        assert!(per_server.values().all(|n| *n > 50), "{:?}", per_server);
+    }
+
+    #[test]
+    fn test_routing_parsing() {
+        let parse = |json: &str| WorkloadConfig::new(serde_json::from_str(json).unwrap());
+        assert_eq!(parse("{}").routing, RoutingMode::Random);
+        assert_eq!(
+            parse(r#"{"routing": "KeyAffinity"}"#).routing,
+            RoutingMode::KeyAffinity
+        );
+        assert_eq!(
+            parse(r#"{"routing": "round_robin"}"#).routing,
+            RoutingMode::RoundRobin
+        );
+        let fixed = parse(r#"{"routing": "fixed", "fixed_server": "epaxos2"}"#);
+        assert_eq!(fixed.routing, RoutingMode::Fixed);
+        assert_eq!(fixed.fixed_server.as_deref(), Some("epaxos2"));
+        assert_eq!(fixed.to_json()["routing"], "Fixed");
+        assert_eq!(fixed.to_json()["fixed_server"], "epaxos2");
+    }
 }
@@ -1,35 +1,338 @@
+use bincode::de::Decoder;
+use bincode::enc::Encoder;
+use bincode::error::{DecodeError, EncodeError};
 use bincode::{Decode, Encode};
+use reactor_actor::ActorProcess;
 use reactor_macros::{DefaultPrio, Msg as DeriveMsg};
-use std::collections::HashSet;
+use std::borrow::Cow;
+use std::collections::{BTreeSet, HashMap};
 use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::warn;
 
+pub(crate) const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+
+/// FNV-1a hash of `bytes`, continuing from `init`. Stable across processes and builds,
+/// unlike std's hashers
+pub(crate) fn fnv1a(init: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(init, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Wall clock time in microseconds since the unix epoch
+pub fn now_us() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_micros() as u64)
+        .unwrap_or(0)
+}
+
+/// trace_ctx for an outgoing PreAccept, Accept or Commit: the current span's with the
+/// telemetry feature, None without. The field is sent either way, so the wire format is
+/// the same in every build
+pub fn outgoing_trace_ctx() -> Option<Vec<u8>> {
+    #[cfg(feature = "telemetry")]
+    return crate::telemetry::current_trace_ctx();
+    #[cfg(not(feature = "telemetry"))]
+    None
+}
+
+/// A key, scoped to a namespace. Keys in different namespaces never conflict, even with
+/// the same name. Ordered by namespace first, so a namespace's keys are contiguous for Scan
 #[derive(Encode, Decode, Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Variable {
+    pub namespace: String, // "" is the default namespace
     pub name: String,
 }
 
+impl Variable {
+    /// Key in the default namespace
+    pub fn new(name: impl Into<String>) -> Self {
+        Variable {
+            namespace: String::new(),
+            name: name.into(),
+        }
+    }
+
+    pub fn in_namespace(namespace: impl Into<String>, name: impl Into<String>) -> Self {
+        Variable {
+            namespace: namespace.into(),
+            name: name.into(),
+        }
+    }
+}
+
 impl fmt::Display for Variable {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.name)
+        if self.namespace.is_empty() {
+            write!(f, "{}", self.name)
+        } else {
+            write!(f, "{}:{}", self.namespace, self.name)
+        }
+    }
+}
+
+/// Opaque value bytes. Workloads use UTF-8 text, but nothing on the server relies on it
+#[derive(Encode, Decode, Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Value(pub Vec<u8>);
+
+/// Values longer than this are truncated when displayed
+const VALUE_DISPLAY_LEN: usize = 64;
+
+impl Value {
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    #[allow(dead_code)]
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The value as text, None if it isn't valid UTF-8
+    pub fn as_str(&self) -> Option<&str> {
+        std::str::from_utf8(&self.0).ok()
+    }
+}
+
+/// Displays a possibly missing value, "NONE" if missing as expected by lcheck
+pub fn fmt_opt_value(val: &Option<Value>) -> String {
+    val.as_ref().map_or("NONE".to_string(), |v| v.to_string())
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Self {
+        Value(s.into_bytes())
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Value(s.as_bytes().to_vec())
+    }
+}
+
+impl From<Vec<u8>> for Value {
+    fn from(bytes: Vec<u8>) -> Self {
+        Value(bytes)
+    }
+}
+
+/// Text as is, anything else as hex. Long values are cut at VALUE_DISPLAY_LEN bytes
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let shown = &self.0[..self.0.len().min(VALUE_DISPLAY_LEN)];
+        match std::str::from_utf8(shown) {
+            Ok(s) => write!(f, "{}", s)?,
+            Err(_) => {
+                write!(f, "0x")?;
+                for b in shown {
+                    write!(f, "{:02x}", b)?;
+                }
+            }
+        }
+        if self.0.len() > VALUE_DISPLAY_LEN {
+            write!(f, "...({} bytes)", self.0.len())?;
+        }
+        Ok(())
     }
 }
 
 #[derive(Encode, Decode, Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Command {
-    Get { key: Variable },
-    Set { key: Variable, val: String },
+    Get {
+        key: Variable,
+    },
+    /// Writes `val`, which reads treat as missing `ttl_ms` after the Set commits
+    Set {
+        key: Variable,
+        val: Value,
+        ttl_ms: Option<u64>,
+    },
+    /// Compare-and-swap: writes `new` only if the current value equals `expected`
+    Cas {
+        key: Variable,
+        expected: Option<Value>,
+        new: Value,
+    },
+    /// Adds `delta` to the integer stored at `key` (missing counts as 0)
+    Incr {
+        key: Variable,
+        delta: i64,
+    },
+    /// Concatenates `suffix` onto the value at `key` (empty if missing)
+    Append {
+        key: Variable,
+        suffix: Value,
+    },
+    /// Writes `val` only if `key` holds no live value when executed
+    SetIfAbsent {
+        key: Variable,
+        val: Value,
+    },
+    /// Writes every pair in a single instance
+    MultiSet {
+        pairs: Vec<(Variable, Value)>,
+    },
+    /// Reads every key at a single point in the execution order
+    MultiGet {
+        keys: Vec<Variable>,
+    },
+    /// Reads `reads`, then applies `writes`, atomically in a single instance
+    Txn {
+        reads: Vec<Variable>,
+        writes: Vec<(Variable, Value)>,
+    },
+    /// Reads up to `limit` pairs with keys in `start..end`, in key order
+    Scan {
+        start: Variable,
+        end: Variable,
+        limit: usize,
+    },
+    /// Fills an instance whose command was lost during recovery. Touches no keys
+    Noop,
+    /// Membership change proposed for a ReconfigRequest. Each replica switches to
+    /// `replica_list` as it commits the instance. Touches no keys
+    Reconfig {
+        replica_list: Vec<String>,
+        epoch: u64,
+    },
+    /// Subscribes the client to changes of `key` on the replica it's sent to, which
+    /// replies with the current value. Not replicated, so not ordered with other commands
+    Watch {
+        key: Variable,
+    },
+    /// Cancels a Watch on the same replica
+    Unwatch {
+        key: Variable,
+    },
 }
 
 impl Command {
-    #[allow(dead_code)]
+    /// A Set whose value reads as missing `ttl_ms` after it commits
+    pub fn set_ex(key: Variable, val: impl Into<Value>, ttl_ms: u64) -> Command {
+        Command::Set {
+            key,
+            val: val.into(),
+            ttl_ms: Some(ttl_ms),
+        }
+    }
+
+    /// Two commands conflict if they touch at least one common key. A Scan touches every
+    /// key in its range, including ones that don't exist yet. Keys compare by namespace too,
+    /// so commands in different namespaces never conflict
     pub fn conflicts_with(&self, other: &Command) -> bool {
-        self.key() == other.key()
+        match (self, other) {
+            (
+                Command::Scan { start, end, .. },
+                Command::Scan {
+                    start: o_start,
+                    end: o_end,
+                    ..
+                },
+            ) => start < o_end && o_start < end,
+            (Command::Scan { .. }, _) => other.keys().iter().any(|k| self.touches(k)),
+            _ => self.keys().iter().any(|k| other.touches(k)),
+        }
+    }
+
+    /// Whether the command reads or writes `key`
+    pub fn touches(&self, key: &Variable) -> bool {
+        match self {
+            Command::Scan { start, end, .. } => start <= key && key < end,
+            _ => self.keys().contains(key),
+        }
+    }
+
+    /// Whether the command modifies the store
+    pub fn is_write(&self) -> bool {
+        !matches!(
+            self,
+            Command::Get { .. }
+                | Command::MultiGet { .. }
+                | Command::Scan { .. }
+                | Command::Watch { .. }
+                | Command::Unwatch { .. }
+        ) && !matches!(self, Command::Txn { writes, .. } if writes.is_empty())
+    }
+
+    /// Operation name, as shown by Display
+    pub fn op_name(&self) -> &'static str {
+        match self {
+            Command::Get { .. } => "GET",
+            Command::Set { .. } => "SET",
+            Command::Cas { .. } => "CAS",
+            Command::Incr { .. } => "INCR",
+            Command::Append { .. } => "APPEND",
+            Command::SetIfAbsent { .. } => "SETNX",
+            Command::MultiSet { .. } => "MSET",
+            Command::MultiGet { .. } => "MGET",
+            Command::Txn { .. } => "TXN",
+            Command::Scan { .. } => "SCAN",
+            Command::Noop => "NOOP",
+            Command::Reconfig { .. } => "RECONFIG",
+            Command::Watch { .. } => "WATCH",
+            Command::Unwatch { .. } => "UNWATCH",
+        }
     }
 
-    pub fn key(&self) -> &Variable {
+    /// Whether the client response depends on execution order. Such commands are replied to
+    /// by the command leader once executed, instead of at commit time. Set replies with the
+    /// version it was assigned, so only MultiSet is acked at commit
+    pub fn replies_at_execution(&self) -> bool {
+        !matches!(self, Command::MultiSet { .. })
+    }
+
+    /// Keys named by the command, reads and writes alike. Only MultiSet and Txn need to allocate. Empty for Scan, whose
+    /// range is handled by `touches`
+    pub fn keys(&self) -> Cow<'_, [Variable]> {
+        match self {
+            Command::Get { key }
+            | Command::Set { key, .. }
+            | Command::Cas { key, .. }
+            | Command::Incr { key, .. }
+            | Command::Append { key, .. }
+            | Command::SetIfAbsent { key, .. }
+            | Command::Watch { key }
+            | Command::Unwatch { key } => Cow::Borrowed(std::slice::from_ref(key)),
+            Command::MultiSet { pairs } => {
+                Cow::Owned(pairs.iter().map(|(k, _)| k.clone()).collect())
+            }
+            Command::MultiGet { keys } => Cow::Borrowed(keys),
+            Command::Txn { reads, writes } => {
+                let mut keys = reads.clone();
+                keys.extend(writes.iter().map(|(k, _)| k.clone()));
+                Cow::Owned(keys)
+            }
+            Command::Scan { .. } | Command::Noop | Command::Reconfig { .. } => Cow::Borrowed(&[]),
+        }
+    }
+
+    /// Values the command would write, or compare against for Cas
+    pub fn values(&self) -> Vec<&Value> {
         match self {
-            Command::Get { key } => key,
-            Command::Set { key, .. } => key,
+            Command::Set { val, .. } | Command::SetIfAbsent { key: _, val } => vec![val],
+            Command::Append { suffix, .. } => vec![suffix],
+            Command::Cas { expected, new, .. } => expected.iter().chain([new]).collect(),
+            Command::MultiSet { pairs: writes } | Command::Txn { writes, .. } => {
+                writes.iter().map(|(_, v)| v).collect()
+            }
+            Command::Get { .. }
+            | Command::Incr { .. }
+            | Command::MultiGet { .. }
+            | Command::Scan { .. }
+            | Command::Noop
+            | Command::Reconfig { .. }
+            | Command::Watch { .. }
+            | Command::Unwatch { .. } => vec![],
         }
     }
 }
@@ -37,42 +340,203 @@ impl Command {
 impl fmt::Display for Command {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Command::Get { key } => write!(f, "Get({})", key),
-            Command::Set { key, val } => write!(f, "Set({},{})", key, val),
+            Command::Get { key } => write!(f, "GET {}", key),
+            Command::Set {
+                key,
+                val,
+                ttl_ms: None,
+            } => write!(f, "SET {}={}", key, val),
+            Command::Set {
+                key,
+                val,
+                ttl_ms: Some(ttl),
+            } => write!(f, "SET {}={} ttl={}ms", key, val, ttl),
+            Command::Cas { key, expected, new } => {
+                write!(f, "CAS {}={}->{}", key, fmt_opt_value(expected), new)
+            }
+            Command::Incr { key, delta } => write!(f, "INCR {} {}", key, delta),
+            Command::Append { key, suffix } => write!(f, "APPEND {}+={}", key, suffix),
+            Command::SetIfAbsent { key, val } => write!(f, "SETNX {}={}", key, val),
+            Command::MultiSet { pairs } => {
+                let pairs: Vec<String> =
+                    pairs.iter().map(|(k, v)| format!("{}={}", k, v)).collect();
+                write!(f, "MSET {}", pairs.join(" "))
+            }
+            Command::MultiGet { keys } => {
+                let keys: Vec<String> = keys.iter().map(|k| k.to_string()).collect();
+                write!(f, "MGET {}", keys.join(" "))
+            }
+            Command::Txn { reads, writes } => {
+                let reads: Vec<String> = reads.iter().map(|k| k.to_string()).collect();
+                let writes: Vec<String> =
+                    writes.iter().map(|(k, v)| format!("{}={}", k, v)).collect();
+                write!(f, "TXN read {} write {}", reads.join(" "), writes.join(" "))
+            }
+            Command::Scan { start, end, limit } => {
+                write!(f, "SCAN {}..{} limit={}", start, end, limit)
+            }
+            Command::Noop => write!(f, "NOOP"),
+            Command::Reconfig {
+                replica_list,
+                epoch,
+            } => write!(f, "RECONFIG {} epoch={}", replica_list.join(","), epoch),
+            Command::Watch { key } => write!(f, "WATCH {}", key),
+            Command::Unwatch { key } => write!(f, "UNWATCH {}", key),
         }
     }
 }
 
-#[derive(Encode, Decode, Debug, Clone)]
+#[derive(Encode, Decode, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ClientRequest {
     pub client_id: String,
     pub msg_id: String,
     pub cmd: Command,
+    pub timestamp_us: Option<u64>, // When the client sent the request
 }
 
-#[derive(Encode, Decode, Debug, Clone)]
+#[derive(Encode, Decode, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum CommandResult {
-    Get { key: Variable, val: Option<String> },
-    Set { key: Variable, status: bool },
+    /// `version` counts the writes to `key` executed before this read
+    Get {
+        key: Variable,
+        val: Option<Value>,
+        version: u64,
+    },
+    /// `version` is the key's version after this write, 0 if acked before executing it
+    Set {
+        key: Variable,
+        version: u64,
+    },
+    Cas {
+        key: Variable,
+        success: bool,
+        actual: Option<Value>,
+    },
+    /// New value after the increment
+    Incr {
+        key: Variable,
+        val: i64,
+    },
+    /// Length of the value after appending
+    Append {
+        key: Variable,
+        len: usize,
+    },
+    /// Whether the key was absent, i.e. whether the write took effect
+    SetIfAbsent {
+        key: Variable,
+        success: bool,
+    },
+    MultiSet {
+        keys: Vec<Variable>,
+        status: bool,
+    },
+    /// Value of each requested key, in request order
+    MultiGet {
+        vals: Vec<(Variable, Option<Value>)>,
+    },
+    /// Value of each read key before the transaction's writes, in request order
+    Txn {
+        reads: Vec<(Variable, Option<Value>)>,
+    },
+    /// Pairs found in the scanned range, in key order
+    Scan {
+        pairs: Vec<(Variable, Value)>,
+    },
+    /// The instance was filled with a Noop, by its client or by a recovery that replaced a
+    /// command nobody else knew of. Nothing was applied
+    Noop,
+    /// The command was ordered but couldn't be applied. The store is left untouched
+    Error {
+        key: Option<Variable>,
+        code: ErrorCode,
+        detail: String,
+    },
+}
+
+/// Why a command failed, see CommandResult::Error
+#[derive(Encode, Decode, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ErrorCode {
+    /// Incr on a value that doesn't parse as an i64
+    NotAnInteger,
+    /// Incr result out of the i64 range
+    Overflow,
+    /// Scan whose start sorts after its end
+    InvalidRange,
+    /// Key name empty or longer than the server allows
+    InvalidKey,
+    /// Value larger than the server allows
+    ValueTooLarge,
+    /// Request without a msg_id or client_id
+    InvalidRequest,
 }
 impl CommandResult {
     #[allow(dead_code)]
-    pub fn key(&self) -> &Variable {
+    pub fn keys(&self) -> Cow<'_, [Variable]> {
         match self {
-            CommandResult::Get { key, .. } => key,
-            CommandResult::Set { key, .. } => key,
+            CommandResult::Get { key, .. }
+            | CommandResult::Set { key, .. }
+            | CommandResult::Cas { key, .. }
+            | CommandResult::Incr { key, .. }
+            | CommandResult::Append { key, .. }
+            | CommandResult::SetIfAbsent { key, .. } => Cow::Borrowed(std::slice::from_ref(key)),
+            CommandResult::MultiSet { keys, .. } => Cow::Borrowed(keys),
+            CommandResult::MultiGet { vals } | CommandResult::Txn { reads: vals } => {
+                Cow::Owned(vals.iter().map(|(k, _)| k.clone()).collect())
+            }
+            CommandResult::Scan { pairs } => {
+                Cow::Owned(pairs.iter().map(|(k, _)| k.clone()).collect())
+            }
+            CommandResult::Error { key, .. } => match key {
+                Some(key) => Cow::Borrowed(std::slice::from_ref(key)),
+                None => Cow::Borrowed(&[]),
+            },
+            CommandResult::Noop => Cow::Borrowed(&[]),
         }
     }
 }
 
-#[derive(Encode, Decode, Debug, Clone)]
+#[derive(Encode, Decode, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ClientResponse {
     pub msg_id: String,
     pub client_id: String,
     pub cmd_result: CommandResult,
+    pub server_received_us: Option<u64>, // When the command leader got the request
+    pub server_responded_us: Option<u64>, // When the command leader sent this response
+}
+
+impl ClientResponse {
+    /// Whether the command failed at execution, see CommandResult::Error
+    pub fn is_error(&self) -> bool {
+        matches!(self.cmd_result, CommandResult::Error { .. })
+    }
+}
+
+/// Pushed to a client watching `key` each time an executed write changes it
+#[derive(Encode, Decode, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct WatchNotifyMsg {
+    pub key: Variable,
+    pub val: Option<Value>, // None if the write left the key expired
+    pub client_id: String,
+}
+
+/// Sent back instead of a ClientResponse when the replica refuses a request
+#[derive(Encode, Decode, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ClientErrorMsg {
+    pub msg_id: String,
+    pub client_id: String,
+    pub reason: String,
+    pub retryable: bool, // Whether the same request may succeed elsewhere or later
 }
 
 #[derive(Encode, Decode, Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Instance {
     pub replica: String,
     pub instance_num: usize,
@@ -80,60 +544,957 @@ pub struct Instance {
 
 impl fmt::Display for Instance {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "Inst({},{})", self.replica, self.instance_num)
+        write!(f, "{}/{}", self.replica, self.instance_num)
     }
 }
 
-#[derive(Encode, Decode, Debug, Clone)]
+/// Bits of a CompactInstance holding the instance number. The replica index takes the rest
+#[cfg(any(test, feature = "bench"))]
+const COMPACT_NUM_BITS: u32 = 48;
+#[cfg(any(test, feature = "bench"))]
+const COMPACT_NUM_MASK: u64 = (1 << COMPACT_NUM_BITS) - 1;
+
+/// An Instance naming its replica by position in the replica list instead of by name.
+/// Encodes as a single u64: replica index in the top 16 bits, instance number in the bottom 48.
+/// Only measured against Instance so far, by the benches
+#[cfg(any(test, feature = "bench"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct CompactInstance {
+    pub replica_idx: u32,
+    pub instance_num: u64,
+}
+
+#[cfg(any(test, feature = "bench"))]
+impl CompactInstance {
+    /// None if the replica index or the instance number doesn't fit in its bits
+    pub fn to_u64(self) -> Option<u64> {
+        if self.replica_idx >= 1 << (64 - COMPACT_NUM_BITS) || self.instance_num > COMPACT_NUM_MASK
+        {
+            return None;
+        }
+        Some((u64::from(self.replica_idx) << COMPACT_NUM_BITS) | self.instance_num)
+    }
+
+    pub fn from_u64(raw: u64) -> Self {
+        CompactInstance {
+            replica_idx: (raw >> COMPACT_NUM_BITS) as u32,
+            instance_num: raw & COMPACT_NUM_MASK,
+        }
+    }
+
+    /// Panics if the index is out of `replica_list`, which must be the list it was made from
+    pub fn to_instance(self, replica_list: &[String]) -> Instance {
+        Instance {
+            replica: replica_list[self.replica_idx as usize].clone(),
+            instance_num: self.instance_num as usize,
+        }
+    }
+}
+
+#[cfg(any(test, feature = "bench"))]
+impl Encode for CompactInstance {
+    fn encode<E: Encoder>(&self, encoder: &mut E) -> Result<(), EncodeError> {
+        let raw = self
+            .to_u64()
+            .ok_or_else(|| EncodeError::OtherString(format!("{:?} overflows a u64", self)))?;
+        raw.encode(encoder)
+    }
+}
+
+#[cfg(any(test, feature = "bench"))]
+impl<Context> Decode<Context> for CompactInstance {
+    fn decode<D: Decoder<Context = Context>>(decoder: &mut D) -> Result<Self, DecodeError> {
+        Ok(CompactInstance::from_u64(u64::decode(decoder)?))
+    }
+}
+
+#[cfg(any(test, feature = "bench"))]
+bincode::impl_borrow_decode!(CompactInstance);
+
+/// Writes a dep set as its replicas, each name once followed by its instance numbers as
+/// gaps from the previous one. Deps of a replica tend to be close together, so the gaps
+/// stay a byte or two as varints where a name and a full number would be sent per dep.
+/// The names go with each message since replica list indices change on Reconfig
+fn encode_deps<E: Encoder>(deps: &BTreeSet<Instance>, encoder: &mut E) -> Result<(), EncodeError> {
+    // BTreeSet order groups the deps by replica, by increasing instance number
+    let mut groups: Vec<(&str, Vec<usize>)> = vec![];
+    for dep in deps {
+        match groups.last_mut() {
+            Some((replica, nums)) if *replica == dep.replica => nums.push(dep.instance_num),
+            _ => groups.push((&dep.replica, vec![dep.instance_num])),
+        }
+    }
+    groups.len().encode(encoder)?;
+    for (replica, nums) in groups {
+        replica.encode(encoder)?;
+        nums.len().encode(encoder)?;
+        let mut prev = 0;
+        for num in nums {
+            (num - prev).encode(encoder)?;
+            prev = num;
+        }
+    }
+    Ok(())
+}
+
+fn decode_deps<Context, D: Decoder<Context = Context>>(
+    decoder: &mut D,
+) -> Result<BTreeSet<Instance>, DecodeError> {
+    let mut deps = BTreeSet::new();
+    for _ in 0..usize::decode(decoder)? {
+        let replica = String::decode(decoder)?;
+        let mut num = 0usize;
+        for _ in 0..usize::decode(decoder)? {
+            num = num
+                .checked_add(usize::decode(decoder)?)
+                .ok_or(DecodeError::Other("dep instance number overflows"))?;
+            deps.insert(Instance {
+                replica: replica.clone(),
+                instance_num: num,
+            });
+        }
+    }
+    Ok(deps)
+}
+
+/// Who may decide an instance. Ordered by epoch, then replica name. An instance starts at
+/// epoch 0 under its leader, and each recovery attempt moves it to a higher ballot
+#[derive(Encode, Decode, Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Ballot {
+    pub epoch: u64,
+    pub replica_name: String,
+}
+
+impl Ballot {
+    /// The ballot of PreAccepts and Accepts sent by the instance's own leader
+    pub fn initial(instance: &Instance) -> Self {
+        Ballot {
+            epoch: 0,
+            replica_name: instance.replica.clone(),
+        }
+    }
+}
+
+impl fmt::Display for Ballot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.epoch, self.replica_name)
+    }
+}
+
+// Encode and Decode are written out below, to send deps with encode_deps
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PreAcceptMsg {
     pub cmd: Command,
     pub seq: u64,
-    pub deps: HashSet<Instance>,
+    pub deps: BTreeSet<Instance>,
     pub instance: Instance,
+    pub trace_ctx: Option<Vec<u8>>, // W3C traceparent of the sender's span
 }
 
-#[derive(Encode, Decode, Debug, Clone)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PreAcceptOkMsg {
     // pub cmd: Command,
     pub seq: u64,
-    pub deps: HashSet<Instance>,
+    pub deps: BTreeSet<Instance>,
     pub instance: Instance,
-    #[cfg(debug_assertions)]
     pub from_replica: String,
 }
 
-#[derive(Encode, Decode, Debug, Clone)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct CommitMsg {
     pub cmd: Command,
     pub seq: u64,
-    pub deps: HashSet<Instance>,
+    pub deps: BTreeSet<Instance>,
     pub instance: Instance,
+    pub commit_ts_ms: u64, // Leader's clock at commit, every replica checks TTLs against it
+    pub trace_ctx: Option<Vec<u8>>,
 }
 
-#[derive(Encode, Decode, Debug, Clone)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct AcceptMsg {
     pub cmd: Command,
     pub seq: u64,
-    pub deps: HashSet<Instance>,
+    pub deps: BTreeSet<Instance>,
     pub instance: Instance,
+    pub ballot: Ballot, // Ballot::initial from the leader, the Prepare's ballot in recovery
+    pub trace_ctx: Option<Vec<u8>>,
+}
+
+impl Encode for PreAcceptMsg {
+    fn encode<E: Encoder>(&self, encoder: &mut E) -> Result<(), EncodeError> {
+        self.cmd.encode(encoder)?;
+        self.seq.encode(encoder)?;
+        encode_deps(&self.deps, encoder)?;
+        self.instance.encode(encoder)?;
+        self.trace_ctx.encode(encoder)?;
+        Ok(())
+    }
+}
+
+impl<Context> Decode<Context> for PreAcceptMsg {
+    fn decode<D: Decoder<Context = Context>>(decoder: &mut D) -> Result<Self, DecodeError> {
+        Ok(PreAcceptMsg {
+            cmd: Command::decode(decoder)?,
+            seq: u64::decode(decoder)?,
+            deps: decode_deps(decoder)?,
+            instance: Instance::decode(decoder)?,
+            trace_ctx: Option::decode(decoder)?,
+        })
+    }
+}
+
+impl Encode for PreAcceptOkMsg {
+    fn encode<E: Encoder>(&self, encoder: &mut E) -> Result<(), EncodeError> {
+        self.seq.encode(encoder)?;
+        encode_deps(&self.deps, encoder)?;
+        self.instance.encode(encoder)?;
+        self.from_replica.encode(encoder)
+    }
+}
+
+impl<Context> Decode<Context> for PreAcceptOkMsg {
+    fn decode<D: Decoder<Context = Context>>(decoder: &mut D) -> Result<Self, DecodeError> {
+        Ok(PreAcceptOkMsg {
+            seq: u64::decode(decoder)?,
+            deps: decode_deps(decoder)?,
+            instance: Instance::decode(decoder)?,
+            from_replica: String::decode(decoder)?,
+        })
+    }
+}
+
+impl Encode for CommitMsg {
+    fn encode<E: Encoder>(&self, encoder: &mut E) -> Result<(), EncodeError> {
+        self.cmd.encode(encoder)?;
+        self.seq.encode(encoder)?;
+        encode_deps(&self.deps, encoder)?;
+        self.instance.encode(encoder)?;
+        self.commit_ts_ms.encode(encoder)?;
+        self.trace_ctx.encode(encoder)?;
+        Ok(())
+    }
 }
 
+impl<Context> Decode<Context> for CommitMsg {
+    fn decode<D: Decoder<Context = Context>>(decoder: &mut D) -> Result<Self, DecodeError> {
+        Ok(CommitMsg {
+            cmd: Command::decode(decoder)?,
+            seq: u64::decode(decoder)?,
+            deps: decode_deps(decoder)?,
+            instance: Instance::decode(decoder)?,
+            commit_ts_ms: u64::decode(decoder)?,
+            trace_ctx: Option::decode(decoder)?,
+        })
+    }
+}
+
+impl Encode for AcceptMsg {
+    fn encode<E: Encoder>(&self, encoder: &mut E) -> Result<(), EncodeError> {
+        self.cmd.encode(encoder)?;
+        self.seq.encode(encoder)?;
+        encode_deps(&self.deps, encoder)?;
+        self.instance.encode(encoder)?;
+        self.ballot.encode(encoder)?;
+        self.trace_ctx.encode(encoder)?;
+        Ok(())
+    }
+}
+
+impl<Context> Decode<Context> for AcceptMsg {
+    fn decode<D: Decoder<Context = Context>>(decoder: &mut D) -> Result<Self, DecodeError> {
+        Ok(AcceptMsg {
+            cmd: Command::decode(decoder)?,
+            seq: u64::decode(decoder)?,
+            deps: decode_deps(decoder)?,
+            instance: Instance::decode(decoder)?,
+            ballot: Ballot::decode(decoder)?,
+            trace_ctx: Option::decode(decoder)?,
+        })
+    }
+}
+
+bincode::impl_borrow_decode!(PreAcceptMsg);
+bincode::impl_borrow_decode!(PreAcceptOkMsg);
+bincode::impl_borrow_decode!(CommitMsg);
+bincode::impl_borrow_decode!(AcceptMsg);
+
 #[derive(Encode, Decode, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct AcceptOkMsg {
     // pub cmd: Command,
     pub instance: Instance,
-    #[cfg(debug_assertions)]
     pub from_replica: String,
 }
 
+/// How far an instance got at the replica answering a recovery Prepare
+#[derive(Encode, Decode, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PrepareStatus {
+    Unknown,
+    PreAccepted,
+    Accepted,
+    Committed,
+}
+
+#[derive(Encode, Decode, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PrepareOkMsg {
+    pub instance: Instance,
+    pub from_replica: String,
+    pub status: PrepareStatus,
+    pub cmd: Option<Command>, // None if Unknown
+    pub seq: u64,
+    pub deps: BTreeSet<Instance>,
+    pub commit_ts_ms: u64, // Only meaningful if Committed
+}
+
+/// A committed log entry shipped to a lagging replica. Same fields as the replica's
+/// CmdEntry, with deps as a Vec
+#[derive(Encode, Decode, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SerializableCmdEntry {
+    pub cmd: Command,
+    pub seq: u64,
+    pub deps: Vec<Instance>,
+    pub status: PrepareStatus, // Only Committed entries are shipped for now
+    pub commit_ts_ms: u64,
+}
+
+/// A replica's store, sent to a lagging replica asking for entries it already compacted.
+/// `data` holds the effects of every instance below `watermark` and of those in `executed`,
+/// the executed entries still in its log
+#[derive(Encode, Decode, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StoreSnapshotMsg {
+    pub watermark: Vec<(String, usize)>,
+    pub executed: Vec<(Instance, SerializableCmdEntry)>,
+    pub data: Vec<(Variable, Value, Option<u64>)>, // Expiry timestamp third
+    pub versions: Vec<(Variable, u64)>,
+    pub exec_clock_ms: u64,
+}
+
+/// Protocol counters of a replica, answering GetMetrics
+#[derive(Encode, Decode, Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MetricsMsg {
+    pub fast_path_commits: u64, // Instances I led that committed after PreAccept
+    pub slow_path_commits: u64, // Instances I led that needed Paxos-Accept
+    pub conflict_detections: u64, // PreAcceptOks that changed seq or deps
+    pub fast_reads_count: u64,  // Gets answered at the fast quorum, ahead of their Commit
+    pub dropped_responses: u64, // Client responses without a client_id to route to
+}
+
+/// Asks a replica for its stored value of `key`, or of every key when None. Testing only:
+/// answered straight from the replica's store without going through the log, so the reply is
+/// not linearizable and may miss writes other replicas already executed
+#[cfg(any(test, feature = "testing"))]
+#[derive(Encode, Decode, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GetStateRequest {
+    pub key: Option<Variable>,
+}
+
+/// Stored values answering a GetStateRequest, in key order. Values expired as of the
+/// replica's newest executed commit are left out
+#[cfg(any(test, feature = "testing"))]
+#[derive(Encode, Decode, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GetStateResponse {
+    pub entries: Vec<(Variable, Value)>,
+}
+
+/// Version of the EMsg encoding, carried by every Envelope. Bump it on any change to EMsg or
+/// the types it carries, so binaries built on both sides of the change refuse each other's
+/// messages instead of misreading them
+pub const WIRE_VERSION: u8 = 8;
+
 #[derive(Encode, Decode, Debug, Clone, DefaultPrio, DeriveMsg)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum EMsg {
     ClientRequest(ClientRequest),
     ClientResponse(ClientResponse),
+    ClientError(ClientErrorMsg),
+    WatchNotify(WatchNotifyMsg),
     PreAccept(PreAcceptMsg),
     PreAcceptOk(PreAcceptOkMsg),
+    /// Several PreAccepts of one leader in one message, answered by one PreAcceptOkBatch
+    PreAcceptBatch(Vec<PreAcceptMsg>),
+    PreAcceptOkBatch(Vec<PreAcceptOkMsg>),
     Commit(CommitMsg),
     Accept(AcceptMsg),
     AcceptOk(AcceptOkMsg),
-    DumpStateMsg,
+    DumpState,
+    /// Yielded by a client's workload once it stops sending, triggers the latency summary
+    WorkloadDone,
+    /// Yielded by a closed-loop workload while it waits for a free slot, so the client
+    /// still checks request deadlines
+    WorkloadTick,
+    DumpStateResponse(String), // JSON summary of the replica state
+    GetMetrics,
+    MetricsResponse(MetricsMsg),
+    /// Asks a peer for the committed entries of `from_instance.replica`'s log, starting at
+    /// `from_instance`
+    RequestLog {
+        requester: String,
+        from_instance: Instance,
+    },
+    LogResponse {
+        entries: Vec<(Instance, SerializableCmdEntry)>,
+    },
+    Ping {
+        from: String,
+        timestamp_us: u64,
+    },
+    Pong {
+        from: String,
+        echo_timestamp_us: u64,
+    },
+    Noop(Instance), // Asks the receiver to recover the instance, committing a Noop if nobody knows it
+    ProposeNoOp(Instance), // Fills an instance being recovered with a Noop, see propose_noop_handler
+    Prepare {
+        instance: Instance,
+        ballot: Ballot,
+    },
+    PrepareOk(PrepareOkMsg),
+    /// Answers a PreAccept, Accept or Prepare carrying a ballot below `current_ballot`
+    BallotRejected {
+        instance: Instance,
+        current_ballot: Ballot,
+    },
+    /// Asks the receiver to lead a membership change to `new_replica_list`. Ignored
+    /// unless `epoch` is past the receiver's current one
+    ReconfigRequest {
+        new_replica_list: Vec<String>,
+        epoch: u64,
+    },
+    /// Sent by `from` to every replica, old and new, once it switched to `epoch`
+    ReconfigAck {
+        epoch: u64,
+        from: String,
+    },
+    /// Periodic report of a replica or client to the metrics actor. Counters are totals
+    /// since `source` started, gauges are current values
+    Metrics {
+        source: String,
+        counters: HashMap<String, u64>,
+        gauges: HashMap<String, f64>,
+    },
+    /// Answers a RequestLog or Prepare for instances the receiver already compacted, see
+    /// StoreSnapshotMsg
+    StoreSnapshot(StoreSnapshotMsg),
+    // Testing only variants go last, so the others are numbered the same in every build
+    #[cfg(any(test, feature = "testing"))]
+    GetState(GetStateRequest),
+    #[cfg(any(test, feature = "testing"))]
+    GetStateResponse(GetStateResponse),
+}
+
+/// What actors send each other: an EMsg and the WIRE_VERSION of the build that sent it.
+/// The version goes first, so it's also the first byte on the wire
+#[derive(Encode, Decode, Debug, Clone, DefaultPrio, DeriveMsg)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Envelope {
+    pub version: u8,
+    pub msg: EMsg,
+}
+
+impl Envelope {
+    /// `msg` from this build
+    pub fn new(msg: EMsg) -> Self {
+        Envelope {
+            version: WIRE_VERSION,
+            msg,
+        }
+    }
+
+    /// The message, if it was sent by a build speaking my WIRE_VERSION
+    pub fn open(self) -> Result<EMsg, String> {
+        if self.version != WIRE_VERSION {
+            return Err(format!(
+                "message of wire version {} but this actor speaks version {}, \
+                 every actor of a cluster must run the same build",
+                self.version, WIRE_VERSION
+            ));
+        }
+        Ok(self.msg)
+    }
+}
+
+/// Runs an EMsg processor as an actor's processor, taking and giving Envelopes. Messages of
+/// another WIRE_VERSION are dropped with a warning
+pub struct Versioned<P>(pub P);
+
+impl<P: ActorProcess<IMsg = EMsg, OMsg = EMsg>> ActorProcess for Versioned<P> {
+    type IMsg = Envelope;
+    type OMsg = Envelope;
+
+    fn process(&mut self, input: Self::IMsg) -> Vec<Self::OMsg> {
+        match input.open() {
+            Ok(msg) => self.0.process(msg).into_iter().map(Envelope::new).collect(),
+            Err(reason) => {
+                warn!("Dropping a message: {}", reason);
+                vec![]
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Answers every message with WorkloadTick
+    struct Ticker;
+
+    impl ActorProcess for Ticker {
+        type IMsg = EMsg;
+        type OMsg = EMsg;
+
+        fn process(&mut self, _input: EMsg) -> Vec<EMsg> {
+            vec![EMsg::WorkloadTick]
+        }
+    }
+
+    fn inst(replica: &str, instance_num: usize) -> Instance {
+        Instance {
+            replica: replica.into(),
+            instance_num,
+        }
+    }
+
+    fn set() -> Command {
+        Command::set_ex(Variable::in_namespace("ns", "k"), vec![0xff, 0x00], 100)
+    }
+
+    /// One message of each EMsg variant
+    fn every_variant() -> Vec<EMsg> {
+        let deps = BTreeSet::from([inst("r1", 3)]);
+        vec![
+            EMsg::ClientRequest(ClientRequest {
+                client_id: "c".into(),
+                msg_id: "1".into(),
+                cmd: set(),
+                timestamp_us: Some(7),
+            }),
+            EMsg::ClientResponse(ClientResponse {
+                msg_id: "1".into(),
+                client_id: "c".into(),
+                cmd_result: CommandResult::Error {
+                    key: None,
+                    code: ErrorCode::InvalidRange,
+                    detail: "b sorts after a".into(),
+                },
+                server_received_us: Some(1),
+                server_responded_us: None,
+            }),
+            EMsg::ClientError(ClientErrorMsg {
+                msg_id: "1".into(),
+                client_id: "c".into(),
+                reason: "overloaded".into(),
+                retryable: true,
+            }),
+            EMsg::WatchNotify(WatchNotifyMsg {
+                key: Variable::new("k"),
+                val: Some("v".into()),
+                client_id: "c".into(),
+            }),
+            EMsg::PreAccept(PreAcceptMsg {
+                cmd: set(),
+                seq: 2,
+                deps: deps.clone(),
+                instance: inst("r0", 0),
+                trace_ctx: None,
+            }),
+            EMsg::PreAcceptOk(PreAcceptOkMsg {
+                seq: 2,
+                deps: deps.clone(),
+                instance: inst("r0", 0),
+                from_replica: "r1".into(),
+            }),
+            EMsg::PreAcceptBatch(vec![]),
+            EMsg::PreAcceptOkBatch(vec![]),
+            EMsg::Commit(CommitMsg {
+                cmd: Command::Noop,
+                seq: 2,
+                deps: deps.clone(),
+                instance: inst("r0", 0),
+                commit_ts_ms: 5,
+                trace_ctx: None,
+            }),
+            EMsg::Accept(AcceptMsg {
+                cmd: Command::Scan {
+                    start: Variable::new("a"),
+                    end: Variable::new("b"),
+                    limit: 10,
+                },
+                seq: 2,
+                deps: deps.clone(),
+                instance: inst("r0", 0),
+                ballot: Ballot::initial(&inst("r0", 0)),
+                trace_ctx: None,
+            }),
+            EMsg::AcceptOk(AcceptOkMsg {
+                instance: inst("r0", 0),
+                from_replica: "r1".into(),
+            }),
+            EMsg::DumpState,
+            EMsg::WorkloadDone,
+            EMsg::WorkloadTick,
+            EMsg::DumpStateResponse("{}".into()),
+            EMsg::GetMetrics,
+            EMsg::MetricsResponse(MetricsMsg::default()),
+            EMsg::RequestLog {
+                requester: "r2".into(),
+                from_instance: inst("r0", 4),
+            },
+            EMsg::LogResponse {
+                entries: vec![(
+                    inst("r0", 4),
+                    SerializableCmdEntry {
+                        cmd: set(),
+                        seq: 1,
+                        deps: vec![inst("r1", 3)],
+                        status: PrepareStatus::Committed,
+                        commit_ts_ms: 9,
+                    },
+                )],
+            },
+            EMsg::Ping {
+                from: "r0".into(),
+                timestamp_us: 1,
+            },
+            EMsg::Pong {
+                from: "r1".into(),
+                echo_timestamp_us: 1,
+            },
+            EMsg::Noop(inst("r0", 1)),
+            EMsg::ProposeNoOp(inst("r0", 1)),
+            EMsg::Prepare {
+                instance: inst("r0", 1),
+                ballot: Ballot::initial(&inst("r0", 1)),
+            },
+            EMsg::PrepareOk(PrepareOkMsg {
+                instance: inst("r0", 1),
+                from_replica: "r1".into(),
+                status: PrepareStatus::Unknown,
+                cmd: None,
+                seq: 0,
+                deps,
+                commit_ts_ms: 0,
+            }),
+            EMsg::BallotRejected {
+                instance: inst("r0", 1),
+                current_ballot: Ballot::initial(&inst("r0", 1)),
+            },
+            EMsg::ReconfigRequest {
+                new_replica_list: vec!["r0".into(), "r1".into()],
+                epoch: 1,
+            },
+            EMsg::ReconfigAck {
+                epoch: 1,
+                from: "r0".into(),
+            },
+            EMsg::Metrics {
+                source: "r0".into(),
+                counters: [("executed".to_string(), 3)].into(),
+                gauges: [("in_flight".to_string(), 0.5)].into(),
+            },
+            EMsg::StoreSnapshot(StoreSnapshotMsg {
+                watermark: vec![("r0".into(), 4)],
+                executed: vec![],
+                data: vec![(Variable::new("k"), "v".into(), Some(9))],
+                versions: vec![(Variable::new("k"), 1)],
+                exec_clock_ms: 9,
+            }),
+            EMsg::GetState(GetStateRequest { key: None }),
+            EMsg::GetStateResponse(GetStateResponse {
+                entries: vec![(Variable::new("k"), "v".into())],
+            }),
+        ]
+    }
+
+    // --- Tests ---
+    #[test]
+    fn test_every_variant_round_trips() {
+        for msg in every_variant() {
+            let envelope = Envelope::new(msg);
+            let bytes = bincode::encode_to_vec(&envelope, bincode::config::standard()).unwrap();
+            let (decoded, _): (Envelope, _) =
+                bincode::decode_from_slice(&bytes, bincode::config::standard()).unwrap();
+            // EMsg has no PartialEq, Debug shows every field
+            assert_eq!(format!("{:?}", decoded), format!("{:?}", envelope));
+            #[cfg(feature = "serde")]
+            {
+                let json = serde_json::to_vec(&envelope).unwrap();
+                let decoded: Envelope = serde_json::from_slice(&json).unwrap();
+                assert_eq!(format!("{:?}", decoded), format!("{:?}", envelope));
+            }
+        }
+    }
+
+    #[test]
+    fn test_versioned_drops_other_wire_versions() {
+        let mut actor = Versioned(Ticker);
+        let out = actor.process(Envelope::new(EMsg::DumpState));
+        assert!(matches!(
+            out.as_slice(),
+            [Envelope {
+                version: WIRE_VERSION,
+                msg: EMsg::WorkloadTick
+            }]
+        ));
+
+        // The version is the first byte on the wire, a build of another version is refused
+        let mut bytes =
+            bincode::encode_to_vec(Envelope::new(EMsg::DumpState), bincode::config::standard())
+                .unwrap();
+        assert_eq!(bytes[0], WIRE_VERSION);
+        bytes[0] = WIRE_VERSION + 1;
+        let (envelope, _): (Envelope, _) =
+            bincode::decode_from_slice(&bytes, bincode::config::standard()).unwrap();
+        assert!(actor.process(envelope).is_empty());
+    }
+
+    #[test]
+    fn test_value_display() {
+        assert_eq!(Value::from("abc").to_string(), "abc");
+        assert_eq!(Value::from(vec![0xff, 0x00]).to_string(), "0xff00");
+        assert_eq!(fmt_opt_value(&None), "NONE");
+
+        let long = Value::from("x".repeat(VALUE_DISPLAY_LEN + 1));
+        assert_eq!(
+            long.to_string(),
+            format!("{}...({} bytes)", "x".repeat(VALUE_DISPLAY_LEN), long.len())
+        );
+    }
+
+    #[test]
+    fn test_display_formats() {
+        let key = Variable::new("k");
+        assert_eq!(key.to_string(), "k");
+        assert_eq!(Variable::in_namespace("t1", "k").to_string(), "t1:k");
+
+        let inst = Instance {
+            replica: "r0".into(),
+            instance_num: 3,
+        };
+        assert_eq!(inst.to_string(), "r0/3");
+        assert_eq!(Ballot::initial(&inst).to_string(), "0.r0");
+
+        assert_eq!(Command::Get { key: key.clone() }.to_string(), "GET k");
+        let set = Command::Set {
+            key: key.clone(),
+            val: "v".into(),
+            ttl_ms: None,
+        };
+        assert_eq!(set.to_string(), "SET k=v");
+        let set_ttl = Command::Set {
+            key,
+            val: "v".into(),
+            ttl_ms: Some(5),
+        };
+        assert_eq!(set_ttl.to_string(), "SET k=v ttl=5ms");
+        assert_eq!(Command::Noop.to_string(), "NOOP");
+    }
+
+    #[test]
+    fn test_client_response_is_error() {
+        let response = |cmd_result| ClientResponse {
+            msg_id: "1".into(),
+            client_id: "c".into(),
+            cmd_result,
+            server_received_us: None,
+            server_responded_us: None,
+        };
+        let key = Variable::new("k");
+        assert!(
+            response(CommandResult::Error {
+                key: Some(key.clone()),
+                code: ErrorCode::NotAnInteger,
+                detail: String::new(),
+            })
+            .is_error()
+        );
+        assert!(!response(CommandResult::Set { key, version: 1 }).is_error());
+    }
+
+    #[test]
+    fn test_ballots_order_by_epoch_then_replica() {
+        let ballot = |epoch, replica: &str| Ballot {
+            epoch,
+            replica_name: replica.into(),
+        };
+        assert!(ballot(0, "r2") < ballot(1, "r0"));
+        assert!(ballot(1, "r0") < ballot(1, "r1"));
+        assert!(ballot(1, "r1") >= ballot(1, "r1"));
+    }
+
+    #[test]
+    fn test_value_roundtrips_binary_through_bincode() {
+        let val = Value::from((0..=255u8).collect::<Vec<u8>>());
+        let config = bincode::config::standard();
+        let bytes = bincode::encode_to_vec(&val, config).unwrap();
+        let (decoded, _): (Value, usize) = bincode::decode_from_slice(&bytes, config).unwrap();
+        assert_eq!(decoded, val);
+    }
+
+    #[test]
+    fn test_compact_instance_bit_layout() {
+        let compact = CompactInstance {
+            replica_idx: 3,
+            instance_num: 42,
+        };
+        assert_eq!(compact.to_u64(), Some((3 << 48) | 42));
+        assert_eq!(CompactInstance::from_u64((3 << 48) | 42), compact);
+
+        let largest = CompactInstance {
+            replica_idx: u16::MAX as u32,
+            instance_num: (1 << 48) - 1,
+        };
+        assert_eq!(largest.to_u64(), Some(u64::MAX));
+        assert_eq!(CompactInstance::from_u64(u64::MAX), largest);
+
+        let too_far = CompactInstance {
+            instance_num: 1 << 48,
+            ..largest
+        };
+        assert_eq!(too_far.to_u64(), None);
+        assert!(bincode::encode_to_vec(too_far, bincode::config::standard()).is_err());
+        let too_many = CompactInstance {
+            replica_idx: 1 << 16,
+            instance_num: 0,
+        };
+        assert_eq!(too_many.to_u64(), None);
+    }
+
+    #[test]
+    fn test_compact_instance_encodes_smaller() {
+        let replica_list = vec!["epaxos1".to_string(), "epaxos2".to_string()];
+        let inst = Instance {
+            replica: "epaxos2".into(),
+            instance_num: 1 << 20,
+        };
+        let compact = CompactInstance {
+            replica_idx: 1,
+            instance_num: 1 << 20,
+        };
+        assert_eq!(compact.to_instance(&replica_list), inst);
+
+        let config = bincode::config::standard();
+        let compact_bytes = bincode::encode_to_vec(compact, config).unwrap();
+        let inst_bytes = bincode::encode_to_vec(&inst, config).unwrap();
+        assert!(compact_bytes.len() < inst_bytes.len());
+        let (decoded, _): (CompactInstance, usize) =
+            bincode::decode_from_slice(&compact_bytes, config).unwrap();
+        assert_eq!(decoded, compact);
+    }
+
+    #[test]
+    fn test_deps_encode_identically_in_any_insertion_order() {
+        let insts: Vec<Instance> = (0..20)
+            .map(|i| Instance {
+                replica: format!("r{}", i % 3),
+                instance_num: i,
+            })
+            .collect();
+        let encode = |deps: BTreeSet<Instance>| {
+            let msg = AcceptMsg {
+                cmd: Command::Noop,
+                seq: 1,
+                deps,
+                instance: insts[0].clone(),
+                ballot: Ballot::initial(&insts[0]),
+                trace_ctx: None,
+            };
+            bincode::encode_to_vec(&msg, bincode::config::standard()).unwrap()
+        };
+
+        let forward = encode(insts.iter().cloned().collect());
+        let backward = encode(insts.iter().rev().cloned().collect());
+        assert_eq!(forward, backward);
+    }
+
+    #[test]
+    fn test_compact_deps_are_smaller_and_round_trip() {
+        // 5 replicas with 10 recent instances each, some not contiguous
+        let deps: BTreeSet<Instance> = (0..5)
+            .flat_map(|r| {
+                (0..10).map(move |i| Instance {
+                    replica: format!("epaxos_server_{}", r),
+                    instance_num: 40_000 + r * 7 + 3 * i + i % 3,
+                })
+            })
+            .collect();
+        assert_eq!(deps.len(), 50);
+        let msg = PreAcceptMsg {
+            cmd: Command::Get {
+                key: Variable::new("k"),
+            },
+            seq: 9,
+            deps: deps.clone(),
+            instance: Instance {
+                replica: "epaxos_server_0".into(),
+                instance_num: 40_100,
+            },
+            trace_ctx: None,
+        };
+        let config = bincode::config::standard();
+        let bytes = bincode::encode_to_vec(&msg, config).unwrap();
+        let (decoded, read): (PreAcceptMsg, usize) =
+            bincode::decode_from_slice(&bytes, config).unwrap();
+        assert_eq!(read, bytes.len());
+        assert_eq!(decoded.deps, deps);
+
+        // The derived encoding of a BTreeSet sends every dep whole. No deps encode as one
+        // byte, the 0 replica count
+        let plain = bincode::encode_to_vec(&deps, config).unwrap().len();
+        let no_deps = PreAcceptMsg {
+            deps: BTreeSet::new(),
+            ..msg.clone()
+        };
+        let compact = bytes.len() - bincode::encode_to_vec(no_deps, config).unwrap().len() + 1;
+        assert!(
+            compact * 4 < plain,
+            "{} bytes of deps, {} without compaction",
+            compact,
+            plain
+        );
+
+        // Gaps of 0 and the extremes of usize survive too
+        for deps in [
+            BTreeSet::new(),
+            BTreeSet::from([
+                Instance {
+                    replica: "a".into(),
+                    instance_num: 0,
+                },
+                Instance {
+                    replica: "a".into(),
+                    instance_num: usize::MAX,
+                },
+                Instance {
+                    replica: "b".into(),
+                    instance_num: 0,
+                },
+            ]),
+        ] {
+            let commit = CommitMsg {
+                cmd: Command::Noop,
+                seq: 0,
+                deps: deps.clone(),
+                instance: msg.instance.clone(),
+                commit_ts_ms: 1,
+                trace_ctx: None,
+            };
+            let bytes = bincode::encode_to_vec(&commit, config).unwrap();
+            let (decoded, _): (CommitMsg, usize) =
+                bincode::decode_from_slice(&bytes, config).unwrap();
+            assert_eq!(decoded.deps, deps);
+        }
+    }
 }
@@ -0,0 +1,310 @@
+use crate::common::{ClientRequest, Command, CompactInstance, EMsg, Instance, Variable};
+use crate::epaxos::{CmdEntry, CmdMetadata, CmdStatus, Processor, ProcessorConfig};
+use reactor_actor::ActorProcess;
+use std::collections::{BTreeSet, VecDeque};
+
+// Entry points for benches/bench_epaxos.rs, which can't reach the private Processor
+
+/// Keys the populated log spreads its Sets over
+const BENCH_KEY_SPACE: usize = 100;
+
+fn bench_set(key: usize) -> Command {
+    Command::Set {
+        key: Variable::new(format!("key_{}", key % BENCH_KEY_SPACE)),
+        val: "val".into(),
+        ttl_ms: None,
+    }
+}
+
+fn replica_names(n_replicas: usize) -> Vec<String> {
+    (0..n_replicas).map(|i| format!("r{}", i)).collect()
+}
+
+/// A single replica with a prepared log, seen as "r0"
+pub struct BenchProcessor(Processor);
+
+impl BenchProcessor {
+    /// Log holding `n_instances` committed, unexecuted Sets spread round-robin over the
+    /// replicas, each depending on the previous one
+    pub fn new_populated(n_replicas: usize, n_instances: usize) -> Self {
+        let names = replica_names(n_replicas);
+        let mut p = Processor::new(names.clone(), names[0].clone());
+        let mut prev: Option<Instance> = None;
+        for i in 0..n_instances {
+            let inst = Instance {
+                replica: names[i % n_replicas].clone(),
+                instance_num: i / n_replicas,
+            };
+            let entry = CmdEntry {
+                cmd: bench_set(i),
+                seq: i as u64 + 1,
+                deps: prev.iter().cloned().collect(),
+                status: CmdStatus::Committed,
+                commit_ts_ms: 0,
+            };
+            if inst.replica == p.replica_name {
+                // Executing my own instances answers their clients
+                p.app_meta.push(CmdMetadata {
+                    client_id: "c".into(),
+                    msg_id: i.to_string(),
+                    received_us: 0,
+                });
+            }
+            p.cmds_insert(&inst, entry);
+            prev = Some(inst);
+        }
+        BenchProcessor(p)
+    }
+
+    /// Chain of `depth` strongly connected components of `scc_size` instances each.
+    /// Members of a component depend on each other in a ring, and on the previous component
+    pub fn new_scc_chain(depth: usize, scc_size: usize) -> Self {
+        let mut p = Processor::new(vec!["r0".into()], "r0".into());
+        let inst = |i: usize| Instance {
+            replica: "r0".into(),
+            instance_num: i,
+        };
+        for d in 0..depth {
+            for m in 0..scc_size {
+                let i = d * scc_size + m;
+                let mut deps = BTreeSet::from([inst(d * scc_size + (m + 1) % scc_size)]);
+                if d > 0 {
+                    deps.insert(inst(i - scc_size));
+                }
+                let entry = CmdEntry {
+                    cmd: bench_set(i),
+                    seq: i as u64 + 1,
+                    deps,
+                    status: CmdStatus::Committed,
+                    commit_ts_ms: 0,
+                };
+                p.cmds_insert(&inst(i), entry);
+            }
+        }
+        BenchProcessor(p)
+    }
+
+    /// Deps and seq a new Set on `key` would get
+    pub fn get_interfs(&self, key: usize) -> (usize, u64) {
+        let (deps, seq) = self.0.get_interfs(&bench_set(key));
+        (deps.len(), seq)
+    }
+
+    /// Components found by Tarjan over the graph reachable from the newest instance
+    pub fn tarjan_scc(&self) -> usize {
+        let Some(root) = self.newest() else {
+            return 0;
+        };
+        let graph = self.0.build_dep_graph(&root);
+        self.0.tarjan_scc(&graph).len()
+    }
+
+    /// Executes everything reachable from the newest instance, returning the replies
+    pub fn execute_newest(&mut self) -> usize {
+        match self.newest() {
+            Some(root) => self.0.execute_cmd(&root).len(),
+            None => 0,
+        }
+    }
+
+    fn newest(&self) -> Option<Instance> {
+        self.0
+            .cmds
+            .iter()
+            .filter(|(_, log)| !log.is_empty())
+            .map(|(replica, log)| Instance {
+                replica: replica.clone(),
+                instance_num: self.0.watermark_of(replica) + log.len() - 1,
+            })
+            .max_by_key(|inst| self.0.lookup(inst).map_or(0, |e| e.seq))
+    }
+}
+
+/// The same deps set bincode-encoded as Instances and as CompactInstances
+pub struct BenchDeps {
+    replica_list: Vec<String>,
+    pub full: Vec<u8>,
+    pub compact: Vec<u8>,
+}
+
+impl BenchDeps {
+    /// `n_deps` instances spread round-robin over `n_replicas` replicas
+    pub fn new(n_replicas: usize, n_deps: usize) -> Self {
+        let names = replica_names(n_replicas);
+        let p = Processor::new(names.clone(), names[0].clone());
+        let deps: Vec<Instance> = (0..n_deps)
+            .map(|i| Instance {
+                replica: names[i % n_replicas].clone(),
+                instance_num: 1_000_000 + i,
+            })
+            .collect();
+        let compact: Vec<CompactInstance> = deps.iter().map(|d| d.to_compact(&p)).collect();
+        let config = bincode::config::standard();
+        BenchDeps {
+            replica_list: names,
+            full: bincode::encode_to_vec(&deps, config).unwrap(),
+            compact: bincode::encode_to_vec(&compact, config).unwrap(),
+        }
+    }
+
+    pub fn decode_full(&self) -> usize {
+        let (deps, _): (Vec<Instance>, usize) =
+            bincode::decode_from_slice(&self.full, bincode::config::standard()).unwrap();
+        deps.len()
+    }
+
+    /// Decodes and maps back to Instances, the work a receiver would do
+    pub fn decode_compact(&self) -> usize {
+        let (deps, _): (Vec<CompactInstance>, usize) =
+            bincode::decode_from_slice(&self.compact, bincode::config::standard()).unwrap();
+        let insts: Vec<Instance> = deps
+            .iter()
+            .map(|d| d.to_instance(&self.replica_list))
+            .collect();
+        insts.len()
+    }
+}
+
+/// Replicas joined by an in-memory FIFO network, routed like the Sender does
+pub struct BenchCluster(Vec<Processor>);
+
+impl BenchCluster {
+    pub fn new(n_replicas: usize) -> Self {
+        Self::with_pipeline_depth(n_replicas, None)
+    }
+
+    /// Replicas leading at most `depth` uncommitted instances each, see ProcessorConfig
+    pub fn with_pipeline_depth(n_replicas: usize, depth: Option<usize>) -> Self {
+        Self::with_config(
+            n_replicas,
+            ProcessorConfig {
+                pipeline_depth: depth,
+                ..Default::default()
+            },
+        )
+    }
+
+    /// Replicas leading at most `depth` uncommitted instances each, packing the PreAccepts
+    /// of queued requests that start together into one message if `batched`
+    pub fn with_pre_accept_batching(n_replicas: usize, depth: usize, batched: bool) -> Self {
+        Self::with_config(
+            n_replicas,
+            ProcessorConfig {
+                pipeline_depth: Some(depth),
+                batch_pre_accepts: batched,
+                ..Default::default()
+            },
+        )
+    }
+
+    /// Replicas acking their Sets at commit and executing them for reads only if `lazy`
+    pub fn with_lazy_execution(n_replicas: usize, lazy: bool) -> Self {
+        Self::with_config(
+            n_replicas,
+            ProcessorConfig {
+                lazy_execution: lazy,
+                ..Default::default()
+            },
+        )
+    }
+
+    fn with_config(n_replicas: usize, config: ProcessorConfig) -> Self {
+        let names = replica_names(n_replicas);
+        let replicas = names
+            .iter()
+            .map(|name| Processor::new_with_config(names.clone(), name.clone(), config.clone()))
+            .collect();
+        BenchCluster(replicas)
+    }
+
+    /// `n_clients` each send `per_client` Sets, client i always to replica i % n_replicas.
+    /// Returns how many client responses came back
+    pub fn run(&mut self, n_clients: usize, per_client: usize) -> usize {
+        self.run_cmds(n_clients, per_client, bench_set).0
+    }
+
+    /// Like run, but returns how many messages went between replicas
+    pub fn run_counting_messages(&mut self, n_clients: usize, per_client: usize) -> usize {
+        self.run_cmds(n_clients, per_client, bench_set).1
+    }
+
+    /// Like run, but every `read_every`th request of a client is a Get on one of the keys
+    /// the Sets write
+    pub fn run_mixed(&mut self, n_clients: usize, per_client: usize, read_every: usize) -> usize {
+        self.run_cmds(n_clients, per_client, |i| {
+            if i % read_every == read_every - 1 {
+                Command::Get {
+                    key: Variable::new(format!("key_{}", i % BENCH_KEY_SPACE)),
+                }
+            } else {
+                bench_set(i)
+            }
+        })
+        .0
+    }
+
+    /// Like run, but every request is on the one key "hot", a Get if `reads` else a Set
+    pub fn run_one_key(&mut self, n_clients: usize, per_client: usize, reads: bool) -> usize {
+        let key = Variable::new("hot");
+        self.run_cmds(n_clients, per_client, |i| {
+            if reads {
+                Command::Get { key: key.clone() }
+            } else {
+                Command::Set {
+                    key: key.clone(),
+                    val: i.to_string().into(),
+                    ttl_ms: None,
+                }
+            }
+        })
+        .0
+    }
+
+    /// Client responses that came back, and messages delivered between replicas
+    fn run_cmds(
+        &mut self,
+        n_clients: usize,
+        per_client: usize,
+        cmd: impl Fn(usize) -> Command,
+    ) -> (usize, usize) {
+        let n = self.0.len();
+        // (from, to, msg)
+        let mut inflight = VecDeque::new();
+        for c in 0..n_clients {
+            for m in 0..per_client {
+                let req = EMsg::ClientRequest(ClientRequest {
+                    client_id: format!("c{}", c),
+                    msg_id: m.to_string(),
+                    cmd: cmd(c * per_client + m),
+                    timestamp_us: None,
+                });
+                inflight.push_back((c % n, c % n, req));
+            }
+        }
+
+        let mut responses = 0;
+        let mut messages = 0;
+        while let Some((from, to, msg)) = inflight.pop_front() {
+            for out in self.0[to].process(msg) {
+                match &out {
+                    EMsg::PreAccept(_)
+                    | EMsg::PreAcceptBatch(_)
+                    | EMsg::Accept(_)
+                    | EMsg::Commit(_) => {
+                        for peer in (0..n).filter(|&i| i != to) {
+                            inflight.push_back((to, peer, out.clone()));
+                            messages += 1;
+                        }
+                    }
+                    EMsg::PreAcceptOk(_) | EMsg::PreAcceptOkBatch(_) | EMsg::AcceptOk(_) => {
+                        inflight.push_back((to, from, out));
+                        messages += 1;
+                    }
+                    EMsg::ClientResponse(_) => responses += 1,
+                    _ => {}
+                }
+            }
+        }
+        (responses, messages)
+    }
+}
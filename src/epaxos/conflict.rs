@@ -0,0 +1,60 @@
+use crate::common::Command;
+use serde::Deserialize;
+use std::fmt;
+use std::sync::Arc;
+
+/// Decides whether two commands interfere, i.e. must be ordered by a dependency.
+/// Every replica must use the same checker, or their dependency graphs diverge
+pub trait ConflictChecker: fmt::Debug + Send + Sync {
+    fn conflicts(&self, a: &Command, b: &Command) -> bool;
+}
+
+/// Commands sharing a key conflict, see Command::conflicts_with. Multi-key commands
+/// compare every key they name, Scans their whole range
+#[derive(Debug, Clone, Copy, Default)]
+pub struct KeyEqualityChecker;
+
+impl ConflictChecker for KeyEqualityChecker {
+    fn conflicts(&self, a: &Command, b: &Command) -> bool {
+        a.conflicts_with(b)
+    }
+}
+
+/// Everything conflicts, turning EPaxos into a totally ordered log
+#[derive(Debug, Clone, Copy)]
+pub struct AlwaysConflictChecker;
+
+impl ConflictChecker for AlwaysConflictChecker {
+    fn conflicts(&self, _a: &Command, _b: &Command) -> bool {
+        true
+    }
+}
+
+/// Nothing conflicts. Only safe if no two commands touch the same key
+#[derive(Debug, Clone, Copy)]
+pub struct NeverConflictChecker;
+
+impl ConflictChecker for NeverConflictChecker {
+    fn conflicts(&self, _a: &Command, _b: &Command) -> bool {
+        false
+    }
+}
+
+/// Checker picked by the `conflict_checker` server payload field
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub enum ConflictCheckerKind {
+    #[default]
+    KeyEquality,
+    Always,
+    Never,
+}
+
+impl ConflictCheckerKind {
+    pub fn build(self) -> Arc<dyn ConflictChecker> {
+        match self {
+            ConflictCheckerKind::KeyEquality => Arc::new(KeyEqualityChecker),
+            ConflictCheckerKind::Always => Arc::new(AlwaysConflictChecker),
+            ConflictCheckerKind::Never => Arc::new(NeverConflictChecker),
+        }
+    }
+}
@@ -1,47 +1,196 @@
 use crate::common::{
-    AcceptMsg, AcceptOkMsg, ClientRequest, ClientResponse, Command, CommandResult, CommitMsg, EMsg,
-    Instance, PreAcceptMsg, PreAcceptOkMsg,
+    AcceptMsg, AcceptOkMsg, Ballot, ClientErrorMsg, ClientRequest, ClientResponse, Command,
+    CommandResult, CommitMsg, EMsg, Instance, MetricsMsg, PreAcceptMsg, PreAcceptOkMsg,
+    PrepareStatus, SerializableCmdEntry, StoreSnapshotMsg, WIRE_VERSION, now_us,
+    outgoing_trace_ctx,
+};
+#[cfg(any(test, feature = "testing"))]
+use crate::common::{GetStateRequest, GetStateResponse, Variable};
+use crate::epaxos::{
+    CmdEntry, CmdMetadata, CmdStatus, LOG_SYNC_BATCH, PEER_TIMEOUT, Processor, ReplicaMode,
+    StoredValue,
 };
-use crate::epaxos::{CmdEntry, CmdMetadata, CmdStatus, Processor};
 
-use tracing::{error, info};
+use serde_json::json;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::Ordering;
+use std::time::Instant;
+use tracing::{error, info, warn};
 
 impl Processor {
+    #[cfg_attr(
+        feature = "telemetry",
+        tracing::instrument(
+            skip_all,
+            fields(
+                client_id = %msg.client_id,
+                msg_id = %msg.msg_id,
+                replica = %self.replica_name,
+                instance = tracing::field::Empty,
+            )
+        )
+    )]
     pub fn client_request_handler(&mut self, msg: ClientRequest) -> Vec<EMsg> {
         let ClientRequest {
             cmd,
             msg_id,
             client_id,
+            timestamp_us,
         } = msg;
+        let received_us = now_us();
+
+        if self.replica_mode == ReplicaMode::ReadOnly {
+            #[cfg(debug_assertions)]
+            info!(
+                "{}: Read-only replica rejecting {} from {}",
+                self.replica_name, cmd, client_id
+            );
+            return vec![EMsg::ClientError(ClientErrorMsg {
+                msg_id,
+                client_id,
+                reason: format!("{} is a read-only replica", self.replica_name),
+                retryable: true,
+            })];
+        }
+
+        if let Err((key, code, detail)) = self.validate(&cmd, &msg_id, &client_id) {
+            #[cfg(debug_assertions)]
+            info!(
+                "{}: Rejecting {} from {}: {}",
+                self.replica_name, msg_id, client_id, detail
+            );
+            return vec![EMsg::ClientResponse(ClientResponse {
+                msg_id,
+                client_id,
+                cmd_result: CommandResult::Error { key, code, detail },
+                server_received_us: Some(received_us),
+                server_responded_us: Some(now_us()),
+            })];
+        }
 
+        // Subscriptions are local to this replica and never enter the log
+        if let Command::Watch { key } | Command::Unwatch { key } = &cmd {
+            let watching = matches!(cmd, Command::Watch { .. });
+            let cmd_result = self.watch(key, client_id.clone(), watching);
+            return vec![EMsg::ClientResponse(ClientResponse {
+                msg_id,
+                client_id,
+                cmd_result,
+                server_received_us: Some(received_us),
+                server_responded_us: Some(now_us()),
+            })];
+        }
+
+        // A retried request must not be proposed twice. Replay the response if it was sent,
+        // otherwise the original is still in flight and will answer on its own
+        if let Some(seen) = self.seen_request(&client_id, &msg_id) {
+            #[cfg(debug_assertions)]
+            info!(
+                "{}: Duplicate request {} from {}, led as {}",
+                self.replica_name, msg_id, client_id, seen.instance
+            );
+            return match &seen.response {
+                Some(resp) => vec![EMsg::ClientResponse(resp.clone())],
+                None => vec![],
+            };
+        }
+
+        // Backpressure: instances parked waiting on deps already fill the budget
+        if self.pending_reads.len() >= self.config.max_pending_reads {
+            #[cfg(debug_assertions)]
+            info!(
+                "{}: {} instances pending, rejecting {} from {}",
+                self.replica_name,
+                self.pending_reads.len(),
+                msg_id,
+                client_id
+            );
+            return vec![EMsg::ClientError(ClientErrorMsg {
+                msg_id,
+                client_id,
+                reason: format!("{} has too many pending instances", self.replica_name),
+                retryable: true,
+            })];
+        }
+
+        // My uncompacted log is too long, commits aren't keeping up with clients
+        let queued = self.queued_requests.len();
+        if self.log_len(&self.replica_name) + queued > self.config.max_inflight_instances {
+            #[cfg(debug_assertions)]
+            info!(
+                "{}: Overloaded, rejecting {} from {}",
+                self.replica_name, msg_id, client_id
+            );
+            return vec![EMsg::ClientError(ClientErrorMsg {
+                msg_id,
+                client_id,
+                reason: "overloaded".into(),
+                retryable: true,
+            })];
+        }
+
+        // The pipeline window is full, start it once my oldest instance commits. Requests
+        // already queued go first
+        if let Some(depth) = self.config.pipeline_depth
+            && (self.pending_instances.len() >= depth || !self.queued_requests.is_empty())
+        {
+            self.queued_requests.push_back(ClientRequest {
+                cmd,
+                msg_id,
+                client_id,
+                timestamp_us,
+            });
+            return vec![];
+        }
+        self.lead_request(cmd, msg_id, client_id, received_us)
+    }
+
+    /// Starts a new instance of mine for an accepted client request, PreAccepting it
+    pub(super) fn lead_request(
+        &mut self,
+        cmd: Command,
+        msg_id: String,
+        client_id: String,
+        received_us: u64,
+    ) -> Vec<EMsg> {
         // Purely for checking starting case where inst_num is already 0, no need to increment
-        let vec_size = self.cmds.get(&self.replica_name).unwrap().len();
+        let vec_size = self.log_len(&self.replica_name) + self.watermark_of(&self.replica_name);
         if vec_size > 0 {
             self.instance_num += 1;
         }
 
         self.quorum_ctr.push(0); // push 0 to quorum_ctr list to not resize later
         self.acc_quorum_ctr.push(0);
+        self.received_from.push(HashSet::new());
+        self.acc_received_from.push(HashSet::new());
 
-        let (deps, seq) = self.get_interfs(&cmd);
+        let (deps, min_seq) = self.get_interfs(&cmd);
+        let seq = self.next_seq(min_seq);
 
         let cmd_entry = CmdEntry {
             cmd: cmd.clone(),
             seq,
             deps: deps.clone(),
             status: CmdStatus::PreAccepted,
+            commit_ts_ms: 0,
         };
 
-        let cmds_vec = self.cmds.get_mut(&self.replica_name).unwrap();
-        cmds_vec.push(Some(cmd_entry));
-
-        // Store client metadata in app_meta
-        self.app_meta.push(CmdMetadata { client_id, msg_id });
-
         let instance = Instance {
             replica: self.replica_name.clone(),
             instance_num: self.instance_num,
         };
+        #[cfg(feature = "telemetry")]
+        tracing::Span::current().record("instance", tracing::field::display(&instance));
+        self.cmds_insert(&instance, cmd_entry);
+        self.remember_request(&client_id, &msg_id, &instance);
+        self.pending_instances.push_back(instance.instance_num);
+
+        // Store client metadata in app_meta
+        self.app_meta.push(CmdMetadata {
+            client_id,
+            msg_id,
+            received_us,
+        });
 
         #[cfg(debug_assertions)]
         info!(
@@ -58,18 +207,33 @@ impl Processor {
             seq,
             deps,
             instance,
+            trace_ctx: outgoing_trace_ctx(),
         });
 
         vec![pre_accept]
     }
 
+    #[cfg_attr(
+        feature = "telemetry",
+        tracing::instrument(skip_all, fields(instance = %msg.instance, replica = %self.replica_name))
+    )]
     pub fn pre_accept_handler(&mut self, msg: PreAcceptMsg) -> Vec<EMsg> {
         let PreAcceptMsg {
             cmd,
             seq,
             deps,
             instance,
+            trace_ctx: _trace_ctx,
         } = msg;
+        #[cfg(feature = "telemetry")]
+        crate::telemetry::set_parent(_trace_ctx);
+
+        if let Some(rejected) = self.check_ballot(&instance, &Ballot::initial(&instance)) {
+            return vec![rejected]; // Someone else prepared the instance, its leader lost it
+        }
+        if self.is_preparing(&instance) {
+            return vec![]; // Being recovered here, the original leader lost the slot
+        }
 
         // Get Interfering instances and max seq, check with incoming msg and update
         let (mut interf_deps, mut interf_seq) = self.get_interfs(&cmd);
@@ -80,6 +244,7 @@ impl Processor {
         interf_deps.extend(deps.clone());
 
         interf_seq = interf_seq.max(seq);
+        self.hlc.update(interf_seq); // My next instances order after this one
 
         #[cfg(debug_assertions)]
         let updated: bool = interf_seq > seq || interf_deps.len() > deps.len();
@@ -90,6 +255,7 @@ impl Processor {
             seq: interf_seq,
             deps: interf_deps.clone(),
             status: CmdStatus::PreAccepted,
+            commit_ts_ms: 0,
         };
         // Add the incoming command to the cmds log
         self.cmds_insert(&instance, cmd_entry);
@@ -108,19 +274,45 @@ impl Processor {
             seq: interf_seq,
             deps: interf_deps,
             instance,
-            #[cfg(debug_assertions)]
             from_replica: self.replica_name.clone(),
         });
 
         vec![pre_accept_ok]
     }
 
+    /// Handles each PreAccept of the batch in order, answering with one PreAcceptOkBatch
+    pub fn pre_accept_batch_handler(&mut self, msgs: Vec<PreAcceptMsg>) -> Vec<EMsg> {
+        let mut oks = vec![];
+        let mut out_msgs = vec![];
+        for msg in msgs {
+            for out in self.pre_accept_handler(msg) {
+                match out {
+                    EMsg::PreAcceptOk(ok) => oks.push(ok),
+                    other => out_msgs.push(other), // BallotRejected
+                }
+            }
+        }
+        if !oks.is_empty() {
+            out_msgs.push(EMsg::PreAcceptOkBatch(oks));
+        }
+        out_msgs
+    }
+
+    pub fn pre_accept_ok_batch_handler(&mut self, msgs: Vec<PreAcceptOkMsg>) -> Vec<EMsg> {
+        msgs.into_iter()
+            .flat_map(|msg| self.pre_accept_ok_handler(msg))
+            .collect()
+    }
+
+    #[cfg_attr(
+        feature = "telemetry",
+        tracing::instrument(skip_all, fields(instance = %msg.instance, replica = %self.replica_name))
+    )]
     pub fn pre_accept_ok_handler(&mut self, msg: PreAcceptOkMsg) -> Vec<EMsg> {
         let PreAcceptOkMsg {
             seq,
             deps,
             instance,
-            #[cfg(debug_assertions)]
             from_replica,
         } = msg;
         #[cfg(debug_assertions)]
@@ -154,15 +346,23 @@ impl Processor {
         //     majority, fast_quorum, self.replica_name
         // );
 
+        // Late reply for an instance already executed and compacted
+        let Some(index) = inst_num.checked_sub(self.watermark_of(&replica)) else {
+            return vec![];
+        };
+
         // Ensure the command exists in the log
-        let cmd_entry_mut: &mut CmdEntry = self
-            .cmds
-            .get_mut(&replica)
-            .unwrap()
-            .get_mut(inst_num)
-            .unwrap()
-            .as_mut()
-            .expect("Command not found in log");
+        let cmd_entry_mut: &mut CmdEntry = if self.config.sparse_log {
+            self.cmds_flat.get_mut(&instance)
+        } else {
+            self.cmds
+                .get_mut(&replica)
+                .unwrap()
+                .get_mut(index)
+                .unwrap()
+                .as_mut()
+        }
+        .expect("Command not found in log");
 
         // Check if already committed
         if matches!(
@@ -178,6 +378,15 @@ impl Processor {
             // TODO: Can add optional debug checks to prove invariance that newer messages would not have unseen interfering commands
         }
 
+        // A replica answering twice, e.g. to a re-sent PreAccept, counts once
+        if !self.received_from[inst_num].insert(from_replica.clone()) {
+            warn!(
+                "{}: Duplicate PreAcceptOk for {} from {}, ignoring",
+                self.replica_name, instance, from_replica
+            );
+            return vec![];
+        }
+
         // Check if accepted
         if matches!(cmd_entry_mut.status, CmdStatus::Accepted) {
             // Ensure quorum counter is less than majority
@@ -194,8 +403,16 @@ impl Processor {
 
         // Check if seq and deps match
         if cmd_entry_mut.seq != seq || cmd_entry_mut.deps != deps {
+            self.conflict_detections += 1;
             // Update seq and deps
             cmd_entry_mut.seq = cmd_entry_mut.seq.max(seq);
+            // Field borrow, cmd_entry_mut still holds self.cmds
+            for dep in &deps {
+                self.rdeps
+                    .entry(dep.clone())
+                    .or_default()
+                    .insert(instance.clone());
+            }
             cmd_entry_mut.deps.extend(deps);
             cmd_entry_mut.status = CmdStatus::Accepted;
         }
@@ -205,8 +422,13 @@ impl Processor {
 
         let ctr = self.quorum_ctr[inst_num];
 
-        // Check if majority is reached
+        // Check if majority is reached. A fast quorum larger than my peers can't be reached,
+        // the instance takes the slow path even without conflicts. Alone, I answer myself
         if ctr == majority {
+            let peers = self.replica_list.len().saturating_sub(1).max(1);
+            if fast_quorum as usize > peers {
+                cmd_entry_mut.status = CmdStatus::Accepted;
+            }
             if matches!(cmd_entry_mut.status, CmdStatus::Accepted) {
                 // check if status is Accepted
                 // Phase 2: Paxos-Accept
@@ -225,7 +447,9 @@ impl Processor {
                     cmd: cmd_entry_mut.cmd.clone(),
                     seq: cmd_entry_mut.seq,
                     deps: cmd_entry_mut.deps.clone(),
+                    ballot: Ballot::initial(&instance),
                     instance,
+                    trace_ctx: outgoing_trace_ctx(),
                 });
                 return vec![accept_msg];
             } else {
@@ -247,68 +471,33 @@ impl Processor {
             // Commit phase
             // changing msg status to committed
             cmd_entry_mut.status = CmdStatus::Committed;
-            #[cfg(debug_assertions)]
-            info!(
-                "{}: Fast Commit started for {}",
-                self.replica_name, instance
-            );
+            cmd_entry_mut.commit_ts_ms = now_us() / 1000;
 
             let commit_msg = EMsg::Commit(CommitMsg {
                 cmd: cmd_entry_mut.cmd.clone(),
                 seq: cmd_entry_mut.seq,
                 deps: cmd_entry_mut.deps.clone(),
                 instance: instance.clone(),
+                commit_ts_ms: cmd_entry_mut.commit_ts_ms,
+                trace_ctx: outgoing_trace_ctx(),
             });
-
-            // Only for write commands (Set)
-            match &cmd_entry_mut.cmd {
-                Command::Set { .. } => {
-                    let CmdMetadata { msg_id, client_id } = &self.app_meta[inst_num];
-
-                    let client_response = EMsg::ClientResponse(ClientResponse {
-                        msg_id: msg_id.clone(),
-                        client_id: client_id.clone(),
-                        cmd_result: CommandResult::Set {
-                            key: cmd_entry_mut.cmd.key().clone(),
-                            status: true,
-                        },
-                    });
-                    #[cfg(debug_assertions)]
-                    info!(
-                        "{}: Sending Client Response for {}",
-                        self.replica_name, instance
-                    );
-
-                    let mut out_msgs = self.handle_pending_reads(&instance);
-
-                    let mut final_msgs = vec![commit_msg, client_response];
-                    final_msgs.append(&mut out_msgs);
-                    return final_msgs;
-                    // return vec![
-                    //     commit_msg,
-                    //     client_response, // send to correct client
-                    // ];
-                }
-                Command::Get { .. } => {
-                    // TODO: Handle if reads also get fast path
-                    // return vec![commit_msg];
-                    let mut out_msgs = vec![commit_msg];
-                    if self.deps_all_ready(&instance) {
-                        let mut exec_out = self.execute_cmd(&instance);
-                        out_msgs.append(&mut exec_out);
-
-                        #[cfg(debug_assertions)]
-                        #[cfg(debug_assertions)]
-                        info!(
-                            "{}: Executed fast committed read {}",
-                            self.replica_name, instance
-                        );
-                    } else {
-                        self.pending_reads.insert(instance.clone());
-                    }
-                    return out_msgs;
-                }
+            self.fast_path_commits += 1;
+            self.received_from[inst_num] = HashSet::new();
+            // Peers still need the Commit, or the read stays PreAccepted in their logs and
+            // they never compact past it
+            if let Some(mut out_msgs) = self.try_fast_commit_read(&instance) {
+                out_msgs.push(commit_msg);
+                return out_msgs;
             }
+            #[cfg(debug_assertions)]
+            info!(
+                "{}: Fast Commit started for {}",
+                self.replica_name, instance
+            );
+
+            let mut out_msgs = vec![commit_msg];
+            out_msgs.append(&mut self.leader_commit_output(&instance));
+            return out_msgs;
         }
         // else {
         // panic!("Quorum intersection invariant violated");
@@ -317,13 +506,21 @@ impl Processor {
 
         vec![]
     }
+    #[cfg_attr(
+        feature = "telemetry",
+        tracing::instrument(skip_all, fields(instance = %msg.instance, replica = %self.replica_name))
+    )]
     pub fn commit_handler(&mut self, msg: CommitMsg) -> Vec<EMsg> {
         let CommitMsg {
             cmd,
             seq,
             deps,
             instance,
+            commit_ts_ms,
+            trace_ctx: _trace_ctx,
         } = msg;
+        #[cfg(feature = "telemetry")]
+        crate::telemetry::set_parent(_trace_ctx);
 
         #[cfg(debug_assertions)]
         info!(
@@ -333,7 +530,14 @@ impl Processor {
             seq,
             deps.len()
         );
-        let is_set_req: bool = matches!(&cmd, Command::Set { .. });
+        if matches!(
+            self.lookup(&instance).map(|e| &e.status),
+            Some(CmdStatus::Executed)
+        ) {
+            return vec![]; // Duplicate commit, e.g. from a recovering replica
+        }
+        let is_write: bool = cmd.is_write();
+        let watched = self.is_watched(&cmd);
 
         // Create a new CmdEntry with the Committed status
         let cmd_entry = CmdEntry {
@@ -341,28 +545,64 @@ impl Processor {
             seq,
             deps,
             status: CmdStatus::Committed,
+            commit_ts_ms,
+        };
+
+        let reconfig = match &cmd_entry.cmd {
+            Command::Reconfig {
+                replica_list,
+                epoch,
+            } => self.apply_reconfig(replica_list.clone(), *epoch),
+            _ => vec![],
         };
 
         // Insert the CmdEntry into the cmds array
         self.cmds_insert(&instance, cmd_entry);
+        self.enqueue_dependents(&instance);
+        // Nothing depends on a read, so no execution ever reaches one led elsewhere. Only its
+        // leader serves it, here it's executed right away to keep compaction going
+        if !is_write
+            && instance.replica != self.replica_name
+            && self.lookup_mut(&instance).is_some()
+        {
+            self.mark_executed(&instance);
+        }
+        // Watchers expect to hear about the write now, not once some read pulls it in.
+        // Parked like a read, so a later commit retries it if deps are missing
+        if watched {
+            self.pending_reads.insert(instance.clone());
+            self.ready_queue.push_back(instance.clone());
+        }
 
-        if is_set_req {
+        if is_write {
             let mut out_msgs = self.handle_pending_reads(&instance);
 
-            let mut final_msgs = vec![];
+            let mut final_msgs = reconfig;
             final_msgs.append(&mut out_msgs);
             return final_msgs;
         }
 
-        vec![]
+        reconfig
     }
+    #[cfg_attr(
+        feature = "telemetry",
+        tracing::instrument(skip_all, fields(instance = %msg.instance, replica = %self.replica_name))
+    )]
     pub fn accept_handler(&mut self, msg: AcceptMsg) -> Vec<EMsg> {
         let AcceptMsg {
             cmd,
             seq,
             deps,
             instance,
+            ballot,
+            trace_ctx: _trace_ctx,
         } = msg;
+        #[cfg(feature = "telemetry")]
+        crate::telemetry::set_parent(_trace_ctx);
+
+        if let Some(rejected) = self.check_ballot(&instance, &ballot) {
+            return vec![rejected];
+        }
 
         #[cfg(debug_assertions)]
         info!(
@@ -373,12 +613,25 @@ impl Processor {
             deps.len()
         );
 
+        match self.lookup(&instance).map(|e| &e.status) {
+            Some(CmdStatus::Preparing) => return vec![],
+            // Already decided, just ack so a recovering replica can finish
+            Some(CmdStatus::Committed | CmdStatus::Executed) => {
+                return vec![EMsg::AcceptOk(AcceptOkMsg {
+                    instance,
+                    from_replica: self.replica_name.clone(),
+                })];
+            }
+            _ => {}
+        }
+
         // Create a new CmdEntry with the Accepted status
         let cmd_entry = CmdEntry {
             cmd: cmd.clone(),
             seq,
             deps: deps.clone(),
             status: CmdStatus::Accepted,
+            commit_ts_ms: 0,
         };
 
         // Create or update the CmdEntry with the Accepted status
@@ -387,16 +640,18 @@ impl Processor {
         // Prepare and send AcceptOk message
         let accept_ok_msg = EMsg::AcceptOk(AcceptOkMsg {
             instance,
-            #[cfg(debug_assertions)]
             from_replica: self.replica_name.clone(),
         });
 
         vec![accept_ok_msg]
     }
+    #[cfg_attr(
+        feature = "telemetry",
+        tracing::instrument(skip_all, fields(instance = %msg.instance, replica = %self.replica_name))
+    )]
     pub fn accept_ok_handler(&mut self, msg: AcceptOkMsg) -> Vec<EMsg> {
         let AcceptOkMsg {
             instance,
-            #[cfg(debug_assertions)]
             from_replica,
         } = msg;
         let Instance {
@@ -409,28 +664,44 @@ impl Processor {
             self.replica_name, instance, from_replica
         );
 
-        // should we check if this replica is same as replica name just to ensure that acceptok comes to leader only?
+        // Not my instance, so it's one I'm recovering
         if replica != self.replica_name {
-            return vec![];
+            return self.recovery_accept_ok(instance, from_replica);
         }
 
         let majority = self.get_majority();
 
+        // Late reply for an instance already executed and compacted
+        let Some(index) = inst_num.checked_sub(self.watermark_of(&replica)) else {
+            return vec![];
+        };
+
         // Ensure the command exists in the log
-        let cmd_entry_mut = self
-            .cmds
-            .get_mut(&replica)
-            .unwrap()
-            .get_mut(inst_num)
-            .unwrap()
-            .as_mut()
-            .expect("Command not found in log");
+        let cmd_entry_mut = if self.config.sparse_log {
+            self.cmds_flat.get_mut(&instance)
+        } else {
+            self.cmds
+                .get_mut(&replica)
+                .unwrap()
+                .get_mut(index)
+                .unwrap()
+                .as_mut()
+        }
+        .expect("Command not found in log");
 
         // Check if already committed
         if matches!(cmd_entry_mut.status, CmdStatus::Committed) {
             return vec![]; // Ignore the message
         }
 
+        if !self.acc_received_from[inst_num].insert(from_replica.clone()) {
+            warn!(
+                "{}: Duplicate AcceptOk for {} from {}, ignoring",
+                self.replica_name, instance, from_replica
+            );
+            return vec![];
+        }
+
         // Increment the counter for AcceptOk messages
         self.acc_quorum_ctr[inst_num] += 1; // reused quorum_ctr
 
@@ -440,78 +711,349 @@ impl Processor {
         if ctr == majority {
             // Commit phase
             cmd_entry_mut.status = CmdStatus::Committed;
+            cmd_entry_mut.commit_ts_ms = now_us() / 1000;
+            self.slow_path_commits += 1;
+            self.received_from[inst_num] = HashSet::new();
+            self.acc_received_from[inst_num] = HashSet::new();
 
             let commit_msg = EMsg::Commit(CommitMsg {
                 cmd: cmd_entry_mut.cmd.clone(),
                 seq: cmd_entry_mut.seq,
                 deps: cmd_entry_mut.deps.clone(),
                 instance: instance.clone(),
+                commit_ts_ms: cmd_entry_mut.commit_ts_ms,
+                trace_ctx: outgoing_trace_ctx(),
             });
 
-            match &cmd_entry_mut.cmd {
-                Command::Set { .. } => {
-                    let CmdMetadata { msg_id, client_id } = &self.app_meta[inst_num];
-
-                    let client_response = EMsg::ClientResponse(ClientResponse {
-                        msg_id: msg_id.clone(),
-                        client_id: client_id.clone(),
-                        cmd_result: CommandResult::Set {
-                            key: cmd_entry_mut.cmd.key().clone(),
-                            status: true,
-                        },
-                    });
-                    #[cfg(debug_assertions)]
-                    info!(
-                        "{}: Sending Client Response for {}",
-                        self.replica_name, instance
-                    );
-
-                    let mut out_msgs = self.handle_pending_reads(&instance);
-
-                    let mut final_msgs = vec![commit_msg, client_response];
-                    final_msgs.append(&mut out_msgs);
-                    return final_msgs;
-                    // return vec![
-                    //     commit_msg,
-                    //     client_response, // send to correct client
-                    // ];
-                }
-                Command::Get { .. } => {
-                    // TODO: Handle if reads also get fast path
-                    // return vec![commit_msg];
-                    let mut out_msgs = vec![commit_msg];
-                    if self.deps_all_ready(&instance) {
-                        let mut exec_out = self.execute_cmd(&instance);
-                        out_msgs.append(&mut exec_out);
-                    } else {
-                        self.pending_reads.insert(instance);
-                    }
-                    return out_msgs;
-                }
+            let mut out_msgs = vec![commit_msg];
+            out_msgs.append(&mut self.leader_commit_output(&instance));
+            return out_msgs;
+        }
+        vec![]
+    }
+
+    /// A Ping from myself is the local heartbeat tick, forward it to peers and sweep expired
+    /// values. A Ping from a peer is answered with a Pong
+    pub fn ping_handler(&mut self, from: String, timestamp_us: u64) -> Vec<EMsg> {
+        if from == self.replica_name {
+            let _swept = self.sweep_expired();
+            let _stale_reads = self.gc_pending_reads();
+            let _compacted = if self.compactable() >= self.config.gc_threshold {
+                self.try_compact_log()
+            } else {
+                0
+            };
+            #[cfg(debug_assertions)]
+            if _swept > 0 || _stale_reads > 0 || _compacted > 0 {
+                info!(
+                    "{}: Swept {} expired values, {} executed pending reads, compacted {} instances",
+                    self.replica_name, _swept, _stale_reads, _compacted
+                );
             }
-            // return vec![commit_msg];
+            let mut out = vec![EMsg::Ping { from, timestamp_us }];
+            out.append(&mut self.recover_blocking_deps(Instant::now()));
+            return out;
+        }
+        vec![EMsg::Pong {
+            from: self.replica_name.clone(),
+            echo_timestamp_us: timestamp_us,
+        }]
+    }
+
+    pub fn pong_handler(&mut self, from: String, echo_timestamp_us: u64) -> Vec<EMsg> {
+        let _rtt_us = now_us().saturating_sub(echo_timestamp_us);
+        #[cfg(debug_assertions)]
+        info!(
+            "{}: Pong received from {}, rtt: {}us",
+            self.replica_name, from, _rtt_us
+        );
+
+        let previous = self.last_seen.lock().unwrap().insert(from, Instant::now());
+
+        // Back from a partition, ask it for whatever we missed meanwhile
+        if previous.is_some_and(|seen| seen.elapsed() >= PEER_TIMEOUT) {
+            return self
+                .executed_watermark()
+                .into_iter()
+                .map(|(replica, instance_num)| EMsg::RequestLog {
+                    requester: self.replica_name.clone(),
+                    from_instance: Instance {
+                        replica,
+                        instance_num,
+                    },
+                })
+                .collect();
         }
         vec![]
     }
 
+    /// Replies with up to LOG_SYNC_BATCH committed entries of `from_instance.replica`'s log,
+    /// starting at `from_instance`. Compacted entries can't be shipped, a StoreSnapshot goes
+    /// ahead of the entries in their place
+    pub fn request_log_handler(
+        &mut self,
+        _requester: String,
+        from_instance: Instance,
+    ) -> Vec<EMsg> {
+        let Instance {
+            replica,
+            instance_num,
+        } = from_instance;
+        if !self.cmds.contains_key(&replica) {
+            return vec![];
+        }
+        let mut out = vec![];
+        if instance_num < self.watermark_of(&replica) {
+            out.push(EMsg::StoreSnapshot(self.store_snapshot()));
+        }
+        let start = instance_num.max(self.watermark_of(&replica));
+
+        let entries: Vec<(Instance, SerializableCmdEntry)> = self
+            .replica_entries(&replica, start)
+            .into_iter()
+            .filter(|(_, e)| matches!(e.status, CmdStatus::Committed | CmdStatus::Executed))
+            .map(|(i, e)| {
+                let instance = Instance {
+                    replica: replica.clone(),
+                    instance_num: i,
+                };
+                let entry = SerializableCmdEntry {
+                    cmd: e.cmd.clone(),
+                    seq: e.seq,
+                    deps: e.deps.iter().cloned().collect(),
+                    status: PrepareStatus::Committed,
+                    commit_ts_ms: e.commit_ts_ms,
+                };
+                (instance, entry)
+            })
+            .take(LOG_SYNC_BATCH)
+            .collect();
+
+        #[cfg(debug_assertions)]
+        info!(
+            "{}: Sending {} entries of {} to {}",
+            self.replica_name,
+            entries.len(),
+            replica,
+            _requester
+        );
+
+        out.push(EMsg::LogResponse { entries });
+        out
+    }
+
+    /// Adopts a peer's store in place of the instances it compacted that I'm missing, see
+    /// StoreSnapshotMsg. Ignored unless it covers every instance I executed, my store would
+    /// lose their writes otherwise
+    pub fn store_snapshot_handler(&mut self, snapshot: StoreSnapshotMsg) -> Vec<EMsg> {
+        let frontier: HashMap<String, usize> = snapshot.watermark.into_iter().collect();
+        let frontier_of = |replica: &str| frontier.get(replica).copied().unwrap_or(0);
+        if !frontier.iter().any(|(r, &w)| w > self.watermark_of(r)) {
+            return vec![]; // Nothing compacted that I lack, LogResponse entries will do
+        }
+
+        let theirs: HashSet<&Instance> = snapshot.executed.iter().map(|(i, _)| i).collect();
+        let mine_compacted = self.watermark.iter().flat_map(|(replica, &w)| {
+            (frontier_of(replica)..w).map(|instance_num| Instance {
+                replica: replica.clone(),
+                instance_num,
+            })
+        });
+        let mine_executed = self
+            .log_entries()
+            .filter(|(_, _, e)| matches!(e.status, CmdStatus::Executed))
+            .map(|(replica, instance_num, _)| Instance {
+                replica: replica.clone(),
+                instance_num,
+            });
+        let uncovered = mine_compacted.chain(mine_executed).find(|instance| {
+            instance.instance_num >= frontier_of(&instance.replica) && !theirs.contains(instance)
+        });
+        if let Some(instance) = uncovered {
+            warn!(
+                "{}: Ignoring a store snapshot missing {}, which I executed",
+                self.replica_name, instance
+            );
+            return vec![];
+        }
+
+        for (replica, &w) in &frontier {
+            self.compact_below(replica, w);
+        }
+        for (instance, entry) in snapshot.executed {
+            self.cmds_insert(
+                &instance,
+                CmdEntry {
+                    cmd: entry.cmd,
+                    seq: entry.seq,
+                    deps: entry.deps.into_iter().collect(),
+                    status: CmdStatus::Executed,
+                    commit_ts_ms: entry.commit_ts_ms,
+                },
+            );
+        }
+        self.data = snapshot
+            .data
+            .into_iter()
+            .map(|(key, val, expires_at_ms)| (key, StoredValue { val, expires_at_ms }))
+            .collect();
+        self.versions = snapshot.versions.into_iter().collect();
+        self.exec_clock_ms = snapshot.exec_clock_ms;
+
+        info!(
+            "{}: Installed a store snapshot up to {:?}",
+            self.replica_name, frontier
+        );
+        // Reads parked on what the snapshot covered can go now
+        let covered: Vec<Instance> = self
+            .pending_reads
+            .iter()
+            .filter(|inst| {
+                matches!(
+                    self.lookup(inst).map(|e| &e.status),
+                    Some(CmdStatus::Executed)
+                )
+            })
+            .cloned()
+            .collect();
+        for inst in &covered {
+            self.pending_reads.remove(inst);
+        }
+        self.ready_queue.extend(self.pending_reads.iter().cloned());
+        self.try_execute_ready()
+    }
+
+    /// Commits every received entry as if its Commit had arrived
+    pub fn log_response_handler(
+        &mut self,
+        entries: Vec<(Instance, SerializableCmdEntry)>,
+    ) -> Vec<EMsg> {
+        let mut out_msgs = vec![];
+        for (instance, entry) in entries {
+            out_msgs.append(&mut self.commit_handler(CommitMsg {
+                cmd: entry.cmd,
+                seq: entry.seq,
+                deps: entry.deps.into_iter().collect(),
+                instance,
+                commit_ts_ms: entry.commit_ts_ms,
+                trace_ctx: outgoing_trace_ctx(),
+            }));
+        }
+        out_msgs
+    }
+
+    pub fn get_metrics_handler(&self) -> Vec<EMsg> {
+        vec![EMsg::MetricsResponse(MetricsMsg {
+            fast_path_commits: self.fast_path_commits,
+            slow_path_commits: self.slow_path_commits,
+            conflict_detections: self.conflict_detections,
+            fast_reads_count: self.fast_reads_count,
+            dropped_responses: self.dropped_responses.load(Ordering::Relaxed),
+        })]
+    }
+
+    /// Counters and gauges of my reports to the metrics actor
+    pub fn metric_values(&self) -> (HashMap<String, u64>, HashMap<String, f64>) {
+        let counters = [
+            ("client_requests", self.client_requests),
+            ("client_errors", self.client_errors),
+            ("fast_path_commits", self.fast_path_commits),
+            ("slow_path_commits", self.slow_path_commits),
+            ("conflict_detections", self.conflict_detections),
+            ("fast_reads", self.fast_reads_count),
+            ("executed", self.executed_count),
+            (
+                "dropped_responses",
+                self.dropped_responses.load(Ordering::Relaxed),
+            ),
+        ];
+        let gauges = [
+            ("pending_instances", self.pending_instances.len()),
+            ("queued_requests", self.queued_requests.len()),
+            ("pending_reads", self.pending_reads.len()),
+        ];
+        (
+            counters
+                .into_iter()
+                .map(|(name, val)| (name.to_string(), val))
+                .collect(),
+            gauges
+                .into_iter()
+                .map(|(name, val)| (name.to_string(), val as f64))
+                .collect(),
+        )
+    }
+
+    /// Testing only: replies with the live values of my store, see GetStateRequest
+    #[cfg(any(test, feature = "testing"))]
+    pub fn get_state_handler(&self, msg: GetStateRequest) -> Vec<EMsg> {
+        let stored = match &msg.key {
+            Some(key) => self.data.range(key..=key),
+            None => self.data.range::<Variable, _>(..),
+        };
+        let entries = stored
+            .filter(|(_, v)| v.live_at(self.exec_clock_ms))
+            .map(|(key, v)| (key.clone(), v.val.clone()))
+            .collect();
+        vec![EMsg::GetStateResponse(GetStateResponse { entries })]
+    }
+
+    /// Logs the full cmds log and replies with a JSON summary of it
     pub fn dump_state_handler(&self) -> Vec<EMsg> {
         info!("{}: Dumping State", self.replica_name);
-        for (replica, cmds_vec) in &self.cmds {
-            info!("Replica: {}", replica);
-            for (i, cmd_entry_opt) in cmds_vec.iter().enumerate() {
-                match cmd_entry_opt {
+        let mut cmds = serde_json::Map::new();
+        for replica in self.cmds.keys() {
+            let watermark = self.watermark_of(replica);
+            info!("Replica: {}, compacted below {}", replica, watermark);
+            let mut entries = vec![];
+            for i in watermark..watermark + self.log_len(replica) {
+                let instance = Instance {
+                    replica: replica.clone(),
+                    instance_num: i,
+                };
+                match self.lookup(&instance) {
                     Some(cmd_entry) => {
+                        let deps: Vec<String> =
+                            cmd_entry.deps.iter().map(|d| d.to_string()).collect();
                         info!(
-                            "  Instance {}: Cmd: {:?}, Seq: {}, Deps: {:?}, Status: {:?}",
-                            i, cmd_entry.cmd, cmd_entry.seq, cmd_entry.deps, cmd_entry.status
+                            "  Instance {}: Cmd: {}, Seq: {}, Deps: [{}], Status: {}",
+                            i,
+                            cmd_entry.cmd,
+                            cmd_entry.seq,
+                            deps.join(", "),
+                            cmd_entry.status
                         );
+                        entries.push(json!({
+                            "instance": i,
+                            "cmd": cmd_entry.cmd.to_string(),
+                            "seq": cmd_entry.seq,
+                            "deps": deps,
+                            "status": format!("{:?}", cmd_entry.status),
+                        }));
                     }
                     None => {
                         info!("  Instance {}: Empty", i);
+                        entries.push(serde_json::Value::Null);
                     }
                 }
             }
+            cmds.insert(replica.clone(), serde_json::Value::Array(entries));
         }
-        vec![]
+
+        let data: serde_json::Map<String, serde_json::Value> = self
+            .data
+            .iter()
+            .map(|(k, v)| (k.to_string(), json!(v.val.to_string())))
+            .collect();
+
+        let state = json!({
+            "replica": self.replica_name,
+            "wire_version": WIRE_VERSION,
+            "instance_num": self.instance_num,
+            "cmds": cmds,
+            "data": data,
+            "data_digest": format!("{:016x}", self.data_digest()),
+        });
+        vec![EMsg::DumpStateResponse(state.to_string())]
     }
 }
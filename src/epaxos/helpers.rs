@@ -1,32 +1,59 @@
-use crate::common::{ClientResponse, Command, CommandResult, EMsg, Instance};
-use crate::epaxos::{CmdEntry, CmdStatus, Processor};
+#[cfg(any(test, feature = "bench"))]
+use crate::common::CompactInstance;
+use crate::common::{
+    ClientResponse, Command, CommandResult, EMsg, ErrorCode, FNV_OFFSET, Instance, PreAcceptMsg,
+    PrepareStatus, SerializableCmdEntry, StoreSnapshotMsg, Value, Variable, WatchNotifyMsg, fnv1a,
+    now_us, outgoing_trace_ctx,
+};
+use crate::epaxos::{
+    CmdEntry, CmdMetadata, CmdStatus, DEDUP_WINDOW, DepGraphStats, ExecutionPolicy, Processor,
+    SeenRequest, StoredValue, TTL_SWEEP_GRACE,
+};
+use crate::store::{self, Store};
 use core::panic;
-use std::collections::{HashMap, HashSet, VecDeque};
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::sync::LazyLock;
 
 #[cfg(debug_assertions)]
 use tracing::info;
 // use tracing::{error};
 
+#[cfg(any(test, feature = "bench"))]
+impl Instance {
+    pub(super) fn to_compact(&self, proc: &Processor) -> CompactInstance {
+        CompactInstance {
+            replica_idx: proc.replica_index(&self.replica),
+            instance_num: self.instance_num as u64,
+        }
+    }
+}
+
 impl Processor {
-    /// Calculate the majority size based on the number of replicas. Excludes self.
+    /// Majority size for the current replica list, from the quorum policy. Excludes self.
     /// Simple invariants:
     /// Must be >= 1
     /// majority + 1 should have intersection with another majority. Hence 2*(majority+1)
     pub fn get_majority(&self) -> u32 {
-        let res = (self.replica_list.len() / 2) as u32;
-        if res < 1 { 1 } else { res }
+        self.quorum_policy.majority(self.replica_list.len())
     }
 
-    /// Calculate the fast quorum size based on the number of replicas. Excludes self.
+    /// Fast quorum size for the current replica list, from the quorum policy. Excludes self.
     /// Simple invariants:
     /// Must be >= majority
     /// Must be >= 1
     pub fn fast_quorum(&self) -> u32 {
-        let res = (self.replica_list.len() as i32) - 2;
-        if res < 1 { 1 } else { res as u32 }
+        self.quorum_policy.fast_quorum(self.replica_list.len())
     }
     // for given new size and replica, increase the cmds[replica] vector to that size with empty values in extra slots
+    // new_size counts instances from 0, including the compacted ones below the watermark
     pub fn resize_cmds(&mut self, new_size: usize, replica: &String) {
+        if self.config.sparse_log {
+            // Nothing to allocate, only the end of the replica's log moves
+            let end = self.cmds_flat_end.entry(replica.clone()).or_default();
+            *end = (*end).max(new_size);
+            return;
+        }
+        let new_size = new_size.saturating_sub(self.watermark_of(replica));
         let cmds_for_replica = self.cmds.get_mut(replica).expect("replica not found");
         let current_size = cmds_for_replica.len();
         if new_size > current_size {
@@ -35,30 +62,38 @@ impl Processor {
     }
 
     /// Insert a CmdEntry into cmds at the position specified by instance
-    /// Overwrites if the position is empty or has the same command
+    /// Overwrites if the position is empty or has the same command, or if either side is a
+    /// Noop (recovery may fill a slot with a Noop, or learn the real command of a placeholder)
     /// Panics if the position is already occupied with a different command
     pub fn cmds_insert(&mut self, instance: &Instance, cmd_entry: CmdEntry) {
-        let index = instance.instance_num;
-        let required_size = index + 1;
+        // Already executed and compacted, e.g. a duplicate Commit
+        let Some(index) = self.slot_index(instance) else {
+            return;
+        };
+        let required_size = instance.instance_num + 1;
 
         self.resize_cmds(required_size, &instance.replica);
 
-        let cmds_vec = self
-            .cmds
-            .get_mut(&instance.replica)
-            .expect("Internal error: Replica vector should exist after initialization.");
-
-        let position = cmds_vec
-            .get_mut(index)
-            .expect("Index should be valid after resize_cmds was called.");
+        let existing = if self.config.sparse_log {
+            self.cmds_flat.get_mut(instance)
+        } else {
+            self.cmds
+                .get_mut(&instance.replica)
+                .expect("Internal error: Replica vector should exist after initialization.")
+                .get_mut(index)
+                .expect("Index should be valid after resize_cmds was called.")
+                .as_mut()
+        };
 
-        match position {
-            None => {
-                *position = Some(cmd_entry);
-            }
+        let new_deps = cmd_entry.deps.clone();
+        let old_deps = match existing {
+            None => BTreeSet::new(),
             Some(existing) => {
                 // check if the existing command is same as cmd_entry
-                if existing.cmd == cmd_entry.cmd {
+                if existing.cmd == cmd_entry.cmd
+                    || existing.cmd == Command::Noop
+                    || cmd_entry.cmd == Command::Noop
+                {
                     // replace existing entry in case other fields (seq, deps, status) have changed
                     // do checks, that the seq and deps of the new entry are >= existing
                     // if cfg!(debug_assertions) {
@@ -75,56 +110,98 @@ impl Processor {
                     //         );
                     //     }
                     // }
-                    *position = Some(cmd_entry);
+                    std::mem::take(&mut existing.deps)
                 } else if cfg!(debug_assertions) {
                     panic!(
                         "{}: {} slot - occupied with different cmd: existing: {:?}, new: {:?}",
                         self.replica_name, instance, existing.cmd, cmd_entry.cmd
                     );
+                } else {
+                    return;
+                }
+            }
+        };
+        if self.config.sparse_log {
+            self.cmds_flat.insert(instance.clone(), cmd_entry);
+        } else {
+            self.cmds.get_mut(&instance.replica).unwrap()[index] = Some(cmd_entry);
+        }
+
+        for dep in old_deps.difference(&new_deps) {
+            if let Some(dependents) = self.rdeps.get_mut(dep) {
+                dependents.remove(instance);
+            }
+        }
+        self.rdeps_add(instance, &new_deps);
+    }
+
+    /// Records `instance` as a dependent of each of `deps`
+    pub fn rdeps_add<'a>(
+        &mut self,
+        instance: &Instance,
+        deps: impl IntoIterator<Item = &'a Instance>,
+    ) {
+        for dep in deps {
+            self.rdeps
+                .entry(dep.clone())
+                .or_default()
+                .insert(instance.clone());
+        }
+    }
+
+    /// Forgets `instance` in the reverse dependency index, both as a dependent of its deps
+    /// and as a dep of others. For instances dropped from the log
+    pub fn rdeps_remove(&mut self, instance: &Instance) {
+        self.rdeps.remove(instance);
+        let deps = match self.lookup(instance) {
+            Some(entry) => entry.deps.clone(),
+            None => return,
+        };
+        for dep in &deps {
+            if let Some(dependents) = self.rdeps.get_mut(dep) {
+                dependents.remove(instance);
+                if dependents.is_empty() {
+                    self.rdeps.remove(dep);
                 }
             }
         }
     }
 
     /// Used to get deps of a given cmd entry
-    /// Iterates through all CmdInstance present in cmds for all replicas, and if key is same,
-    /// add it to cmd_entry deps
-    pub fn get_interfs(&self, cmd: &Command) -> (HashSet<Instance>, u64) {
-        let mut deps = HashSet::new();
+    /// Iterates through all CmdInstance present in cmds for all replicas, and if the
+    /// conflict checker says they interfere, add it to cmd_entry deps
+    pub fn get_interfs(&self, cmd: &Command) -> (BTreeSet<Instance>, u64) {
+        let mut deps = BTreeSet::new();
         let mut max_seq = 0;
 
-        let is_read = matches!(cmd, Command::Get { .. });
+        let is_read = !cmd.is_write();
 
-        for (r, cmds_vec) in &self.cmds {
-            for (i, cmd_opt) in cmds_vec.iter().enumerate() {
-                if let Some(c) = cmd_opt {
-                    if matches!(c.cmd, Command::Get { .. }) {
-                        continue;
-                    }
+        for (r, instance_num, c) in self.log_entries() {
+            if !c.cmd.is_write() {
+                continue;
+            }
 
-                    // Skip commands that are already executed
-                    if matches!(c.status, CmdStatus::Executed) {
-                        continue;
-                    }
+            // Skip commands that are already executed
+            if matches!(c.status, CmdStatus::Executed) {
+                continue;
+            }
 
-                    let entry_is_read = matches!(c.cmd, Command::Get { .. });
+            let entry_is_read = !c.cmd.is_write();
 
-                    // RULE:
-                    // - If incoming command is READ, ignore READ dependencies.
-                    // - If incoming command is WRITE, include both READ and WRITE deps.
-                    if is_read && entry_is_read {
-                        continue; // READ should not depend on READ
-                    }
+            // RULE:
+            // - If incoming command is READ, ignore READ dependencies.
+            // - If incoming command is WRITE, include both READ and WRITE deps.
+            if is_read && entry_is_read {
+                continue; // READ should not depend on READ
+            }
 
-                    if c.cmd.conflicts_with(cmd) {
-                        deps.insert(Instance {
-                            replica: r.clone(),
-                            instance_num: i,
-                        });
-                        // Update max_seq with the maximum seq value from the dependency
-                        max_seq = max_seq.max(c.seq);
-                    }
-                }
+            if self.conflict_checker.conflicts(&c.cmd, cmd) {
+                deps.insert(Instance {
+                    replica: r.clone(),
+                    instance_num,
+                });
+                // Update max_seq with the maximum seq value from the dependency
+                max_seq = max_seq.max(c.seq);
             }
         }
 
@@ -132,7 +209,14 @@ impl Processor {
         (deps, max_seq)
     }
 
-    fn build_dep_graph(&self, root: &Instance) -> HashMap<Instance, Vec<Instance>> {
+    /// Seq of a new instance I lead: a hybrid clock timestamp, so at least `min_seq` from
+    /// get_interfs and above every seq I handed out or PreAccepted before
+    pub fn next_seq(&mut self, min_seq: u64) -> u64 {
+        self.hlc.update(min_seq.saturating_sub(1));
+        self.hlc.now()
+    }
+
+    pub fn build_dep_graph(&self, root: &Instance) -> HashMap<Instance, Vec<Instance>> {
         let mut graph = HashMap::<Instance, Vec<Instance>>::new();
         let mut stack = vec![root.clone()];
         let mut visited = HashSet::<Instance>::new();
@@ -143,13 +227,7 @@ impl Processor {
             }
             visited.insert(inst.clone());
 
-            let entry_opt = self
-                .cmds
-                .get(&inst.replica)
-                .and_then(|v| v.get(inst.instance_num))
-                .and_then(|opt| opt.as_ref());
-
-            if let Some(entry) = entry_opt {
+            if let Some(entry) = self.lookup(&inst) {
                 let mut deps_vec = Vec::new();
                 for dep in &entry.deps {
                     deps_vec.push(dep.clone());
@@ -161,7 +239,7 @@ impl Processor {
         graph
     }
 
-    fn tarjan_scc(&self, graph: &HashMap<Instance, Vec<Instance>>) -> Vec<Vec<Instance>> {
+    pub fn tarjan_scc(&self, graph: &HashMap<Instance, Vec<Instance>>) -> Vec<Vec<Instance>> {
         // Standard Tarjan SCC implementation
         // I give the full working version below:
 
@@ -246,6 +324,55 @@ impl Processor {
         result
     }
 
+    /// Size, SCCs and depth of the dependency graph executing `root` would walk
+    #[allow(dead_code)]
+    pub fn dep_graph_stats(&self, root: &Instance) -> DepGraphStats {
+        let graph = self.build_dep_graph(root);
+        let sccs = self.tarjan_scc(&graph);
+        Self::graph_stats(root, &graph, &sccs)
+    }
+
+    fn graph_stats(
+        root: &Instance,
+        graph: &HashMap<Instance, Vec<Instance>>,
+        sccs: &[Vec<Instance>],
+    ) -> DepGraphStats {
+        // Deps outside the log (compacted) aren't nodes, so edges to them don't count
+        let edge_count = graph
+            .values()
+            .flatten()
+            .filter(|dep| graph.contains_key(*dep))
+            .count();
+
+        // BFS from the root
+        let mut max_depth = 0;
+        let mut depth = HashMap::from([(root, 0)]);
+        let mut queue = VecDeque::from([root]);
+        while let Some(inst) = queue.pop_front() {
+            let d = depth[inst];
+            max_depth = max_depth.max(d);
+            for dep in graph.get(inst).into_iter().flatten() {
+                if graph.contains_key(dep) && !depth.contains_key(dep) {
+                    depth.insert(dep, d + 1);
+                    queue.push_back(dep);
+                }
+            }
+        }
+
+        // tarjan_scc also gives each dep outside the log its own SCC
+        let sccs: Vec<_> = sccs
+            .iter()
+            .filter(|scc| scc.iter().any(|inst| graph.contains_key(inst)))
+            .collect();
+        DepGraphStats {
+            node_count: graph.len(),
+            edge_count,
+            scc_count: sccs.len(),
+            max_scc_size: sccs.iter().map(|scc| scc.len()).max().unwrap_or(0),
+            max_depth,
+        }
+    }
+
     fn topo_sort_scc(
         &self,
         // sccs: &Vec<Vec<Instance>>,
@@ -306,22 +433,339 @@ impl Processor {
         order.into_iter().map(|i| sccs[i].clone()).collect()
     }
 
-    fn lookup(&self, instance: &Instance) -> Option<&CmdEntry> {
+    /// Entry of `instance`. Instances below the watermark were compacted away, they get a
+    /// shared Executed placeholder
+    pub fn lookup(&self, instance: &Instance) -> Option<&CmdEntry> {
+        let Some(index) = self.slot_index(instance) else {
+            return Some(&COMPACTED);
+        };
+        if self.config.sparse_log {
+            return self.cmds_flat.get(instance);
+        }
         self.cmds
             .get(&instance.replica) // Get the vector of commands for the given replica
-            .and_then(|cmds| cmds.get(instance.instance_num)) // Get the command entry at the given instance number
+            .and_then(|cmds| cmds.get(index)) // Get the command entry at the given instance number
             .and_then(|opt| opt.as_ref()) // Unwrap the Option<CmdEntry> to get a reference to CmdEntry
     }
 
-    pub fn mark_executed(&mut self, instance: &Instance) {
-        // Locate the command entry in the cmds log
-        if let Some(Some(cmd_entry)) = self
-            .cmds
+    /// Mutable entry of `instance`, None if missing or compacted
+    pub fn lookup_mut(&mut self, instance: &Instance) -> Option<&mut CmdEntry> {
+        let index = self.slot_index(instance)?;
+        if self.config.sparse_log {
+            return self.cmds_flat.get_mut(instance);
+        }
+        self.cmds
             .get_mut(&instance.replica)
-            .and_then(|cmds| cmds.get_mut(instance.instance_num))
+            .and_then(|cmds| cmds.get_mut(index))
+            .and_then(|opt| opt.as_mut())
+    }
+
+    /// Every entry in the log, whichever way it's stored, as (replica, instance_num, entry).
+    /// In no particular order
+    pub fn log_entries(&self) -> impl Iterator<Item = (&String, usize, &CmdEntry)> {
+        let dense = self.cmds.iter().flat_map(|(replica, cmds_vec)| {
+            let watermark = self.watermark_of(replica);
+            cmds_vec
+                .iter()
+                .enumerate()
+                .filter_map(move |(i, e)| Some((replica, watermark + i, e.as_ref()?)))
+        });
+        // One side is always empty
+        let sparse = self
+            .cmds_flat
+            .iter()
+            .map(|(inst, e)| (&inst.replica, inst.instance_num, e));
+        dense.chain(sparse)
+    }
+
+    /// Entries of `replica` from `from` on, by instance number
+    pub fn replica_entries(&self, replica: &str, from: usize) -> Vec<(usize, &CmdEntry)> {
+        let mut entries: Vec<_> = self
+            .log_entries()
+            .filter(|(r, i, _)| *r == replica && *i >= from)
+            .map(|(_, i, e)| (i, e))
+            .collect();
+        entries.sort_unstable_by_key(|(i, _)| *i);
+        entries
+    }
+
+    /// Slots of `replica`'s log above the watermark, including empty ones
+    pub fn log_len(&self, replica: &str) -> usize {
+        if self.config.sparse_log {
+            let end = self.cmds_flat_end.get(replica).copied().unwrap_or(0);
+            end.saturating_sub(self.watermark_of(replica))
+        } else {
+            self.cmds.get(replica).map_or(0, Vec::len)
+        }
+    }
+
+    /// Rough heap size of the log: the slots of the cmds vectors or the cmds_flat table,
+    /// plus the deps of every entry. Commands' own allocations aren't counted
+    #[allow(dead_code)]
+    pub fn log_memory_bytes(&self) -> usize {
+        let slots = if self.config.sparse_log {
+            // hashbrown keeps a control byte per bucket
+            self.cmds_flat.capacity() * (std::mem::size_of::<(Instance, CmdEntry)>() + 1)
+        } else {
+            self.cmds
+                .values()
+                .map(|cmds_vec| cmds_vec.capacity() * std::mem::size_of::<Option<CmdEntry>>())
+                .sum()
+        };
+        let deps: usize = self
+            .log_entries()
+            .map(|(_, _, e)| e.deps.len() * std::mem::size_of::<Instance>())
+            .sum();
+        slots + deps
+    }
+
+    /// Number of leading instances of `replica` dropped by try_compact_log
+    pub fn watermark_of(&self, replica: &str) -> usize {
+        self.watermark.get(replica).copied().unwrap_or(0)
+    }
+
+    /// Position of `name` in the replica list, the replica index of its CompactInstances
+    #[cfg(any(test, feature = "bench"))]
+    pub fn replica_index(&self, name: &str) -> u32 {
+        self.replica_list
+            .iter()
+            .position(|r| r == name)
+            .unwrap_or_else(|| panic!("Unknown replica {}", name)) as u32
+    }
+
+    /// Position of `instance` in its replica's cmds vector, None if compacted
+    pub fn slot_index(&self, instance: &Instance) -> Option<usize> {
+        instance
+            .instance_num
+            .checked_sub(self.watermark_of(&instance.replica))
+    }
+
+    /// For each replica, the first instance that isn't executed yet (or is missing).
+    /// Everything below it has been applied locally
+    pub fn executed_watermark(&self) -> HashMap<String, usize> {
+        if self.config.sparse_log {
+            return self
+                .cmds
+                .keys()
+                .map(|replica| {
+                    let executed = (self.watermark_of(replica)..)
+                        .take_while(|&instance_num| {
+                            let instance = Instance {
+                                replica: replica.clone(),
+                                instance_num,
+                            };
+                            self.cmds_flat
+                                .get(&instance)
+                                .is_some_and(|e| matches!(e.status, CmdStatus::Executed))
+                        })
+                        .count();
+                    (replica.clone(), self.watermark_of(replica) + executed)
+                })
+                .collect();
+        }
+        self.cmds
+            .iter()
+            .map(|(replica, cmds_vec)| {
+                let executed = cmds_vec
+                    .iter()
+                    .take_while(|e| {
+                        matches!(
+                            e,
+                            Some(CmdEntry {
+                                status: CmdStatus::Executed,
+                                ..
+                            })
+                        )
+                    })
+                    .count();
+                (replica.clone(), self.watermark_of(replica) + executed)
+            })
+            .collect()
+    }
+
+    /// Drops pending_reads entries that were executed (or compacted) without being removed.
+    /// Returns how many were dropped
+    pub fn gc_pending_reads(&mut self) -> usize {
+        let before = self.pending_reads.len();
+        let executed: Vec<Instance> = self
+            .pending_reads
+            .iter()
+            .filter(|inst| match self.slot_index(inst) {
+                Some(_) => self
+                    .lookup(inst)
+                    .is_some_and(|e| matches!(e.status, CmdStatus::Executed)),
+                None => true, // Below the watermark, so executed
+            })
+            .cloned()
+            .collect();
+        for inst in &executed {
+            self.pending_reads.remove(inst);
+        }
+        before - self.pending_reads.len()
+    }
+
+    /// Slides the pipeline window past my instances that committed, then starts queued
+    /// client requests while it has room. Instances leave in order, so the window spans
+    /// from my oldest uncommitted instance. With batch_pre_accepts, the requests started
+    /// together go out as one PreAcceptBatch
+    pub fn advance_pipeline(&mut self) -> Vec<EMsg> {
+        while let Some(&instance_num) = self.pending_instances.front() {
+            let instance = Instance {
+                replica: self.replica_name.clone(),
+                instance_num,
+            };
+            let committed = self
+                .lookup(&instance)
+                .is_some_and(|e| matches!(e.status, CmdStatus::Committed | CmdStatus::Executed));
+            if !committed {
+                break;
+            }
+            self.pending_instances.pop_front();
+        }
+
+        // Batching refills the window only once it drained, so its requests start together
+        if self.config.batch_pre_accepts && !self.pending_instances.is_empty() {
+            return vec![];
+        }
+        let mut out = vec![];
+        let depth = self.config.pipeline_depth.unwrap_or(usize::MAX);
+        while self.pending_instances.len() < depth
+            && let Some(req) = self.queued_requests.pop_front()
         {
+            out.append(&mut self.lead_request(req.cmd, req.msg_id, req.client_id, now_us()));
+        }
+        if !self.config.batch_pre_accepts {
+            return out;
+        }
+        let (pre_accepts, mut out): (Vec<EMsg>, Vec<EMsg>) = out
+            .into_iter()
+            .partition(|m| matches!(m, EMsg::PreAccept(_)));
+        match &pre_accepts[..] {
+            [] | [_] => out.extend(pre_accepts),
+            _ => {
+                let instance_nums = pre_accepts
+                    .iter()
+                    .filter_map(|m| match m {
+                        EMsg::PreAccept(msg) => Some(msg.instance.instance_num),
+                        _ => None,
+                    })
+                    .collect();
+                out.push(self.batch_pre_accept(instance_nums));
+            }
+        }
+        out
+    }
+
+    /// One PreAcceptBatch for these instances of mine, from what my log holds for them
+    pub fn batch_pre_accept(&self, instance_nums: Vec<usize>) -> EMsg {
+        let msgs = instance_nums
+            .into_iter()
+            .filter_map(|instance_num| {
+                let instance = Instance {
+                    replica: self.replica_name.clone(),
+                    instance_num,
+                };
+                let entry = self.lookup(&instance)?;
+                Some(PreAcceptMsg {
+                    cmd: entry.cmd.clone(),
+                    seq: entry.seq,
+                    deps: entry.deps.clone(),
+                    instance,
+                    trace_ctx: outgoing_trace_ctx(),
+                })
+            })
+            .collect();
+        EMsg::PreAcceptBatch(msgs)
+    }
+
+    /// How many entries try_compact_log would drop now
+    pub fn compactable(&self) -> usize {
+        self.executed_watermark()
+            .into_iter()
+            .map(|(replica, frontier)| frontier.saturating_sub(self.watermark_of(&replica)))
+            .sum()
+    }
+
+    /// Drops the executed prefix of every replica's log, moving the watermark past it.
+    /// Compaction is local: a peer recovering a compacted instance only learns it executed
+    /// here. Returns how many entries were dropped
+    pub fn try_compact_log(&mut self) -> usize {
+        let mut dropped = 0;
+        for (replica, frontier) in self.executed_watermark() {
+            dropped += self.compact_below(&replica, frontier);
+        }
+        dropped
+    }
+
+    /// My store and log watermarks, with the executed entries still in the log. Sent to a
+    /// replica asking for instances I compacted
+    pub fn store_snapshot(&self) -> StoreSnapshotMsg {
+        let mut executed: Vec<(Instance, SerializableCmdEntry)> = self
+            .log_entries()
+            .filter(|(_, _, e)| matches!(e.status, CmdStatus::Executed))
+            .map(|(replica, instance_num, e)| {
+                let instance = Instance {
+                    replica: replica.clone(),
+                    instance_num,
+                };
+                let entry = SerializableCmdEntry {
+                    cmd: e.cmd.clone(),
+                    seq: e.seq,
+                    deps: e.deps.iter().cloned().collect(),
+                    status: PrepareStatus::Committed,
+                    commit_ts_ms: e.commit_ts_ms,
+                };
+                (instance, entry)
+            })
+            .collect();
+        executed.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+        StoreSnapshotMsg {
+            watermark: self
+                .watermark
+                .iter()
+                .map(|(r, w)| (r.clone(), *w))
+                .collect(),
+            executed,
+            data: self
+                .data
+                .iter()
+                .map(|(k, v)| (k.clone(), v.val.clone(), v.expires_at_ms))
+                .collect(),
+            versions: self.versions.iter().map(|(k, v)| (k.clone(), *v)).collect(),
+            exec_clock_ms: self.exec_clock_ms,
+        }
+    }
+
+    /// Drops `replica`'s entries below `frontier` and raises its watermark there, returning
+    /// how many instances that covered. Nothing happens if the watermark is already past it
+    pub fn compact_below(&mut self, replica: &str, frontier: usize) -> usize {
+        let old = self.watermark_of(replica);
+        if frontier <= old {
+            return 0;
+        }
+        for instance_num in old..frontier {
+            let instance = Instance {
+                replica: replica.to_string(),
+                instance_num,
+            };
+            self.rdeps_remove(&instance);
+            self.highest_ballot.remove(&instance);
+            self.cmds_flat.remove(&instance);
+        }
+        if !self.config.sparse_log {
+            let log = self.cmds.entry(replica.to_string()).or_default();
+            log.drain(..(frontier - old).min(log.len()));
+        }
+        self.watermark.insert(replica.to_string(), frontier);
+        frontier - old
+    }
+
+    pub fn mark_executed(&mut self, instance: &Instance) {
+        // Locate the command entry in the cmds log
+        if let Some(cmd_entry) = self.lookup_mut(instance) {
             // Set the status to Executed
             cmd_entry.status = CmdStatus::Executed;
+            self.executed_count += 1;
         } else {
             // If the command entry does not exist, log an error or handle appropriately
             panic!("Command not found in log for instance: {:?}", instance);
@@ -337,49 +781,63 @@ impl Processor {
 
         // Find SCCs
         let sccs = self.tarjan_scc(&graph);
+        if tracing::enabled!(tracing::Level::DEBUG) {
+            let stats = Self::graph_stats(root, &graph, &sccs);
+            tracing::debug!("{}: Executing {}: {:?}", self.replica_name, root, stats);
+        }
 
         // topo order
         let order = self.topo_sort_scc(&sccs, &graph);
 
         // Reverse order
         for mut sorted in order.into_iter().rev() {
-            // Execute SCC in seq order
+            // Execute SCC in seq order, ties broken by instance so every replica agrees
             // let mut sorted = scc.clone();
-            sorted.sort_by_key(|inst| self.lookup(inst).unwrap().seq);
+            sorted.sort_by_key(|inst| (self.lookup(inst).unwrap().seq, inst.clone()));
 
             for inst in sorted {
                 if let Some(entry) = self.lookup(&inst) {
                     if matches!(entry.status, CmdStatus::Executed) {
                         continue;
                     }
-
-                    match entry.cmd.clone() {
-                        Command::Set { key, val } => {
-                            #[cfg(debug_assertions)]
-                            info!("{}: Write executed for {}", self.replica_name, inst);
-
-                            self.data.insert(key, val);
-                            self.mark_executed(&inst);
-                        }
-                        Command::Get { key } => {
-                            // Check if the current replica is the command leader for this read
-                            if inst.replica != self.replica_name {
-                                continue; // Skip processing if not the command leader
-                            }
-                            let val = self.data.get(&key).cloned();
-                            let meta = &self.app_meta[inst.instance_num];
-                            // check if an entry exists in app meta for that instance
-                            // send response only if current replica is the command leader of the current read
-                            // otherwise continue TODO
-                            out.push(EMsg::ClientResponse(ClientResponse {
-                                msg_id: meta.msg_id.clone(),
-                                client_id: meta.client_id.clone(),
-                                cmd_result: CommandResult::Get { key, val },
-                            }));
-
-                            self.pending_reads.remove(&inst);
-                            self.mark_executed(&inst);
+                    // TTLs are checked against the commit timestamp, so all replicas agree
+                    let ts = entry.commit_ts_ms;
+                    let watched = self.watched_versions(&entry.cmd);
+                    #[cfg(debug_assertions)]
+                    let is_write = entry.cmd.is_write();
+
+                    let cmd_result = match entry.cmd.clone() {
+                        // A read changes nothing and only its command leader serves it.
+                        // Other replicas still mark it executed, so executed_watermark and
+                        // compaction move past it
+                        cmd if !cmd.is_write() && inst.replica != self.replica_name => None,
+                        // Answered at commit, see commit_ack
+                        cmd @ Command::MultiSet { .. } => {
+                            store::apply(self, cmd, ts);
+                            None
                         }
+                        // Writes are applied on every replica. Cas, SetIfAbsent and Incr
+                        // results depend on execution order, so only the command leader
+                        // replies. Noop is filler for a recovered instance, only answered
+                        // if it took the slot of one of my own clients' commands.
+                        // Reconfig is applied as it committed, see apply_reconfig, and
+                        // Watch and Unwatch are answered by the replica that got them
+                        cmd => store::apply(self, cmd, ts),
+                    };
+                    #[cfg(debug_assertions)]
+                    if is_write {
+                        info!(
+                            "{}: Write executed for {}, result: {:?}",
+                            self.replica_name, inst, cmd_result
+                        );
+                    }
+
+                    self.mark_executed(&inst);
+                    self.pending_reads.remove(&inst);
+                    self.exec_clock_ms = self.exec_clock_ms.max(ts);
+                    out.extend(self.watch_notifications(watched, ts));
+                    if let Some(cmd_result) = cmd_result {
+                        out.extend(self.execution_response(&inst, cmd_result));
                     }
                 }
             }
@@ -388,39 +846,372 @@ impl Processor {
         out
     }
 
-    fn get_pending_reads(&self, write_instance: &Instance) -> Vec<Instance> {
-        self.pending_reads
+    /// Whether `cmd` may write a key some client watches through me
+    pub fn is_watched(&self, cmd: &Command) -> bool {
+        !self.watchers.is_empty()
+            && cmd.is_write()
+            && cmd.keys().iter().any(|k| self.watchers.contains_key(k))
+    }
+
+    /// Current version of each watched key `cmd` may write, to tell afterwards which ones
+    /// it changed. Failed Cas or SetIfAbsent leave the version alone
+    fn watched_versions(&self, cmd: &Command) -> Vec<(Variable, u64)> {
+        if self.watchers.is_empty() || !cmd.is_write() {
+            return vec![];
+        }
+        let mut keys = cmd.keys().into_owned();
+        keys.sort();
+        keys.dedup();
+        keys.into_iter()
+            .filter(|k| self.watchers.contains_key(k))
+            .map(|k| {
+                let version = self.version(&k);
+                (k, version)
+            })
+            .collect()
+    }
+
+    /// A WatchNotify per watcher of each key whose version moved past the one in `before`
+    fn watch_notifications(&self, before: Vec<(Variable, u64)>, ts: u64) -> Vec<EMsg> {
+        let mut out = vec![];
+        for (key, version) in before {
+            if self.version(&key) == version {
+                continue;
+            }
+            let val = self.read_at(&key, ts).cloned();
+            for client_id in &self.watchers[&key] {
+                out.push(EMsg::WatchNotify(WatchNotifyMsg {
+                    key: key.clone(),
+                    val: val.clone(),
+                    client_id: client_id.clone(),
+                }));
+            }
+        }
+        out
+    }
+
+    /// Adds or removes `client_id` as a watcher of `key`, answering with the key's value
+    /// as of the last executed command
+    pub fn watch(&mut self, key: &Variable, client_id: String, watching: bool) -> CommandResult {
+        if watching {
+            self.watchers
+                .entry(key.clone())
+                .or_default()
+                .insert(client_id);
+        } else if let Some(clients) = self.watchers.get_mut(key) {
+            clients.remove(&client_id);
+            if clients.is_empty() {
+                self.watchers.remove(key);
+            }
+        }
+        CommandResult::Get {
+            key: key.clone(),
+            val: self.read_at(key, self.exec_clock_ms).cloned(),
+            version: self.version(key),
+        }
+    }
+
+    /// Initial data, written before any command. Every replica must be given the same pairs
+    pub fn preload(&mut self, pairs: Vec<(Variable, Value)>) {
+        for (key, val) in pairs {
+            self.write_at(key, val, 0, None);
+        }
+    }
+
+    /// Drops expired values from `data`. Commands from other leaders may carry slightly older
+    /// commit timestamps, so only values expired TTL_SWEEP_GRACE before the newest executed
+    /// command are dropped. Returns how many were removed
+    pub fn sweep_expired(&mut self) -> usize {
+        let horizon = self
+            .exec_clock_ms
+            .saturating_sub(TTL_SWEEP_GRACE.as_millis() as u64);
+        let before = self.data.len();
+        self.data
+            .retain(|_, v| v.expires_at_ms.is_none_or(|e| e > horizon));
+        before - self.data.len()
+    }
+
+    /// Checks a client request against `limits` before it takes an instance
+    pub fn validate(
+        &self,
+        cmd: &Command,
+        msg_id: &str,
+        client_id: &str,
+    ) -> Result<(), (Option<Variable>, ErrorCode, String)> {
+        if msg_id.is_empty() || client_id.is_empty() {
+            let detail = "msg_id and client_id must not be empty".to_string();
+            return Err((None, ErrorCode::InvalidRequest, detail));
+        }
+        for key in cmd.keys().iter() {
+            if key.name.is_empty() || key.name.len() > self.limits.max_key_len {
+                let detail = format!(
+                    "key name must be 1 to {} bytes, got {}",
+                    self.limits.max_key_len,
+                    key.name.len()
+                );
+                return Err((Some(key.clone()), ErrorCode::InvalidKey, detail));
+            }
+        }
+        if let Some(val) = cmd
+            .values()
+            .into_iter()
+            .find(|v| v.len() > self.limits.max_value_len)
+        {
+            let detail = format!(
+                "value of {} bytes exceeds the {} byte limit",
+                val.len(),
+                self.limits.max_value_len
+            );
+            let key = cmd.keys().first().cloned();
+            return Err((key, ErrorCode::ValueTooLarge, detail));
+        }
+        Ok(())
+    }
+
+    /// The instance already led for this request, if it's still in the client's window
+    pub fn seen_request(&self, client_id: &str, msg_id: &str) -> Option<&SeenRequest> {
+        self.seen_requests.get(client_id)?.requests.get(msg_id)
+    }
+
+    /// Records that `instance` leads this request, forgetting the client's oldest request
+    /// once it has DEDUP_WINDOW of them
+    pub fn remember_request(&mut self, client_id: &str, msg_id: &str, instance: &Instance) {
+        let history = self.seen_requests.entry(client_id.to_string()).or_default();
+        history.order.push_back(msg_id.to_string());
+        history.requests.insert(
+            msg_id.to_string(),
+            SeenRequest {
+                instance: instance.clone(),
+                response: None,
+            },
+        );
+        if history.order.len() > DEDUP_WINDOW {
+            let oldest = history.order.pop_front().unwrap();
+            history.requests.remove(&oldest);
+        }
+    }
+
+    /// Remembers `resp` so a retry of the same request gets it back without re-proposing
+    fn cache_response(&mut self, resp: ClientResponse) -> ClientResponse {
+        if let Some(seen) = self
+            .seen_requests
+            .get_mut(&resp.client_id)
+            .and_then(|h| h.requests.get_mut(&resp.msg_id))
+        {
+            seen.response = Some(resp.clone());
+        }
+        resp
+    }
+
+    /// Client response for an executed instance, only sent by its command leader
+    fn execution_response(&mut self, inst: &Instance, cmd_result: CommandResult) -> Option<EMsg> {
+        if inst.replica != self.replica_name {
+            return None;
+        }
+        self.pending_reads.remove(inst);
+        if self.execution_policy == ExecutionPolicy::Lazy
+            && matches!(cmd_result, CommandResult::Set { .. })
+        {
+            return None; // Acked at commit
+        }
+        // A Noop recovery filled into a slot of mine I never proposed anything in
+        let meta = self.app_meta.get(inst.instance_num)?;
+        let resp = ClientResponse {
+            msg_id: meta.msg_id.clone(),
+            client_id: meta.client_id.clone(),
+            cmd_result,
+            server_received_us: Some(meta.received_us),
+            server_responded_us: Some(now_us()),
+        };
+        Some(EMsg::ClientResponse(self.cache_response(resp)))
+    }
+
+    /// Called on a fast commit of an instance I lead. A Get the fast quorum gave no deps
+    /// follows no write in flight, and no other Get holds it up since get_interfs never
+    /// makes a read a dep. It's counted in no_conflict_fast_reads. With fast_reads my data
+    /// already holds what it reads, so the reply is returned to go out ahead of the Commit.
+    /// None if the instance's output waits on the Commit as usual
+    pub fn try_fast_commit_read(&mut self, instance: &Instance) -> Option<Vec<EMsg>> {
+        let entry = self.lookup(instance)?;
+        if !matches!(entry.cmd, Command::Get { .. }) || !entry.deps.is_empty() {
+            return None;
+        }
+        self.no_conflict_fast_reads += 1;
+        if !self.config.fast_reads {
+            return None;
+        }
+        self.fast_reads_count += 1;
+        #[cfg(debug_assertions)]
+        info!("{}: Fast read of {}", self.replica_name, instance);
+        Some(self.leader_commit_output(instance))
+    }
+
+    /// FNV-1a hash of the live keys and values, in key order. Replicas that executed the
+    /// same commands have the same digest, so comparing digests checks they agree
+    pub fn data_digest(&self) -> u64 {
+        self.data
             .iter()
-            .filter(|read_instance| {
-                // Check if the value matches, not the reference
-                if let Some(read_entry) = self.lookup(read_instance) {
-                    read_entry.deps.contains(write_instance)
-                } else {
-                    false
-                }
+            .filter(|(_, v)| v.live_at(self.exec_clock_ms))
+            .fold(FNV_OFFSET, |hash, (key, v)| {
+                // Separators, so "ab"="c" and "a"="bc" hash apart
+                let hash = fnv1a(hash, key.to_string().as_bytes());
+                let hash = fnv1a(hash, &[0]);
+                let hash = fnv1a(hash, &v.val.0);
+                fnv1a(hash, &[0xff])
             })
-            .cloned() // Clone the instances to return owned values
+    }
+
+    /// Output of the command leader once `instance` is committed.
+    /// Commands whose result doesn't depend on execution (MultiSet, and Sets under
+    /// ExecutionPolicy::Lazy) are acked right away, and left for reads to execute.
+    /// The rest are executed now if their deps are ready, otherwise parked in pending_reads
+    pub fn leader_commit_output(&mut self, instance: &Instance) -> Vec<EMsg> {
+        self.enqueue_dependents(instance);
+        let cmd = self
+            .lookup(instance)
+            .expect("Command not found in log")
+            .cmd
+            .clone();
+
+        let lazy_set =
+            self.execution_policy == ExecutionPolicy::Lazy && matches!(cmd, Command::Set { .. });
+        if !cmd.replies_at_execution() || lazy_set {
+            let CmdMetadata {
+                msg_id,
+                client_id,
+                received_us,
+            } = &self.app_meta[instance.instance_num];
+
+            let resp = ClientResponse {
+                msg_id: msg_id.clone(),
+                client_id: client_id.clone(),
+                cmd_result: commit_ack(&cmd),
+                server_received_us: Some(*received_us),
+                server_responded_us: Some(now_us()),
+            };
+            let client_response = EMsg::ClientResponse(self.cache_response(resp));
+            #[cfg(debug_assertions)]
+            info!(
+                "{}: Sending Client Response for {}",
+                self.replica_name, instance
+            );
+
+            let mut out_msgs = vec![client_response];
+            out_msgs.append(&mut self.handle_pending_reads(instance));
+            return out_msgs;
+        }
+
+        let mut out_msgs = vec![];
+        if self.deps_all_ready(instance) {
+            out_msgs = self.execute_cmd(instance);
+            #[cfg(debug_assertions)]
+            info!(
+                "{}: Executed committed {} at leader",
+                self.replica_name, instance
+            );
+        } else {
+            self.pending_reads.insert(instance.clone());
+        }
+        // Executed or not, this may be a write that pending reads are waiting on
+        if cmd.is_write() {
+            out_msgs.append(&mut self.handle_pending_reads(instance));
+        }
+        if let Command::Reconfig {
+            replica_list,
+            epoch,
+        } = cmd
+        {
+            out_msgs.append(&mut self.apply_reconfig(replica_list, epoch));
+        }
+        out_msgs
+    }
+
+    fn get_pending_reads(&self, write_instance: &Instance) -> Vec<Instance> {
+        self.dependents(write_instance)
+            .into_iter()
+            .filter(|inst| self.pending_reads.contains(inst))
             .collect()
     }
 
+    /// Whether everything `inst` transitively depends on is committed, i.e. execute_cmd can
+    /// run it. Executed instances end the walk, their own deps already ran
     pub fn deps_all_ready(&self, inst: &Instance) -> bool {
-        let entry = match self.lookup(inst) {
-            Some(e) => e,
-            None => return false,
-        };
+        if self.lookup(inst).is_none() {
+            return false;
+        }
+        let mut stack = vec![inst.clone()];
+        let mut visited = HashSet::new();
+        while let Some(current) = stack.pop() {
+            if !visited.insert(current.clone()) {
+                continue;
+            }
+            let Some(entry) = self.lookup(&current) else {
+                return false;
+            };
+            for dep in &entry.deps {
+                match self.lookup(dep).map(|e| &e.status) {
+                    Some(CmdStatus::Executed) => {}
+                    Some(CmdStatus::Committed) => stack.push(dep.clone()),
+                    _ => return false,
+                }
+            }
+        }
+        true
+    }
 
-        for dep_inst in &entry.deps {
-            match self.lookup(dep_inst) {
-                Some(dep_entry) => {
-                    if !matches!(dep_entry.status, CmdStatus::Committed | CmdStatus::Executed) {
-                        return false;
-                    }
+    /// Instances whose deps include `instance`
+    fn dependents(&self, instance: &Instance) -> Vec<Instance> {
+        self.rdeps
+            .get(instance)
+            .map(|dependents| dependents.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Queues the parked instances that `instance` committing may have unblocked. They can
+    /// depend on it through other committed but unexecuted instances, so those are followed
+    pub fn enqueue_dependents(&mut self, instance: &Instance) {
+        let mut stack = vec![instance.clone()];
+        let mut visited = HashSet::new();
+        while let Some(current) = stack.pop() {
+            for dependent in self.dependents(&current) {
+                if !visited.insert(dependent.clone()) {
+                    continue;
+                }
+                if self.pending_reads.contains(&dependent) {
+                    self.ready_queue.push_back(dependent.clone());
+                }
+                if matches!(
+                    self.lookup(&dependent).map(|e| &e.status),
+                    Some(CmdStatus::Committed)
+                ) {
+                    stack.push(dependent);
                 }
-                None => return false,
             }
         }
+    }
 
-        true
+    /// Executes queued instances whose deps are now all committed, at most
+    /// execution_batch_size per call. The rest of the queue waits for the next message, the
+    /// instances not ready stay parked in pending_reads until another commit queues them again
+    pub fn try_execute_ready(&mut self) -> Vec<EMsg> {
+        let mut out = vec![];
+        let mut batch = 0;
+        while batch < self.config.execution_batch_size
+            && let Some(inst) = self.ready_queue.pop_front()
+        {
+            let executed = matches!(
+                self.lookup(&inst).map(|e| &e.status),
+                Some(CmdStatus::Executed)
+            );
+            if executed || !self.deps_all_ready(&inst) {
+                continue;
+            }
+            #[cfg(debug_assertions)]
+            info!("{}: Executing queued {}", self.replica_name, inst);
+            out.append(&mut self.execute_cmd(&inst));
+            batch += 1;
+        }
+        out
     }
 
     // get pending reads list on this particular write from pending_reads struct
@@ -434,8 +1225,6 @@ impl Processor {
     // check the pending reads list on this write cmd deps again
     // Now if all the dependency in each read has status committed or executed call execute command on thet read
     pub fn handle_pending_reads(&mut self, instance: &Instance) -> Vec<EMsg> {
-        let mut out_msgs = Vec::new();
-
         let pending_reads_on_write = self.get_pending_reads(instance);
         if pending_reads_on_write.is_empty() {
             // No reads waiting: normal commit
@@ -465,7 +1254,7 @@ impl Processor {
             );
             return vec![];
         }
-        out_msgs = self.execute_cmd(instance);
+        let mut out_msgs = self.execute_cmd(instance);
         // self.execute_cmd(instance);
 
         #[cfg(debug_assertions)]
@@ -492,11 +1281,71 @@ impl Processor {
     }
 }
 
+impl Store for Processor {
+    fn read_at(&self, key: &Variable, ts_ms: u64) -> Option<&Value> {
+        self.data
+            .get(key)
+            .filter(|v| v.live_at(ts_ms))
+            .map(|v| &v.val)
+    }
+
+    /// The version is deterministic, as writes execute in the same order everywhere
+    fn write_at(&mut self, key: Variable, val: Value, ts_ms: u64, ttl_ms: Option<u64>) {
+        let expires_at_ms = ttl_ms.map(|ttl| ts_ms.saturating_add(ttl));
+        *self.versions.entry(key.clone()).or_insert(0) += 1;
+        self.data.insert(key, StoredValue { val, expires_at_ms });
+    }
+
+    fn version(&self, key: &Variable) -> u64 {
+        self.versions.get(key).copied().unwrap_or(0)
+    }
+
+    fn scan(
+        &self,
+        start: &Variable,
+        end: &Variable,
+        limit: usize,
+        ts_ms: u64,
+    ) -> Vec<(Variable, Value)> {
+        self.data
+            .range::<Variable, _>(start..end)
+            .filter(|(_, v)| v.live_at(ts_ms))
+            .take(limit)
+            .map(|(k, v)| (k.clone(), v.val.clone()))
+            .collect()
+    }
+}
+
+/// What lookup returns for instances compacted below the watermark
+static COMPACTED: LazyLock<CmdEntry> = LazyLock::new(|| CmdEntry {
+    cmd: Command::Noop,
+    seq: 0,
+    deps: BTreeSet::new(),
+    status: CmdStatus::Executed,
+    commit_ts_ms: 0,
+});
+
+/// Result sent at commit time for commands that don't wait for execution
+fn commit_ack(cmd: &Command) -> CommandResult {
+    match cmd {
+        Command::MultiSet { pairs } => CommandResult::MultiSet {
+            keys: pairs.iter().map(|(k, _)| k.clone()).collect(),
+            status: true,
+        },
+        // Not executed yet, so the version is unknown
+        Command::Set { key, .. } => CommandResult::Set {
+            key: key.clone(),
+            version: 0,
+        },
+        _ => unreachable!("{} is replied to at execution", cmd),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::common::{Command, Variable};
-    use crate::epaxos::{CmdEntry, CmdStatus};
+    use crate::epaxos::{CmdEntry, CmdMetadata, CmdStatus, ConflictCheckerKind, ProcessorConfig};
+    use reactor_actor::ActorProcess;
 
     // --- Helpers ---
 
@@ -511,12 +1360,27 @@ mod tests {
 
     fn mock_cmd(key: &str) -> Command {
         Command::Set {
-            key: Variable { name: key.into() },
+            key: Variable::new(key),
             val: "val".into(),
+            ttl_ms: None,
         }
     }
 
     // --- Tests ---
+    #[test]
+    fn test_compact_instance_round_trip() {
+        let p = mock_processor(5);
+        for replica in 0..5 {
+            let inst = Instance {
+                replica: format!("r{}", replica),
+                instance_num: 1000 + replica,
+            };
+            let compact = inst.to_compact(&p);
+            assert_eq!(compact.replica_idx, replica as u32);
+            assert_eq!(compact.to_instance(&p.replica_list), inst);
+        }
+    }
+
     #[test]
     fn test_quorum_calculations() {
         let cases = vec![
@@ -568,6 +1432,54 @@ mod tests {
         assert_eq!(p.cmds.get(&target_replica).unwrap().len(), 5);
     }
 
+    #[test]
+    fn test_sparse_log_lookup() {
+        let list = (0..3).map(|i| format!("r{}", i)).collect();
+        let config = ProcessorConfig {
+            sparse_log: true,
+            ..Default::default()
+        };
+        let mut p = Processor::new_with_config(list, "r0".to_string(), config);
+        let inst = |instance_num| Instance {
+            replica: "r1".into(),
+            instance_num,
+        };
+        for num in [0, 500, 1000] {
+            let entry = CmdEntry {
+                cmd: mock_cmd(&format!("key{}", num)),
+                seq: num as u64,
+                deps: BTreeSet::new(),
+                status: CmdStatus::Committed,
+                commit_ts_ms: 0,
+            };
+            p.cmds_insert(&inst(num), entry);
+        }
+
+        for num in [0, 500, 1000] {
+            let entry = p.lookup(&inst(num)).expect("inserted");
+            assert_eq!(entry.cmd, mock_cmd(&format!("key{}", num)));
+            assert_eq!(entry.seq, num as u64);
+        }
+        assert!(p.lookup(&inst(1)).is_none());
+        assert!(p.lookup(&inst(1001)).is_none());
+        assert_eq!(p.log_len("r1"), 1001);
+        assert!(p.cmds["r1"].is_empty());
+
+        p.mark_executed(&inst(500));
+        assert!(matches!(
+            p.lookup(&inst(500)).unwrap().status,
+            CmdStatus::Executed
+        ));
+
+        // Three entries cost far less than 1001 vector slots
+        let mut dense = mock_processor(3);
+        for num in [0, 500, 1000] {
+            let entry = p.lookup(&inst(num)).unwrap().clone();
+            dense.cmds_insert(&inst(num), entry);
+        }
+        assert!(p.log_memory_bytes() * 10 < dense.log_memory_bytes());
+    }
+
     #[test]
     fn test_cmds_insert_normal() {
         let mut p = mock_processor(3);
@@ -579,8 +1491,9 @@ mod tests {
         let entry = CmdEntry {
             cmd: mock_cmd("key1"),
             seq: 10,
-            deps: HashSet::new(),
+            deps: BTreeSet::new(),
             status: CmdStatus::PreAccepted,
+            commit_ts_ms: 0,
         };
 
         p.cmds_insert(&inst, entry);
@@ -607,11 +1520,12 @@ mod tests {
         let mut entry = CmdEntry {
             cmd: cmd.clone(),
             seq: 10,
-            deps: HashSet::from([Instance {
+            deps: BTreeSet::from([Instance {
                 replica: "r2".into(),
                 instance_num: 0,
             }]),
             status: CmdStatus::PreAccepted,
+            commit_ts_ms: 0,
         };
         p.cmds_insert(&inst, entry.clone());
 
@@ -623,10 +1537,11 @@ mod tests {
         });
         entry.status = CmdStatus::Accepted;
 
-        p.cmds_insert(&inst, entry);
+        p.cmds_insert(&inst, entry.clone());
 
         // 3. Verify Update Persisted
         let stored = p.cmds.get("r1").unwrap()[1].as_ref().unwrap();
+        assert_eq!(stored, &entry);
         assert_eq!(stored.seq, 20);
         assert_eq!(stored.deps.len(), 2);
         assert!(matches!(stored.status, CmdStatus::Accepted));
@@ -648,8 +1563,9 @@ mod tests {
             CmdEntry {
                 cmd: cmd.clone(),
                 seq: 20,
-                deps: HashSet::new(),
+                deps: BTreeSet::new(),
                 status: CmdStatus::PreAccepted,
+                commit_ts_ms: 0,
             },
         );
 
@@ -659,8 +1575,9 @@ mod tests {
             CmdEntry {
                 cmd,
                 seq: 10,
-                deps: HashSet::new(),
+                deps: BTreeSet::new(),
                 status: CmdStatus::PreAccepted,
+                commit_ts_ms: 0,
             },
         );
     }
@@ -685,8 +1602,9 @@ mod tests {
             CmdEntry {
                 cmd: cmd.clone(),
                 seq: 10,
-                deps: HashSet::from([dep_inst]),
+                deps: BTreeSet::from([dep_inst]),
                 status: CmdStatus::PreAccepted,
+                commit_ts_ms: 0,
             },
         );
 
@@ -696,8 +1614,9 @@ mod tests {
             CmdEntry {
                 cmd,
                 seq: 10,
-                deps: HashSet::new(),
+                deps: BTreeSet::new(),
                 status: CmdStatus::PreAccepted,
+                commit_ts_ms: 0,
             },
         );
     }
@@ -715,8 +1634,9 @@ mod tests {
         let entry1 = CmdEntry {
             cmd: mock_cmd("key1"),
             seq: 1,
-            deps: HashSet::new(),
+            deps: BTreeSet::new(),
             status: CmdStatus::PreAccepted,
+            commit_ts_ms: 0,
         };
 
         // Fill the slot
@@ -726,12 +1646,38 @@ mod tests {
         let entry2 = CmdEntry {
             cmd: mock_cmd("key2"),
             seq: 2,
-            deps: HashSet::new(),
+            deps: BTreeSet::new(),
             status: CmdStatus::PreAccepted,
+            commit_ts_ms: 0,
         };
         p.cmds_insert(&inst, entry2);
     }
 
+    #[test]
+    #[should_panic(expected = "slot - occupied")]
+    fn test_cmds_insert_panic_on_other_op_same_key() {
+        let mut p = mock_processor(3);
+        let inst = Instance {
+            replica: "r1".into(),
+            instance_num: 0,
+        };
+        let get = Command::Get {
+            key: Variable::new("key1"),
+        };
+        assert_ne!(get, mock_cmd("key1"));
+
+        for cmd in [get, mock_cmd("key1")] {
+            let entry = CmdEntry {
+                cmd,
+                seq: 1,
+                deps: BTreeSet::new(),
+                status: CmdStatus::PreAccepted,
+                commit_ts_ms: 0,
+            };
+            p.cmds_insert(&inst, entry);
+        }
+    }
+
     #[test]
     fn test_get_interfs_logic() {
         let mut p = mock_processor(3);
@@ -744,8 +1690,9 @@ mod tests {
         let entry_a = CmdEntry {
             cmd: mock_cmd("A"),
             seq: 50,
-            deps: HashSet::new(),
+            deps: BTreeSet::new(),
             status: CmdStatus::Accepted,
+            commit_ts_ms: 0,
         };
         p.cmds_insert(&inst_a, entry_a);
 
@@ -757,8 +1704,9 @@ mod tests {
         let entry_b = CmdEntry {
             cmd: mock_cmd("B"),
             seq: 20,
-            deps: HashSet::new(),
+            deps: BTreeSet::new(),
             status: CmdStatus::Committed,
+            commit_ts_ms: 0,
         };
         p.cmds_insert(&inst_b, entry_b);
 
@@ -777,6 +1725,49 @@ mod tests {
         assert!(deps_c.is_empty());
         assert_eq!(seq_c, 1, "Default seq should be 0 + 1");
     }
+
+    #[test]
+    fn test_namespaces_never_interfere() {
+        let mut p = mock_processor(3);
+        let set_in = |namespace: &str| Command::Set {
+            key: Variable::in_namespace(namespace, "k"),
+            val: "v".into(),
+            ttl_ms: None,
+        };
+        let inst = make_inst("r1", 0);
+        commit_entry(&mut p, &inst, set_in("t1"), 1, vec![]);
+
+        let (deps, seq) = p.get_interfs(&set_in("t2"));
+        assert!(deps.is_empty());
+        assert_eq!(seq, 1);
+        let (deps, _) = p.get_interfs(&set_in("t1"));
+        assert!(deps.contains(&inst));
+
+        // A Scan over the whole default namespace leaves other namespaces alone
+        let scan = Command::Scan {
+            start: Variable::new("a"),
+            end: Variable::new("z"),
+            limit: 10,
+        };
+        assert!(!scan.conflicts_with(&set_in("t1")));
+        assert!(scan.conflicts_with(&mock_cmd("k")));
+    }
+
+    #[test]
+    fn test_conflict_checker_decides_deps() {
+        let mut p = mock_processor(3);
+        let inst = make_inst("r1", 0);
+        commit_entry(&mut p, &inst, mock_cmd("a"), 1, vec![]);
+
+        assert!(p.get_interfs(&mock_cmd("b")).0.is_empty());
+
+        p.conflict_checker = ConflictCheckerKind::Always.build();
+        assert_eq!(p.get_interfs(&mock_cmd("b")).0, BTreeSet::from([inst]));
+
+        p.conflict_checker = ConflictCheckerKind::Never.build();
+        assert!(p.get_interfs(&mock_cmd("a")).0.is_empty());
+    }
+
     #[test]
     fn test_get_interfs_max_seq_aggregation() {
         let mut p = mock_processor(3);
@@ -790,8 +1781,9 @@ mod tests {
             CmdEntry {
                 cmd: mock_cmd("A"),
                 seq: 100,
-                deps: HashSet::new(),
+                deps: BTreeSet::new(),
                 status: CmdStatus::PreAccepted,
+                commit_ts_ms: 0,
             },
         );
 
@@ -804,8 +1796,9 @@ mod tests {
             CmdEntry {
                 cmd: mock_cmd("A"),
                 seq: 200,
-                deps: HashSet::new(),
+                deps: BTreeSet::new(),
                 status: CmdStatus::PreAccepted,
+                commit_ts_ms: 0,
             },
         );
 
@@ -833,8 +1826,9 @@ mod tests {
         CmdEntry {
             cmd: mock_cmd("key"), // command content irrelevant for graph tests
             seq,
-            deps: HashSet::from_iter(deps),
+            deps: BTreeSet::from_iter(deps),
             status: CmdStatus::PreAccepted,
+            commit_ts_ms: 0,
         }
     }
 
@@ -898,6 +1892,60 @@ mod tests {
         assert_eq!(sccs[0][0], inst_a);
     }
 
+    #[test]
+    fn test_dep_graph_stats_chain() {
+        // A -> B -> C
+        let mut p = mock_processor(3);
+        let inst_a = make_inst("r0", 0);
+        let inst_b = make_inst("r1", 0);
+        let inst_c = make_inst("r2", 0);
+        p.cmds_insert(&inst_a, make_entry(10, vec![inst_b.clone()]));
+        p.cmds_insert(&inst_b, make_entry(10, vec![inst_c.clone()]));
+        p.cmds_insert(&inst_c, make_entry(10, vec![]));
+
+        let expected = DepGraphStats {
+            node_count: 3,
+            edge_count: 2,
+            scc_count: 3,
+            max_scc_size: 1,
+            max_depth: 2,
+        };
+        assert_eq!(p.dep_graph_stats(&inst_a), expected);
+    }
+
+    #[test]
+    fn test_dep_graph_stats_cycle_and_diamond() {
+        // A -> {B, C}, B -> D, C -> D, D -> C, and D -> a compacted instance
+        let mut p = mock_processor(4);
+        let inst_a = make_inst("r0", 0);
+        let inst_b = make_inst("r1", 0);
+        let inst_c = make_inst("r2", 0);
+        let inst_d = make_inst("r3", 0);
+        let gone = make_inst("r3", 7);
+        p.cmds_insert(
+            &inst_a,
+            make_entry(10, vec![inst_b.clone(), inst_c.clone()]),
+        );
+        p.cmds_insert(&inst_b, make_entry(10, vec![inst_d.clone()]));
+        p.cmds_insert(&inst_c, make_entry(10, vec![inst_d.clone()]));
+        p.cmds_insert(&inst_d, make_entry(10, vec![inst_c.clone(), gone]));
+
+        let expected = DepGraphStats {
+            node_count: 4,
+            edge_count: 5,
+            scc_count: 3, // {A}, {B}, {C, D}
+            max_scc_size: 2,
+            max_depth: 2,
+        };
+        assert_eq!(p.dep_graph_stats(&inst_a), expected);
+        // From D only the cycle is reachable
+        let stats = p.dep_graph_stats(&inst_d);
+        assert_eq!(
+            (stats.node_count, stats.scc_count, stats.max_depth),
+            (2, 1, 1)
+        );
+    }
+
     #[test]
     fn test_topo_sort_diamond_structure() {
         // Diamond:
@@ -1012,4 +2060,612 @@ mod tests {
         assert!(pos_a < pos_b);
         assert!(pos_c < pos_d);
     }
+
+    // ------------------------------------------------------------------------
+    // EXECUTION TESTS
+    // ------------------------------------------------------------------------
+
+    fn mock_cas(key: &str, expected: Option<&str>, new: &str) -> Command {
+        Command::Cas {
+            key: Variable::new(key),
+            expected: expected.map(Value::from),
+            new: new.into(),
+        }
+    }
+
+    // Helper to insert a committed entry, registering client metadata if we lead it
+    fn commit_entry(
+        p: &mut Processor,
+        inst: &Instance,
+        cmd: Command,
+        seq: u64,
+        deps: Vec<Instance>,
+    ) {
+        if inst.replica == p.replica_name {
+            p.app_meta.push(CmdMetadata {
+                client_id: "client".into(),
+                msg_id: format!("m{}", inst.instance_num),
+                received_us: 0,
+            });
+        }
+        p.cmds_insert(
+            inst,
+            CmdEntry {
+                cmd,
+                seq,
+                deps: BTreeSet::from_iter(deps),
+                status: CmdStatus::Committed,
+                commit_ts_ms: 0,
+            },
+        );
+    }
+
+    fn cas_results(out: &[EMsg]) -> Vec<bool> {
+        out.iter()
+            .filter_map(|m| match m {
+                EMsg::ClientResponse(ClientResponse {
+                    cmd_result: CommandResult::Cas { success, .. },
+                    ..
+                }) => Some(*success),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_conflicting_cas_exactly_one_succeeds() {
+        // Both CAS expect the key to be absent. r0/0 is ordered before r0/1 via deps.
+        let mut p = mock_processor(3);
+        let inst_a = make_inst("r0", 0);
+        let inst_b = make_inst("r0", 1);
+
+        commit_entry(&mut p, &inst_a, mock_cas("lock", None, "a"), 1, vec![]);
+        commit_entry(
+            &mut p,
+            &inst_b,
+            mock_cas("lock", None, "b"),
+            2,
+            vec![inst_a.clone()],
+        );
+
+        let out = p.execute_cmd(&inst_b);
+
+        assert_eq!(cas_results(&out), vec![true, false]);
+        let key = Variable::new("lock");
+        assert_eq!(p.data.get(&key).and_then(|v| v.val.as_str()), Some("a"));
+    }
+
+    #[test]
+    fn test_conflicting_cas_cycle_resolved_by_seq() {
+        // A <-> B cycle, B has the lower seq so B executes first and wins
+        let mut p = mock_processor(3);
+        let inst_a = make_inst("r0", 0);
+        let inst_b = make_inst("r0", 1);
+
+        commit_entry(
+            &mut p,
+            &inst_a,
+            mock_cas("lock", None, "a"),
+            20,
+            vec![inst_b.clone()],
+        );
+        commit_entry(
+            &mut p,
+            &inst_b,
+            mock_cas("lock", None, "b"),
+            10,
+            vec![inst_a.clone()],
+        );
+
+        let out = p.execute_cmd(&inst_a);
+
+        let results = cas_results(&out);
+        assert_eq!(results.len(), 2);
+        assert_eq!(results.iter().filter(|s| **s).count(), 1);
+        let key = Variable::new("lock");
+        assert_eq!(p.data.get(&key).and_then(|v| v.val.as_str()), Some("b"));
+    }
+
+    #[test]
+    fn test_executed_watermark_advances() {
+        let mut p = mock_processor(3);
+        let (a, b, c) = (make_inst("r1", 0), make_inst("r1", 1), make_inst("r1", 2));
+        commit_entry(&mut p, &a, mock_cmd("x"), 1, vec![]);
+        commit_entry(&mut p, &b, mock_cmd("y"), 1, vec![]);
+        commit_entry(&mut p, &c, mock_cmd("x"), 2, vec![a.clone()]);
+        assert_eq!(p.executed_watermark()["r1"], 0);
+
+        // A gap at r1/1 holds the watermark back
+        p.execute_cmd(&c);
+        assert_eq!(p.executed_watermark()["r1"], 1);
+        assert_eq!(p.executed_watermark()["r0"], 0);
+
+        p.execute_cmd(&b);
+        assert_eq!(p.executed_watermark()["r1"], 3);
+    }
+
+    #[test]
+    fn test_compaction_keeps_executed_view() {
+        let mut p = mock_processor(3);
+        let (a, b) = (make_inst("r1", 0), make_inst("r1", 1));
+        commit_entry(&mut p, &a, mock_cmd("x"), 1, vec![]);
+        commit_entry(&mut p, &b, mock_cmd("x"), 2, vec![a.clone()]);
+        p.execute_cmd(&a);
+
+        assert_eq!(p.try_compact_log(), 1);
+        assert_eq!(p.try_compact_log(), 0);
+        assert_eq!(p.cmds["r1"].len(), 1);
+        assert_eq!(p.watermark_of("r1"), 1);
+
+        // The dropped instance still reads as executed, so b can run on top of it
+        assert!(matches!(p.lookup(&a).unwrap().status, CmdStatus::Executed));
+        assert!(p.deps_all_ready(&b));
+        p.execute_cmd(&b);
+        assert!(matches!(p.lookup(&b).unwrap().status, CmdStatus::Executed));
+
+        // Instances past the watermark still land in the right slot
+        let c = make_inst("r1", 3);
+        commit_entry(&mut p, &c, mock_cmd("y"), 1, vec![]);
+        assert_eq!(p.cmds["r1"].len(), 3);
+        assert_eq!(p.lookup(&c).unwrap().seq, 1);
+    }
+
+    #[test]
+    fn test_cas_non_leader_applies_without_reply() {
+        // r1 leads the CAS, r0 only applies it to its local store
+        let mut p = mock_processor(3);
+        let inst = make_inst("r1", 0);
+        commit_entry(&mut p, &inst, mock_cas("lock", None, "x"), 1, vec![]);
+
+        let out = p.execute_cmd(&inst);
+
+        assert!(out.is_empty());
+        assert!(matches!(
+            p.lookup(&inst).unwrap().status,
+            CmdStatus::Executed
+        ));
+        let key = Variable::new("lock");
+        assert_eq!(p.data.get(&key).and_then(|v| v.val.as_str()), Some("x"));
+    }
+
+    fn incr_results(out: &[EMsg]) -> Vec<Result<i64, ErrorCode>> {
+        out.iter()
+            .filter_map(|m| match m {
+                EMsg::ClientResponse(ClientResponse {
+                    cmd_result: CommandResult::Incr { val, .. },
+                    ..
+                }) => Some(Ok(*val)),
+                EMsg::ClientResponse(ClientResponse {
+                    cmd_result: CommandResult::Error { code, .. },
+                    ..
+                }) => Some(Err(*code)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_incr_missing_key_starts_at_zero() {
+        let mut p = mock_processor(3);
+        let key = Variable::new("ctr");
+        let inst_a = make_inst("r0", 0);
+        let inst_b = make_inst("r1", 0);
+        let inst_c = make_inst("r0", 1);
+
+        let incr = |delta| Command::Incr {
+            key: key.clone(),
+            delta,
+        };
+        commit_entry(&mut p, &inst_a, incr(2), 1, vec![]);
+        // r1's increment is applied here but answered by r1
+        commit_entry(&mut p, &inst_b, incr(5), 2, vec![inst_a.clone()]);
+        commit_entry(&mut p, &inst_c, incr(-1), 3, vec![inst_b.clone()]);
+
+        let out = p.execute_cmd(&inst_c);
+
+        assert_eq!(incr_results(&out), vec![Ok(2), Ok(6)]);
+        assert_eq!(p.data.get(&key).and_then(|v| v.val.as_str()), Some("6"));
+    }
+
+    #[test]
+    fn test_incr_non_numeric_value_is_an_error() {
+        let mut p = mock_processor(3);
+        let key = Variable::new("k");
+        let inst_set = make_inst("r1", 0);
+        let inst_incr = make_inst("r0", 0);
+
+        commit_entry(&mut p, &inst_set, mock_cmd("k"), 1, vec![]);
+        commit_entry(
+            &mut p,
+            &inst_incr,
+            Command::Incr {
+                key: key.clone(),
+                delta: 1,
+            },
+            2,
+            vec![inst_set.clone()],
+        );
+
+        let out = p.execute_cmd(&inst_incr);
+
+        assert_eq!(incr_results(&out), vec![Err(ErrorCode::NotAnInteger)]);
+        // Store is left untouched and the instance still counts as executed
+        assert_eq!(p.data.get(&key).and_then(|v| v.val.as_str()), Some("val"));
+        assert!(matches!(
+            p.lookup(&inst_incr).unwrap().status,
+            CmdStatus::Executed
+        ));
+    }
+
+    #[test]
+    fn test_incr_overflow_is_an_error() {
+        let mut p = mock_processor(3);
+        let key = Variable::new("k");
+        p.write_at(key.clone(), i64::MAX.to_string().into(), 0, None);
+        let inst = make_inst("r0", 0);
+        commit_entry(
+            &mut p,
+            &inst,
+            Command::Incr {
+                key: key.clone(),
+                delta: 1,
+            },
+            1,
+            vec![],
+        );
+
+        let out = p.execute_cmd(&inst);
+
+        assert_eq!(incr_results(&out), vec![Err(ErrorCode::Overflow)]);
+        assert_eq!(p.read_at(&key, 0), Some(&i64::MAX.to_string().into()));
+    }
+
+    #[test]
+    fn test_leader_commit_output_defers_incr_until_deps_ready() {
+        let mut p = mock_processor(3);
+        let dep = make_inst("r1", 0);
+        let inst = make_inst("r0", 0);
+        commit_entry(
+            &mut p,
+            &inst,
+            Command::Incr {
+                key: Variable::new("key"),
+                delta: 1,
+            },
+            2,
+            vec![dep.clone()],
+        );
+
+        // Dep not known yet: no reply, parked until it commits
+        assert!(p.leader_commit_output(&inst).is_empty());
+        assert!(p.pending_reads.contains(&inst));
+
+        commit_entry(&mut p, &dep, mock_cmd("key"), 1, vec![]);
+        let out = p.handle_pending_reads(&dep);
+
+        assert!(matches!(
+            &out[..],
+            [EMsg::ClientResponse(ClientResponse {
+                cmd_result: CommandResult::Error { key: Some(key), code: ErrorCode::NotAnInteger, detail },
+                ..
+            })] if key.name == "key" && detail == "value \"val\" is not an integer"
+        ));
+        assert!(p.pending_reads.is_empty());
+    }
+
+    // Reverse dependency index rebuilt from scratch. Compacted deps aren't indexed
+    fn scanned_rdeps(p: &Processor) -> HashMap<Instance, HashSet<Instance>> {
+        let mut rdeps: HashMap<Instance, HashSet<Instance>> = HashMap::new();
+        for (replica, log) in &p.cmds {
+            for (i, entry) in log.iter().enumerate() {
+                let inst = make_inst(replica, p.watermark_of(replica) + i);
+                let live_deps = entry.iter().flat_map(|e| &e.deps);
+                for dep in live_deps.filter(|dep| p.slot_index(dep).is_some()) {
+                    rdeps.entry(dep.clone()).or_default().insert(inst.clone());
+                }
+            }
+        }
+        rdeps
+    }
+
+    fn indexed_rdeps(p: &Processor) -> HashMap<Instance, HashSet<Instance>> {
+        p.rdeps
+            .iter()
+            .filter(|(_, dependents)| !dependents.is_empty())
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn test_rdeps_consistent_with_cmds() {
+        let mut p = mock_processor(3);
+        let (a, b, c, d) = (
+            make_inst("r1", 0),
+            make_inst("r1", 1),
+            make_inst("r2", 0),
+            make_inst("r0", 0),
+        );
+        commit_entry(&mut p, &a, mock_cmd("x"), 1, vec![]);
+        commit_entry(&mut p, &b, mock_cmd("x"), 2, vec![a.clone()]);
+        commit_entry(&mut p, &c, mock_cmd("x"), 3, vec![a.clone(), b.clone()]);
+        // Re-inserted with different deps, e.g. after Accept
+        commit_entry(&mut p, &c, mock_cmd("x"), 3, vec![b.clone()]);
+        commit_entry(
+            &mut p,
+            &d,
+            mock_cmd("x"),
+            4,
+            vec![c.clone(), make_inst("r2", 5)],
+        );
+
+        assert_eq!(indexed_rdeps(&p), scanned_rdeps(&p));
+        assert_eq!(p.dependents(&a), vec![b.clone()]);
+        assert!(p.dependents(&d).is_empty());
+
+        // Compaction forgets dropped instances
+        p.execute_cmd(&b);
+        assert_eq!(p.try_compact_log(), 2);
+        assert!(!p.rdeps.contains_key(&a));
+        assert!(!p.rdeps.contains_key(&b));
+        assert_eq!(indexed_rdeps(&p), scanned_rdeps(&p));
+    }
+
+    #[test]
+    fn test_chained_deps_committed_out_of_order_execute() {
+        let mut p = mock_processor(3);
+        let (a, b, c) = (make_inst("r1", 0), make_inst("r2", 0), make_inst("r0", 0));
+        let key = Variable::new("key");
+        let incr = Command::Incr {
+            key: key.clone(),
+            delta: 1,
+        };
+        let commit = |inst: &Instance, seq, deps: Vec<Instance>| {
+            EMsg::Commit(crate::common::CommitMsg {
+                cmd: incr.clone(),
+                seq,
+                deps: deps.into_iter().collect(),
+                instance: inst.clone(),
+                commit_ts_ms: 0,
+                trace_ctx: None,
+            })
+        };
+
+        // c <- b <- a, my c commits first, then b, then a
+        p.app_meta.push(CmdMetadata {
+            client_id: "c".into(),
+            msg_id: "1".into(),
+            received_us: 0,
+        });
+        commit_entry(&mut p, &c, incr.clone(), 3, vec![b.clone()]);
+        assert!(p.leader_commit_output(&c).is_empty());
+
+        assert!(p.process(commit(&b, 2, vec![a.clone()])).is_empty());
+        assert!(p.pending_reads.contains(&c));
+
+        let out = p.process(commit(&a, 1, vec![]));
+        assert_eq!(incr_results(&out), vec![Ok(3)]);
+        for inst in [&a, &b, &c] {
+            assert!(matches!(
+                p.lookup(inst).unwrap().status,
+                CmdStatus::Executed
+            ));
+        }
+        assert!(p.pending_reads.is_empty());
+        assert!(p.ready_queue.is_empty());
+    }
+
+    fn mock_multi_set(keys: &[&str]) -> Command {
+        Command::MultiSet {
+            pairs: keys
+                .iter()
+                .map(|k| (Variable::new(*k), "multi".into()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_multi_key_conflicts() {
+        let ab = mock_multi_set(&["A", "B"]);
+        assert!(ab.conflicts_with(&mock_cmd("A")));
+        assert!(mock_cmd("B").conflicts_with(&ab));
+        assert!(!ab.conflicts_with(&mock_cmd("C")));
+        assert!(ab.conflicts_with(&Command::MultiGet {
+            keys: vec![Variable::new("C"), Variable::new("B")],
+        }));
+    }
+
+    #[test]
+    fn test_multi_set_ordered_against_single_sets() {
+        let mut p = mock_processor(3);
+        let set_a = make_inst("r1", 0);
+        let set_b = make_inst("r2", 0);
+        commit_entry(&mut p, &set_a, mock_cmd("A"), 3, vec![]);
+        commit_entry(&mut p, &set_b, mock_cmd("B"), 7, vec![]);
+
+        // MultiSet on A and B depends on both earlier Sets
+        let (deps, seq) = p.get_interfs(&mock_multi_set(&["A", "B"]));
+        assert_eq!(deps, BTreeSet::from([set_a.clone(), set_b.clone()]));
+        assert_eq!(seq, 8);
+
+        // A later Set on either key depends on the MultiSet
+        let multi = make_inst("r0", 0);
+        commit_entry(
+            &mut p,
+            &multi,
+            mock_multi_set(&["A", "B"]),
+            seq,
+            vec![set_a, set_b],
+        );
+        for key in ["A", "B"] {
+            let (deps, seq) = p.get_interfs(&mock_cmd(key));
+            assert!(deps.contains(&multi));
+            assert_eq!(seq, 9);
+        }
+    }
+
+    #[test]
+    fn test_multi_get_sees_multi_set() {
+        let mut p = mock_processor(3);
+        let multi = make_inst("r1", 0);
+        let read = make_inst("r0", 0);
+        commit_entry(&mut p, &multi, mock_multi_set(&["A", "B"]), 1, vec![]);
+        let keys: Vec<Variable> = ["A", "B", "C"].iter().map(|k| Variable::new(*k)).collect();
+        commit_entry(
+            &mut p,
+            &read,
+            Command::MultiGet { keys: keys.clone() },
+            2,
+            vec![multi],
+        );
+
+        let out = p.execute_cmd(&read);
+
+        assert_eq!(out.len(), 1);
+        let EMsg::ClientResponse(ClientResponse {
+            cmd_result: CommandResult::MultiGet { vals },
+            ..
+        }) = &out[0]
+        else {
+            unreachable!("Expected MultiGet response");
+        };
+        let expected: Vec<_> = keys
+            .into_iter()
+            .zip([Some("multi".into()), Some("multi".into()), None])
+            .collect();
+        assert_eq!(vals, &expected);
+    }
+
+    fn mock_scan(start: &str, end: &str) -> Command {
+        Command::Scan {
+            start: Variable::new(start),
+            end: Variable::new(end),
+            limit: 10,
+        }
+    }
+
+    #[test]
+    fn test_scan_observes_set_in_range() {
+        let mut p = mock_processor(3);
+        let set = make_inst("r1", 0);
+        commit_entry(&mut p, &set, mock_cmd("key_5"), 1, vec![]);
+
+        let (deps, seq) = p.get_interfs(&mock_scan("key_1", "key_9"));
+        assert_eq!(deps, BTreeSet::from([set.clone()]));
+
+        let scan = make_inst("r0", 0);
+        commit_entry(&mut p, &scan, mock_scan("key_1", "key_9"), seq, vec![set]);
+        let out = p.execute_cmd(&scan);
+
+        assert!(matches!(
+            &out[..],
+            [EMsg::ClientResponse(ClientResponse {
+                cmd_result: CommandResult::Scan { pairs },
+                ..
+            })] if pairs == &vec![(Variable::new("key_5"), "val".into())]
+        ));
+    }
+
+    #[test]
+    fn test_scan_ending_before_set_does_not_conflict() {
+        let mut p = mock_processor(3);
+        commit_entry(&mut p, &make_inst("r1", 0), mock_cmd("key_5"), 1, vec![]);
+
+        // End is exclusive
+        let scan = mock_scan("key_1", "key_5");
+        assert!(!scan.conflicts_with(&mock_cmd("key_5")));
+        assert!(p.get_interfs(&scan).0.is_empty());
+
+        assert!(mock_scan("key_1", "key_6").conflicts_with(&mock_cmd("key_5")));
+        assert!(mock_scan("key_1", "key_6").conflicts_with(&mock_scan("key_5", "key_9")));
+        assert!(!mock_scan("key_1", "key_5").conflicts_with(&mock_scan("key_5", "key_9")));
+    }
+
+    #[test]
+    fn test_inverted_scan_is_an_error() {
+        let mut p = mock_processor(3);
+        let scan = make_inst("r0", 0);
+        commit_entry(&mut p, &scan, mock_scan("key_9", "key_1"), 1, vec![]);
+        let out = p.execute_cmd(&scan);
+
+        assert!(matches!(
+            &out[..],
+            [EMsg::ClientResponse(ClientResponse {
+                cmd_result: CommandResult::Error {
+                    key: None,
+                    code: ErrorCode::InvalidRange,
+                    ..
+                },
+                ..
+            })]
+        ));
+    }
+
+    // Pretend `inst` was committed by its leader at logical time `ts`
+    fn set_commit_ts(p: &mut Processor, inst: &Instance, ts: u64) {
+        p.cmds.get_mut(&inst.replica).unwrap()[inst.instance_num]
+            .as_mut()
+            .unwrap()
+            .commit_ts_ms = ts;
+    }
+
+    fn get_results(out: &[EMsg]) -> Vec<Option<Value>> {
+        out.iter()
+            .filter_map(|m| match m {
+                EMsg::ClientResponse(ClientResponse {
+                    cmd_result: CommandResult::Get { val, .. },
+                    ..
+                }) => Some(val.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_ttl_evaluated_at_commit_timestamp() {
+        let mut p = mock_processor(3);
+        let key = Variable::new("k");
+        let set = make_inst("r1", 0);
+        commit_entry(
+            &mut p,
+            &set,
+            Command::set_ex(key.clone(), "v", 100),
+            1,
+            vec![],
+        );
+        set_commit_ts(&mut p, &set, 1_000);
+
+        let before = make_inst("r0", 0);
+        let after = make_inst("r0", 1);
+        for (inst, ts) in [(&before, 1_099), (&after, 1_100)] {
+            commit_entry(
+                &mut p,
+                inst,
+                Command::Get { key: key.clone() },
+                2,
+                vec![set.clone()],
+            );
+            set_commit_ts(&mut p, inst, ts);
+        }
+
+        // Execution order doesn't matter, only the commit timestamps do
+        assert_eq!(get_results(&p.execute_cmd(&after)), vec![None]);
+        assert_eq!(get_results(&p.execute_cmd(&before)), vec![Some("v".into())]);
+    }
+
+    #[test]
+    fn test_sweep_drops_values_expired_past_grace() {
+        let mut p = mock_processor(3);
+        let grace = TTL_SWEEP_GRACE.as_millis() as u64;
+        p.write_at(Variable::new("ttl"), "v".into(), 0, Some(10));
+        p.write_at(Variable::new("forever"), "v".into(), 0, None);
+
+        // Expired, but a lagging leader could still commit a read that sees it
+        p.exec_clock_ms = 10 + grace - 1;
+        assert_eq!(p.sweep_expired(), 0);
+
+        p.exec_clock_ms = 10 + grace;
+        assert_eq!(p.sweep_expired(), 1);
+        assert_eq!(p.data.len(), 1);
+        assert!(p.data.contains_key(&Variable::new("forever")));
+    }
 }
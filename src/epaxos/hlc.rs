@@ -0,0 +1,115 @@
+use crate::common::now_us;
+
+/// Bits of a timestamp holding the logical counter. The physical milliseconds take the rest
+const LOGICAL_BITS: u32 = 16;
+const LOGICAL_MAX: u32 = (1 << LOGICAL_BITS) - 1;
+
+/// Hybrid logical clock handing out seqs. Timestamps pack as `(physical_ms << 16) | logical`,
+/// so they follow wall-clock time while staying strictly increasing, also across remote
+/// timestamps merged through update
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct HybridClock {
+    physical_ms: u64,
+    logical: u32,
+}
+
+impl HybridClock {
+    fn packed(&self) -> u64 {
+        (self.physical_ms << LOGICAL_BITS) | u64::from(self.logical)
+    }
+
+    fn set(&mut self, ts: u64) {
+        self.physical_ms = ts >> LOGICAL_BITS;
+        self.logical = (ts as u32) & LOGICAL_MAX;
+    }
+
+    /// A timestamp greater than every one returned or merged before
+    pub fn now(&mut self) -> u64 {
+        self.now_at(now_us() / 1000)
+    }
+
+    fn now_at(&mut self, wall_ms: u64) -> u64 {
+        if wall_ms > self.physical_ms {
+            self.physical_ms = wall_ms;
+            self.logical = 0;
+        } else {
+            // Carries into physical_ms once the counter is full
+            let next = self.packed() + 1;
+            self.set(next);
+        }
+        self.packed()
+    }
+
+    /// Merges a timestamp seen elsewhere, so the next now() is greater than `ts`
+    pub fn update(&mut self, ts: u64) {
+        if ts > self.packed() {
+            self.set(ts);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    // --- Tests ---
+    #[test]
+    fn test_now_follows_wall_clock() {
+        let mut hlc = HybridClock::default();
+        assert_eq!(hlc.now_at(5), 5 << LOGICAL_BITS);
+        // Same millisecond, or the wall clock going back, bumps the counter
+        assert_eq!(hlc.now_at(5), (5 << LOGICAL_BITS) | 1);
+        assert_eq!(hlc.now_at(3), (5 << LOGICAL_BITS) | 2);
+        assert_eq!(hlc.now_at(6), 6 << LOGICAL_BITS);
+    }
+
+    #[test]
+    fn test_update_moves_past_remote_timestamp() {
+        let mut hlc = HybridClock::default();
+        hlc.now_at(5);
+        let remote = (9 << LOGICAL_BITS) | 7;
+        hlc.update(remote);
+        assert_eq!(hlc.now_at(5), remote + 1);
+        // Older timestamps change nothing
+        hlc.update(1);
+        assert_eq!(hlc.now_at(5), remote + 2);
+    }
+
+    #[test]
+    fn test_logical_overflow_carries_into_physical() {
+        let mut hlc = HybridClock::default();
+        hlc.update((5 << LOGICAL_BITS) | u64::from(LOGICAL_MAX));
+        assert_eq!(hlc.now_at(5), 6 << LOGICAL_BITS);
+    }
+
+    #[test]
+    fn test_monotonic_under_concurrent_updates() {
+        // Replicas exchanging timestamps, each merging what the others issued
+        let clocks: Vec<_> = (0..4)
+            .map(|_| Arc::new(Mutex::new(HybridClock::default())))
+            .collect();
+        let handles: Vec<_> = (0..clocks.len())
+            .map(|i| {
+                let clocks = clocks.clone();
+                thread::spawn(move || {
+                    let mut issued = vec![];
+                    for round in 0..1_000 {
+                        let ts = clocks[i].lock().unwrap().now();
+                        let peer = (i + round + 1) % clocks.len();
+                        clocks[peer].lock().unwrap().update(ts);
+                        issued.push(ts);
+                    }
+                    issued
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            let issued = handle.join().unwrap();
+            assert!(issued.windows(2).all(|w| w[0] < w[1]));
+            assert!(issued[0] >> LOGICAL_BITS >= now_us() / 1000 - 60_000);
+        }
+    }
+}
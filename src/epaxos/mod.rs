@@ -1,24 +1,88 @@
-use crate::common::{Command, EMsg, Instance, Variable};
+use crate::common::{
+    Ballot, ClientRequest, ClientResponse, Command, EMsg, Envelope, Instance, PrepareOkMsg, Value,
+    Variable, Versioned, now_us,
+};
+use crate::metrics::{MetricsConfig, MetricsReporter, metrics_route};
 use reactor_actor::codec::BincodeCodec;
 use reactor_actor::{BehaviourBuilder, RouteTo, RuntimeCtx, SendErrAction};
+use serde::Deserialize;
 
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::task;
 // use tracing::info;
+pub use conflict::ConflictCheckerKind;
+use conflict::{ConflictChecker, KeyEqualityChecker};
+use hlc::HybridClock;
+use quorum::QuorumPolicy;
+pub use quorum::QuorumPolicyKind;
+#[cfg(feature = "bench")]
+pub mod bench;
+mod conflict;
 mod handlers;
 mod helpers;
+mod hlc;
+mod quorum;
+mod reconfig;
+mod recovery;
+#[cfg(feature = "testing")]
+pub mod sim;
+#[cfg(all(feature = "serde", any(test, feature = "testing")))]
+mod snapshot;
+
+/// Interval between heartbeat Pings sent to every peer
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+/// Peers with no Pong for this long are skipped when broadcasting
+const PEER_TIMEOUT: Duration = Duration::from_secs(5);
+/// Expired values are kept this long past the newest executed commit timestamp before the
+/// sweep drops them, covering clock skew between command leaders
+const TTL_SWEEP_GRACE: Duration = Duration::from_secs(10);
+/// Most entries sent in one LogResponse
+const LOG_SYNC_BATCH: usize = 100;
+/// Requests remembered per client for deduplication. A retry arriving after this many newer
+/// requests from the same client is proposed again
+const DEDUP_WINDOW: usize = 1024;
+
+/// Last time a Pong was received from each peer. Written by the Processor, read by the Sender
+type LastSeen = Arc<Mutex<HashMap<String, Instant>>>;
+/// Client responses the Sender had nowhere to route. Written by the Sender, read by the Processor
+type DroppedResponses = Arc<AtomicU64>;
+/// Replicas the Sender broadcasts to: the replica list, joined by the replicas of the old
+/// one while a Reconfig is in transition. Written by the Processor, read by the Sender
+type Members = Arc<Mutex<Vec<String>>>;
 
 // //////////////////////////////////////////////////////////////////////////////
 //                                  Processor
 // //////////////////////////////////////////////////////////////////////////////
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 enum CmdStatus {
     PreAccepted,
     Accepted,
     Committed,
     Executed,
+    /// Being recovered by this replica. Messages from the original leader are ignored
+    Preparing,
 }
 
-#[derive(Debug, Clone)]
+impl fmt::Display for CmdStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            CmdStatus::PreAccepted => "preaccepted",
+            CmdStatus::Accepted => "accepted",
+            CmdStatus::Committed => "committed",
+            CmdStatus::Executed => "executed",
+            CmdStatus::Preparing => "preparing",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 struct CmdEntry {
     cmd: Command,
 
@@ -26,33 +90,188 @@ struct CmdEntry {
     seq: u64,
 
     /// Dependencies on other (replica, instance) pairs.
-    deps: HashSet<Instance>, // Can be ordered set.
+    deps: BTreeSet<Instance>, // Ordered so every replica encodes it identically
     status: CmdStatus,
+
+    /// Logical time assigned by the command leader at commit, 0 until committed.
+    commit_ts_ms: u64,
+}
+
+/// A value in `data`, with the commit timestamp from which reads treat it as missing
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct StoredValue {
+    val: Value,
+    expires_at_ms: Option<u64>,
+}
+
+impl StoredValue {
+    fn live_at(&self, ts_ms: u64) -> bool {
+        self.expires_at_ms.is_none_or(|e| ts_ms < e)
+    }
+}
+
+/// Whether the replica leads instances for client requests
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub enum ReplicaMode {
+    #[default]
+    Leader,
+    /// Takes part in PreAccept/Accept/Commit from other leaders, but rejects client requests
+    ReadOnly,
+}
+
+/// When a leader executes its own writes, see ProcessorConfig::lazy_execution
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ExecutionPolicy {
+    /// At commit, as soon as their deps are, so Set acks carry the key's new version
+    #[default]
+    Eager,
+    /// Sets are acked at commit and executed only once a read depends on them, like
+    /// other replicas do with every write
+    Lazy,
+}
+
+/// Bounds on incoming client requests. Anything outside is rejected before taking an instance
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RequestLimits {
+    pub max_key_len: usize,   // Bytes in a key name
+    pub max_value_len: usize, // Bytes in each value written
+}
+
+impl Default for RequestLimits {
+    fn default() -> Self {
+        RequestLimits {
+            max_key_len: 256,
+            max_value_len: 1 << 20,
+        }
+    }
+}
+
+/// Shape of the dependency graph executed from one root, see Processor::dep_graph_stats
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DepGraphStats {
+    pub node_count: usize, // Instances reachable from the root through deps in the log
+    pub edge_count: usize, // Deps between those instances
+    pub scc_count: usize,  // Strongly connected components, each executed as one unit
+    pub max_scc_size: usize, // Instances in the largest SCC
+    pub max_depth: usize,  // Longest shortest-path from the root, in deps
 }
 
+/// Tunables of a replica, given to Processor::new_with_config
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ProcessorConfig {
+    pub gc_threshold: usize, // Executed instances that must be droppable before the log is compacted
+    pub max_pending_reads: usize, // Parked instances past which new client requests are refused
+    pub execution_batch_size: usize, // Ready instances executed per message, the rest wait a message
+    pub recovery_timeout_ms: u64, // How long a dead leader's instance may block execution before I recover it
+    pub max_inflight_instances: usize, // Entries in my own log past which new client requests are refused
+    pub sparse_log: bool, // Keep the log in cmds_flat, by instance, instead of the cmds vectors
+    pub pipeline_depth: Option<usize>, // Uncommitted instances I lead at once, None for no limit
+    pub fast_reads: bool, // Answer Gets with no deps at the fast quorum, ahead of their Commit
+    pub lazy_execution: bool, // Ack my Sets at commit, executing them only for reads, see ExecutionPolicy
+    pub batch_pre_accepts: bool, // Send the PreAccepts of queued requests starting together as one PreAcceptBatch
+    pub quorum_policy: QuorumPolicyKind, // Quorum sizes of the instances I lead
+}
+
+impl Default for ProcessorConfig {
+    fn default() -> Self {
+        ProcessorConfig {
+            gc_threshold: 1,
+            max_pending_reads: 10_000,
+            execution_batch_size: 1024,
+            recovery_timeout_ms: 2 * PEER_TIMEOUT.as_millis() as u64,
+            max_inflight_instances: 10_000,
+            sparse_log: false,
+            pipeline_depth: None,
+            fast_reads: false,
+            lazy_execution: false,
+            batch_pre_accepts: false,
+            quorum_policy: QuorumPolicyKind::Standard,
+        }
+    }
+}
+
+/// A client request led by this replica
 #[derive(Debug, Clone)]
+struct SeenRequest {
+    instance: Instance,
+    response: Option<ClientResponse>, // Set once answered, replayed to retries
+}
+
+/// The last DEDUP_WINDOW requests of one client, by msg_id
+#[derive(Debug, Clone, Default)]
+struct ClientHistory {
+    order: VecDeque<String>, // msg_ids, oldest first
+    requests: HashMap<String, SeenRequest>,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 struct CmdMetadata {
     client_id: String,
     msg_id: String,
+    received_us: u64, // When the client request reached this replica
 }
 
 #[derive(Debug, Clone)]
 struct Processor {
     #[allow(dead_code)]
-    data: HashMap<Variable, String>,
+    data: BTreeMap<Variable, StoredValue>, // Ordered for Scan
+    versions: HashMap<Variable, u64>, // Writes executed per key. Kept when a value expires
     // cmds: HashMap<String, Vec<CmdInstance>>,
     cmds: HashMap<String, Vec<Option<CmdEntry>>>,
+    // With config.sparse_log, the log lives here instead and every cmds vector stays empty.
+    // Gaps in instance numbers then cost nothing
+    cmds_flat: HashMap<Instance, CmdEntry>,
+    cmds_flat_end: HashMap<String, usize>, // One past the highest instance of each replica in cmds_flat
 
     // instance_num: u64,
     instance_num: usize,
     quorum_ctr: Vec<u32>, // Counter for PreAcceptOk messages, // Indexed by instance number
     acc_quorum_ctr: Vec<u32>, // Counter for AcceptOk messages, // Indexed by instance number
+    received_from: Vec<HashSet<String>>, // Replicas counted in quorum_ctr, until commit
+    acc_received_from: Vec<HashSet<String>>, // Replicas counted in acc_quorum_ctr, until commit
     #[allow(dead_code)]
     app_meta: Vec<CmdMetadata>, // Indexed by instance number
 
-    replica_list: Vec<String>,
+    replica_list: Vec<String>,        // Quorums are counted over these
     replica_name: String,             // Myself
     pending_reads: HashSet<Instance>, // pending list of outstanding reads
+    ready_queue: VecDeque<Instance>,  // Pending instances a commit may have unblocked
+    rdeps: HashMap<Instance, HashSet<Instance>>, // Instances that depend on each instance
+    last_seen: LastSeen,              // Shared with Sender for dead replica detection
+    dropped_responses: DroppedResponses, // Shared with Sender, reported by GetMetrics
+    members: Members,                 // Shared with Sender, see Members
+    epoch: u64,                       // Raised by every Reconfig I commit
+    reconfig_acks: HashSet<String>, // Replicas known to be on `epoch`, until all of replica_list are
+    replica_mode: ReplicaMode,
+    execution_policy: ExecutionPolicy, // From config.lazy_execution
+    limits: RequestLimits,
+    conflict_checker: Arc<dyn ConflictChecker>, // Same on every replica, see get_interfs
+    quorum_policy: Arc<dyn QuorumPolicy>,       // Sizes get_majority and fast_quorum
+    seen_requests: HashMap<String, ClientHistory>, // Client requests led by me, by client_id
+    exec_clock_ms: u64, // Newest commit timestamp executed so far, drives the TTL sweep
+    prepare_oks: HashMap<Instance, Vec<PrepareOkMsg>>, // Replies for instances I'm recovering
+    recovery_acks: HashMap<Instance, HashSet<String>>, // AcceptOks for instances I'm recovering
+    fast_path_commits: u64,
+    slow_path_commits: u64,
+    conflict_detections: u64,
+    fast_reads_count: u64, // Gets answered at the fast quorum ahead of their Commit, see fast_reads
+    no_conflict_fast_reads: u64, // Gets I led that fast committed with no deps, see try_fast_commit_read
+    client_requests: u64,        // ClientRequests received, retries included
+    client_errors: u64,          // ClientErrors sent back
+    executed_count: u64,         // Instances executed, mine and my peers'
+    metrics: Option<MetricsReporter>, // Set when the payload names a metrics actor
+    watermark: HashMap<String, usize>, // Instances below this were executed and dropped from cmds
+    watchers: HashMap<Variable, HashSet<String>>, // Client ids watching each key through me
+    highest_ballot: HashMap<Instance, Ballot>, // Only instances past Ballot::initial, by a Prepare
+    blocked_since: HashMap<Instance, Instant>, // Uncommitted deps holding up execution, first seen
+    hlc: HybridClock,            // Seqs of instances I lead, merged with every seq I PreAccept
+    pending_instances: VecDeque<usize>, // My instances from the oldest uncommitted one, the pipeline window
+    queued_requests: VecDeque<ClientRequest>, // Client requests waiting for room in the window
+    config: ProcessorConfig,
 }
 
 impl reactor_actor::ActorProcess for Processor {
@@ -60,83 +279,261 @@ impl reactor_actor::ActorProcess for Processor {
     type OMsg = EMsg;
 
     fn process(&mut self, input: Self::IMsg) -> Vec<Self::OMsg> {
-        match input {
-            EMsg::ClientRequest(msg) => self.client_request_handler(msg),
+        let mut out = match input {
+            EMsg::ClientRequest(msg) => {
+                self.client_requests += 1;
+                self.client_request_handler(msg)
+            }
 
             EMsg::PreAccept(msg) => self.pre_accept_handler(msg),
             EMsg::PreAcceptOk(msg) => self.pre_accept_ok_handler(msg),
+            EMsg::PreAcceptBatch(msgs) => self.pre_accept_batch_handler(msgs),
+            EMsg::PreAcceptOkBatch(msgs) => self.pre_accept_ok_batch_handler(msgs),
             EMsg::Commit(msg) => self.commit_handler(msg),
             EMsg::Accept(msg) => self.accept_handler(msg),
             EMsg::AcceptOk(msg) => self.accept_ok_handler(msg),
-            EMsg::DumpStateMsg => self.dump_state_handler(),
+            EMsg::DumpState => self.dump_state_handler(),
+            EMsg::GetMetrics => self.get_metrics_handler(),
+            #[cfg(any(test, feature = "testing"))]
+            EMsg::GetState(msg) => self.get_state_handler(msg),
+            EMsg::RequestLog {
+                requester,
+                from_instance,
+            } => self.request_log_handler(requester, from_instance),
+            EMsg::LogResponse { entries } => self.log_response_handler(entries),
+            EMsg::StoreSnapshot(msg) => self.store_snapshot_handler(msg),
+            EMsg::Ping { from, timestamp_us } => self.ping_handler(from, timestamp_us),
+            EMsg::Pong {
+                from,
+                echo_timestamp_us,
+            } => self.pong_handler(from, echo_timestamp_us),
+            EMsg::Noop(instance) => self.noop_handler(instance),
+            EMsg::ProposeNoOp(instance) => self.propose_noop_handler(instance),
+            EMsg::Prepare { instance, ballot } => self.prepare_handler(instance, ballot),
+            EMsg::PrepareOk(msg) => self.prepare_ok_handler(msg),
+            EMsg::BallotRejected {
+                instance,
+                current_ballot,
+            } => self.ballot_rejected_handler(instance, current_ballot),
+            EMsg::ReconfigRequest {
+                new_replica_list,
+                epoch,
+            } => self.reconfig_request_handler(new_replica_list, epoch),
+            EMsg::ReconfigAck { epoch, from } => self.reconfig_ack_handler(epoch, from),
             _ => {
                 panic!("Server got an unexpected message")
             }
+        };
+        out.append(&mut self.try_execute_ready());
+        out.append(&mut self.advance_pipeline());
+        self.client_errors += out
+            .iter()
+            .filter(|m| matches!(m, EMsg::ClientError(_)))
+            .count() as u64;
+        // Heartbeats call in every HEARTBEAT_INTERVAL, so reports are at most that late
+        if let Some(mut reporter) = self.metrics.take() {
+            out.extend(reporter.report_if_due(Instant::now(), || self.metric_values()));
+            self.metrics = Some(reporter);
         }
+        out
     }
 }
 
 impl Processor {
+    #[allow(dead_code)]
     fn new(replica_list: Vec<String>, replica_name: String) -> Self {
+        Processor::new_with_config(replica_list, replica_name, ProcessorConfig::default())
+    }
+
+    fn new_with_config(
+        replica_list: Vec<String>,
+        replica_name: String,
+        config: ProcessorConfig,
+    ) -> Self {
         // initialize cmds for each replica
         let mut cmds: HashMap<String, Vec<Option<CmdEntry>>> = HashMap::new();
         for replica in &replica_list {
             cmds.insert(replica.clone(), vec![]);
         }
+        // Every peer starts as alive, giving it PEER_TIMEOUT to answer the first Ping
+        let now = Instant::now();
+        let last_seen = replica_list
+            .iter()
+            .filter(|r| **r != replica_name)
+            .map(|r| (r.clone(), now))
+            .collect();
+        let members = replica_list.clone();
         Processor {
-            data: HashMap::new(),
+            data: BTreeMap::new(),
+            versions: HashMap::new(),
             cmds,
+            cmds_flat: HashMap::new(),
+            cmds_flat_end: HashMap::new(),
             instance_num: 0,
             quorum_ctr: vec![],
             acc_quorum_ctr: vec![],
+            received_from: vec![],
+            acc_received_from: vec![],
             app_meta: vec![],
             replica_list,
             replica_name,
             pending_reads: HashSet::new(),
+            ready_queue: VecDeque::new(),
+            rdeps: HashMap::new(),
+            last_seen: Arc::new(Mutex::new(last_seen)),
+            dropped_responses: Arc::new(AtomicU64::new(0)),
+            members: Arc::new(Mutex::new(members)),
+            epoch: 0,
+            reconfig_acks: HashSet::new(),
+            replica_mode: ReplicaMode::Leader,
+            execution_policy: if config.lazy_execution {
+                ExecutionPolicy::Lazy
+            } else {
+                ExecutionPolicy::Eager
+            },
+            limits: RequestLimits::default(),
+            conflict_checker: Arc::new(KeyEqualityChecker),
+            quorum_policy: config.quorum_policy.build(),
+            seen_requests: HashMap::new(),
+            exec_clock_ms: 0,
+            prepare_oks: HashMap::new(),
+            recovery_acks: HashMap::new(),
+            fast_path_commits: 0,
+            slow_path_commits: 0,
+            conflict_detections: 0,
+            fast_reads_count: 0,
+            no_conflict_fast_reads: 0,
+            client_requests: 0,
+            client_errors: 0,
+            executed_count: 0,
+            metrics: None,
+            watermark: HashMap::new(),
+            watchers: HashMap::new(),
+            highest_ballot: HashMap::new(),
+            blocked_since: HashMap::new(),
+            hlc: HybridClock::default(),
+            pending_instances: VecDeque::new(),
+            queued_requests: VecDeque::new(),
+            config,
         }
     }
 }
 
+// //////////////////////////////////////////////////////////////////////////////
+//                                  Generator
+// //////////////////////////////////////////////////////////////////////////////
+
+/// Yields a Ping every HEARTBEAT_INTERVAL. The Processor forwards it to all peers and
+/// sweeps expired values
+struct HeartbeatGenerator {
+    replica_name: String,
+}
+
+impl Iterator for HeartbeatGenerator {
+    type Item = EMsg;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        task::block_in_place(|| {
+            std::thread::sleep(HEARTBEAT_INTERVAL);
+        });
+        Some(EMsg::Ping {
+            from: self.replica_name.clone(),
+            timestamp_us: now_us(),
+        })
+    }
+}
+
 struct Sender {
     replica_name: String,
-    replica_list: Vec<String>,
+    members: Members,
+    last_seen: LastSeen,
+    dropped_responses: DroppedResponses,
+    metrics_actor: Option<String>,
 }
 impl Sender {
+    /// All replicas other than myself, old and new ones during a Reconfig
+    fn peers(&self) -> Vec<String> {
+        self.members
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|r| *r != &self.replica_name)
+            .cloned()
+            .collect()
+    }
+
+    /// A peer is alive if it answered a Ping within PEER_TIMEOUT
+    fn is_alive(&self, replica: &str) -> bool {
+        let last_seen = self.last_seen.lock().unwrap();
+        match last_seen.get(replica) {
+            Some(seen) => seen.elapsed() < PEER_TIMEOUT,
+            None => true,
+        }
+    }
+
+    /// Client responses go to the actor named by client_id, whichever connection the request
+    /// came in on. Without one there's nobody to answer, so the response is counted and dropped.
+    /// Clients that went away are dropped by the runtime, see SendErrAction::Drop
+    fn client_destination(&self, client_id: &str) -> Vec<String> {
+        if client_id.is_empty() {
+            self.dropped_responses.fetch_add(1, Ordering::Relaxed);
+            return vec![];
+        }
+        vec![client_id.to_string()]
+    }
+
     /// Computes the explicit string destinations for a given message.
     /// Panics if the message type relies on context (Reply) or is invalid.
     fn resolve_destinations(&self, output: &EMsg) -> Vec<String> {
         match output {
-            EMsg::ClientResponse(response) => {
-                vec![response.client_id.clone()]
-            }
-            EMsg::PreAccept(_) | EMsg::Accept(_) | EMsg::Commit(_) => {
-                let mut dests: Vec<String> = self
-                    .replica_list
-                    .iter()
-                    .filter(|r| *r != &self.replica_name)
-                    .cloned()
-                    .collect();
-
-                if dests.is_empty() {
-                    dests.push(self.replica_name.clone());
+            EMsg::ClientResponse(response) => self.client_destination(&response.client_id),
+            EMsg::ClientError(error) => self.client_destination(&error.client_id),
+            EMsg::WatchNotify(notify) => self.client_destination(&notify.client_id),
+            EMsg::PreAccept(_)
+            | EMsg::PreAcceptBatch(_)
+            | EMsg::Accept(_)
+            | EMsg::Commit(_)
+            | EMsg::Prepare { .. } => {
+                let peers = self.peers();
+                if peers.is_empty() {
+                    // Single replica setup, talk to myself
+                    return vec![self.replica_name.clone()];
                 }
-                dests
+                // Skip peers that stopped answering heartbeats
+                peers.into_iter().filter(|r| self.is_alive(r)).collect()
             }
+            // Heartbeats go to every peer, so dead ones get detected when they come back.
+            // Acks too, every replica needs them to end the transition
+            EMsg::Ping { .. } | EMsg::ReconfigAck { .. } => self.peers(),
             _ => panic!("Message type requires contextual routing or is invalid"),
         }
     }
 }
 
 impl reactor_actor::ActorSend for Sender {
-    type OMsg = EMsg;
+    type OMsg = Envelope;
 
     async fn before_send<'a>(&'a mut self, output: &Self::OMsg) -> RouteTo<'a> {
-        match output {
+        match &output.msg {
             // Handle contextual replies immediately
-            EMsg::PreAcceptOk(_) | EMsg::AcceptOk(_) => RouteTo::Reply,
+            EMsg::PreAcceptOk(_)
+            | EMsg::PreAcceptOkBatch(_)
+            | EMsg::AcceptOk(_)
+            | EMsg::Pong { .. }
+            | EMsg::PrepareOk(_)
+            | EMsg::BallotRejected { .. }
+            | EMsg::DumpStateResponse(_)
+            | EMsg::MetricsResponse(_)
+            // RequestLog is sent in answer to a Pong, to the peer that should fill the gap
+            | EMsg::RequestLog { .. }
+            | EMsg::LogResponse { .. }
+            | EMsg::StoreSnapshot(_) => RouteTo::Reply,
+            #[cfg(any(test, feature = "testing"))]
+            EMsg::GetStateResponse(_) => RouteTo::Reply,
+            EMsg::Metrics { .. } => metrics_route(self.metrics_actor.as_deref()),
             // Handle explicit destinations via helper
             _ => {
-                let dests = self.resolve_destinations(output);
+                let dests = self.resolve_destinations(&output.msg);
 
                 // Optimize routing type based on vector length
                 if dests.len() == 1 {
@@ -154,19 +551,2049 @@ impl reactor_actor::ActorSend for Sender {
 // //////////////////////////////////////////////////////////////////////////////
 
 /// Epaxos server actor
-pub async fn server(ctx: RuntimeCtx, replica_list: Vec<String>) {
+#[allow(clippy::too_many_arguments)]
+pub async fn server(
+    ctx: RuntimeCtx,
+    replica_list: Vec<String>,
+    replica_mode: ReplicaMode,
+    limits: RequestLimits,
+    conflict_checker: ConflictCheckerKind,
+    preload: Vec<(Variable, Value)>,
+    config: ProcessorConfig,
+    metrics: Option<MetricsConfig>,
+) {
     let replica_name = ctx.addr.to_string();
-    BehaviourBuilder::new(
-        Processor::new(replica_list.clone(), replica_name.clone()),
-        BincodeCodec::default(),
-    )
-    .send(Sender {
-        replica_name,
-        replica_list,
-    })
-    .on_send_failure(SendErrAction::Drop)
-    .build()
-    .run(ctx)
-    .await
-    .unwrap();
+    let mut processor = Processor::new_with_config(replica_list, replica_name.clone(), config);
+    processor.metrics = metrics
+        .as_ref()
+        .map(|m| MetricsReporter::new(replica_name.clone(), m));
+    processor.replica_mode = replica_mode;
+    processor.limits = limits;
+    processor.conflict_checker = conflict_checker.build();
+    processor.preload(preload);
+    let last_seen = processor.last_seen.clone();
+    let dropped_responses = processor.dropped_responses.clone();
+    let members = processor.members.clone();
+    BehaviourBuilder::new(Versioned(processor), BincodeCodec::default())
+        .send(Sender {
+            replica_name: replica_name.clone(),
+            members,
+            last_seen,
+            dropped_responses,
+            metrics_actor: metrics.map(|m| m.actor),
+        })
+        .generator_if(true, || {
+            HeartbeatGenerator { replica_name }.map(Envelope::new)
+        })
+        .on_send_failure(SendErrAction::Drop)
+        .build()
+        .run(ctx)
+        .await
+        .unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::{
+        AcceptMsg, AcceptOkMsg, ClientResponse, CommandResult, ErrorCode, GetStateRequest,
+        MetricsMsg, PreAcceptOkMsg, PrepareStatus,
+    };
+    use reactor_actor::ActorProcess;
+    use std::collections::VecDeque;
+
+    // --- Helpers ---
+
+    /// Keeps the name and fields of every span created while it's the default subscriber
+    #[cfg(feature = "telemetry")]
+    #[derive(Default)]
+    struct SpanRecorder {
+        spans: Mutex<Vec<(&'static tracing::Metadata<'static>, SpanFields)>>,
+    }
+
+    #[cfg(feature = "telemetry")]
+    #[derive(Default)]
+    struct SpanFields(HashMap<&'static str, String>);
+
+    #[cfg(feature = "telemetry")]
+    impl tracing::field::Visit for SpanFields {
+        fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn fmt::Debug) {
+            self.0.insert(field.name(), format!("{:?}", value));
+        }
+    }
+
+    #[cfg(feature = "telemetry")]
+    impl tracing::Subscriber for SpanRecorder {
+        fn enabled(&self, _: &tracing::Metadata<'_>) -> bool {
+            true
+        }
+        fn new_span(&self, attrs: &tracing::span::Attributes<'_>) -> tracing::span::Id {
+            let mut fields = SpanFields::default();
+            attrs.record(&mut fields);
+            let mut spans = self.spans.lock().unwrap();
+            spans.push((attrs.metadata(), fields));
+            tracing::span::Id::from_u64(spans.len() as u64)
+        }
+        fn record(&self, span: &tracing::span::Id, values: &tracing::span::Record<'_>) {
+            let mut spans = self.spans.lock().unwrap();
+            values.record(&mut spans[span.into_u64() as usize - 1].1);
+        }
+        fn record_follows_from(&self, _: &tracing::span::Id, _: &tracing::span::Id) {}
+        fn event(&self, _: &tracing::Event<'_>) {}
+        fn enter(&self, _: &tracing::span::Id) {}
+        fn exit(&self, _: &tracing::span::Id) {}
+    }
+
+    fn mock_processor(peers: usize) -> Processor {
+        let list = (0..peers).map(|i| format!("r{}", i)).collect();
+        // "r0" is assumed to be self
+        Processor::new(list, "r0".to_string())
+    }
+
+    fn mock_sender(p: &Processor) -> Sender {
+        Sender {
+            replica_name: p.replica_name.clone(),
+            members: p.members.clone(),
+            last_seen: p.last_seen.clone(),
+            dropped_responses: p.dropped_responses.clone(),
+            metrics_actor: None,
+        }
+    }
+
+    fn mock_commit() -> EMsg {
+        EMsg::Commit(crate::common::CommitMsg {
+            cmd: Command::Get {
+                key: Variable::new("k"),
+            },
+            seq: 1,
+            deps: BTreeSet::new(),
+            instance: Instance {
+                replica: "r0".into(),
+                instance_num: 0,
+            },
+            commit_ts_ms: 1,
+            trace_ctx: None,
+        })
+    }
+
+    // Pretend a peer last answered `ago` in the past
+    fn mark_seen(p: &Processor, replica: &str, ago: Duration) {
+        let seen = Instant::now().checked_sub(ago).unwrap();
+        p.last_seen
+            .lock()
+            .unwrap()
+            .insert(replica.to_string(), seen);
+    }
+
+    // Build `n` replicas "r0".."r{n-1}" that all know each other
+    fn mock_cluster(n: usize) -> Vec<Processor> {
+        let list: Vec<String> = (0..n).map(|i| format!("r{}", i)).collect();
+        list.iter()
+            .map(|name| Processor::new(list.clone(), name.clone()))
+            .collect()
+    }
+
+    // Deliver messages FIFO between replicas until the network is quiet, mirroring the
+    // Sender's routing. Returns the client responses produced along the way
+    fn pump(cluster: &mut [Processor], inflight: Vec<(usize, EMsg)>) -> Vec<EMsg> {
+        pump_with_down(cluster, inflight, &[])
+    }
+
+    // Same as pump, but replicas in `down` neither receive nor send anything
+    fn pump_with_down(
+        cluster: &mut [Processor],
+        inflight: Vec<(usize, EMsg)>,
+        down: &[usize],
+    ) -> Vec<EMsg> {
+        // (from, to, msg), oks are replied to whoever sent the request
+        let mut inflight: VecDeque<(usize, usize, EMsg)> = inflight
+            .into_iter()
+            .map(|(to, msg)| (to, to, msg))
+            .collect();
+        let mut responses = vec![];
+        while let Some((from, to, msg)) = inflight.pop_front() {
+            if down.contains(&to) {
+                continue;
+            }
+            for out in cluster[to].process(msg) {
+                match &out {
+                    EMsg::PreAccept(_)
+                    | EMsg::PreAcceptBatch(_)
+                    | EMsg::Accept(_)
+                    | EMsg::Commit(_)
+                    | EMsg::Prepare { .. }
+                    | EMsg::ReconfigAck { .. } => {
+                        for peer in (0..cluster.len()).filter(|&i| i != to) {
+                            inflight.push_back((to, peer, out.clone()));
+                        }
+                    }
+                    EMsg::PreAcceptOk(_)
+                    | EMsg::PreAcceptOkBatch(_)
+                    | EMsg::AcceptOk(_)
+                    | EMsg::PrepareOk(_)
+                    | EMsg::BallotRejected { .. } => inflight.push_back((to, from, out)),
+                    _ => responses.push(out),
+                }
+            }
+        }
+        responses
+    }
+
+    // Execute every committed instance on every replica, returning the replies produced
+    fn execute_all(cluster: &mut [Processor]) -> Vec<EMsg> {
+        let mut out = vec![];
+        for p in cluster.iter_mut() {
+            let committed: Vec<Instance> = p
+                .log_entries()
+                .filter(|(_, _, e)| matches!(e.status, CmdStatus::Committed))
+                .map(|(replica, instance_num, _)| Instance {
+                    replica: replica.clone(),
+                    instance_num,
+                })
+                .collect();
+            for inst in committed {
+                if p.deps_all_ready(&inst) {
+                    out.append(&mut p.execute_cmd(&inst));
+                }
+            }
+        }
+        out
+    }
+
+    fn client_request(replica: usize, msg_id: &str, cmd: Command) -> (usize, EMsg) {
+        let msg = EMsg::ClientRequest(crate::common::ClientRequest {
+            client_id: "c".into(),
+            msg_id: msg_id.into(),
+            cmd,
+            timestamp_us: None,
+        });
+        (replica, msg)
+    }
+
+    fn client_append(replica: usize, suffix: &str) -> (usize, EMsg) {
+        let cmd = Command::Append {
+            key: Variable::new("k"),
+            suffix: suffix.into(),
+        };
+        client_request(replica, suffix, cmd)
+    }
+
+    fn r0_inst() -> Instance {
+        Instance {
+            replica: "r0".into(),
+            instance_num: 0,
+        }
+    }
+
+    fn mock_prepare_ok(status: PrepareStatus, suffix: &str, seq: u64) -> PrepareOkMsg {
+        PrepareOkMsg {
+            instance: r0_inst(),
+            from_replica: "r2".into(),
+            status,
+            cmd: Some(Command::Append {
+                key: Variable::new("k"),
+                suffix: suffix.into(),
+            }),
+            seq,
+            deps: BTreeSet::new(),
+            commit_ts_ms: 0,
+        }
+    }
+
+    // --- Tests ---
+    #[test]
+    fn test_ping_answered_with_pong() {
+        let mut p = mock_processor(3);
+
+        let out = p.process(EMsg::Ping {
+            from: "r1".into(),
+            timestamp_us: 42,
+        });
+
+        assert_eq!(out.len(), 1);
+        assert!(matches!(
+            &out[0],
+            EMsg::Pong { from, echo_timestamp_us: 42 } if from == "r0"
+        ));
+    }
+
+    #[test]
+    fn test_local_ping_is_forwarded_to_all_peers() {
+        let mut p = mock_processor(3);
+        mark_seen(&p, "r2", Duration::from_secs(60));
+
+        let out = p.process(EMsg::Ping {
+            from: "r0".into(),
+            timestamp_us: 1,
+        });
+        assert!(matches!(&out[0], EMsg::Ping { .. }));
+
+        // Dead peers still get pinged so they can come back
+        let dests = mock_sender(&p).resolve_destinations(&out[0]);
+        assert_eq!(dests, vec!["r1".to_string(), "r2".to_string()]);
+    }
+
+    #[test]
+    fn test_non_responding_replica_skipped_in_broadcast() {
+        let p = mock_processor(3);
+        mark_seen(&p, "r1", Duration::from_secs(1));
+        mark_seen(&p, "r2", PEER_TIMEOUT + Duration::from_secs(1));
+
+        let dests = mock_sender(&p).resolve_destinations(&mock_commit());
+
+        assert_eq!(dests, vec!["r1".to_string()]);
+    }
+
+    #[test]
+    fn test_pong_revives_replica() {
+        let mut p = mock_processor(3);
+        mark_seen(&p, "r2", PEER_TIMEOUT + Duration::from_secs(1));
+        let sender = mock_sender(&p);
+        assert!(!sender.is_alive("r2"));
+
+        p.process(EMsg::Pong {
+            from: "r2".into(),
+            echo_timestamp_us: 1,
+        });
+
+        assert!(sender.is_alive("r2"));
+        assert_eq!(
+            sender.resolve_destinations(&mock_commit()),
+            vec!["r1".to_string(), "r2".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_metrics_reported_only_when_configured() {
+        let mut cluster = mock_cluster(3);
+        let set = Command::Set {
+            key: Variable::new("k"),
+            val: "v".into(),
+            ttl_ms: None,
+        };
+        pump(&mut cluster, vec![client_request(0, "1", set)]);
+        let tick = || EMsg::Ping {
+            from: "r0".into(),
+            timestamp_us: 1,
+        };
+        let is_report = |m: &EMsg| matches!(m, EMsg::Metrics { .. });
+        assert!(!cluster[0].process(tick()).iter().any(is_report));
+
+        let config = MetricsConfig {
+            actor: "metrics".into(),
+            interval_ms: 0,
+        };
+        cluster[0].metrics = Some(MetricsReporter::new("r0".into(), &config));
+        let out = cluster[0].process(tick());
+        let Some(EMsg::Metrics {
+            source,
+            counters,
+            gauges,
+        }) = out.into_iter().find(is_report)
+        else {
+            panic!("Expected a Metrics report");
+        };
+        assert_eq!(source, "r0");
+        assert_eq!(counters["client_requests"], 1);
+        assert_eq!(counters["fast_path_commits"], 1);
+        assert_eq!(counters["executed"], 1);
+        assert_eq!(counters["client_errors"], 0);
+        assert_eq!(gauges["pending_instances"], 0.0);
+    }
+
+    #[test]
+    fn test_dump_state_reports_committed_instances() {
+        let mut p = mock_processor(3);
+        for i in 0..2 {
+            p.process(EMsg::Commit(crate::common::CommitMsg {
+                cmd: Command::Set {
+                    key: Variable::new(format!("k{}", i)),
+                    val: "v".into(),
+                    ttl_ms: None,
+                },
+                seq: i as u64 + 1,
+                deps: BTreeSet::new(),
+                instance: Instance {
+                    replica: "r1".into(),
+                    instance_num: i,
+                },
+                commit_ts_ms: 1,
+                trace_ctx: None,
+            }));
+        }
+
+        let out = p.process(EMsg::DumpState);
+
+        assert_eq!(out.len(), 1);
+        let EMsg::DumpStateResponse(state) = &out[0] else {
+            panic!("Expected DumpStateResponse");
+        };
+        let state: serde_json::Value = serde_json::from_str(state).unwrap();
+        assert_eq!(state["replica"], "r0");
+        assert_eq!(state["wire_version"], crate::common::WIRE_VERSION);
+        let r1_log = state["cmds"]["r1"].as_array().unwrap();
+        assert_eq!(r1_log.len(), 2);
+        assert_eq!(r1_log[1]["instance"], 1);
+        assert_eq!(r1_log[1]["seq"], 2);
+        assert_eq!(r1_log[1]["status"], "Committed");
+        assert_eq!(r1_log[0]["cmd"], "SET k0=v");
+        assert_eq!(r1_log[0]["deps"].as_array().unwrap().len(), 0);
+        assert_eq!(CmdStatus::Committed.to_string(), "committed");
+    }
+
+    #[test]
+    fn test_dump_digests_agree_after_commands() {
+        let dump = |p: &mut Processor| -> serde_json::Value {
+            let EMsg::DumpStateResponse(state) = p.process(EMsg::DumpState).remove(0) else {
+                panic!("Expected DumpStateResponse");
+            };
+            serde_json::from_str(&state).unwrap()
+        };
+        let mut cluster = mock_cluster(3);
+        let empty = dump(&mut cluster[0])["data_digest"].clone();
+
+        let set = |k: &str, v: &str| Command::Set {
+            key: Variable::new(k),
+            val: v.into(),
+            ttl_ms: None,
+        };
+        pump(
+            &mut cluster,
+            vec![
+                client_request(0, "1", set("a", "1")),
+                client_request(1, "2", set("b", "2")),
+            ],
+        );
+        execute_all(&mut cluster);
+        let dumps: Vec<_> = cluster.iter_mut().map(dump).collect();
+        for d in &dumps {
+            assert_eq!(d["data_digest"], dumps[0]["data_digest"]);
+            assert_eq!(d["data"]["a"], "1");
+        }
+        assert_ne!(dumps[0]["data_digest"], empty);
+        let r0_log = dumps[2]["cmds"]["r0"].as_array().unwrap();
+        assert_eq!(r0_log[0]["cmd"], "SET a=1");
+        assert_eq!(r0_log[0]["status"], "Executed");
+
+        // Same keys, one value swapped
+        pump(&mut cluster, vec![client_request(0, "3", set("a", "2"))]);
+        execute_all(&mut cluster);
+        assert_ne!(
+            dump(&mut cluster[0])["data_digest"],
+            dumps[0]["data_digest"]
+        );
+    }
+
+    #[test]
+    fn test_single_replica_sends_to_self() {
+        let p = mock_processor(1);
+        let dests = mock_sender(&p).resolve_destinations(&mock_commit());
+        assert_eq!(dests, vec!["r0".to_string()]);
+    }
+
+    #[test]
+    fn test_concurrent_appends_converge() {
+        let mut cluster = mock_cluster(3);
+
+        // Both leaders propose before seeing each other's PreAccept
+        let mut responses = pump(
+            &mut cluster,
+            vec![client_append(0, "a"), client_append(1, "b")],
+        );
+        responses.append(&mut execute_all(&mut cluster));
+
+        let vals: Vec<_> = cluster
+            .iter()
+            .map(|p| {
+                p.data
+                    .get(&Variable::new("k"))
+                    .and_then(|v| v.val.as_str().map(str::to_string))
+            })
+            .collect();
+        let first = vals[0].clone().unwrap();
+        assert!(first == "ab" || first == "ba", "got {}", first);
+        assert!(
+            vals.iter().all(|v| v.as_deref() == Some(first.as_str())),
+            "{:?}",
+            vals
+        );
+
+        // Each leader replies once its own append has run, in the agreed order
+        let mut lens: Vec<usize> = responses
+            .into_iter()
+            .map(|r| match r {
+                EMsg::ClientResponse(ClientResponse {
+                    cmd_result: CommandResult::Append { len, .. },
+                    server_received_us: Some(received),
+                    server_responded_us: Some(responded),
+                    ..
+                }) => {
+                    assert!(received <= responded);
+                    len
+                }
+                other => panic!("Expected Append response, got {:?}", other),
+            })
+            .collect();
+        lens.sort();
+        assert_eq!(lens, vec![1, 2]);
+    }
+
+    #[test]
+    fn test_concurrent_set_if_absent_one_wins() {
+        let k = Variable::new("k");
+        let setnx = |replica, val: &str| {
+            let cmd = Command::SetIfAbsent {
+                key: k.clone(),
+                val: val.into(),
+            };
+            client_request(replica, val, cmd)
+        };
+
+        for requests in [
+            vec![setnx(0, "a"), setnx(1, "b")],
+            vec![setnx(1, "b"), setnx(0, "a")],
+        ] {
+            let mut cluster = mock_cluster(3);
+            let mut responses = pump(&mut cluster, requests);
+            responses.append(&mut execute_all(&mut cluster));
+
+            let wins: Vec<bool> = responses
+                .iter()
+                .map(|r| match r {
+                    EMsg::ClientResponse(ClientResponse {
+                        cmd_result: CommandResult::SetIfAbsent { success, .. },
+                        ..
+                    }) => *success,
+                    other => panic!("Expected SetIfAbsent response, got {:?}", other),
+                })
+                .collect();
+            assert_eq!(wins.len(), 2);
+            assert_eq!(wins.iter().filter(|&&w| w).count(), 1);
+
+            let winner = cluster[0].data.get(&k).unwrap().val.clone();
+            for p in &cluster {
+                assert_eq!(p.data.get(&k).map(|v| &v.val), Some(&winner));
+            }
+        }
+    }
+
+    #[test]
+    fn test_contended_key_versions_agree() {
+        let mut cluster = mock_cluster(3);
+        let k = Variable::new("k");
+        let set = |replica, val: &str| {
+            let cmd = Command::Set {
+                key: k.clone(),
+                val: val.into(),
+                ttl_ms: None,
+            };
+            client_request(replica, val, cmd)
+        };
+
+        let mut responses = pump(&mut cluster, vec![set(0, "a"), set(1, "b"), set(2, "c")]);
+        responses.append(&mut execute_all(&mut cluster));
+
+        // Each write got a distinct version, the highest one being the value left everywhere
+        let mut versions: Vec<(u64, String)> = responses
+            .iter()
+            .map(|r| match r {
+                EMsg::ClientResponse(ClientResponse {
+                    msg_id,
+                    cmd_result: CommandResult::Set { version, .. },
+                    ..
+                }) => (*version, msg_id.clone()),
+                other => panic!("Expected Set response, got {:?}", other),
+            })
+            .collect();
+        versions.sort();
+        assert_eq!(
+            versions.iter().map(|(v, _)| *v).collect::<Vec<_>>(),
+            vec![1, 2, 3]
+        );
+        let last = versions[2].1.as_str();
+        for p in &cluster {
+            assert_eq!(p.versions[&k], 3);
+            assert_eq!(p.data[&k].val.as_str(), Some(last));
+        }
+
+        // A later read sees the latest version
+        let get = client_request(0, "get", Command::Get { key: k.clone() });
+        let mut responses = pump(&mut cluster, vec![get]);
+        responses.append(&mut execute_all(&mut cluster));
+        assert!(matches!(
+            &responses[..],
+            [EMsg::ClientResponse(ClientResponse {
+                cmd_result: CommandResult::Get { version: 3, .. },
+                ..
+            })]
+        ));
+    }
+
+    #[test]
+    fn test_multi_get_answers_in_request_order() {
+        let mut cluster = mock_cluster(3);
+        for (i, key) in ["A", "B", "C"].into_iter().enumerate() {
+            let set = Command::Set {
+                key: Variable::new(key),
+                val: format!("val_{}", key).into(),
+                ttl_ms: None,
+            };
+            pump(&mut cluster, vec![client_request(i, key, set)]);
+        }
+        execute_all(&mut cluster);
+
+        let keys: Vec<Variable> = ["C", "A", "B"].into_iter().map(Variable::new).collect();
+        let read = Command::MultiGet { keys: keys.clone() };
+        let mut responses = pump(&mut cluster, vec![client_request(1, "mget", read)]);
+        responses.append(&mut execute_all(&mut cluster));
+        let [
+            EMsg::ClientResponse(ClientResponse {
+                cmd_result: CommandResult::MultiGet { vals },
+                ..
+            }),
+        ] = &responses[..]
+        else {
+            panic!("Expected one MultiGet response, got {:?}", responses);
+        };
+        let expected: Vec<_> = keys
+            .into_iter()
+            .zip(["val_C", "val_A", "val_B"])
+            .map(|(k, v)| (k, Some(v.into())))
+            .collect();
+        assert_eq!(vals, &expected);
+    }
+
+    #[test]
+    fn test_overlapping_txns_serialize() {
+        let mut cluster = mock_cluster(3);
+        let (x, y) = (Variable::new("x"), Variable::new("y"));
+        // Each reads one key and writes both, so they conflict on everything
+        let txn = |replica, name: &str, read: &Variable| {
+            let cmd = Command::Txn {
+                reads: vec![read.clone()],
+                writes: vec![(x.clone(), name.into()), (y.clone(), name.into())],
+            };
+            client_request(replica, name, cmd)
+        };
+
+        let mut responses = pump(&mut cluster, vec![txn(0, "t1", &x), txn(1, "t2", &y)]);
+        responses.append(&mut execute_all(&mut cluster));
+
+        let reads: HashMap<String, Option<Value>> = responses
+            .into_iter()
+            .map(|r| match r {
+                EMsg::ClientResponse(ClientResponse {
+                    msg_id,
+                    cmd_result: CommandResult::Txn { mut reads },
+                    ..
+                }) => (msg_id, reads.remove(0).1),
+                other => panic!("Expected Txn response, got {:?}", other),
+            })
+            .collect();
+
+        // Whichever ran second saw all of the first's writes, which it then overwrote
+        let last = cluster[0].data[&x].val.clone();
+        let (second, first) = if last.as_str() == Some("t1") {
+            ("t1", "t2")
+        } else {
+            ("t2", "t1")
+        };
+        assert_eq!(reads[first], None);
+        assert_eq!(reads[second], Some(Value::from(first)));
+        for p in &cluster {
+            assert_eq!(p.data[&x].val, last);
+            assert_eq!(p.data[&y].val, last);
+        }
+    }
+
+    fn metrics(p: &mut Processor) -> MetricsMsg {
+        match p.process(EMsg::GetMetrics).remove(0) {
+            EMsg::MetricsResponse(m) => m,
+            other => panic!("Expected MetricsResponse, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_responses_route_by_client_id() {
+        let mut cluster = mock_cluster(3);
+        // Sent over some other actor's connection on behalf of c2
+        let req = EMsg::ClientRequest(crate::common::ClientRequest {
+            client_id: "c2".into(),
+            msg_id: "1".into(),
+            cmd: Command::Get {
+                key: Variable::new("k"),
+            },
+            timestamp_us: None,
+        });
+        let mut responses = pump(&mut cluster, vec![(0, req)]);
+        responses.append(&mut execute_all(&mut cluster));
+        assert_eq!(responses.len(), 1);
+
+        let sender = mock_sender(&cluster[0]);
+        assert_eq!(
+            sender.resolve_destinations(&responses[0]),
+            vec!["c2".to_string()]
+        );
+        assert_eq!(metrics(&mut cluster[0]).dropped_responses, 0);
+    }
+
+    #[test]
+    fn test_response_without_client_is_counted_not_sent() {
+        let mut p = mock_processor(3);
+        let (_, req) = client_request(0, "1", Command::Noop);
+        let EMsg::ClientRequest(mut req) = req else {
+            unreachable!()
+        };
+        req.client_id = String::new();
+        let out = p.process(EMsg::ClientRequest(req));
+
+        assert!(mock_sender(&p).resolve_destinations(&out[0]).is_empty());
+        assert_eq!(metrics(&mut p).dropped_responses, 1);
+    }
+
+    #[test]
+    fn test_metrics_count_fast_and_slow_paths() {
+        // Writes to distinct keys never conflict
+        let mut cluster = mock_cluster(3);
+        for (i, key) in ["x", "y", "z"].into_iter().enumerate() {
+            let cmd = Command::Append {
+                key: Variable::new(key),
+                suffix: "a".into(),
+            };
+            pump(&mut cluster, vec![client_request(i, key, cmd)]);
+        }
+        for p in cluster.iter_mut() {
+            let m = metrics(p);
+            assert_eq!(m.fast_path_commits, 1);
+            assert_eq!(m.slow_path_commits, 0);
+            assert_eq!(m.conflict_detections, 0);
+        }
+
+        // Concurrent writes to one key make at least one leader go through Paxos-Accept
+        let mut cluster = mock_cluster(3);
+        pump(
+            &mut cluster,
+            vec![client_append(0, "a"), client_append(1, "b")],
+        );
+        let (m0, m1) = (metrics(&mut cluster[0]), metrics(&mut cluster[1]));
+        assert!(m0.slow_path_commits + m1.slow_path_commits > 0);
+        assert!(m0.conflict_detections + m1.conflict_detections > 0);
+        assert_eq!(
+            m0.fast_path_commits
+                + m0.slow_path_commits
+                + m1.fast_path_commits
+                + m1.slow_path_commits,
+            2
+        );
+    }
+
+    #[cfg(feature = "telemetry")]
+    #[test]
+    fn test_handler_spans_carry_instance() {
+        let recorder = Arc::new(SpanRecorder::default());
+        let mut cluster = mock_cluster(3);
+        tracing::subscriber::with_default(recorder.clone(), || {
+            pump(&mut cluster, vec![client_append(0, "a")]);
+        });
+
+        let spans = recorder.spans.lock().unwrap();
+        let instances_of = |name: &str| {
+            spans
+                .iter()
+                .filter(|(span, _)| span.name() == name)
+                .map(|(_, fields)| (fields.0["instance"].as_str(), fields.0["replica"].as_str()))
+                .collect::<Vec<_>>()
+        };
+        // client_request_handler records its instance later, through Span::current
+        let (_, client_span) = spans
+            .iter()
+            .find(|(span, _)| span.name() == "client_request_handler")
+            .unwrap();
+        assert_eq!(client_span.0["client_id"], "c");
+        assert_eq!(client_span.0["msg_id"], "a");
+        let mut pre_accepts = instances_of("pre_accept_handler");
+        pre_accepts.sort();
+        assert_eq!(pre_accepts, [("r0/0", "r1"), ("r0/0", "r2")]);
+        assert!(!instances_of("pre_accept_ok_handler").is_empty());
+        assert!(
+            instances_of("commit_handler")
+                .iter()
+                .all(|(inst, _)| *inst == "r0/0")
+        );
+    }
+
+    #[test]
+    fn test_seqs_are_hybrid_clock_timestamps() {
+        let mut cluster = mock_cluster(3);
+        let before_ms = crate::common::now_us() / 1000;
+        pump(&mut cluster, vec![client_append(0, "a")]);
+        pump(&mut cluster, vec![client_append(1, "b")]);
+
+        let first = cluster[2].lookup(&inst("r0", 0)).unwrap().seq;
+        let second = cluster[2].lookup(&inst("r1", 0)).unwrap().seq;
+        assert!(first >> 16 >= before_ms);
+        assert!(second > first);
+        // A leader's timestamp is past every interfering seq, so replicas agree with it
+        for p in cluster.iter_mut().take(2) {
+            assert_eq!(metrics(p).fast_path_commits, 1);
+        }
+    }
+
+    #[test]
+    fn test_watchers_notified_of_changes() {
+        let mut cluster = mock_cluster(3);
+        let k = Variable::new("k");
+        let watch = |cmd: Command| {
+            let msg = EMsg::ClientRequest(crate::common::ClientRequest {
+                client_id: "w".into(),
+                msg_id: cmd.op_name().into(),
+                cmd,
+                timestamp_us: None,
+            });
+            (1, msg)
+        };
+        let notifications = |out: &[EMsg]| -> Vec<(String, Option<Value>)> {
+            out.iter()
+                .filter_map(|msg| match msg {
+                    EMsg::WatchNotify(n) => Some((n.client_id.clone(), n.val.clone())),
+                    _ => None,
+                })
+                .collect()
+        };
+        let set = |msg_id: &str, val: &str| {
+            let cmd = Command::Set {
+                key: Variable::new("k"),
+                val: val.into(),
+                ttl_ms: None,
+            };
+            client_request(0, msg_id, cmd)
+        };
+
+        // Watching a key that doesn't exist yet still subscribes
+        let out = pump(&mut cluster, vec![watch(Command::Watch { key: k.clone() })]);
+        assert!(matches!(&out[..], [EMsg::ClientResponse(r)]
+            if r.cmd_result == CommandResult::Get { key: k.clone(), val: None, version: 0 }));
+
+        // Only r1, where the watch was registered, notifies
+        let out = pump(&mut cluster, vec![set("1", "a")]);
+        assert_eq!(notifications(&out), vec![("w".into(), Some("a".into()))]);
+
+        // A Cas that fails changes nothing
+        let cas = Command::Cas {
+            key: k.clone(),
+            expected: Some("zz".into()),
+            new: "b".into(),
+        };
+        let out = pump(&mut cluster, vec![client_request(0, "2", cas)]);
+        assert!(notifications(&out).is_empty());
+
+        pump(
+            &mut cluster,
+            vec![watch(Command::Unwatch { key: k.clone() })],
+        );
+        assert!(cluster[1].watchers.is_empty());
+        let out = pump(&mut cluster, vec![set("3", "c")]);
+        assert!(notifications(&out).is_empty());
+    }
+
+    #[test]
+    fn test_key_affinity_routing_lowers_conflicts() {
+        use crate::client::key_to_replica;
+
+        // Hot keys written by many concurrent clients, each request routed by `route`
+        fn conflicts(route: impl Fn(usize, &Variable) -> usize) -> u64 {
+            let mut cluster = mock_cluster(3);
+            let requests = (0..30)
+                .map(|i| {
+                    let key = Variable::new(format!("k{}", i % 4));
+                    let to = route(i, &key);
+                    let cmd = Command::Append {
+                        key,
+                        suffix: "a".into(),
+                    };
+                    client_request(to, &i.to_string(), cmd)
+                })
+                .collect();
+            assert_eq!(pump(&mut cluster, requests).len(), 30);
+            cluster
+                .iter_mut()
+                .map(|p| metrics(p).conflict_detections)
+                .sum()
+        }
+
+        let replicas: Vec<String> = (0..3).map(|i| format!("r{}", i)).collect();
+        let spread = conflicts(|i, _| i % 3);
+        let affinity = conflicts(|_, key| {
+            let owner = key_to_replica(key, &replicas);
+            replicas.iter().position(|r| r == owner).unwrap()
+        });
+        assert!(
+            affinity < spread,
+            "affinity {} vs spread {}",
+            affinity,
+            spread
+        );
+    }
+
+    #[test]
+    fn test_sparse_log_cluster_converges() {
+        let list: Vec<String> = (0..3).map(|i| format!("r{}", i)).collect();
+        let config = ProcessorConfig {
+            sparse_log: true,
+            ..Default::default()
+        };
+        let mut sparse: Vec<_> = list
+            .iter()
+            .map(|name| Processor::new_with_config(list.clone(), name.clone(), config.clone()))
+            .collect();
+        let mut dense = mock_cluster(3);
+        let k = Variable::new("k");
+
+        for cluster in [&mut sparse, &mut dense] {
+            for round in 0..5 {
+                let requests = (0..3)
+                    .map(|r| client_append(r, &format!("{}{}", r, round)))
+                    .collect();
+                pump(cluster, requests);
+                execute_all(cluster);
+            }
+        }
+        // Seqs come from the wall clock, so only replicas of one cluster agree on the order
+        let expected = sparse[0].data[&k].val.clone();
+        assert_eq!(expected.len(), dense[0].data[&k].val.len());
+        for s in sparse.iter_mut() {
+            assert!(s.cmds.values().all(Vec::is_empty));
+            assert_eq!(s.log_len("r0"), 5);
+            assert_eq!(s.data[&k].val, expected);
+            assert_eq!(s.try_compact_log(), 15);
+            assert!(s.cmds_flat.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_partitioned_replica_catches_up_from_log() {
+        let mut cluster = mock_cluster(3);
+        let k = Variable::new("k");
+
+        // r2 misses everything while partitioned
+        for suffix in ["a", "b", "c"] {
+            pump_with_down(&mut cluster, vec![client_append(0, suffix)], &[2]);
+        }
+        pump_with_down(&mut cluster, vec![client_append(1, "d")], &[2]);
+        assert!(cluster[2].cmds["r0"].is_empty());
+
+        // Back online, r2 hears from r0 again after a long silence
+        mark_seen(&cluster[2], "r0", PEER_TIMEOUT * 2);
+        let requests = cluster[2].process(EMsg::Pong {
+            from: "r0".into(),
+            echo_timestamp_us: 0,
+        });
+        assert_eq!(requests.len(), 3);
+        for request in requests {
+            assert!(matches!(&request, EMsg::RequestLog { requester, .. } if requester == "r2"));
+            for response in cluster[0].process(request) {
+                cluster[2].process(response);
+            }
+        }
+        execute_all(&mut cluster);
+
+        assert_eq!(cluster[2].cmds["r0"].len(), 3);
+        assert_eq!(cluster[2].cmds["r1"].len(), 1);
+        for p in &cluster {
+            assert_eq!(p.data[&k].val.len(), 4);
+            assert_eq!(p.data[&k].val, cluster[0].data[&k].val);
+        }
+    }
+
+    #[test]
+    fn test_partitioned_replica_catches_up_after_peers_compacted() {
+        let mut cluster = mock_cluster(3);
+        let k = Variable::new("k");
+
+        for suffix in ["a", "b", "c"] {
+            pump_with_down(&mut cluster, vec![client_append(0, suffix)], &[2]);
+        }
+        pump_with_down(&mut cluster, vec![client_append(1, "d")], &[2]);
+        execute_all(&mut cluster);
+        for p in &mut cluster[..2] {
+            let from = p.replica_name.clone();
+            p.process(EMsg::Ping {
+                from,
+                timestamp_us: 0,
+            });
+            assert_eq!(p.watermark_of("r0"), 3);
+        }
+
+        // Recovering a compacted instance gets the store back too
+        let ballot = Ballot {
+            epoch: 1,
+            replica_name: "r2".into(),
+        };
+        let out = cluster[0].process(EMsg::Prepare {
+            instance: inst("r0", 0),
+            ballot,
+        });
+        assert!(matches!(&out[..], [EMsg::StoreSnapshot(_)]));
+
+        mark_seen(&cluster[2], "r0", PEER_TIMEOUT * 2);
+        let requests = cluster[2].process(EMsg::Pong {
+            from: "r0".into(),
+            echo_timestamp_us: 0,
+        });
+        for request in requests {
+            for response in cluster[0].process(request) {
+                cluster[2].process(response);
+            }
+        }
+        assert_eq!(cluster[2].watermark_of("r0"), 3);
+        assert_eq!(cluster[2].watermark_of("r1"), 1);
+        assert_eq!(cluster[2].data[&k].val, cluster[0].data[&k].val);
+
+        // And takes part again from there
+        pump(&mut cluster, vec![client_append(2, "e")]);
+        execute_all(&mut cluster);
+        for p in &cluster {
+            assert_eq!(p.data[&k].val.len(), 5);
+            assert_eq!(p.data[&k].val, cluster[0].data[&k].val);
+        }
+    }
+
+    #[test]
+    fn test_request_log_is_batched() {
+        let mut p = mock_processor(3);
+        for i in 0..LOG_SYNC_BATCH + 5 {
+            let mut commit = mock_commit();
+            if let EMsg::Commit(msg) = &mut commit {
+                msg.instance.replica = "r1".into();
+                msg.instance.instance_num = i;
+            }
+            p.process(commit);
+        }
+
+        let from = |instance_num| EMsg::RequestLog {
+            requester: "r2".into(),
+            from_instance: Instance {
+                replica: "r1".into(),
+                instance_num,
+            },
+        };
+        let batch_len = |out: Vec<EMsg>| match &out[..] {
+            [EMsg::LogResponse { entries }] => entries.len(),
+            other => panic!("Expected LogResponse, got {:?}", other),
+        };
+        assert_eq!(batch_len(p.process(from(0))), LOG_SYNC_BATCH);
+        assert_eq!(batch_len(p.process(from(LOG_SYNC_BATCH))), 5);
+    }
+
+    fn rejection_code(out: &[EMsg]) -> Option<ErrorCode> {
+        match out {
+            [
+                EMsg::ClientResponse(ClientResponse {
+                    cmd_result: CommandResult::Error { code, .. },
+                    ..
+                }),
+            ] => Some(*code),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_oversized_value_is_rejected_before_the_log() {
+        let mut p = mock_processor(3);
+        p.limits.max_value_len = 4;
+
+        let cmd = Command::Set {
+            key: Variable::new("k"),
+            val: "too long".into(),
+            ttl_ms: None,
+        };
+        let (_, req) = client_request(0, "1", cmd);
+        let out = p.process(req);
+        assert_eq!(rejection_code(&out), Some(ErrorCode::ValueTooLarge));
+        assert!(p.cmds["r0"].is_empty());
+        assert!(p.app_meta.is_empty());
+
+        // A rejected request doesn't count as seen, so a fixed retry can go through
+        let (_, req) = client_append(0, "1");
+        assert!(matches!(&p.process(req)[..], [EMsg::PreAccept(_)]));
+    }
+
+    #[test]
+    fn test_invalid_keys_and_ids_are_rejected() {
+        let mut p = mock_processor(3);
+        p.limits.max_key_len = 8;
+
+        let (_, req) = client_request(
+            0,
+            "1",
+            Command::Get {
+                key: Variable::new(""),
+            },
+        );
+        assert_eq!(rejection_code(&p.process(req)), Some(ErrorCode::InvalidKey));
+
+        let long_key = Variable::new("much_too_long");
+        let (_, req) = client_request(
+            0,
+            "2",
+            Command::MultiGet {
+                keys: vec![long_key],
+            },
+        );
+        assert_eq!(rejection_code(&p.process(req)), Some(ErrorCode::InvalidKey));
+
+        let (_, req) = client_request(
+            0,
+            "",
+            Command::Get {
+                key: Variable::new("k"),
+            },
+        );
+        assert_eq!(
+            rejection_code(&p.process(req)),
+            Some(ErrorCode::InvalidRequest)
+        );
+
+        assert!(p.cmds["r0"].is_empty());
+    }
+
+    #[test]
+    fn test_read_only_replica_rejects_clients_but_follows() {
+        let mut cluster = mock_cluster(3);
+        cluster[0].replica_mode = ReplicaMode::ReadOnly;
+
+        let (_, req) = client_append(0, "a");
+        let out = cluster[0].process(req);
+        assert_eq!(out.len(), 1);
+        let EMsg::ClientError(err) = &out[0] else {
+            panic!("Expected ClientError");
+        };
+        assert_eq!((err.client_id.as_str(), err.msg_id.as_str()), ("c", "a"));
+        assert!(err.retryable);
+        assert!(cluster[0].cmds["r0"].is_empty());
+        assert_eq!(
+            mock_sender(&cluster[0]).resolve_destinations(&out[0]),
+            vec!["c".to_string()]
+        );
+
+        // Still replicates commands led by others
+        pump(&mut cluster, vec![client_append(1, "b")]);
+        execute_all(&mut cluster);
+        let k = Variable::new("k");
+        assert_eq!(
+            cluster[0].data.get(&k).and_then(|v| v.val.as_str()),
+            Some("b")
+        );
+    }
+
+    #[test]
+    fn test_duplicate_request_executed_once() {
+        let mut cluster = mock_cluster(3);
+        let k = Variable::new("k");
+
+        // Retried while the original is still in flight: dropped
+        let mut responses = pump(
+            &mut cluster,
+            vec![client_append(0, "a"), client_append(0, "a")],
+        );
+        responses.append(&mut execute_all(&mut cluster));
+        assert_eq!(responses.len(), 1);
+
+        // Retried after the response went out: replayed from the cache
+        let mut replay = pump(&mut cluster, vec![client_append(0, "a")]);
+        replay.append(&mut execute_all(&mut cluster));
+        assert!(matches!(
+            (&replay[..], &responses[..]),
+            ([EMsg::ClientResponse(a)], [EMsg::ClientResponse(b)]) if a == b
+        ));
+
+        for p in &cluster {
+            assert_eq!(p.data.get(&k).and_then(|v| v.val.as_str()), Some("a"));
+        }
+        assert_eq!(cluster[0].cmds["r0"].len(), 1);
+    }
+
+    #[test]
+    fn test_duplicate_request_broadcast_once() {
+        let mut p = mock_processor(3);
+        let (_, req) = client_append(0, "a");
+
+        let first = p.process(req.clone());
+        let second = p.process(req);
+        assert!(matches!(&first[..], [EMsg::PreAccept(_)]));
+        assert!(second.is_empty());
+        assert_eq!(p.cmds["r0"].len(), 1);
+        assert_eq!(p.seen_request("c", "a").unwrap().instance, r0_inst());
+    }
+
+    #[test]
+    fn test_dedup_window_forgets_oldest_request() {
+        let mut p = mock_processor(3);
+        for i in 0..=DEDUP_WINDOW {
+            let inst = Instance {
+                replica: "r0".into(),
+                instance_num: i,
+            };
+            p.remember_request("c", &i.to_string(), &inst);
+        }
+
+        assert!(p.seen_request("c", "0").is_none());
+        assert!(p.seen_request("c", "1").is_some());
+        assert_eq!(p.seen_requests["c"].requests.len(), DEDUP_WINDOW);
+        // Windows are per client
+        assert!(p.seen_request("other", "1").is_none());
+    }
+
+    #[test]
+    fn test_recovery_finishes_pre_accepted_command() {
+        let mut cluster = mock_cluster(3);
+        let k = Variable::new("k");
+
+        // r0 crashes right after its PreAccept reached r1 only
+        let (_, request) = client_append(0, "a");
+        let pre_accept = cluster[0].process(request).remove(0);
+        pump_with_down(&mut cluster, vec![(1, pre_accept)], &[0]);
+
+        pump_with_down(&mut cluster, vec![(1, EMsg::Noop(r0_inst()))], &[0]);
+        execute_all(&mut cluster);
+
+        for p in &cluster[1..] {
+            let entry = p.lookup(&r0_inst()).unwrap();
+            assert!(matches!(entry.status, CmdStatus::Executed));
+            assert_eq!(p.data.get(&k).and_then(|v| v.val.as_str()), Some("a"));
+        }
+    }
+
+    #[test]
+    fn test_recovery_fills_unknown_instance_with_noop() {
+        let mut cluster = mock_cluster(3);
+
+        pump(&mut cluster, vec![(1, EMsg::Noop(r0_inst()))]);
+        execute_all(&mut cluster);
+
+        for p in &cluster {
+            let entry = p.lookup(&r0_inst()).unwrap();
+            assert_eq!(entry.cmd, Command::Noop);
+            assert!(matches!(entry.status, CmdStatus::Executed));
+        }
+        assert!(cluster[1].prepare_oks.is_empty());
+        assert!(cluster[1].recovery_acks.is_empty());
+    }
+
+    #[test]
+    fn test_preparing_instance_rejects_original_leader() {
+        let mut cluster = mock_cluster(3);
+        let (_, request) = client_append(0, "a");
+        let pre_accept = cluster[0].process(request).remove(0);
+
+        let out = cluster[1].process(EMsg::Noop(r0_inst()));
+        assert!(matches!(&out[..], [EMsg::Prepare { .. }]));
+        // A second trigger doesn't start another round
+        assert!(cluster[1].process(EMsg::Noop(r0_inst())).is_empty());
+
+        let out = cluster[1].process(pre_accept);
+        assert!(
+            matches!(&out[..], [EMsg::BallotRejected { current_ballot, .. }]
+            if current_ballot.epoch == 1 && current_ballot.replica_name == "r1")
+        );
+        assert!(cluster[1].is_preparing(&r0_inst()));
+    }
+
+    #[test]
+    fn test_higher_ballot_prepare_supersedes_lower() {
+        let mut cluster = mock_cluster(3);
+        let prepare_r1 = cluster[1].process(EMsg::Noop(r0_inst())).remove(0);
+        let prepare_r2 = cluster[2].process(EMsg::Noop(r0_inst())).remove(0);
+        // Both at epoch 1, ties go to the higher replica name
+        assert!(matches!(&prepare_r2, EMsg::Prepare { ballot, .. }
+            if ballot.epoch == 1 && ballot.replica_name == "r2"));
+
+        let out = cluster[0].process(prepare_r1.clone());
+        assert!(matches!(&out[..], [EMsg::PrepareOk(_)]));
+        let out = cluster[0].process(prepare_r2.clone());
+        assert!(matches!(&out[..], [EMsg::PrepareOk(_)]));
+
+        // r1's attempt is now stale at r0
+        let rejected = cluster[0].process(prepare_r1).remove(0);
+        assert!(
+            matches!(&rejected, EMsg::BallotRejected { current_ballot, .. }
+            if current_ballot.replica_name == "r2")
+        );
+        assert!(cluster[1].process(rejected).is_empty());
+        assert!(!cluster[1].prepare_oks.contains_key(&r0_inst()));
+
+        // So is anything the original leader sends at epoch 0
+        let stale_accept = EMsg::Accept(AcceptMsg {
+            cmd: Command::Noop,
+            seq: 1,
+            deps: BTreeSet::new(),
+            instance: r0_inst(),
+            ballot: Ballot::initial(&r0_inst()),
+            trace_ctx: None,
+        });
+        let out = cluster[0].process(stale_accept);
+        assert!(matches!(&out[..], [EMsg::BallotRejected { .. }]));
+
+        // r2's Prepare is answered everywhere, r1 included
+        let out = cluster[1].process(prepare_r2);
+        assert!(matches!(&out[..], [EMsg::PrepareOk(_)]));
+    }
+
+    #[test]
+    fn test_recover_instance_prefers_accepted() {
+        let mut p = mock_processor(3);
+        let responses = vec![
+            mock_prepare_ok(PrepareStatus::PreAccepted, "a", 5),
+            mock_prepare_ok(PrepareStatus::Accepted, "b", 2),
+        ];
+
+        let out = p.recover_instance(r0_inst(), responses);
+        match &out[..] {
+            [EMsg::Accept(m)] => {
+                assert_eq!(m.seq, 2);
+                assert!(
+                    matches!(&m.cmd, Command::Append { suffix, .. } if suffix.as_str() == Some("b"))
+                );
+            }
+            other => panic!("Expected Accept, got {:?}", other),
+        }
+
+        // Replicas that pre-accepted different commands means none can have committed
+        p.noop_handler(r0_inst());
+        let responses = vec![
+            mock_prepare_ok(PrepareStatus::PreAccepted, "a", 1),
+            mock_prepare_ok(PrepareStatus::PreAccepted, "b", 1),
+        ];
+        let out = p.recover_instance(r0_inst(), responses);
+        assert!(matches!(&out[..], [EMsg::Accept(m)] if m.cmd == Command::Noop));
+    }
+
+    fn mock_configured(config: ProcessorConfig) -> Processor {
+        let list = (0..3).map(|i| format!("r{}", i)).collect();
+        Processor::new_with_config(list, "r0".to_string(), config)
+    }
+
+    fn inst(replica: &str, instance_num: usize) -> Instance {
+        Instance {
+            replica: replica.into(),
+            instance_num,
+        }
+    }
+
+    // A Set to `key` committed by its leader, as a follower sees it
+    fn commit_set(p: &mut Processor, instance: Instance, key: &str, deps: &[Instance]) {
+        p.process(EMsg::Commit(crate::common::CommitMsg {
+            cmd: Command::Set {
+                key: Variable::new(key),
+                val: "v".into(),
+                ttl_ms: None,
+            },
+            seq: 1,
+            deps: deps.iter().cloned().collect(),
+            instance,
+            commit_ts_ms: 1,
+            trace_ctx: None,
+        }));
+    }
+
+    fn tick(p: &mut Processor) -> Vec<EMsg> {
+        p.process(EMsg::Ping {
+            from: "r0".into(),
+            timestamp_us: 0,
+        })
+    }
+
+    #[test]
+    fn test_gc_threshold_delays_compaction() {
+        let mut p = mock_configured(ProcessorConfig {
+            gc_threshold: 3,
+            ..ProcessorConfig::default()
+        });
+        commit_set(&mut p, inst("r1", 0), "a", &[]);
+        commit_set(&mut p, inst("r1", 1), "a", &[inst("r1", 0)]);
+        p.execute_cmd(&inst("r1", 1));
+        tick(&mut p);
+        assert_eq!(p.watermark_of("r1"), 0);
+
+        commit_set(&mut p, inst("r1", 2), "a", &[inst("r1", 1)]);
+        p.execute_cmd(&inst("r1", 2));
+        tick(&mut p);
+        assert_eq!(p.watermark_of("r1"), 3);
+    }
+
+    #[test]
+    fn test_reads_led_elsewhere_do_not_block_compaction() {
+        let mut cluster = mock_cluster(3);
+        let k = Variable::new("k");
+        let set = Command::Set {
+            key: k.clone(),
+            val: "v".into(),
+            ttl_ms: None,
+        };
+        pump(
+            &mut cluster,
+            vec![
+                client_request(1, "get", Command::Get { key: k }),
+                client_request(1, "set", set),
+            ],
+        );
+        // Nothing depends on the Get, so its Commit is all that gets r0 and r2 past it
+        for p in [&cluster[0], &cluster[2]] {
+            assert!(matches!(
+                p.lookup(&inst("r1", 0)).unwrap().status,
+                CmdStatus::Executed
+            ));
+        }
+        execute_all(&mut cluster);
+        for p in &mut cluster {
+            let from = p.replica_name.clone();
+            p.process(EMsg::Ping {
+                from,
+                timestamp_us: 0,
+            });
+            // r0 and r2 never serve r1's Get, but still get past it
+            assert_eq!(p.executed_watermark()["r1"], 2, "{}", p.replica_name);
+            assert_eq!(p.watermark_of("r1"), 2, "{}", p.replica_name);
+        }
+    }
+
+    #[test]
+    fn test_max_pending_reads_refuses_clients() {
+        let mut p = mock_configured(ProcessorConfig {
+            max_pending_reads: 1,
+            ..ProcessorConfig::default()
+        });
+        p.pending_reads.insert(inst("r1", 0));
+        let (_, req) = client_append(0, "a");
+        let out = p.process(req.clone());
+        assert!(matches!(&out[..], [EMsg::ClientError(err)] if err.retryable));
+        assert!(p.cmds["r0"].is_empty());
+
+        p.pending_reads.clear();
+        assert!(matches!(&p.process(req)[..], [EMsg::PreAccept(_)]));
+    }
+
+    #[test]
+    fn test_pending_reads_empty_once_everything_executes() {
+        let mut cluster = mock_cluster(3);
+        let requests: Vec<_> = (0..2000)
+            .map(|i| {
+                let key = Variable::new(format!("k{}", i % 10));
+                let cmd = if i % 2 == 0 {
+                    Command::Get { key }
+                } else {
+                    Command::Set {
+                        key,
+                        val: "v".into(),
+                        ttl_ms: None,
+                    }
+                };
+                client_request(i % 3, &i.to_string(), cmd)
+            })
+            .collect();
+        // Concurrent batches, so some Gets commit before the Sets they depend on
+        for batch in requests.chunks(20) {
+            pump(&mut cluster, batch.to_vec());
+            for p in cluster.iter_mut() {
+                p.try_compact_log(); // Keeps get_interfs scans short
+            }
+        }
+        execute_all(&mut cluster);
+
+        for p in cluster.iter_mut() {
+            p.gc_pending_reads();
+            assert_eq!(p.pending_reads.len(), 0);
+        }
+    }
+
+    #[test]
+    fn test_gc_pending_reads_drops_executed_entries() {
+        let mut p = mock_configured(ProcessorConfig::default());
+        commit_set(&mut p, inst("r1", 0), "a", &[]);
+        commit_set(&mut p, inst("r1", 1), "b", &[]);
+        p.mark_executed(&inst("r1", 0));
+        // Left behind by a path that executed without removing it
+        p.pending_reads.extend([inst("r1", 0), inst("r1", 1)]);
+
+        tick(&mut p);
+        assert_eq!(p.pending_reads, HashSet::from([inst("r1", 1)]));
+        assert_eq!(p.gc_pending_reads(), 0);
+    }
+
+    #[test]
+    fn test_execution_batch_size_spreads_execution() {
+        let mut p = mock_configured(ProcessorConfig {
+            execution_batch_size: 1,
+            ..ProcessorConfig::default()
+        });
+        for key in ["a", "b", "c"] {
+            let num = p.cmds["r1"].len();
+            commit_set(&mut p, inst("r1", num), key, &[]);
+        }
+        p.ready_queue.extend((0..3).map(|i| inst("r1", i)));
+        let executed = |p: &Processor| {
+            p.cmds["r1"]
+                .iter()
+                .flatten()
+                .filter(|e| matches!(e.status, CmdStatus::Executed))
+                .count()
+        };
+
+        p.try_execute_ready();
+        assert_eq!(executed(&p), 1);
+        // Each message handled drains one more
+        let ping = EMsg::Ping {
+            from: "r1".into(),
+            timestamp_us: 0,
+        };
+        p.process(ping.clone());
+        assert_eq!(executed(&p), 2);
+        p.process(ping);
+        assert_eq!(executed(&p), 3);
+    }
+
+    #[test]
+    fn test_recovery_timeout_recovers_blocking_dep() {
+        let pre_accept = EMsg::PreAccept(crate::common::PreAcceptMsg {
+            cmd: Command::Get {
+                key: Variable::new("a"),
+            },
+            seq: 1,
+            deps: BTreeSet::new(),
+            instance: inst("r1", 0),
+            trace_ctx: None,
+        });
+        let blocked = |config| {
+            let mut p = mock_configured(config);
+            p.process(pre_accept.clone());
+            // A parked instance waiting on r1/0, whose leader never commits it
+            commit_set(&mut p, inst("r2", 0), "a", &[inst("r1", 0)]);
+            p.pending_reads.insert(inst("r2", 0));
+            p
+        };
+
+        // The default timeout hasn't passed yet
+        let mut p = blocked(ProcessorConfig::default());
+        let out = tick(&mut p);
+        assert!(!out.iter().any(|m| matches!(m, EMsg::Prepare { .. })));
+        assert!(p.blocked_since.contains_key(&inst("r1", 0)));
+
+        let mut p = blocked(ProcessorConfig {
+            recovery_timeout_ms: 0,
+            ..ProcessorConfig::default()
+        });
+        let out = tick(&mut p);
+        assert!(
+            out.iter()
+                .any(|m| matches!(m, EMsg::Prepare { instance, .. } if *instance == inst("r1", 0)))
+        );
+        assert!(p.is_preparing(&inst("r1", 0)));
+    }
+
+    #[test]
+    fn test_overloaded_leader_stays_bounded() {
+        let max_inflight_instances = 10;
+        let list: Vec<String> = (0..3).map(|i| format!("r{}", i)).collect();
+        let mut cluster: Vec<Processor> = list
+            .iter()
+            .map(|name| {
+                let config = ProcessorConfig {
+                    max_inflight_instances,
+                    ..ProcessorConfig::default()
+                };
+                Processor::new_with_config(list.clone(), name.clone(), config)
+            })
+            .collect();
+
+        // Clients keep sending while nothing commits
+        let set = |i: usize| Command::Set {
+            key: Variable::new(format!("k{}", i)),
+            val: "v".into(),
+            ttl_ms: None,
+        };
+        let (mut overloaded, mut pre_accepts) = (0, vec![]);
+        for i in 0..1000 {
+            let (_, req) = client_request(0, &i.to_string(), set(i));
+            for out in cluster[0].process(req) {
+                match out {
+                    EMsg::ClientError(err) => {
+                        assert!(err.retryable);
+                        assert_eq!(err.reason, "overloaded");
+                        overloaded += 1;
+                    }
+                    pre_accept => pre_accepts.push(pre_accept),
+                }
+            }
+            assert!(cluster[0].cmds["r0"].len() <= max_inflight_instances + 1);
+        }
+        assert_eq!(pre_accepts.len(), max_inflight_instances + 1);
+        assert_eq!(overloaded, 1000 - pre_accepts.len());
+
+        // Once the backlog commits, executes and is compacted, requests get through again
+        let oks: Vec<_> = pre_accepts
+            .into_iter()
+            .flat_map(|pre_accept| cluster[1].process(pre_accept))
+            .map(|ok| (0, ok))
+            .collect();
+        pump(&mut cluster, oks);
+        execute_all(&mut cluster);
+        tick(&mut cluster[0]);
+        assert!(cluster[0].cmds["r0"].is_empty());
+        let (_, req) = client_request(0, "more", set(0));
+        assert!(matches!(&cluster[0].process(req)[..], [EMsg::PreAccept(_)]));
+    }
+
+    #[test]
+    fn test_pipeline_depth_bounds_uncommitted_instances() {
+        let mut p = mock_configured(ProcessorConfig {
+            pipeline_depth: Some(2),
+            ..Default::default()
+        });
+        let mut peer = Processor::new(p.replica_list.clone(), "r1".into());
+
+        // Four requests at once: two start, two wait for room
+        let mut pre_accepts = vec![];
+        for suffix in ["a", "b", "c", "d"] {
+            let (_, req) = client_append(0, suffix);
+            pre_accepts.extend(p.process(req));
+        }
+        assert_eq!(pre_accepts.len(), 2);
+        assert_eq!(p.pending_instances, [0, 1]);
+        assert_eq!(p.queued_requests.len(), 2);
+
+        // r1 is a majority with r0, each commit lets a queued request start
+        let mut started = 0;
+        let mut to_peer = pre_accepts;
+        while !to_peer.is_empty() {
+            let mut next = vec![];
+            for msg in to_peer {
+                started += matches!(msg, EMsg::PreAccept(_)) as usize;
+                for reply in peer.process(msg) {
+                    next.extend(p.process(reply).into_iter().filter(|m| {
+                        matches!(m, EMsg::PreAccept(_) | EMsg::Accept(_) | EMsg::Commit(_))
+                    }));
+                    assert!(p.pending_instances.len() <= 2);
+                }
+            }
+            to_peer = next;
+        }
+        assert_eq!(started, 4);
+        assert!(p.pending_instances.is_empty());
+        assert!(p.queued_requests.is_empty());
+        for n in 0..4 {
+            assert!(matches!(
+                p.lookup(&inst("r0", n)).unwrap().status,
+                CmdStatus::Committed | CmdStatus::Executed
+            ));
+        }
+    }
+
+    #[test]
+    fn test_concurrent_gets_on_one_key_commit_fast() {
+        let mut cluster = mock_cluster(3);
+        let get = Command::Get {
+            key: Variable::new("k"),
+        };
+        // Each leader has its own Get in the log when the other's PreAccept arrives
+        let mut out = pump(
+            &mut cluster,
+            vec![
+                client_request(0, "g0", get.clone()),
+                client_request(1, "g1", get),
+            ],
+        );
+        out.append(&mut execute_all(&mut cluster));
+        let answered = out
+            .iter()
+            .filter(|m| matches!(m, EMsg::ClientResponse(r) if matches!(r.cmd_result, CommandResult::Get { .. })))
+            .count();
+        assert_eq!(answered, 2);
+        for p in &mut cluster[..2] {
+            assert_eq!(p.no_conflict_fast_reads, 1);
+            let m = metrics(p);
+            assert_eq!((m.fast_path_commits, m.slow_path_commits), (1, 0));
+            assert_eq!(m.conflict_detections, 0);
+        }
+
+        // Two Sets crossing the same way do conflict, and go through Accept
+        let set = |v: &str| Command::Set {
+            key: Variable::new("k"),
+            val: v.into(),
+            ttl_ms: None,
+        };
+        pump(
+            &mut cluster,
+            vec![
+                client_request(0, "s0", set("a")),
+                client_request(1, "s1", set("b")),
+            ],
+        );
+        let slow: u64 = cluster
+            .iter_mut()
+            .map(|p| metrics(p).slow_path_commits)
+            .sum();
+        assert!(slow > 0);
+    }
+
+    #[test]
+    fn test_fast_read_answers_ahead_of_commit() {
+        let list: Vec<String> = (0..3).map(|i| format!("r{}", i)).collect();
+        let config = ProcessorConfig {
+            fast_reads: true,
+            ..Default::default()
+        };
+        let mut cluster: Vec<_> = list
+            .iter()
+            .map(|name| Processor::new_with_config(list.clone(), name.clone(), config.clone()))
+            .collect();
+        let k = Variable::new("k");
+        let set = Command::Set {
+            key: k.clone(),
+            val: "v".into(),
+            ttl_ms: None,
+        };
+        pump(&mut cluster, vec![client_request(0, "set", set)]);
+        execute_all(&mut cluster);
+
+        // No write is in flight, so every PreAcceptOk comes back without deps
+        let get = Command::Get { key: k.clone() };
+        let out = pump(&mut cluster, vec![client_request(0, "get", get)]);
+        assert!(matches!(&out[..], [EMsg::ClientResponse(r)]
+            if r.cmd_result == CommandResult::Get { key: k.clone(), val: Some("v".into()), version: 1 }));
+        let m = metrics(&mut cluster[0]);
+        assert_eq!((m.fast_reads_count, m.fast_path_commits), (1, 2));
+        assert_eq!(cluster[0].no_conflict_fast_reads, 1);
+        assert!(matches!(
+            cluster[0].lookup(&inst("r0", 1)).unwrap().status,
+            CmdStatus::Executed
+        ));
+        // Still committed at the peers, which then compact past it
+        for p in &mut cluster[1..] {
+            assert!(matches!(
+                p.lookup(&inst("r0", 1)).unwrap().status,
+                CmdStatus::Committed | CmdStatus::Executed
+            ));
+            let from = p.replica_name.clone();
+            p.process(EMsg::Ping {
+                from,
+                timestamp_us: 0,
+            });
+            assert_eq!(p.watermark_of("r0"), 2, "{}", p.replica_name);
+        }
+    }
+
+    #[test]
+    fn test_reconfig_adds_replica_and_grows_quorums() {
+        let old: Vec<String> = (0..3).map(|i| format!("r{}", i)).collect();
+        let new: Vec<String> = (0..4).map(|i| format!("r{}", i)).collect();
+        let mut cluster: Vec<_> = old
+            .iter()
+            .map(|name| Processor::new(old.clone(), name.clone()))
+            .collect();
+        cluster.push(Processor::new(new.clone(), "r3".into()));
+        let set = |key: &str| {
+            let cmd = Command::Set {
+                key: Variable::new(key),
+                val: "v".into(),
+                ttl_ms: None,
+            };
+            client_request(0, key, cmd)
+        };
+        let committed = |p: &Processor, n| {
+            matches!(
+                p.lookup(&inst("r0", n)).unwrap().status,
+                CmdStatus::Committed | CmdStatus::Executed
+            )
+        };
+
+        // r1 alone is a majority of three with r0
+        pump_with_down(&mut cluster, vec![set("a")], &[2, 3]);
+        assert!(committed(&cluster[0], 0));
+
+        let reconfig = EMsg::ReconfigRequest {
+            new_replica_list: new.clone(),
+            epoch: 1,
+        };
+        pump(&mut cluster, vec![(0, reconfig)]);
+        for p in &cluster {
+            assert_eq!((&p.replica_list, p.epoch), (&new, 1));
+            assert_eq!((p.get_majority(), p.fast_quorum()), (2, 2));
+            // Every replica acked, the transition is over
+            assert_eq!(*p.members.lock().unwrap(), new);
+        }
+
+        // Now it takes two peers
+        pump_with_down(&mut cluster, vec![set("b")], &[2, 3]);
+        assert!(!committed(&cluster[0], 2));
+        pump_with_down(&mut cluster, vec![set("c")], &[3]);
+        assert!(committed(&cluster[0], 3));
+
+        // Stale epochs change nothing
+        let stale = EMsg::ReconfigRequest {
+            new_replica_list: old.clone(),
+            epoch: 1,
+        };
+        assert!(cluster[1].process(stale).is_empty());
+        assert_eq!(cluster[1].replica_list, new);
+    }
+
+    #[test]
+    fn test_sender_broadcasts_to_old_and_new_replicas_in_transition() {
+        let mut p = mock_processor(3);
+        let new: Vec<String> = ["r0", "r1", "r3"].map(String::from).to_vec();
+        let out = p.process(EMsg::ReconfigRequest {
+            new_replica_list: new.clone(),
+            epoch: 1,
+        });
+        let sender = mock_sender(&p);
+        assert_eq!(sender.resolve_destinations(&out[0]), ["r1", "r2", "r3"]);
+
+        // Switched, but r3 hasn't acked yet
+        p.apply_reconfig(new, 1);
+        assert_eq!(p.replica_list, ["r0", "r1", "r3"]);
+        assert_eq!(sender.peers(), ["r1", "r2", "r3"]);
+        p.process(EMsg::ReconfigAck {
+            epoch: 1,
+            from: "r1".into(),
+        });
+        p.process(EMsg::ReconfigAck {
+            epoch: 1,
+            from: "r3".into(),
+        });
+        assert_eq!(sender.peers(), ["r1", "r3"]);
+    }
+
+    #[test]
+    fn test_unknown_instance_recovered_as_noop_unblocks_dependents() {
+        let mut cluster = mock_cluster(3);
+        // r0 crashed before anyone saw its instance, which r2's write depends on
+        for p in &mut cluster[1..] {
+            commit_set(p, inst("r2", 0), "a", &[r0_inst()]);
+        }
+        execute_all(&mut cluster);
+        assert!(matches!(
+            cluster[1].lookup(&inst("r2", 0)).unwrap().status,
+            CmdStatus::Committed
+        ));
+        // Only the recovery itself decides to fill the instance
+        assert!(cluster[1].process(EMsg::ProposeNoOp(r0_inst())).is_empty());
+
+        // r1 and r2 both answer the Prepare without knowing the instance
+        pump_with_down(&mut cluster, vec![(1, EMsg::Noop(r0_inst()))], &[0]);
+        execute_all(&mut cluster);
+
+        for p in &cluster[1..] {
+            let entry = p.lookup(&r0_inst()).unwrap();
+            assert_eq!((&entry.cmd, entry.seq), (&Command::Noop, 1));
+            assert!(matches!(entry.status, CmdStatus::Executed));
+            assert!(matches!(
+                p.lookup(&inst("r2", 0)).unwrap().status,
+                CmdStatus::Executed
+            ));
+            assert_eq!(
+                p.data.get(&Variable::new("a")).and_then(|v| v.val.as_str()),
+                Some("v")
+            );
+        }
+    }
+
+    #[test]
+    fn test_duplicate_oks_counted_once() {
+        let mut p = mock_processor(5); // Majority 2, fast quorum 3
+        let (_, req) = client_append(0, "a");
+        p.process(req);
+        let pre_accept_ok = |from: &str, seq: u64| {
+            EMsg::PreAcceptOk(PreAcceptOkMsg {
+                seq,
+                deps: BTreeSet::new(),
+                instance: r0_inst(),
+                from_replica: from.into(),
+            })
+        };
+        let accept_ok = |from: &str| {
+            EMsg::AcceptOk(AcceptOkMsg {
+                instance: r0_inst(),
+                from_replica: from.into(),
+            })
+        };
+
+        // r1 saw a higher seq, so the instance goes the slow path
+        let seq = p.lookup(&r0_inst()).unwrap().seq;
+        assert!(p.process(pre_accept_ok("r1", seq + 1)).is_empty());
+        assert!(p.process(pre_accept_ok("r1", seq + 1)).is_empty());
+        assert_eq!(p.quorum_ctr[0], 1);
+        let out = p.process(pre_accept_ok("r2", seq));
+        assert!(matches!(&out[..], [EMsg::Accept(_)]));
+
+        assert!(p.process(accept_ok("r1")).is_empty());
+        assert!(p.process(accept_ok("r1")).is_empty());
+        assert_eq!(p.acc_quorum_ctr[0], 1);
+        let out = p.process(accept_ok("r2"));
+        assert!(matches!(&out[0], EMsg::Commit(_)));
+    }
+
+    #[test]
+    fn test_lazy_execution_acks_sets_at_commit() {
+        let list: Vec<String> = (0..3).map(|i| format!("r{}", i)).collect();
+        let config = ProcessorConfig {
+            lazy_execution: true,
+            ..Default::default()
+        };
+        let mut cluster: Vec<_> = list
+            .iter()
+            .map(|name| Processor::new_with_config(list.clone(), name.clone(), config.clone()))
+            .collect();
+        let k = Variable::new("k");
+        let set = Command::Set {
+            key: k.clone(),
+            val: "v".into(),
+            ttl_ms: None,
+        };
+        let out = pump(&mut cluster, vec![client_request(0, "set", set)]);
+        assert!(matches!(&out[..], [EMsg::ClientResponse(r)]
+            if r.cmd_result == CommandResult::Set { key: k.clone(), version: 0 }));
+        assert!(matches!(
+            cluster[0].lookup(&r0_inst()).unwrap().status,
+            CmdStatus::Committed
+        ));
+        assert!(cluster[0].data.is_empty());
+
+        // The Get depends on the Set, which executes first
+        let out = pump(
+            &mut cluster,
+            vec![client_request(0, "get", Command::Get { key: k.clone() })],
+        );
+        assert!(matches!(&out[..], [EMsg::ClientResponse(r)]
+            if r.cmd_result == CommandResult::Get { key: k.clone(), val: Some("v".into()), version: 1 }));
+        assert!(matches!(
+            cluster[0].lookup(&r0_inst()).unwrap().status,
+            CmdStatus::Executed
+        ));
+    }
+
+    #[test]
+    fn test_get_state_reads_store_without_consensus() {
+        let mut cluster = mock_cluster(3);
+        let set = |key: &str, ttl_ms| Command::Set {
+            key: Variable::new(key),
+            val: format!("{}_val", key).into(),
+            ttl_ms,
+        };
+        pump(
+            &mut cluster,
+            vec![
+                client_request(0, "a", set("a", None)),
+                client_request(0, "b", set("b", None)),
+                client_request(0, "gone", set("gone", Some(0))),
+            ],
+        );
+        let get_state = |p: &mut Processor, key: Option<&str>| {
+            let key = key.map(Variable::new);
+            match p.process(EMsg::GetState(GetStateRequest { key })).remove(0) {
+                EMsg::GetStateResponse(r) => r.entries,
+                other => panic!("Expected GetStateResponse, got {:?}", other),
+            }
+        };
+        let instance_num = cluster[0].instance_num;
+
+        // Expired values are left out
+        assert_eq!(
+            get_state(&mut cluster[0], None),
+            vec![
+                (Variable::new("a"), "a_val".into()),
+                (Variable::new("b"), "b_val".into()),
+            ]
+        );
+        assert_eq!(
+            get_state(&mut cluster[0], Some("b")),
+            vec![(Variable::new("b"), "b_val".into())]
+        );
+        assert!(get_state(&mut cluster[0], Some("missing")).is_empty());
+        // Nothing went through the log
+        assert_eq!(cluster[0].instance_num, instance_num);
+    }
+
+    #[test]
+    fn test_queued_requests_starting_together_share_one_pre_accept_batch() {
+        let mut p = mock_configured(ProcessorConfig {
+            pipeline_depth: Some(2),
+            batch_pre_accepts: true,
+            ..Default::default()
+        });
+        let mut peer = Processor::new(p.replica_list.clone(), "r1".into());
+        // Distinct keys, so the instances don't depend on each other
+        let mut pre_accepts = vec![];
+        for key in ["a", "b", "c", "d"] {
+            let set = Command::Set {
+                key: Variable::new(key),
+                val: "v".into(),
+                ttl_ms: None,
+            };
+            let (_, req) = client_request(0, key, set);
+            pre_accepts.extend(p.process(req));
+        }
+        assert_eq!(pre_accepts.len(), 2);
+
+        // r0/1 commits first, so the window only moves once r0/0 commits, by two slots
+        let second = peer.process(pre_accepts.pop().unwrap());
+        let out: Vec<EMsg> = second.into_iter().flat_map(|ok| p.process(ok)).collect();
+        assert!(!out.iter().any(|m| matches!(m, EMsg::PreAccept(_))));
+        let first = peer.process(pre_accepts.pop().unwrap());
+        let out: Vec<EMsg> = first.into_iter().flat_map(|ok| p.process(ok)).collect();
+        let batch = out
+            .into_iter()
+            .find(|m| matches!(m, EMsg::PreAcceptBatch(_)))
+            .expect("Expected a PreAcceptBatch");
+        let EMsg::PreAcceptBatch(msgs) = &batch else {
+            unreachable!()
+        };
+        let instances: Vec<Instance> = msgs.iter().map(|m| m.instance.clone()).collect();
+        assert_eq!(instances, [inst("r0", 2), inst("r0", 3)]);
+
+        // One PreAcceptOkBatch back commits both
+        let oks = peer.process(batch);
+        assert!(matches!(&oks[..], [EMsg::PreAcceptOkBatch(oks)] if oks.len() == 2));
+        for ok in oks {
+            p.process(ok);
+        }
+        for n in 0..4 {
+            assert!(matches!(
+                p.lookup(&inst("r0", n)).unwrap().status,
+                CmdStatus::Committed | CmdStatus::Executed
+            ));
+        }
+    }
+
+    #[test]
+    fn test_quorum_policies_pick_commit_paths() {
+        let commit_paths = |quorum_policy, down: &[usize]| {
+            let list: Vec<String> = (0..5).map(|i| format!("r{}", i)).collect();
+            let config = ProcessorConfig {
+                quorum_policy,
+                ..Default::default()
+            };
+            let mut cluster: Vec<_> = list
+                .iter()
+                .map(|name| Processor::new_with_config(list.clone(), name.clone(), config.clone()))
+                .collect();
+            let set = Command::Set {
+                key: Variable::new("k"),
+                val: "v".into(),
+                ttl_ms: None,
+            };
+            pump_with_down(&mut cluster, vec![client_request(0, "set", set)], down);
+            let m = metrics(&mut cluster[0]);
+            (m.fast_path_commits, m.slow_path_commits)
+        };
+
+        // Fast quorum of 3 peers
+        assert_eq!(commit_paths(QuorumPolicyKind::Standard, &[]), (1, 0));
+        assert_eq!(commit_paths(QuorumPolicyKind::AlwaysSlowPath, &[]), (0, 1));
+        // With two peers down, 2 matching oks are only a fast quorum for AlwaysFastPath
+        assert_eq!(commit_paths(QuorumPolicyKind::Standard, &[3, 4]), (0, 0));
+        assert_eq!(
+            commit_paths(QuorumPolicyKind::AlwaysFastPath, &[3, 4]),
+            (1, 0)
+        );
+    }
 }
@@ -0,0 +1,74 @@
+use serde::Deserialize;
+use std::fmt;
+use std::sync::Arc;
+
+/// Decides how many PreAcceptOks and AcceptOks a leader waits for. Sizes exclude the
+/// leader itself, and `n_replicas` counts it. Only the leader of an instance consults it
+pub trait QuorumPolicy: fmt::Debug + Send + Sync {
+    /// Oks that let the slow path go on. Two majorities plus their leaders must intersect
+    fn majority(&self, n_replicas: usize) -> u32;
+    /// Matching PreAcceptOks that commit on the fast path. At least the majority
+    fn fast_quorum(&self, n_replicas: usize) -> u32;
+}
+
+/// The EPaxos sizes: a majority of floor(N/2) and a fast quorum of N-2 peers, both at least 1
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StandardQuorumPolicy;
+
+impl QuorumPolicy for StandardQuorumPolicy {
+    fn majority(&self, n_replicas: usize) -> u32 {
+        ((n_replicas / 2) as u32).max(1)
+    }
+
+    fn fast_quorum(&self, n_replicas: usize) -> u32 {
+        (n_replicas.saturating_sub(2) as u32).max(1)
+    }
+}
+
+/// A fast quorum larger than the peers, so every instance goes through Paxos-Accept
+#[derive(Debug, Clone, Copy)]
+pub struct AlwaysSlowPathPolicy;
+
+impl QuorumPolicy for AlwaysSlowPathPolicy {
+    fn majority(&self, n_replicas: usize) -> u32 {
+        StandardQuorumPolicy.majority(n_replicas)
+    }
+
+    fn fast_quorum(&self, n_replicas: usize) -> u32 {
+        n_replicas as u32
+    }
+}
+
+/// Commits on the fast path with a majority of matching oks. Not safe to recover from,
+/// for tests only
+#[derive(Debug, Clone, Copy)]
+pub struct AlwaysFastPathPolicy;
+
+impl QuorumPolicy for AlwaysFastPathPolicy {
+    fn majority(&self, n_replicas: usize) -> u32 {
+        StandardQuorumPolicy.majority(n_replicas)
+    }
+
+    fn fast_quorum(&self, n_replicas: usize) -> u32 {
+        StandardQuorumPolicy.majority(n_replicas)
+    }
+}
+
+/// Policy picked by the `quorum_policy` field of the processor config
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub enum QuorumPolicyKind {
+    #[default]
+    Standard,
+    AlwaysSlowPath,
+    AlwaysFastPath,
+}
+
+impl QuorumPolicyKind {
+    pub fn build(self) -> Arc<dyn QuorumPolicy> {
+        match self {
+            QuorumPolicyKind::Standard => Arc::new(StandardQuorumPolicy),
+            QuorumPolicyKind::AlwaysSlowPath => Arc::new(AlwaysSlowPathPolicy),
+            QuorumPolicyKind::AlwaysFastPath => Arc::new(AlwaysFastPathPolicy),
+        }
+    }
+}
@@ -0,0 +1,102 @@
+use crate::common::{ClientRequest, Command, EMsg};
+use crate::epaxos::Processor;
+use std::time::Instant;
+use tracing::{info, warn};
+
+// Membership changes. A Reconfig command goes through the log like any other, and every
+// replica switches its replica list, and so its quorum sizes, as it commits the instance.
+// From the request on, broadcasts also reach the replicas of the old list, until every
+// replica of the new one acked the epoch.
+
+impl Processor {
+    /// Leads a Reconfig instance switching to `new_replica_list` at `epoch`
+    pub fn reconfig_request_handler(
+        &mut self,
+        new_replica_list: Vec<String>,
+        epoch: u64,
+    ) -> Vec<EMsg> {
+        if epoch <= self.epoch {
+            warn!(
+                "{}: Ignoring reconfiguration to epoch {}, already on {}",
+                self.replica_name, epoch, self.epoch
+            );
+            return vec![];
+        }
+        info!(
+            "{}: Reconfiguring to {:?} at epoch {}",
+            self.replica_name, new_replica_list, epoch
+        );
+        self.widen_members(&new_replica_list);
+        // Proposed on behalf of myself, so a repeated request is deduplicated like a retry
+        self.client_request_handler(ClientRequest {
+            client_id: self.replica_name.clone(),
+            msg_id: format!("reconfig_{}", epoch),
+            cmd: Command::Reconfig {
+                replica_list: new_replica_list,
+                epoch,
+            },
+            timestamp_us: None,
+        })
+    }
+
+    /// Switches to `replica_list` once its Reconfig instance committed here. Quorum sizes
+    /// follow from the new list, also for instances already in flight
+    pub fn apply_reconfig(&mut self, replica_list: Vec<String>, epoch: u64) -> Vec<EMsg> {
+        if epoch <= self.epoch {
+            return vec![]; // A Reconfig ordered after a newer one
+        }
+        info!(
+            "{}: Switching to {:?} at epoch {}",
+            self.replica_name, replica_list, epoch
+        );
+        self.widen_members(&replica_list);
+        let now = Instant::now();
+        for replica in &replica_list {
+            self.cmds.entry(replica.clone()).or_default();
+            if *replica != self.replica_name {
+                self.last_seen
+                    .lock()
+                    .unwrap()
+                    .entry(replica.clone())
+                    .or_insert(now);
+            }
+        }
+        self.replica_list = replica_list;
+        self.epoch = epoch;
+        self.reconfig_acks = [self.replica_name.clone()].into();
+        self.end_transition_if_acked();
+        vec![EMsg::ReconfigAck {
+            epoch,
+            from: self.replica_name.clone(),
+        }]
+    }
+
+    pub fn reconfig_ack_handler(&mut self, epoch: u64, from: String) -> Vec<EMsg> {
+        if epoch == self.epoch {
+            self.reconfig_acks.insert(from);
+            self.end_transition_if_acked();
+        }
+        vec![]
+    }
+
+    /// Adds the replicas of `replica_list` to those broadcasts go to
+    fn widen_members(&self, replica_list: &[String]) {
+        let mut members = self.members.lock().unwrap();
+        for replica in replica_list {
+            if !members.contains(replica) {
+                members.push(replica.clone());
+            }
+        }
+    }
+
+    /// Stops broadcasting to replicas outside the replica list once all of it is on my epoch
+    fn end_transition_if_acked(&self) {
+        if self
+            .replica_list
+            .iter()
+            .all(|r| self.reconfig_acks.contains(r))
+        {
+            *self.members.lock().unwrap() = self.replica_list.clone();
+        }
+    }
+}
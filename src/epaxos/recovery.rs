@@ -0,0 +1,347 @@
+use crate::common::{
+    AcceptMsg, Ballot, Command, CommitMsg, EMsg, Instance, PrepareOkMsg, PrepareStatus, now_us,
+    outgoing_trace_ctx,
+};
+use crate::epaxos::{CmdEntry, CmdStatus, Processor};
+use std::collections::{BTreeSet, HashSet};
+use std::time::{Duration, Instant};
+
+#[cfg(debug_assertions)]
+use tracing::info;
+
+// Explicit prepare, run by any replica to finish an instance whose leader went silent.
+// Each attempt takes a ballot above any seen for the instance. Replicas reject PreAccepts,
+// Accepts and Prepares below the highest ballot they saw, so an older attempt, or the
+// original leader, can't decide the instance behind a newer one's back.
+
+impl Processor {
+    /// Starts recovering `instance`: marks it Preparing and asks every peer what it knows
+    #[cfg_attr(
+        feature = "telemetry",
+        tracing::instrument(skip_all, fields(instance = %instance, replica = %self.replica_name))
+    )]
+    pub fn noop_handler(&mut self, instance: Instance) -> Vec<EMsg> {
+        let own_view = self.prepare_ok_for(&instance);
+        if matches!(own_view.status, PrepareStatus::Committed)
+            || self.prepare_oks.contains_key(&instance)
+        {
+            // Nothing to recover, or already recovering
+            return vec![];
+        }
+
+        #[cfg(debug_assertions)]
+        info!("{}: Recovering {}", self.replica_name, instance);
+
+        match self.lookup_mut(&instance) {
+            Some(entry) => entry.status = CmdStatus::Preparing,
+            None => self.cmds_insert(
+                &instance,
+                CmdEntry {
+                    cmd: Command::Noop,
+                    seq: 0,
+                    deps: BTreeSet::new(),
+                    status: CmdStatus::Preparing,
+                    commit_ts_ms: 0,
+                },
+            ),
+        }
+        let current = self.current_ballot(&instance);
+        let ballot = Ballot {
+            epoch: current.epoch + 1,
+            replica_name: self.replica_name.clone(),
+        };
+        self.highest_ballot.insert(instance.clone(), ballot.clone());
+        self.prepare_oks.insert(instance.clone(), vec![own_view]);
+        vec![EMsg::Prepare { instance, ballot }]
+    }
+
+    #[cfg_attr(
+        feature = "telemetry",
+        tracing::instrument(skip_all, fields(instance = %instance, replica = %self.replica_name))
+    )]
+    pub fn prepare_handler(&mut self, instance: Instance, ballot: Ballot) -> Vec<EMsg> {
+        if let Some(rejected) = self.check_ballot(&instance, &ballot) {
+            return vec![rejected];
+        }
+        if self.slot_index(&instance).is_none() {
+            // Compacted, the command is gone but its effect is in my store. The recovering
+            // replica is behind, so it gets that instead
+            return vec![EMsg::StoreSnapshot(self.store_snapshot())];
+        }
+        vec![EMsg::PrepareOk(self.prepare_ok_for(&instance))]
+    }
+
+    /// Starts recovering the uncommitted deps of parked instances that have been holding up
+    /// execution for recovery_timeout_ms, unless I lead them. Run on the heartbeat tick
+    pub fn recover_blocking_deps(&mut self, now: Instant) -> Vec<EMsg> {
+        let mut blocking = HashSet::new();
+        for parked in &self.pending_reads {
+            let Some(entry) = self.lookup(parked) else {
+                continue;
+            };
+            for dep in &entry.deps {
+                let compacted = dep.instance_num < self.watermark_of(&dep.replica);
+                let committed = matches!(
+                    self.lookup(dep).map(|e| &e.status),
+                    Some(CmdStatus::Committed | CmdStatus::Executed)
+                );
+                if !compacted && !committed && dep.replica != self.replica_name {
+                    blocking.insert(dep.clone());
+                }
+            }
+        }
+        self.blocked_since.retain(|dep, _| blocking.contains(dep));
+
+        let timeout = Duration::from_millis(self.config.recovery_timeout_ms);
+        let mut out = vec![];
+        for dep in blocking {
+            let since = *self.blocked_since.entry(dep.clone()).or_insert(now);
+            if now.duration_since(since) >= timeout {
+                out.append(&mut self.noop_handler(dep));
+            }
+        }
+        out
+    }
+
+    /// The highest ballot seen for `instance`
+    pub fn current_ballot(&self, instance: &Instance) -> Ballot {
+        self.highest_ballot
+            .get(instance)
+            .cloned()
+            .unwrap_or_else(|| Ballot::initial(instance))
+    }
+
+    /// Admits a message for `instance` carrying `ballot` if it is at least the highest seen,
+    /// raising the highest to it. A higher ballot from someone else ends my own recovery
+    /// attempt. Otherwise returns the BallotRejected to answer with
+    pub fn check_ballot(&mut self, instance: &Instance, ballot: &Ballot) -> Option<EMsg> {
+        let current = self.current_ballot(instance);
+        if *ballot < current {
+            #[cfg(debug_assertions)]
+            info!(
+                "{}: Rejecting ballot {} for {}, at {}",
+                self.replica_name, ballot, instance, current
+            );
+            return Some(EMsg::BallotRejected {
+                instance: instance.clone(),
+                current_ballot: current,
+            });
+        }
+        if *ballot > current {
+            self.highest_ballot.insert(instance.clone(), ballot.clone());
+            if ballot.replica_name != self.replica_name {
+                self.prepare_oks.remove(instance);
+                self.recovery_acks.remove(instance);
+            }
+        }
+        None
+    }
+
+    /// A peer saw a higher ballot for `instance` than the one I sent, so whatever I was
+    /// doing for it (leading or recovering) was superseded
+    #[cfg_attr(
+        feature = "telemetry",
+        tracing::instrument(skip_all, fields(instance = %instance, replica = %self.replica_name))
+    )]
+    pub fn ballot_rejected_handler(
+        &mut self,
+        instance: Instance,
+        current_ballot: Ballot,
+    ) -> Vec<EMsg> {
+        #[cfg(debug_assertions)]
+        info!(
+            "{}: Superseded on {} by ballot {}",
+            self.replica_name, instance, current_ballot
+        );
+        self.check_ballot(&instance, &current_ballot);
+        vec![]
+    }
+
+    /// Collects Prepare replies, deciding once a majority (counting myself) answered
+    #[cfg_attr(
+        feature = "telemetry",
+        tracing::instrument(skip_all, fields(instance = %msg.instance, replica = %self.replica_name))
+    )]
+    pub fn prepare_ok_handler(&mut self, msg: PrepareOkMsg) -> Vec<EMsg> {
+        let majority = self.get_majority();
+        let Some(responses) = self.prepare_oks.get_mut(&msg.instance) else {
+            return vec![]; // Late reply, already decided
+        };
+        responses.push(msg.clone());
+        if (responses.len() as u32) <= majority {
+            return vec![];
+        }
+
+        let responses = self.prepare_oks.remove(&msg.instance).unwrap();
+        self.recover_instance(msg.instance, responses)
+    }
+
+    /// EPaxos recovery decision over the Prepare replies of a majority:
+    /// - Committed somewhere: commit it everywhere
+    /// - Accepted somewhere: run Paxos-Accept with the highest seq accepted
+    /// - Pre-accepted with the same command by every replica that saw it: it may have
+    ///   committed on the fast path, so run Paxos-Accept with it
+    /// - Otherwise nobody can have committed it: fill the instance with a Noop
+    pub fn recover_instance(
+        &mut self,
+        instance: Instance,
+        responses: Vec<PrepareOkMsg>,
+    ) -> Vec<EMsg> {
+        if let Some(r) = responses
+            .iter()
+            .find(|r| r.status == PrepareStatus::Committed)
+        {
+            let msg = CommitMsg {
+                cmd: r
+                    .cmd
+                    .clone()
+                    .expect("Committed PrepareOk without a command"),
+                seq: r.seq,
+                deps: r.deps.clone(),
+                instance,
+                commit_ts_ms: r.commit_ts_ms,
+                trace_ctx: outgoing_trace_ctx(),
+            };
+            return self.recovery_commit(msg);
+        }
+
+        let accepted = responses
+            .iter()
+            .filter(|r| r.status == PrepareStatus::Accepted)
+            .max_by_key(|r| r.seq);
+        let pre_accepted: Vec<&PrepareOkMsg> = responses
+            .iter()
+            .filter(|r| r.status == PrepareStatus::PreAccepted)
+            .collect();
+
+        let (cmd, seq, deps) = match (accepted, pre_accepted.first()) {
+            (Some(r), _) => (r.cmd.clone(), r.seq, r.deps.clone()),
+            (None, Some(first)) if pre_accepted.iter().all(|r| r.cmd == first.cmd) => {
+                let seq = pre_accepted.iter().map(|r| r.seq).max().unwrap_or(0);
+                let deps = pre_accepted
+                    .iter()
+                    .flat_map(|r| r.deps.iter().cloned())
+                    .collect();
+                (first.cmd.clone(), seq, deps)
+            }
+            _ => return self.propose_noop_handler(instance),
+        };
+        let cmd = cmd.expect("PrepareOk without a command");
+        self.accept_recovered(instance, cmd, seq, deps)
+    }
+
+    /// Fills `instance`, which I'm recovering, with a Noop so later instances depending on it
+    /// can execute. Only safe once a Prepare round showed nobody can have committed anything
+    /// in it, so recover_instance is what decides to. The Noop goes through Paxos-Accept at
+    /// my recovery ballot, a PreAccept would carry the leader's initial ballot and be
+    /// rejected by every replica that saw my Prepare
+    pub fn propose_noop_handler(&mut self, instance: Instance) -> Vec<EMsg> {
+        if !self.is_preparing(&instance)
+            || self.current_ballot(&instance).replica_name != self.replica_name
+        {
+            return vec![]; // Not recovering it, or superseded by someone else's ballot
+        }
+        self.accept_recovered(instance, Command::Noop, 1, BTreeSet::new())
+    }
+
+    /// Runs Paxos-Accept for what recovery decided `instance` holds
+    fn accept_recovered(
+        &mut self,
+        instance: Instance,
+        cmd: Command,
+        seq: u64,
+        deps: BTreeSet<Instance>,
+    ) -> Vec<EMsg> {
+        #[cfg(debug_assertions)]
+        info!(
+            "{}: Recovery of {} decided {}, seq: {}",
+            self.replica_name, instance, cmd, seq
+        );
+
+        self.cmds_insert(
+            &instance,
+            CmdEntry {
+                cmd: cmd.clone(),
+                seq,
+                deps: deps.clone(),
+                status: CmdStatus::Accepted,
+                commit_ts_ms: 0,
+            },
+        );
+        self.recovery_acks.insert(instance.clone(), HashSet::new());
+        vec![EMsg::Accept(AcceptMsg {
+            cmd,
+            seq,
+            deps,
+            ballot: self.current_ballot(&instance),
+            instance,
+            trace_ctx: outgoing_trace_ctx(),
+        })]
+    }
+
+    /// AcceptOk for an instance led by another replica, i.e. one I'm recovering
+    pub fn recovery_accept_ok(&mut self, instance: Instance, from_replica: String) -> Vec<EMsg> {
+        let majority = self.get_majority();
+        let Some(acks) = self.recovery_acks.get_mut(&instance) else {
+            return vec![];
+        };
+        acks.insert(from_replica);
+        if (acks.len() as u32) < majority {
+            return vec![];
+        }
+        self.recovery_acks.remove(&instance);
+
+        let entry = self
+            .lookup_mut(&instance)
+            .expect("Recovered command not found in log")
+            .clone();
+        self.recovery_commit(CommitMsg {
+            cmd: entry.cmd,
+            seq: entry.seq,
+            deps: entry.deps,
+            instance,
+            commit_ts_ms: now_us() / 1000,
+            trace_ctx: outgoing_trace_ctx(),
+        })
+    }
+
+    /// Commits locally like any replica would, then tells everyone else
+    fn recovery_commit(&mut self, msg: CommitMsg) -> Vec<EMsg> {
+        let mut out_msgs = vec![EMsg::Commit(msg.clone())];
+        out_msgs.append(&mut self.commit_handler(msg));
+        out_msgs
+    }
+
+    /// What this replica knows about `instance`
+    fn prepare_ok_for(&self, instance: &Instance) -> PrepareOkMsg {
+        let entry = self.lookup(instance);
+        let status = match entry {
+            None => PrepareStatus::Unknown,
+            Some(e) => match e.status {
+                CmdStatus::PreAccepted => PrepareStatus::PreAccepted,
+                CmdStatus::Accepted => PrepareStatus::Accepted,
+                CmdStatus::Committed | CmdStatus::Executed => PrepareStatus::Committed,
+                // Placeholder from my own recovery attempt
+                CmdStatus::Preparing if e.cmd == Command::Noop => PrepareStatus::Unknown,
+                CmdStatus::Preparing => PrepareStatus::PreAccepted,
+            },
+        };
+        let known = entry.filter(|_| status != PrepareStatus::Unknown);
+        PrepareOkMsg {
+            instance: instance.clone(),
+            from_replica: self.replica_name.clone(),
+            status,
+            cmd: known.map(|e| e.cmd.clone()),
+            seq: known.map_or(0, |e| e.seq),
+            deps: known.map_or_else(BTreeSet::new, |e| e.deps.clone()),
+            commit_ts_ms: known.map_or(0, |e| e.commit_ts_ms),
+        }
+    }
+
+    pub fn is_preparing(&self, instance: &Instance) -> bool {
+        matches!(
+            self.lookup(instance).map(|e| &e.status),
+            Some(CmdStatus::Preparing)
+        )
+    }
+}
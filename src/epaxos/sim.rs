@@ -0,0 +1,234 @@
+pub use crate::common::{
+    ClientRequest, ClientResponse, Command, CommandResult, EMsg, Value, Variable, now_us,
+};
+use crate::epaxos::Processor;
+pub use crate::epaxos::ProcessorConfig;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use reactor_actor::ActorProcess;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+// Entry point for the tests/ integration tests, which can't reach the private Processor
+
+/// Deliveries before run_until_quiet gives up on a cluster that keeps talking
+const MAX_STEPS: usize = 1_000_000;
+
+/// N replicas, named "r0".."r{N-1}", joined by an in-memory network routed like the Sender
+/// does. Nothing moves until step is called, so tests pick the interleaving. Messages are
+/// delivered in send order unless delay_messages spreads them out, and may be dropped by
+/// partition or drop_messages_from. Drops and delays come from an RNG seeded at creation,
+/// so a run replays exactly
+pub struct ClusterSimulator {
+    replicas: Vec<Processor>,
+    // (deliver_at_ms, send order) -> (from, to, msg), oks go back to `from`
+    queue: BTreeMap<(u64, u64), (String, String, EMsg)>,
+    sent: u64,                      // Messages queued so far, breaks ties in delivery time
+    clock_ms: u64,                  // Delivery time of the last delivered message
+    delay_ms: (u64, u64),           // Bounds of the delay drawn for each replica-to-replica message
+    cut: HashSet<(String, String)>, // (from, to) links dropping everything
+    drop_prob: HashMap<(String, String), f64>, // (from, to) links dropping some messages
+    dropped: usize,
+    rng: StdRng,
+    responses: Vec<EMsg>, // Messages for clients, in delivery order
+}
+
+impl ClusterSimulator {
+    pub fn new(n_replicas: usize) -> Self {
+        Self::with_config(n_replicas, ProcessorConfig::default(), 0)
+    }
+
+    /// Replicas built from `config`, drawing drops and delays from `seed`
+    pub fn with_config(n_replicas: usize, config: ProcessorConfig, seed: u64) -> Self {
+        let names: Vec<String> = (0..n_replicas).map(|i| format!("r{}", i)).collect();
+        let replicas = names
+            .iter()
+            .map(|name| Processor::new_with_config(names.clone(), name.clone(), config.clone()))
+            .collect();
+        ClusterSimulator {
+            replicas,
+            queue: BTreeMap::new(),
+            sent: 0,
+            clock_ms: 0,
+            delay_ms: (0, 0),
+            cut: HashSet::new(),
+            drop_prob: HashMap::new(),
+            dropped: 0,
+            rng: StdRng::seed_from_u64(seed),
+            responses: vec![],
+        }
+    }
+
+    /// Restarts `replica` from its exported log and store, see Processor::export_log_json.
+    /// It keeps its config, and loses what was in flight as after a crash
+    #[cfg(feature = "serde")]
+    pub fn restart_from_snapshot(&mut self, replica: &str) {
+        let i = self.index_of(replica);
+        let json = self.replicas[i].export_log_json();
+        let config = self.replicas[i].config.clone();
+        self.replicas[i] = Processor::import_log_json(&json, config).expect("export imports");
+    }
+
+    /// Queues `msg` for `replica`, sent by the client named in it. Clients are never cut off
+    pub fn client_request(&mut self, replica: &str, msg: ClientRequest) {
+        let from = msg.client_id.clone();
+        self.enqueue(from, replica.to_string(), EMsg::ClientRequest(msg), 0);
+    }
+
+    /// Queues the heartbeat tick of `replica`, which pings its peers, sweeps and starts
+    /// recovering instances that have blocked execution for recovery_timeout_ms
+    pub fn tick(&mut self, replica: &str) {
+        let ping = EMsg::Ping {
+            from: replica.to_string(),
+            timestamp_us: now_us(),
+        };
+        self.enqueue(replica.to_string(), replica.to_string(), ping, 0);
+    }
+
+    /// Drops each message sent from `src` to `dst` with `probability`, 0 to stop dropping
+    pub fn drop_messages_from(&mut self, src: &str, dst: &str, probability: f64) {
+        let link = (src.to_string(), dst.to_string());
+        if probability > 0.0 {
+            self.drop_prob.insert(link, probability.min(1.0));
+        } else {
+            self.drop_prob.remove(&link);
+        }
+    }
+
+    /// Drops every message between a replica of `group_a` and one of `group_b`, both ways.
+    /// Messages already queued still arrive
+    pub fn partition(&mut self, group_a: &[String], group_b: &[String]) {
+        for a in group_a {
+            for b in group_b {
+                self.cut.insert((a.clone(), b.clone()));
+                self.cut.insert((b.clone(), a.clone()));
+            }
+        }
+    }
+
+    /// Lifts every partition and drop_messages_from rule
+    pub fn heal_partition(&mut self) {
+        self.cut.clear();
+        self.drop_prob.clear();
+    }
+
+    /// Delays each message between replicas by a time drawn from `min_ms..=max_ms`, so
+    /// messages sent together arrive in random order. 0, 0 restores send order
+    pub fn delay_messages(&mut self, min_ms: u64, max_ms: u64) {
+        assert!(
+            min_ms <= max_ms,
+            "Empty delay range {}..={}",
+            min_ms,
+            max_ms
+        );
+        self.delay_ms = (min_ms, max_ms);
+    }
+
+    /// Messages lost to partitions and drops so far
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+
+    /// Delivers the next queued message and queues what its target sends back. Returns
+    /// false if there was nothing to deliver
+    pub fn step(&mut self) -> bool {
+        let Some(((deliver_at_ms, _), (from, to, msg))) = self.queue.pop_first() else {
+            return false;
+        };
+        self.clock_ms = deliver_at_ms;
+        let i = self.index_of(&to);
+        for out in self.replicas[i].process(msg) {
+            match &out {
+                EMsg::PreAccept(_)
+                | EMsg::PreAcceptBatch(_)
+                | EMsg::Accept(_)
+                | EMsg::Commit(_)
+                | EMsg::Prepare { .. }
+                | EMsg::Ping { .. }
+                | EMsg::ReconfigAck { .. } => {
+                    let peers: Vec<String> = self
+                        .replicas
+                        .iter()
+                        .filter(|p| p.replica_name != to)
+                        .map(|p| p.replica_name.clone())
+                        .collect();
+                    for peer in peers {
+                        self.send(to.clone(), peer, out.clone());
+                    }
+                }
+                EMsg::PreAcceptOk(_)
+                | EMsg::PreAcceptOkBatch(_)
+                | EMsg::AcceptOk(_)
+                | EMsg::PrepareOk(_)
+                | EMsg::BallotRejected { .. }
+                | EMsg::Pong { .. }
+                | EMsg::RequestLog { .. }
+                | EMsg::LogResponse { .. }
+                | EMsg::StoreSnapshot(_) => self.send(to.clone(), from.clone(), out),
+                _ => self.responses.push(out),
+            }
+        }
+        true
+    }
+
+    /// Steps until no message is left in flight, returning how many were delivered.
+    /// Panics after MAX_STEPS
+    pub fn run_until_quiet(&mut self) -> usize {
+        let mut steps = 0;
+        while self.step() {
+            steps += 1;
+            assert!(
+                steps < MAX_STEPS,
+                "Cluster still busy after {} steps",
+                steps
+            );
+        }
+        steps
+    }
+
+    /// Live values in the store of `replica`
+    pub fn data_of(&self, replica: &str) -> HashMap<Variable, String> {
+        let p = &self.replicas[self.index_of(replica)];
+        p.data
+            .iter()
+            .filter(|(_, v)| v.live_at(p.exec_clock_ms))
+            .map(|(key, v)| (key.clone(), v.val.to_string()))
+            .collect()
+    }
+
+    /// Client responses and errors produced so far, oldest first
+    pub fn take_responses(&mut self) -> Vec<EMsg> {
+        std::mem::take(&mut self.responses)
+    }
+
+    /// Queues a message between replicas, unless its link drops it
+    fn send(&mut self, from: String, to: String, msg: EMsg) {
+        let link = (from, to);
+        let lost = self.cut.contains(&link)
+            || self
+                .drop_prob
+                .get(&link)
+                .is_some_and(|&p| self.rng.random_bool(p));
+        if lost {
+            self.dropped += 1;
+            return;
+        }
+        let (min_ms, max_ms) = self.delay_ms;
+        let delay_ms = self.rng.random_range(min_ms..=max_ms);
+        let (from, to) = link;
+        self.enqueue(from, to, msg, delay_ms);
+    }
+
+    fn enqueue(&mut self, from: String, to: String, msg: EMsg, delay_ms: u64) {
+        self.sent += 1;
+        let deliver_at_ms = self.clock_ms + delay_ms;
+        self.queue
+            .insert((deliver_at_ms, self.sent), (from, to, msg));
+    }
+
+    fn index_of(&self, replica: &str) -> usize {
+        self.replicas
+            .iter()
+            .position(|p| p.replica_name == replica)
+            .unwrap_or_else(|| panic!("No replica named {}", replica))
+    }
+}
@@ -0,0 +1,204 @@
+use crate::common::{Instance, Variable};
+use crate::epaxos::{CmdEntry, CmdMetadata, Processor, ProcessorConfig, StoredValue};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+/// The log and store of a Processor, as written by export_log_json. In-flight state
+/// (quorum counters, pending reads, recovery) is left out, so it suits committed fixtures
+#[derive(Serialize, Deserialize)]
+struct LogSnapshot {
+    replica_name: String,
+    replica_list: Vec<String>,
+    instance_num: usize,
+    cmds: HashMap<String, Vec<Option<CmdEntry>>>,
+    watermark: HashMap<String, usize>,
+    app_meta: Vec<CmdMetadata>,
+    // JSON object keys must be strings, so keyed maps go as pairs
+    data: Vec<(Variable, StoredValue)>,
+    versions: Vec<(Variable, u64)>,
+    exec_clock_ms: u64,
+}
+
+impl Processor {
+    /// Serializes the log, watermarks and store, see import_log_json
+    pub fn export_log_json(&self) -> String {
+        let snapshot = LogSnapshot {
+            replica_name: self.replica_name.clone(),
+            replica_list: self.replica_list.clone(),
+            instance_num: self.instance_num,
+            cmds: self.dense_cmds(),
+            watermark: self.watermark.clone(),
+            app_meta: self.app_meta.clone(),
+            data: self
+                .data
+                .iter()
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect(),
+            versions: self.versions.iter().map(|(k, v)| (k.clone(), *v)).collect(),
+            exec_clock_ms: self.exec_clock_ms,
+        };
+        serde_json::to_string(&snapshot).expect("log snapshot serializes")
+    }
+
+    /// The log laid out as cmds vectors, also when it's kept in cmds_flat
+    fn dense_cmds(&self) -> HashMap<String, Vec<Option<CmdEntry>>> {
+        if !self.config.sparse_log {
+            return self.cmds.clone();
+        }
+        self.cmds
+            .keys()
+            .map(|replica| {
+                let watermark = self.watermark_of(replica);
+                let mut log = vec![None; self.log_len(replica)];
+                for (i, entry) in self.replica_entries(replica, watermark) {
+                    log[i - watermark] = Some(entry.clone());
+                }
+                (replica.clone(), log)
+            })
+            .collect()
+    }
+
+    /// A fresh Processor built from `config`, holding the exported log and store. The config
+    /// isn't part of the export, so the log is laid out as `config` says. Derived indexes
+    /// like rdeps are rebuilt, everything else starts as in Processor::new_with_config
+    pub fn import_log_json(json: &str, config: ProcessorConfig) -> Result<Self, serde_json::Error> {
+        let snapshot: LogSnapshot = serde_json::from_str(json)?;
+        let mut p =
+            Processor::new_with_config(snapshot.replica_list, snapshot.replica_name, config);
+        p.instance_num = snapshot.instance_num;
+        p.watermark = snapshot.watermark;
+        if p.config.sparse_log {
+            for (replica, log) in snapshot.cmds {
+                let watermark = p.watermark_of(&replica);
+                p.cmds_flat_end
+                    .insert(replica.clone(), watermark + log.len());
+                for (i, entry) in log.into_iter().enumerate() {
+                    if let Some(entry) = entry {
+                        let instance = Instance {
+                            replica: replica.clone(),
+                            instance_num: watermark + i,
+                        };
+                        p.cmds_flat.insert(instance, entry);
+                    }
+                }
+                p.cmds.insert(replica, vec![]);
+            }
+        } else {
+            p.cmds = snapshot.cmds;
+        }
+        p.quorum_ctr = vec![0; snapshot.app_meta.len()];
+        p.acc_quorum_ctr = vec![0; snapshot.app_meta.len()];
+        p.received_from = vec![HashSet::new(); snapshot.app_meta.len()];
+        p.acc_received_from = vec![HashSet::new(); snapshot.app_meta.len()];
+        p.app_meta = snapshot.app_meta;
+        p.data = snapshot.data.into_iter().collect();
+        p.versions = snapshot.versions.into_iter().collect();
+        p.exec_clock_ms = snapshot.exec_clock_ms;
+
+        let mut entries = vec![];
+        for (replica, instance_num, entry) in p.log_entries() {
+            let instance = Instance {
+                replica: replica.clone(),
+                instance_num,
+            };
+            // Compacted deps aren't indexed, as after try_compact_log
+            let live_deps = entry.deps.iter().filter(|dep| p.slot_index(dep).is_some());
+            entries.push((instance, live_deps.cloned().collect::<Vec<_>>()));
+        }
+        for (instance, deps) in entries {
+            p.rdeps_add(&instance, &deps);
+        }
+        Ok(p)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::{ClientRequest, Command, CommitMsg, EMsg};
+    use crate::epaxos::CmdStatus;
+    use reactor_actor::ActorProcess;
+    use std::collections::BTreeSet;
+
+    // --- Helpers ---
+
+    fn inst(replica: &str, instance_num: usize) -> Instance {
+        Instance {
+            replica: replica.into(),
+            instance_num,
+        }
+    }
+
+    fn commit(p: &mut Processor, instance: Instance, key: &str, deps: &[Instance]) {
+        p.process(EMsg::Commit(CommitMsg {
+            cmd: Command::Set {
+                key: Variable::new(key),
+                val: format!("{}@{}", key, instance).into(),
+                ttl_ms: None,
+            },
+            seq: 1,
+            deps: deps.iter().cloned().collect::<BTreeSet<_>>(),
+            instance,
+            commit_ts_ms: 10,
+            trace_ctx: None,
+        }));
+    }
+
+    fn statuses(p: &Processor) -> Vec<(String, usize, String)> {
+        let mut all: Vec<_> = p
+            .cmds
+            .iter()
+            .flat_map(|(replica, log)| {
+                log.iter().enumerate().filter_map(move |(i, e)| {
+                    Some((replica.clone(), i, e.as_ref()?.status.to_string()))
+                })
+            })
+            .collect();
+        all.sort();
+        all
+    }
+
+    // --- Tests ---
+
+    #[test]
+    fn test_log_json_round_trip() {
+        let list: Vec<String> = (0..3).map(|i| format!("r{}", i)).collect();
+        let mut p = Processor::new(list, "r0".into());
+        commit(&mut p, inst("r1", 0), "a", &[]);
+        commit(&mut p, inst("r1", 1), "b", &[inst("r1", 0)]);
+        commit(&mut p, inst("r2", 0), "a", &[inst("r1", 0)]);
+        p.execute_cmd(&inst("r1", 1));
+        assert_eq!(p.try_compact_log(), 2);
+        // One instance of my own, still waiting on its PreAcceptOks
+        p.process(EMsg::ClientRequest(ClientRequest {
+            client_id: "c".into(),
+            msg_id: "1".into(),
+            cmd: Command::Get {
+                key: Variable::new("a"),
+            },
+            timestamp_us: None,
+        }));
+
+        let q = Processor::import_log_json(&p.export_log_json(), p.config.clone()).unwrap();
+        assert_eq!(q.data, p.data);
+        assert_eq!(q.versions, p.versions);
+        let count = |p: &Processor| p.cmds.values().flatten().flatten().count();
+        assert_eq!(count(&q), count(&p));
+        assert_eq!(statuses(&q), statuses(&p));
+        assert_eq!(q.watermark, p.watermark);
+        assert_eq!(q.instance_num, p.instance_num);
+        assert_eq!(q.rdeps, p.rdeps);
+        assert!(matches!(
+            q.lookup(&inst("r0", 0)).map(|e| &e.status),
+            Some(CmdStatus::PreAccepted)
+        ));
+
+        // The imported replica carries on where the original left off
+        let mut q = q;
+        q.execute_cmd(&inst("r2", 0));
+        assert!(matches!(
+            q.lookup(&inst("r2", 0)).map(|e| &e.status),
+            Some(CmdStatus::Executed)
+        ));
+    }
+}
@@ -1,20 +1,42 @@
+mod cli;
+mod client;
 mod common;
+mod metrics;
 mod reader;
-// mod ss;
-mod client;
+mod ss;
+mod store;
 mod writer;
 
 mod epaxos;
+#[cfg(feature = "telemetry")]
+mod telemetry;
 
-use crate::reader::reader as reader_behaviour;
-// use crate::ss::server as ss_behaviour;
+#[cfg(feature = "bench")]
+pub use crate::epaxos::bench;
+#[cfg(feature = "testing")]
+pub use crate::epaxos::sim;
+
+use crate::cli::cli as cli_behaviour;
+use crate::client::closed_loop_client as closed_loop_client_behaviour;
 use crate::client::cp_client as client_behaviour;
+use crate::common::Value;
+use crate::common::Variable;
 use crate::epaxos::server as epaxos_behaviour;
+use crate::epaxos::{ConflictCheckerKind, ProcessorConfig, ReplicaMode, RequestLimits};
+use crate::metrics::MetricsConfig;
+use crate::metrics::metrics as metrics_behaviour;
+use crate::reader::ReaderConfig;
+use crate::reader::reader as reader_behaviour;
+use crate::ss::server as kv_server_behaviour;
+use crate::writer::WriterConfig;
 use crate::writer::writer as writer_behaviour;
 use reactor_actor::RuntimeCtx;
 use std::collections::HashMap;
+use std::time::Duration;
 
 pub use reactor_actor::{actor, setup_shared_logger_ref};
+#[cfg(feature = "telemetry")]
+pub use telemetry::setup_otlp_tracer;
 
 pub const SLEEP_MS: u64 = 100;
 
@@ -22,10 +44,39 @@ lazy_static::lazy_static! {
     static ref RUNTIME: tokio::runtime::Runtime = tokio::runtime::Runtime::new().unwrap();
 }
 
-// #[actor]
-// fn ss(ctx: RuntimeCtx, _payload: HashMap<String, serde_json::Value>) {
-//     RUNTIME.spawn(ss_behaviour(ctx));
-// }
+/// "preload": {"<namespace>": {"<key>": "<value>"}}, pairs to store before any request
+fn preload_from(payload: &mut HashMap<String, serde_json::Value>) -> Vec<(Variable, Value)> {
+    payload
+        .remove("preload")
+        .map(|v| {
+            serde_json::from_value::<HashMap<String, HashMap<String, String>>>(v)
+                .expect("preload must map namespaces to key/value objects")
+        })
+        .unwrap_or_default()
+        .into_iter()
+        .flat_map(|(namespace, pairs)| {
+            pairs
+                .into_iter()
+                .map(move |(key, val)| (Variable::in_namespace(namespace.clone(), key), val.into()))
+        })
+        .collect()
+}
+
+/// "metrics": {"actor": "<name>", "interval_ms": 1000}, the metrics actor to report to.
+/// None if left out, then nothing is reported
+fn metrics_from(payload: &mut HashMap<String, serde_json::Value>) -> Option<MetricsConfig> {
+    payload.remove("metrics").map(|v| {
+        serde_json::from_value::<MetricsConfig>(v)
+            .expect("metrics must be {\"actor\": .., \"interval_ms\": ..}")
+    })
+}
+
+// Single node store without replication, a baseline for the epaxos numbers
+#[actor]
+fn kv_server(ctx: RuntimeCtx, mut payload: HashMap<String, serde_json::Value>) {
+    let preload = preload_from(&mut payload);
+    RUNTIME.spawn(kv_server_behaviour(ctx, preload));
+}
 
 #[actor]
 fn epaxos_server(ctx: RuntimeCtx, mut payload: HashMap<String, serde_json::Value>) {
@@ -41,7 +92,66 @@ fn epaxos_server(ctx: RuntimeCtx, mut payload: HashMap<String, serde_json::Value
                 .to_string()
         })
         .collect::<Vec<String>>();
-    RUNTIME.spawn(epaxos_behaviour(ctx, replica_list));
+    // "mode": "ReadOnly" makes the replica reject client requests instead of leading them
+    let replica_mode = payload
+        .remove("mode")
+        .map(|v| serde_json::from_value::<ReplicaMode>(v).expect("mode must be Leader or ReadOnly"))
+        .unwrap_or_default();
+    // "limits": {"max_key_len": .., "max_value_len": ..} bounds client requests, see RequestLimits
+    let limits = payload
+        .remove("limits")
+        .map(|v| serde_json::from_value::<RequestLimits>(v).expect("invalid limits"))
+        .unwrap_or_default();
+    // "processor": {"gc_threshold": .., "max_pending_reads": .., "execution_batch_size": ..,
+    // "recovery_timeout_ms": ..}, every field optional, see ProcessorConfig
+    let processor_config = payload
+        .remove("processor")
+        .map(|v| serde_json::from_value::<ProcessorConfig>(v).expect("invalid processor config"))
+        .unwrap_or_default();
+    // "conflict_checker": "KeyEquality" (default), "Always" or "Never". Same on every replica
+    let conflict_checker = payload
+        .remove("conflict_checker")
+        .map(|v| {
+            serde_json::from_value::<ConflictCheckerKind>(v)
+                .expect("conflict_checker must be KeyEquality, Always or Never")
+        })
+        .unwrap_or_default();
+    // Seeds the store, identically on every replica
+    let preload = preload_from(&mut payload);
+    let metrics = metrics_from(&mut payload);
+    // "otlp_endpoint": "http://localhost:4317" exports handler spans, with the telemetry feature
+    if let Some(endpoint) = payload.remove("otlp_endpoint") {
+        let endpoint = endpoint.as_str().expect("otlp_endpoint must be a string");
+        #[cfg(feature = "telemetry")]
+        setup_otlp_once(endpoint);
+        #[cfg(not(feature = "telemetry"))]
+        tracing::warn!(
+            "otlp_endpoint {} ignored, needs the telemetry feature",
+            endpoint
+        );
+    }
+    RUNTIME.spawn(epaxos_behaviour(
+        ctx,
+        replica_list,
+        replica_mode,
+        limits,
+        conflict_checker,
+        preload,
+        processor_config,
+        metrics,
+    ));
+}
+
+/// Every replica in the process shares one tracer
+#[cfg(feature = "telemetry")]
+fn setup_otlp_once(endpoint: &str) {
+    static SETUP: std::sync::Once = std::sync::Once::new();
+    SETUP.call_once(|| {
+        let _runtime = RUNTIME.enter(); // The batch exporter runs on it
+        if let Err(e) = setup_otlp_tracer(endpoint) {
+            tracing::warn!("Can't export spans to {}: {}", endpoint, e);
+        }
+    });
 }
 
 #[actor]
@@ -52,7 +162,39 @@ fn reader(ctx: RuntimeCtx, mut payload: HashMap<String, serde_json::Value>) {
         .as_str()
         .expect("server must be a string")
         .to_string();
-    RUNTIME.spawn(reader_behaviour(ctx, server));
+    // "key", "count" and "interval_ms" pick what is read, how often and how far apart.
+    // "expected_value" checks every read, re-reading a mismatch up to "max_attempts" times
+    // "retry_backoff_ms" apart, doubling
+    let defaults = ReaderConfig::default();
+    let config = ReaderConfig {
+        key: payload.remove("key").map_or(defaults.key, |v| {
+            v.as_str().expect("key must be a string").to_string()
+        }),
+        count: payload.remove("count").map_or(defaults.count, |v| {
+            v.as_u64().expect("count must be a number") as usize
+        }),
+        interval_ms: payload
+            .remove("interval_ms")
+            .map_or(defaults.interval_ms, |v| {
+                v.as_u64().expect("interval_ms must be a number")
+            }),
+        expected_value: payload.remove("expected_value").map(|v| {
+            v.as_str()
+                .expect("expected_value must be a string")
+                .to_string()
+        }),
+        max_attempts: payload
+            .remove("max_attempts")
+            .map_or(defaults.max_attempts, |v| {
+                v.as_u64().expect("max_attempts must be a number") as usize
+            }),
+        retry_backoff_ms: payload
+            .remove("retry_backoff_ms")
+            .map_or(defaults.retry_backoff_ms, |v| {
+                v.as_u64().expect("retry_backoff_ms must be a number")
+            }),
+    };
+    RUNTIME.spawn(reader_behaviour(ctx, server, config));
 }
 
 #[actor]
@@ -63,7 +205,40 @@ fn writer(ctx: RuntimeCtx, mut payload: HashMap<String, serde_json::Value>) {
         .as_str()
         .expect("server must be a string")
         .to_string();
-    RUNTIME.spawn(writer_behaviour(ctx, server));
+    // "key", "value_prefix", "count" and "interval_ms" pick what is written, how often and
+    // how far apart
+    let defaults = WriterConfig::default();
+    let config = WriterConfig {
+        key: payload.remove("key").map_or(defaults.key, |v| {
+            v.as_str().expect("key must be a string").to_string()
+        }),
+        value_prefix: payload.remove("value_prefix").map(|v| {
+            v.as_str()
+                .expect("value_prefix must be a string")
+                .to_string()
+        }),
+        count: payload.remove("count").map_or(defaults.count, |v| {
+            v.as_u64().expect("count must be a number") as usize
+        }),
+        interval_ms: payload
+            .remove("interval_ms")
+            .map_or(defaults.interval_ms, |v| {
+                v.as_u64().expect("interval_ms must be a number")
+            }),
+    };
+    RUNTIME.spawn(writer_behaviour(ctx, server, config));
+}
+
+// Reads get/set/del/dump lines from stdin and sends them to `server`, printing replies
+#[actor]
+fn cli(ctx: RuntimeCtx, mut payload: HashMap<String, serde_json::Value>) {
+    let server = payload
+        .remove("server")
+        .expect("server field missing")
+        .as_str()
+        .expect("server must be a string")
+        .to_string();
+    RUNTIME.spawn(cli_behaviour(ctx, server));
 }
 
 #[actor]
@@ -77,11 +252,62 @@ fn client(ctx: RuntimeCtx, mut payload: HashMap<String, serde_json::Value>) {
         .map(|v| v.as_str().unwrap().to_string())
         .collect();
 
-    let workload = if let Some(wl) = payload.remove("workload") {
-        Some(serde_json::from_value::<client::Workload>(wl).unwrap())
-    } else {
-        None
-    };
+    let workload = payload
+        .remove("workload")
+        .map(|wl| serde_json::from_value::<client::Workload>(wl).unwrap());
+    let metrics = metrics_from(&mut payload);
+
+    RUNTIME.spawn(client_behaviour(ctx, servers, workload, metrics));
+}
 
-    RUNTIME.spawn(client_behaviour(ctx, servers, workload));
+#[actor]
+fn closed_loop_client(ctx: RuntimeCtx, mut payload: HashMap<String, serde_json::Value>) {
+    let servers: Vec<String> = payload
+        .remove("servers")
+        .expect("servers field missing")
+        .as_array()
+        .expect("servers must be an array")
+        .iter()
+        .map(|v| v.as_str().expect("server must be a string").to_string())
+        .collect();
+
+    let workload = payload
+        .remove("workload")
+        .map(|wl| serde_json::from_value::<client::Workload>(wl).unwrap());
+    let max_outstanding = payload
+        .remove("max_outstanding")
+        .map(|v| v.as_u64().expect("max_outstanding must be a number") as usize)
+        .unwrap_or(1);
+    let timeout = payload
+        .remove("timeout_ms")
+        .map(|v| v.as_u64().expect("timeout_ms must be a number"))
+        .unwrap_or(1000);
+    let metrics = metrics_from(&mut payload);
+
+    RUNTIME.spawn(closed_loop_client_behaviour(
+        ctx,
+        servers,
+        workload,
+        max_outstanding,
+        Duration::from_millis(timeout),
+        metrics,
+    ));
+}
+
+// Collects the reports of replicas and clients naming it in their "metrics" payload, and
+// logs them merged every "interval_ms". "output_path" also gets each merged snapshot as a
+// JSON line
+#[actor]
+fn metrics(ctx: RuntimeCtx, mut payload: HashMap<String, serde_json::Value>) {
+    let interval_ms = payload
+        .remove("interval_ms")
+        .map(|v| v.as_u64().expect("interval_ms must be a number"))
+        .unwrap_or(metrics::DEFAULT_INTERVAL_MS);
+    let output_path = payload.remove("output_path").map(|v| {
+        v.as_str()
+            .expect("output_path must be a string")
+            .to_string()
+            .into()
+    });
+    RUNTIME.spawn(metrics_behaviour(ctx, interval_ms, output_path));
 }
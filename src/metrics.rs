@@ -0,0 +1,288 @@
+use crate::common::{EMsg, Envelope, Versioned};
+use reactor_actor::codec::BincodeCodec;
+use reactor_actor::{BehaviourBuilder, RouteTo, RuntimeCtx, SendErrAction};
+use serde::Deserialize;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// How often replicas and clients report, and the metrics actor logs, unless configured
+pub const DEFAULT_INTERVAL_MS: u64 = 1000;
+/// Sources silent for this many intervals are listed as stale in the merged snapshot
+const STALE_INTERVALS: u32 = 3;
+
+/// "metrics": {"actor": "metrics", "interval_ms": 1000} in a replica's or client's payload,
+/// the metrics actor to report to and how often. Without it nothing is reported
+#[derive(Debug, Clone, Deserialize)]
+pub struct MetricsConfig {
+    pub actor: String,
+    #[serde(default = "default_interval_ms")]
+    pub interval_ms: u64,
+}
+
+fn default_interval_ms() -> u64 {
+    DEFAULT_INTERVAL_MS
+}
+
+/// Where a Sender routes EMsg::Metrics. Nowhere if no metrics actor is configured, though
+/// a Processor with no MetricsReporter never produces one
+pub fn metrics_route(actor: Option<&str>) -> RouteTo<'_> {
+    match actor {
+        Some(actor) => RouteTo::from(actor),
+        None => RouteTo::Multiple(Cow::Owned(vec![])),
+    }
+}
+
+/// Times the reports of one replica or client. Its Processor asks on every message it
+/// handles, heartbeats and workload ticks included, so a report goes out at most one of
+/// those late
+#[derive(Debug, Clone)]
+pub struct MetricsReporter {
+    source: String,
+    every: Duration,
+    next_at: Instant,
+}
+
+impl MetricsReporter {
+    /// Reports as `source`, the first one an interval from now
+    pub fn new(source: String, config: &MetricsConfig) -> Self {
+        let every = Duration::from_millis(config.interval_ms);
+        MetricsReporter {
+            source,
+            every,
+            next_at: Instant::now() + every,
+        }
+    }
+
+    /// A Metrics message of what `collect` returns, if an interval passed since the last
+    /// one. `collect` gives the counters and gauges, and only runs when a report is due
+    pub fn report_if_due(
+        &mut self,
+        now: Instant,
+        collect: impl FnOnce() -> (HashMap<String, u64>, HashMap<String, f64>),
+    ) -> Option<EMsg> {
+        if now < self.next_at {
+            return None;
+        }
+        self.next_at = now + self.every;
+        let (counters, gauges) = collect();
+        Some(EMsg::Metrics {
+            source: self.source.clone(),
+            counters,
+            gauges,
+        })
+    }
+}
+
+/// The last report of one source. Counters are totals since the source started, so a
+/// newer report replaces an older one
+#[derive(Debug, Clone)]
+struct SourceMetrics {
+    counters: HashMap<String, u64>,
+    gauges: HashMap<String, f64>,
+    received: Instant,
+}
+
+// //////////////////////////////////////////////////////////////////////////////
+//                                  Processor
+// //////////////////////////////////////////////////////////////////////////////
+
+/// Keeps the last report of every source, and every interval logs them merged: counters
+/// and gauges summed by name across sources, with each source's own report alongside.
+/// Logged when a report arrives, nothing is logged while no source reports
+struct Processor {
+    sources: BTreeMap<String, SourceMetrics>,
+    every: Duration,
+    next_log_at: Instant,
+    output: Option<BufWriter<File>>, // Gets every merged snapshot as a JSON line, if configured
+}
+
+impl reactor_actor::ActorProcess for Processor {
+    type IMsg = EMsg;
+    type OMsg = EMsg;
+
+    fn process(&mut self, input: Self::IMsg) -> Vec<Self::OMsg> {
+        match input {
+            EMsg::Metrics {
+                source,
+                counters,
+                gauges,
+            } => {
+                let now = Instant::now();
+                self.record(source, counters, gauges, now);
+                if now >= self.next_log_at {
+                    self.next_log_at = now + self.every;
+                    self.export(now);
+                }
+            }
+            other => warn!("Metrics actor ignores {:?}", other),
+        }
+        vec![]
+    }
+}
+
+impl Processor {
+    fn new(every: Duration, output: Option<BufWriter<File>>) -> Self {
+        Processor {
+            sources: BTreeMap::new(),
+            every,
+            next_log_at: Instant::now(),
+            output,
+        }
+    }
+
+    fn record(
+        &mut self,
+        source: String,
+        counters: HashMap<String, u64>,
+        gauges: HashMap<String, f64>,
+        now: Instant,
+    ) {
+        self.sources.insert(
+            source,
+            SourceMetrics {
+                counters,
+                gauges,
+                received: now,
+            },
+        );
+    }
+
+    /// Every source's last report, with the counters and gauges summed across sources.
+    /// Stale sources still count, their totals don't go away
+    fn merged(&self, now: Instant) -> serde_json::Value {
+        let mut counters = BTreeMap::<&str, u64>::new();
+        let mut gauges = BTreeMap::<&str, f64>::new();
+        let mut by_source = serde_json::Map::new();
+        let mut stale = vec![];
+        for (source, m) in &self.sources {
+            for (name, val) in &m.counters {
+                *counters.entry(name).or_default() += val;
+            }
+            for (name, val) in &m.gauges {
+                *gauges.entry(name).or_default() += val;
+            }
+            if now.saturating_duration_since(m.received) > self.every * STALE_INTERVALS {
+                stale.push(source);
+            }
+            by_source.insert(
+                source.clone(),
+                serde_json::json!({"counters": m.counters, "gauges": m.gauges}),
+            );
+        }
+        serde_json::json!({
+            "sources": self.sources.len(),
+            "stale_sources": stale,
+            "counters": counters,
+            "gauges": gauges,
+            "by_source": by_source,
+        })
+    }
+
+    fn export(&mut self, now: Instant) {
+        let snapshot = self.merged(now).to_string();
+        info!("Metrics {}", snapshot);
+        if let Some(output) = &mut self.output
+            && let Err(e) = writeln!(output, "{}", snapshot).and_then(|_| output.flush())
+        {
+            warn!("Can't write the metrics output: {}", e);
+        }
+    }
+}
+
+// //////////////////////////////////////////////////////////////////////////////
+//                                  Sender
+// //////////////////////////////////////////////////////////////////////////////
+
+struct Sender {}
+
+impl reactor_actor::ActorSend for Sender {
+    type OMsg = Envelope;
+
+    /// The metrics actor only listens
+    async fn before_send<'a>(&'a mut self, _output: &Self::OMsg) -> RouteTo<'a> {
+        RouteTo::Multiple(Cow::Owned(vec![]))
+    }
+}
+
+// //////////////////////////////////////////////////////////////////////////////
+//                                  ACTORS
+// //////////////////////////////////////////////////////////////////////////////
+
+/// Metrics collector actor, logging a merged snapshot about every `interval_ms` and
+/// appending it to `output_path` if set
+pub async fn metrics(ctx: RuntimeCtx, interval_ms: u64, output_path: Option<PathBuf>) {
+    let output = output_path.and_then(|path| match File::create(&path) {
+        Ok(file) => Some(BufWriter::new(file)),
+        Err(e) => {
+            warn!("Can't create metrics output {}: {}", path.display(), e);
+            None
+        }
+    });
+    let processor = Processor::new(Duration::from_millis(interval_ms), output);
+    BehaviourBuilder::new(Versioned(processor), BincodeCodec::default())
+        .send(Sender {})
+        .on_send_failure(SendErrAction::Drop)
+        .build()
+        .run(ctx)
+        .await
+        .unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reactor_actor::ActorProcess;
+
+    fn report(source: &str, committed: u64, in_flight: f64) -> EMsg {
+        EMsg::Metrics {
+            source: source.to_string(),
+            counters: HashMap::from([("committed".to_string(), committed)]),
+            gauges: HashMap::from([("in_flight".to_string(), in_flight)]),
+        }
+    }
+
+    // --- Tests ---
+    #[test]
+    fn test_merged_snapshot_sums_the_last_report_of_each_source() {
+        let mut p = Processor::new(Duration::from_secs(60), None);
+        assert!(p.process(report("r0", 5, 1.0)).is_empty());
+        p.process(report("r1", 7, 2.5));
+        // Totals, the newer report replaces the older
+        p.process(report("r0", 6, 0.0));
+
+        let now = Instant::now();
+        let merged = p.merged(now);
+        assert_eq!(merged["sources"], 2);
+        assert_eq!(merged["counters"]["committed"], 13);
+        assert_eq!(merged["gauges"]["in_flight"], 2.5);
+        assert_eq!(merged["by_source"]["r0"]["counters"]["committed"], 6);
+        assert_eq!(merged["stale_sources"].as_array().unwrap().len(), 0);
+
+        let later = now + Duration::from_secs(60) * (STALE_INTERVALS + 1);
+        assert_eq!(p.merged(later)["stale_sources"][0], "r0");
+    }
+
+    #[test]
+    fn test_reporter_waits_an_interval_between_reports() {
+        let config = MetricsConfig {
+            actor: "metrics".to_string(),
+            interval_ms: 1000,
+        };
+        let mut reporter = MetricsReporter::new("c1".to_string(), &config);
+        let start = Instant::now();
+        let collect = || (HashMap::new(), HashMap::new());
+        assert!(reporter.report_if_due(start, collect).is_none());
+
+        let due = start + Duration::from_millis(1000);
+        let Some(EMsg::Metrics { source, .. }) = reporter.report_if_due(due, collect) else {
+            panic!("Expected a Metrics report");
+        };
+        assert_eq!(source, "c1");
+        assert!(reporter.report_if_due(due, collect).is_none());
+    }
+}
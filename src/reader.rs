@@ -1,21 +1,54 @@
 use crate::SLEEP_MS;
-use crate::common::{ClientRequest, Command, CommandResult, EMsg, Variable};
+use crate::common::{
+    ClientRequest, ClientResponse, Command, CommandResult, EMsg, Envelope, Variable, Versioned,
+    fmt_opt_value,
+};
 use reactor_actor::codec::BincodeCodec;
 use reactor_actor::{BehaviourBuilder, RouteTo, RuntimeCtx, SendErrAction};
 
+use std::collections::HashMap;
 use std::time::Duration;
 
-#[cfg(feature = "verbose")]
-use tracing::info;
+use tracing::{error, info, warn};
 
 // //////////////////////////////////////////////////////////////////////////////
 //                                  Generator
 // //////////////////////////////////////////////////////////////////////////////
 
+/// What the reader reads, from the `key`, `count`, `interval_ms`, `expected_value`,
+/// `max_attempts` and `retry_backoff_ms` fields of its payload
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReaderConfig {
+    pub key: String,
+    pub count: usize,
+    /// Pause between two reads. The first one always waits SLEEP_MS
+    pub interval_ms: u64,
+    /// Value every read must return. None only logs what the reads return
+    pub expected_value: Option<String>,
+    /// Reads of a value, the first one included, before it is reported as FAIL
+    pub max_attempts: usize,
+    /// Pause before re-reading a mismatch, doubling with every attempt
+    pub retry_backoff_ms: u64,
+}
+
+impl Default for ReaderConfig {
+    fn default() -> Self {
+        ReaderConfig {
+            key: "key1".to_string(),
+            count: 10,
+            interval_ms: 0,
+            expected_value: None,
+            max_attempts: 5,
+            retry_backoff_ms: SLEEP_MS,
+        }
+    }
+}
+
 /// Iterator which yields read requests with a delay. Used by reactor-generator to create messages
 struct ReadReqGenerator {
     count: usize,
     addr: String,
+    config: ReaderConfig,
 }
 
 impl Iterator for ReadReqGenerator {
@@ -25,21 +58,19 @@ impl Iterator for ReadReqGenerator {
         if self.count == 0 {
             // Initial delay before first read
             std::thread::sleep(Duration::from_millis(SLEEP_MS));
+        } else if self.count < self.config.count && self.config.interval_ms > 0 {
+            std::thread::sleep(Duration::from_millis(self.config.interval_ms));
         }
-        if self.count < 10 {
-            // std::thread::sleep(Duration::from_millis(2 * SLEEP_MS));
-            // std::thread::sleep(Duration::from_millis(100));
+        if self.count < self.config.count {
             self.count += 1;
             let cmd = Command::Get {
-                key: Variable {
-                    name: "key1".to_string(),
-                },
-                // key: Variable(format!("foo{}", self.count)),
+                key: Variable::new(self.config.key.clone()),
             };
             Some(EMsg::ClientRequest(ClientRequest {
                 client_id: self.addr.clone(),
                 msg_id: format!("{}_r_{}", self.addr, self.count),
                 cmd,
+                timestamp_us: None,
             }))
         } else {
             None
@@ -55,8 +86,74 @@ impl Iterator for ReadReqGenerator {
 /// stores state in the struct fields
 /// process() method defines how to handle incoming messages, and return corresponding output messages
 struct Processor {
-    #[cfg(feature = "verbose")]
     reader_client: String,
+    config: ReaderConfig,
+    retries: HashMap<String, (String, usize)>, // Re-sent msg_id -> (first msg_id, attempt)
+}
+
+impl Processor {
+    fn new(reader_client: String, config: ReaderConfig) -> Self {
+        Processor {
+            reader_client,
+            config,
+            retries: HashMap::new(),
+        }
+    }
+
+    /// Checks a response against expected_value. A mismatch or a missing value is read again
+    /// after a backoff, until max_attempts reads of it failed
+    fn check_response(&mut self, resp: &ClientResponse) -> Vec<EMsg> {
+        let Some(expected) = &self.config.expected_value else {
+            return vec![];
+        };
+        let (first_msg_id, attempt) = self
+            .retries
+            .remove(&resp.msg_id)
+            .unwrap_or_else(|| (resp.msg_id.clone(), 1));
+        let got = match &resp.cmd_result {
+            CommandResult::Get { val, .. } => fmt_opt_value(val),
+            other => format!("{:?}", other),
+        };
+        let passed = matches!(&resp.cmd_result,
+            CommandResult::Get { val: Some(v), .. } if v.to_string() == *expected);
+        if passed {
+            info!(
+                "{} PASS {} = {} ({}, attempt {})",
+                self.reader_client, self.config.key, got, first_msg_id, attempt
+            );
+            return vec![];
+        }
+        if attempt >= self.config.max_attempts {
+            error!(
+                "{} FAIL {}: expected {}, got {} ({}, after {} attempts)",
+                self.reader_client, self.config.key, expected, got, first_msg_id, attempt
+            );
+            return vec![];
+        }
+        warn!(
+            "{} MISMATCH {}: expected {}, got {} ({}, attempt {}/{}), retrying",
+            self.reader_client,
+            self.config.key,
+            expected,
+            got,
+            first_msg_id,
+            attempt,
+            self.config.max_attempts
+        );
+        let backoff = self.config.retry_backoff_ms << (attempt - 1).min(16);
+        std::thread::sleep(Duration::from_millis(backoff));
+        let msg_id = format!("{}_a{}", first_msg_id, attempt + 1);
+        self.retries
+            .insert(msg_id.clone(), (first_msg_id, attempt + 1));
+        vec![EMsg::ClientRequest(ClientRequest {
+            client_id: self.reader_client.clone(),
+            msg_id,
+            cmd: Command::Get {
+                key: Variable::new(self.config.key.clone()),
+            },
+            timestamp_us: None,
+        })]
+    }
 }
 
 impl reactor_actor::ActorProcess for Processor {
@@ -68,22 +165,22 @@ impl reactor_actor::ActorProcess for Processor {
             EMsg::ClientRequest(_msg) => {
                 #[cfg(feature = "verbose")]
                 {
-                    info!("{} Getting {}", self.reader_client, _msg.cmd.key().name);
+                    info!("{} Getting {}", self.reader_client, _msg.cmd.keys()[0].name);
                 }
                 vec![input]
             }
 
-            EMsg::ClientResponse(_msg) => {
+            EMsg::ClientResponse(msg) => {
                 #[cfg(feature = "verbose")]
-                if let CommandResult::Get { key, val } = &_msg.cmd_result {
+                if let CommandResult::Get { key, val, .. } = &msg.cmd_result {
                     info!(
                         "{} Get {} = {}",
                         self.reader_client,
                         key.name,
-                        (val).as_deref().unwrap_or("NONE")
+                        fmt_opt_value(val)
                     );
                 }
-                vec![]
+                self.check_response(msg)
             }
             _ => {
                 panic!("Reader got unexpected message")
@@ -103,10 +200,10 @@ struct Sender {
 }
 
 impl reactor_actor::ActorSend for Sender {
-    type OMsg = EMsg;
+    type OMsg = Envelope;
 
     async fn before_send<'a>(&'a mut self, output: &Self::OMsg) -> RouteTo<'a> {
-        match &output {
+        match &output.msg {
             EMsg::ClientRequest(_) => RouteTo::from(self.server.as_str()),
             _ => {
                 panic!("Reader tried to send non ReadRequest")
@@ -133,18 +230,19 @@ impl Sender {
 /// - Only modify the method calls which take these earlier defined structs. Rest is default boilerplate
 /// - `on_send_failure` is to provide setting on what to do when sending fails, retry or drop. Go to `SendErrAction` for more details
 /// - Go to docs of `BehaviourBuilder` and `Behavior` struct for more details
-pub async fn reader(ctx: RuntimeCtx, server: String) {
+pub async fn reader(ctx: RuntimeCtx, server: String, config: ReaderConfig) {
     BehaviourBuilder::new(
-        Processor {
-            #[cfg(feature = "verbose")]
-            reader_client: ctx.addr.to_string(),
-        },
+        Versioned(Processor::new(ctx.addr.to_string(), config.clone())),
         BincodeCodec::default(),
     )
     .send(Sender::new(server))
-    .generator_if(true, || ReadReqGenerator {
-        count: 0,
-        addr: ctx.addr.to_string(),
+    .generator_if(true, || {
+        ReadReqGenerator {
+            count: 0,
+            addr: ctx.addr.to_string(),
+            config,
+        }
+        .map(Envelope::new)
     })
     .on_send_failure(SendErrAction::Drop)
     .build()
@@ -152,3 +250,131 @@ pub async fn reader(ctx: RuntimeCtx, server: String) {
     .await
     .unwrap();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reactor_actor::ActorProcess;
+    use std::time::Instant;
+
+    fn generator(config: ReaderConfig) -> ReadReqGenerator {
+        ReadReqGenerator {
+            count: 0,
+            addr: "reader1".to_string(),
+            config,
+        }
+    }
+
+    fn checking(expected: &str, max_attempts: usize) -> Processor {
+        Processor::new(
+            "reader1".to_string(),
+            ReaderConfig {
+                expected_value: Some(expected.to_string()),
+                max_attempts,
+                retry_backoff_ms: 0,
+                ..ReaderConfig::default()
+            },
+        )
+    }
+
+    fn get_response(msg_id: &str, val: Option<&str>) -> EMsg {
+        EMsg::ClientResponse(ClientResponse {
+            msg_id: msg_id.to_string(),
+            client_id: "reader1".to_string(),
+            cmd_result: CommandResult::Get {
+                key: Variable::new("key1"),
+                val: val.map(Into::into),
+                version: 0,
+            },
+            server_received_us: None,
+            server_responded_us: None,
+        })
+    }
+
+    fn read_keys(msgs: &[EMsg]) -> Vec<String> {
+        msgs.iter()
+            .map(|msg| match msg {
+                EMsg::ClientRequest(req) => req.cmd.keys()[0].name.clone(),
+                other => panic!("Unexpected {:?}", other),
+            })
+            .collect()
+    }
+
+    // --- Tests ---
+    #[test]
+    fn test_default_reads_key1_ten_times() {
+        let msgs: Vec<EMsg> = generator(ReaderConfig::default()).collect();
+        assert_eq!(read_keys(&msgs), vec!["key1"; 10]);
+        let EMsg::ClientRequest(last) = &msgs[9] else {
+            unreachable!()
+        };
+        assert_eq!(last.client_id, "reader1");
+        assert_eq!(last.msg_id, "reader1_r_10");
+    }
+
+    #[test]
+    fn test_reads_given_key_count_times_spaced_by_interval() {
+        let start = Instant::now();
+        let msgs: Vec<EMsg> = generator(ReaderConfig {
+            key: "probe".to_string(),
+            count: 3,
+            interval_ms: 20,
+            ..ReaderConfig::default()
+        })
+        .collect();
+        assert_eq!(read_keys(&msgs), vec!["probe"; 3]);
+        // The initial delay, then two intervals
+        assert!(start.elapsed() >= Duration::from_millis(SLEEP_MS + 40));
+    }
+
+    #[test]
+    fn test_zero_count_reads_nothing() {
+        let config = ReaderConfig {
+            count: 0,
+            ..ReaderConfig::default()
+        };
+        assert_eq!(generator(config).count(), 0);
+    }
+
+    #[test]
+    fn test_matching_value_passes_without_retry() {
+        let mut p = checking("v1", 3);
+        assert!(p.process(get_response("r_1", Some("v1"))).is_empty());
+        assert!(p.retries.is_empty());
+    }
+
+    #[test]
+    fn test_mismatch_is_read_again_until_it_passes() {
+        let mut p = checking("v1", 3);
+        let out = p.process(get_response("r_1", None));
+        let [EMsg::ClientRequest(retry)] = &out[..] else {
+            panic!("Expected a retry, got {:?}", out)
+        };
+        assert_eq!(retry.msg_id, "r_1_a2");
+        assert_eq!(read_keys(&out), vec!["key1"]);
+
+        let out = p.process(get_response("r_1_a2", Some("old")));
+        let [EMsg::ClientRequest(retry)] = &out[..] else {
+            panic!("Expected a retry, got {:?}", out)
+        };
+        assert_eq!(retry.msg_id, "r_1_a3");
+
+        assert!(p.process(get_response("r_1_a3", Some("v1"))).is_empty());
+        assert!(p.retries.is_empty());
+    }
+
+    #[test]
+    fn test_gives_up_after_max_attempts() {
+        let mut p = checking("v1", 2);
+        assert_eq!(p.process(get_response("r_1", Some("old"))).len(), 1);
+        // The second read fails too, which is the last one
+        assert!(p.process(get_response("r_1_a2", Some("old"))).is_empty());
+        assert!(p.retries.is_empty());
+    }
+
+    #[test]
+    fn test_no_expectation_only_logs() {
+        let mut p = Processor::new("reader1".to_string(), ReaderConfig::default());
+        assert!(p.process(get_response("r_1", None)).is_empty());
+    }
+}
@@ -1,14 +1,29 @@
-use crate::common::{ClientResponse, Command, CommandResult, EMsg};
+use crate::common::{
+    ClientErrorMsg, ClientRequest, ClientResponse, EMsg, Envelope, Value, Variable, Versioned,
+    now_us,
+};
+use crate::store::{self, Store};
 use reactor_actor::codec::BincodeCodec;
 use reactor_actor::{BehaviourBuilder, RouteTo, RuntimeCtx, SendErrAction};
 
-use std::collections::HashMap;
+use std::collections::BTreeMap;
+
+/// A value with the number of writes to its key so far and when it stops being readable
+#[derive(Debug, Clone)]
+struct Entry {
+    val: Value,
+    version: u64,
+    expires_at_ms: Option<u64>,
+}
 
 // //////////////////////////////////////////////////////////////////////////////
 //                                  Processor
 // //////////////////////////////////////////////////////////////////////////////
+
+/// Single node store answering commands as they arrive, with no replication. A baseline
+/// to compare the epaxos numbers against
 struct Processor {
-    data: HashMap<String, String>,
+    data: BTreeMap<Variable, Entry>,
 }
 
 impl reactor_actor::ActorProcess for Processor {
@@ -16,30 +31,8 @@ impl reactor_actor::ActorProcess for Processor {
     type OMsg = EMsg;
 
     fn process(&mut self, input: Self::IMsg) -> Vec<Self::OMsg> {
-        match &input {
-            EMsg::ClientRequest(msg) => {
-                let cmd_result = match &msg.cmd {
-                    Command::Get { key } => {
-                        let val = self.data.get(&key.name).cloned();
-                        CommandResult::Get {
-                            key: key.clone(),
-                            val,
-                        }
-                    }
-                    Command::Set { key, val } => {
-                        self.data.insert(key.name.clone(), val.clone());
-                        CommandResult::Set {
-                            key: key.clone(),
-                            status: true,
-                        }
-                    }
-                };
-
-                vec![EMsg::ClientResponse(ClientResponse {
-                    msg_id: msg.msg_id.clone(),
-                    cmd_result,
-                })]
-            }
+        match input {
+            EMsg::ClientRequest(req) => vec![self.handle(req)],
             _ => {
                 panic!("Server got an unexpected message")
             }
@@ -48,24 +41,104 @@ impl reactor_actor::ActorProcess for Processor {
 }
 
 impl Processor {
-    fn new() -> Self {
-        let mut map = HashMap::new();
-        map.insert("key1".to_string(), "val1".to_string());
-        Processor { data: map }
+    fn new(seed: Vec<(Variable, Value)>) -> Self {
+        let mut processor = Processor {
+            data: BTreeMap::new(),
+        };
+        for (key, val) in seed {
+            processor.write_at(key, val, 0, None);
+        }
+        processor
+    }
+
+    /// Applies a request and answers it. Expiry is checked against the wall clock, there's
+    /// no other replica to agree with
+    fn handle(&mut self, req: ClientRequest) -> EMsg {
+        let received_us = now_us();
+        let now_ms = received_us / 1000;
+        let Some(cmd_result) = store::apply(self, req.cmd, now_ms) else {
+            return EMsg::ClientError(ClientErrorMsg {
+                msg_id: req.msg_id,
+                client_id: req.client_id,
+                reason: "not supported by the non-replicated server".to_string(),
+                retryable: false,
+            });
+        };
+
+        EMsg::ClientResponse(ClientResponse {
+            msg_id: req.msg_id,
+            client_id: req.client_id,
+            cmd_result,
+            server_received_us: Some(received_us),
+            server_responded_us: Some(now_us()),
+        })
+    }
+}
+
+impl Store for Processor {
+    fn read_at(&self, key: &Variable, ts_ms: u64) -> Option<&Value> {
+        self.data
+            .get(key)
+            .filter(|e| e.expires_at_ms.is_none_or(|exp| ts_ms < exp))
+            .map(|e| &e.val)
+    }
+
+    fn write_at(&mut self, key: Variable, val: Value, ts_ms: u64, ttl_ms: Option<u64>) {
+        let version = self.version(&key) + 1;
+        let expires_at_ms = ttl_ms.map(|ttl| ts_ms.saturating_add(ttl));
+        self.data.insert(
+            key,
+            Entry {
+                val,
+                version,
+                expires_at_ms,
+            },
+        );
+    }
+
+    fn version(&self, key: &Variable) -> u64 {
+        self.data.get(key).map_or(0, |e| e.version)
+    }
+
+    fn scan(
+        &self,
+        start: &Variable,
+        end: &Variable,
+        limit: usize,
+        ts_ms: u64,
+    ) -> Vec<(Variable, Value)> {
+        self.data
+            .range::<Variable, _>(start..end)
+            .filter(|(_, e)| e.expires_at_ms.is_none_or(|exp| ts_ms < exp))
+            .take(limit)
+            .map(|(k, e)| (k.clone(), e.val.clone()))
+            .collect()
     }
 }
 
+// //////////////////////////////////////////////////////////////////////////////
+//                                  Sender
+// //////////////////////////////////////////////////////////////////////////////
+
 struct Sender {}
+
 impl reactor_actor::ActorSend for Sender {
-    type OMsg = EMsg;
+    type OMsg = Envelope;
 
-    async fn before_send<'a>(&'a mut self, _output: &Self::OMsg) -> RouteTo<'a> {
-        match &_output {
-            EMsg::ClientResponse(_) => RouteTo::Reply,
+    /// Like the epaxos server, answers go to the actor named by client_id, so the same
+    /// clients work against both. Without one there's nobody to answer
+    async fn before_send<'a>(&'a mut self, output: &Self::OMsg) -> RouteTo<'a> {
+        let client_id = match &output.msg {
+            EMsg::ClientResponse(resp) => &resp.client_id,
+            EMsg::ClientError(err) => &err.client_id,
             _ => {
                 panic!("Server tried to send non ClientResponse")
             }
+        };
+        if client_id.is_empty() {
+            return RouteTo::Multiple(std::borrow::Cow::Owned(vec![]));
         }
+        RouteTo::Single(std::borrow::Cow::Owned(client_id.clone()))
     }
 }
 
@@ -73,8 +146,9 @@ impl reactor_actor::ActorSend for Sender {
 //                                  ACTORS
 // //////////////////////////////////////////////////////////////////////////////
 
-pub async fn server(ctx: RuntimeCtx) {
-    BehaviourBuilder::new(Processor::new(), BincodeCodec::default())
+/// Non-replicated server actor, storing `seed` before the first request
+pub async fn server(ctx: RuntimeCtx, seed: Vec<(Variable, Value)>) {
+    BehaviourBuilder::new(Versioned(Processor::new(seed)), BincodeCodec::default())
         .send(Sender {})
         .on_send_failure(SendErrAction::Drop)
         .build()
@@ -82,3 +156,77 @@ pub async fn server(ctx: RuntimeCtx) {
         .await
         .unwrap();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::{Command, CommandResult};
+
+    fn request(msg_id: &str, cmd: Command) -> ClientRequest {
+        ClientRequest {
+            client_id: "c1".to_string(),
+            msg_id: msg_id.to_string(),
+            cmd,
+            timestamp_us: None,
+        }
+    }
+
+    fn get(p: &mut Processor, key: &str) -> (Option<Value>, u64) {
+        let EMsg::ClientResponse(ClientResponse {
+            cmd_result: CommandResult::Get { val, version, .. },
+            ..
+        }) = p.handle(request(
+            "get",
+            Command::Get {
+                key: Variable::new(key),
+            },
+        ))
+        else {
+            panic!("Expected a Get response")
+        };
+        (val, version)
+    }
+
+    // --- Tests ---
+    #[test]
+    fn test_seeded_keys_read_back_and_count_writes() {
+        let mut p = Processor::new(vec![(Variable::new("k"), "v0".into())]);
+        assert_eq!(get(&mut p, "k"), (Some("v0".into()), 1));
+        assert_eq!(get(&mut p, "missing"), (None, 0));
+
+        let out = p.handle(request(
+            "set",
+            Command::Set {
+                key: Variable::new("k"),
+                val: "v1".into(),
+                ttl_ms: None,
+            },
+        ));
+        let EMsg::ClientResponse(resp) = out else {
+            panic!("Expected a ClientResponse")
+        };
+        assert_eq!(resp.client_id, "c1");
+        assert_eq!(resp.msg_id, "set");
+        assert!(matches!(
+            resp.cmd_result,
+            CommandResult::Set { version: 2, .. }
+        ));
+        assert_eq!(get(&mut p, "k"), (Some("v1".into()), 2));
+
+        // del from the cli: an empty value that has already expired
+        p.handle(request("del", Command::set_ex(Variable::new("k"), "", 0)));
+        assert_eq!(get(&mut p, "k"), (None, 3));
+    }
+
+    #[test]
+    fn test_watch_is_refused() {
+        let mut p = Processor::new(vec![]);
+        let out = p.handle(request(
+            "w",
+            Command::Watch {
+                key: Variable::new("k"),
+            },
+        ));
+        assert!(matches!(out, EMsg::ClientError(err) if !err.retryable && err.client_id == "c1"));
+    }
+}
@@ -0,0 +1,144 @@
+use crate::common::{Command, CommandResult, ErrorCode, Value, Variable};
+
+/// Key-value data a command runs against. The epaxos replicas and the non-replicated server
+/// keep theirs differently, `apply` gives both the same command semantics
+pub(crate) trait Store {
+    /// Value at `key` as seen at `ts_ms`, None if missing or expired
+    fn read_at(&self, key: &Variable, ts_ms: u64) -> Option<&Value>;
+
+    /// Stores `val` at `key`, expiring `ttl_ms` after `ts_ms` if set. Bumps the key's version
+    fn write_at(&mut self, key: Variable, val: Value, ts_ms: u64, ttl_ms: Option<u64>);
+
+    /// Number of writes to `key` so far, 0 if never written
+    fn version(&self, key: &Variable) -> u64;
+
+    /// Up to `limit` pairs live at `ts_ms` with keys in `start..end`, in key order
+    fn scan(
+        &self,
+        start: &Variable,
+        end: &Variable,
+        limit: usize,
+        ts_ms: u64,
+    ) -> Vec<(Variable, Value)>;
+}
+
+/// Runs `cmd` against `store` at `ts_ms`. None for the commands that aren't about the data:
+/// Reconfig, Watch and Unwatch
+pub(crate) fn apply(store: &mut impl Store, cmd: Command, ts_ms: u64) -> Option<CommandResult> {
+    let cmd_result = match cmd {
+        Command::Get { key } => {
+            let val = store.read_at(&key, ts_ms).cloned();
+            let version = store.version(&key);
+            CommandResult::Get { key, val, version }
+        }
+        Command::Set { key, val, ttl_ms } => {
+            store.write_at(key.clone(), val, ts_ms, ttl_ms);
+            let version = store.version(&key);
+            CommandResult::Set { key, version }
+        }
+        Command::Cas { key, expected, new } => {
+            let actual = store.read_at(&key, ts_ms).cloned();
+            let success = actual == expected;
+            if success {
+                store.write_at(key.clone(), new, ts_ms, None);
+            }
+            CommandResult::Cas {
+                key,
+                success,
+                actual,
+            }
+        }
+        Command::Incr { key, delta } => match apply_incr(store, &key, delta, ts_ms) {
+            Ok(val) => CommandResult::Incr { key, val },
+            Err((code, detail)) => CommandResult::Error {
+                key: Some(key),
+                code,
+                detail,
+            },
+        },
+        Command::Append { key, suffix } => {
+            let mut val = store.read_at(&key, ts_ms).cloned().unwrap_or_default();
+            val.0.extend_from_slice(suffix.as_bytes());
+            let len = val.len();
+            store.write_at(key.clone(), val, ts_ms, None);
+            CommandResult::Append { key, len }
+        }
+        Command::SetIfAbsent { key, val } => {
+            let success = store.read_at(&key, ts_ms).is_none();
+            if success {
+                store.write_at(key.clone(), val, ts_ms, None);
+            }
+            CommandResult::SetIfAbsent { key, success }
+        }
+        Command::MultiSet { pairs } => {
+            let keys = pairs.iter().map(|(k, _)| k.clone()).collect();
+            for (key, val) in pairs {
+                store.write_at(key, val, ts_ms, None);
+            }
+            CommandResult::MultiSet { keys, status: true }
+        }
+        Command::MultiGet { keys } => CommandResult::MultiGet {
+            vals: read_all(store, keys, ts_ms),
+        },
+        Command::Txn { reads, writes } => {
+            let reads = read_all(store, reads, ts_ms);
+            for (key, val) in writes {
+                store.write_at(key, val, ts_ms, None);
+            }
+            CommandResult::Txn { reads }
+        }
+        Command::Scan { start, end, limit } => {
+            // BTreeMap::range panics on an inverted range
+            if start > end {
+                CommandResult::Error {
+                    key: None,
+                    code: ErrorCode::InvalidRange,
+                    detail: format!("{} sorts after {}", start, end),
+                }
+            } else {
+                let pairs = store.scan(&start, &end, limit, ts_ms);
+                CommandResult::Scan { pairs }
+            }
+        }
+        Command::Noop => CommandResult::Noop,
+        Command::Reconfig { .. } | Command::Watch { .. } | Command::Unwatch { .. } => {
+            return None;
+        }
+    };
+    Some(cmd_result)
+}
+
+fn read_all(store: &impl Store, keys: Vec<Variable>, ts_ms: u64) -> Vec<(Variable, Option<Value>)> {
+    keys.into_iter()
+        .map(|k| {
+            let v = store.read_at(&k, ts_ms).cloned();
+            (k, v)
+        })
+        .collect()
+}
+
+/// Adds delta to the integer at key, treating a missing key as 0.
+/// Leaves the store untouched if the value isn't an integer or the sum overflows
+fn apply_incr(
+    store: &mut impl Store,
+    key: &Variable,
+    delta: i64,
+    ts_ms: u64,
+) -> Result<i64, (ErrorCode, String)> {
+    let current = match store.read_at(key, ts_ms) {
+        Some(val) => val
+            .as_str()
+            .and_then(|s| s.parse::<i64>().ok())
+            .ok_or_else(|| {
+                let detail = format!("value {:?} is not an integer", val.to_string());
+                (ErrorCode::NotAnInteger, detail)
+            })?,
+        None => 0,
+    };
+    let new = current.checked_add(delta).ok_or_else(|| {
+        let detail = format!("{} + {} overflows", current, delta);
+        (ErrorCode::Overflow, detail)
+    })?;
+    store.write_at(key.clone(), new.to_string().into(), ts_ms, None);
+    Ok(new)
+}
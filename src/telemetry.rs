@@ -0,0 +1,47 @@
+use opentelemetry::propagation::TextMapPropagator;
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use std::collections::HashMap;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::layer::SubscriberExt;
+
+/// W3C header carried in trace_ctx
+const TRACEPARENT: &str = "traceparent";
+
+/// Exports spans to the OTLP collector at `endpoint` (gRPC, e.g. "http://localhost:4317").
+/// Installs the global tracing subscriber, so it fails if a logger was set up before
+pub fn setup_otlp_tracer(endpoint: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_tonic()
+        .with_endpoint(endpoint)
+        .build()?;
+    let provider = opentelemetry_sdk::trace::TracerProvider::builder()
+        .with_batch_exporter(exporter, opentelemetry_sdk::runtime::Tokio)
+        .build();
+    let tracer = provider.tracer("epaxos");
+    opentelemetry::global::set_tracer_provider(provider);
+
+    let subscriber = tracing_subscriber::registry()
+        .with(tracing_subscriber::fmt::layer())
+        .with(tracing_opentelemetry::layer().with_tracer(tracer));
+    tracing::subscriber::set_global_default(subscriber)?;
+    Ok(())
+}
+
+/// Context of the current span, to put in an outgoing message's trace_ctx
+pub fn current_trace_ctx() -> Option<Vec<u8>> {
+    let mut carrier = HashMap::new();
+    TraceContextPropagator::new().inject_context(&tracing::Span::current().context(), &mut carrier);
+    carrier.remove(TRACEPARENT).map(String::into_bytes)
+}
+
+/// Makes the span of the handler receiving a message a child of the sender's span
+pub fn set_parent(trace_ctx: Option<Vec<u8>>) {
+    let Some(traceparent) = trace_ctx.and_then(|bytes| String::from_utf8(bytes).ok()) else {
+        return;
+    };
+    let carrier = HashMap::from([(TRACEPARENT.to_string(), traceparent)]);
+    let parent = TraceContextPropagator::new().extract(&carrier);
+    tracing::Span::current().set_parent(parent);
+}
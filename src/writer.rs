@@ -1,10 +1,12 @@
 use crate::SLEEP_MS;
-use crate::common::{ClientRequest, Command, CommandResult, EMsg, Variable};
+use crate::common::{ClientRequest, Command, EMsg, Envelope, Variable, Versioned};
 use reactor_actor::codec::BincodeCodec;
 use reactor_actor::{BehaviourBuilder, RouteTo, RuntimeCtx, SendErrAction};
 
 use std::time::Duration;
 
+#[cfg(feature = "verbose")]
+use crate::common::CommandResult;
 #[cfg(feature = "verbose")]
 use tracing::info;
 
@@ -12,37 +14,76 @@ use tracing::info;
 //                                  Generator
 // //////////////////////////////////////////////////////////////////////////////
 
+/// What the writer writes, from the `key`, `value_prefix`, `count` and `interval_ms` fields of
+/// its payload
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WriterConfig {
+    pub key: String,
+    /// Values are the prefix followed by the write's sequence number, from 1.
+    /// None prefixes with "value" and the writer's addr
+    pub value_prefix: Option<String>,
+    pub count: usize,
+    /// Pause before each write
+    pub interval_ms: u64,
+}
+
+impl Default for WriterConfig {
+    fn default() -> Self {
+        WriterConfig {
+            key: "key1".to_string(),
+            value_prefix: None,
+            count: 10,
+            interval_ms: SLEEP_MS,
+        }
+    }
+}
+
 /// Iterator which yields write requests with a delay. Used by reactor-generator to create messages
 struct WriteReqGenerator {
     count: usize,
     addr: String,
+    value_prefix: String,
+    config: WriterConfig,
+}
+
+impl WriteReqGenerator {
+    fn new(addr: String, config: WriterConfig) -> Self {
+        let value_prefix = config
+            .value_prefix
+            .clone()
+            .unwrap_or_else(|| format!("value{}", addr));
+        WriteReqGenerator {
+            count: 0,
+            addr,
+            value_prefix,
+            config,
+        }
+    }
 }
 
 impl Iterator for WriteReqGenerator {
     type Item = EMsg;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.count < 10 {
-            std::thread::sleep(Duration::from_millis(SLEEP_MS));
-            // std::thread::sleep(Duration::from_millis(100));
+        if self.count < self.config.count {
+            std::thread::sleep(Duration::from_millis(self.config.interval_ms));
             self.count += 1;
 
             let cmd = Command::Set {
-                key: Variable {
-                    name: "key1".to_string(),
-                },
-                // key: Variable(format!("foo{}", self.count)),
-                val: format!("value{}{}", self.addr, self.count),
+                key: Variable::new(self.config.key.clone()),
+                val: format!("{}{}", self.value_prefix, self.count).into(),
+                ttl_ms: None,
             };
             Some(EMsg::ClientRequest(ClientRequest {
                 client_id: self.addr.clone(),
                 msg_id: format!("{}_r_{}", self.addr, self.count),
                 cmd,
+                timestamp_us: None,
             }))
         // } else if self.count == 1 {
-        //     //send EMsg::DumpStateMsg
+        //     //send EMsg::DumpState
         //     self.count += 1;
-        //     Some(EMsg::DumpStateMsg)
+        //     Some(EMsg::DumpState)
         } else {
             None
         }
@@ -66,7 +107,7 @@ impl reactor_actor::ActorProcess for Processor {
         match &input {
             EMsg::ClientRequest(_msg) => {
                 #[cfg(feature = "verbose")]
-                if let Command::Set { key, val } = &_msg.cmd {
+                if let Command::Set { key, val, .. } = &_msg.cmd {
                     info!(
                         "{} Writing: key={} val={}",
                         self.writer_client, key.name, val
@@ -77,21 +118,26 @@ impl reactor_actor::ActorProcess for Processor {
 
             EMsg::ClientResponse(_resp) => {
                 #[cfg(feature = "verbose")]
-                if let CommandResult::Set { key, status } = &_resp.cmd_result {
+                if let CommandResult::Set { key, version } = &_resp.cmd_result {
                     info!(
-                        "{} WriteResponse: {} -> success={}",
-                        self.writer_client, key.name, status
+                        "{} WriteResponse: {} -> version={}",
+                        self.writer_client, key.name, version
                     );
                 }
                 vec![]
             } // _ => panic!("Writer got unexpected message"),
-            EMsg::DumpStateMsg => {
+            EMsg::DumpState => {
                 #[cfg(feature = "verbose")]
                 {
-                    info!("{} Sending DumpStateMsg", self.writer_client);
+                    info!("{} Sending DumpState", self.writer_client);
                 }
                 vec![input]
             }
+            EMsg::DumpStateResponse(_state) => {
+                #[cfg(feature = "verbose")]
+                info!("{} State: {}", self.writer_client, _state);
+                vec![]
+            }
             _ => {
                 panic!("Writer got unexpected message")
             }
@@ -108,11 +154,11 @@ struct Sender {
 }
 
 impl reactor_actor::ActorSend for Sender {
-    type OMsg = EMsg;
+    type OMsg = Envelope;
 
     async fn before_send<'a>(&'a mut self, output: &Self::OMsg) -> RouteTo<'a> {
-        match &output {
-            EMsg::ClientRequest(_) | EMsg::DumpStateMsg => RouteTo::from(self.server.as_str()),
+        match &output.msg {
+            EMsg::ClientRequest(_) | EMsg::DumpState => RouteTo::from(self.server.as_str()),
             _ => panic!("Writer tried to send non WriteRequest"),
         }
     }
@@ -128,18 +174,17 @@ impl Sender {
 //                                  ACTORS
 // //////////////////////////////////////////////////////////////////////////////
 
-pub async fn writer(ctx: RuntimeCtx, server: String) {
+pub async fn writer(ctx: RuntimeCtx, server: String, config: WriterConfig) {
     BehaviourBuilder::new(
-        Processor {
+        Versioned(Processor {
             #[cfg(feature = "verbose")]
             writer_client: ctx.addr.to_string(),
-        },
+        }),
         BincodeCodec::default(),
     )
     .send(Sender::new(server))
-    .generator_if(true, || WriteReqGenerator {
-        count: 0,
-        addr: ctx.addr.to_string(),
+    .generator_if(true, || {
+        WriteReqGenerator::new(ctx.addr.to_string(), config).map(Envelope::new)
     })
     .on_send_failure(SendErrAction::Drop)
     .build()
@@ -147,3 +192,54 @@ pub async fn writer(ctx: RuntimeCtx, server: String) {
     .await
     .unwrap();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    fn writes(config: WriterConfig) -> Vec<ClientRequest> {
+        WriteReqGenerator::new("writer1".to_string(), config)
+            .map(|msg| match msg {
+                EMsg::ClientRequest(req) => req,
+                other => panic!("Unexpected {:?}", other),
+            })
+            .collect()
+    }
+
+    fn value(req: &ClientRequest) -> String {
+        match &req.cmd {
+            Command::Set { val, .. } => val.to_string(),
+            other => panic!("Unexpected {}", other),
+        }
+    }
+
+    // --- Tests ---
+    #[test]
+    fn test_default_values_carry_addr_and_sequence_number() {
+        let reqs = writes(WriterConfig {
+            interval_ms: 0,
+            ..WriterConfig::default()
+        });
+        assert_eq!(reqs.len(), 10);
+        assert!(reqs.iter().all(|r| r.cmd.keys()[0].name == "key1"));
+        assert_eq!(value(&reqs[0]), "valuewriter11");
+        assert_eq!(value(&reqs[9]), "valuewriter110");
+    }
+
+    #[test]
+    fn test_stream_of_distinct_writes() {
+        let reqs = writes(WriterConfig {
+            key: "k".to_string(),
+            value_prefix: Some("v_".to_string()),
+            count: 25,
+            interval_ms: 0,
+        });
+        let values: Vec<String> = reqs.iter().map(value).collect();
+        let expected: Vec<String> = (1..=25).map(|i| format!("v_{}", i)).collect();
+        assert_eq!(values, expected);
+        assert!(reqs.iter().all(|r| r.cmd.keys()[0].name == "k"));
+        let msg_ids: HashSet<&str> = reqs.iter().map(|r| r.msg_id.as_str()).collect();
+        assert_eq!(msg_ids.len(), reqs.len());
+    }
+}
@@ -0,0 +1,221 @@
+use epaxos::sim::{
+    ClientRequest, ClusterSimulator, Command, CommandResult, EMsg, ProcessorConfig, Variable,
+};
+use std::collections::HashMap;
+
+fn request(client: usize, cmd: Command) -> ClientRequest {
+    ClientRequest {
+        client_id: format!("c{}", client),
+        msg_id: format!("c{}_0", client),
+        cmd,
+        timestamp_us: None,
+    }
+}
+
+#[test]
+fn test_concurrent_sets_are_linearizable() {
+    let mut cluster = ClusterSimulator::new(3);
+    let replicas = ["r0", "r1", "r2"];
+    let key = Variable::new("k");
+
+    // All ten are in flight before any replica hears of another
+    for c in 0..10 {
+        let set = Command::Set {
+            key: key.clone(),
+            val: format!("v{}", c).into(),
+            ttl_ms: None,
+        };
+        cluster.client_request(replicas[c % 3], request(c, set));
+    }
+    cluster.run_until_quiet();
+
+    // Each Set took its own place in one order of the writes to k
+    let mut versions: HashMap<u64, String> = HashMap::new();
+    for msg in cluster.take_responses() {
+        let EMsg::ClientResponse(resp) = msg else {
+            panic!("Unexpected {:?}", msg)
+        };
+        let CommandResult::Set { version, .. } = resp.cmd_result else {
+            panic!("Unexpected {:?}", resp.cmd_result)
+        };
+        let c = resp.client_id.trim_start_matches('c');
+        assert!(versions.insert(version, format!("v{}", c)).is_none());
+    }
+    assert_eq!(versions.len(), 10);
+    assert!((1..=10).all(|v| versions.contains_key(&v)));
+
+    // Every replica ends on the write ordered last
+    for replica in replicas {
+        assert_eq!(cluster.data_of(replica).get(&key), Some(&versions[&10]));
+    }
+
+    // And a later read sees it too
+    cluster.client_request("r1", request(10, Command::Get { key: key.clone() }));
+    cluster.run_until_quiet();
+    let [EMsg::ClientResponse(resp)] = &cluster.take_responses()[..] else {
+        panic!("Expected one response")
+    };
+    let CommandResult::Get { val, version, .. } = &resp.cmd_result else {
+        panic!("Unexpected {:?}", resp.cmd_result)
+    };
+    assert_eq!(*version, 10);
+    assert_eq!(
+        val.as_ref().map(|v| v.to_string()),
+        Some(versions[&10].clone())
+    );
+}
+
+fn set(client: usize, key: &str, val: &str) -> ClientRequest {
+    let cmd = Command::Set {
+        key: Variable::new(key),
+        val: val.into(),
+        ttl_ms: None,
+    };
+    request(client, cmd)
+}
+
+fn names(replicas: &[&str]) -> Vec<String> {
+    replicas.iter().map(|r| r.to_string()).collect()
+}
+
+/// Replicas that recover a blocking dep on their first tick. Leaders leave their writes
+/// unexecuted until a read needs them: a replica leaves executed instances out of the deps
+/// it reports, so one that missed the commits would otherwise read without them
+fn recovering_cluster(n_replicas: usize, seed: u64) -> ClusterSimulator {
+    let config = ProcessorConfig {
+        recovery_timeout_ms: 0,
+        lazy_execution: true,
+        ..ProcessorConfig::default()
+    };
+    ClusterSimulator::with_config(n_replicas, config, seed)
+}
+
+/// msg_ids of the Sets acked so far
+fn acked(cluster: &mut ClusterSimulator) -> Vec<String> {
+    cluster
+        .take_responses()
+        .into_iter()
+        .filter_map(|msg| match msg {
+            EMsg::ClientResponse(resp) => Some(resp.msg_id),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Has each replica in turn read `key` through the log, ticking it so a read blocked on
+/// instances it missed recovers those. Returns what each replica read
+fn read_everywhere(cluster: &mut ClusterSimulator, replicas: &[&str], key: &str) -> Vec<String> {
+    let mut vals = vec![];
+    for (i, replica) in replicas.iter().enumerate() {
+        let get = Command::Get {
+            key: Variable::new(key),
+        };
+        cluster.client_request(replica, request(100 + i, get));
+        for _ in 0..3 {
+            cluster.run_until_quiet();
+            cluster.tick(replica);
+        }
+        cluster.run_until_quiet();
+        let [EMsg::ClientResponse(resp)] = &cluster.take_responses()[..] else {
+            panic!("{} didn't answer the read", replica)
+        };
+        let CommandResult::Get { val, .. } = &resp.cmd_result else {
+            panic!("Unexpected {:?}", resp.cmd_result)
+        };
+        vals.push(val.as_ref().map_or("NONE".to_string(), |v| v.to_string()));
+    }
+    vals
+}
+
+#[test]
+fn test_majority_side_of_a_partition_makes_progress() {
+    let mut cluster = ClusterSimulator::new(5);
+    cluster.partition(&names(&["r0", "r1", "r2", "r3"]), &names(&["r4"]));
+
+    cluster.client_request("r0", set(0, "a", "majority"));
+    cluster.client_request("r4", set(1, "b", "minority"));
+    cluster.run_until_quiet();
+
+    // Only the side holding a fast quorum could commit
+    assert_eq!(acked(&mut cluster), vec!["c0_0"]);
+    assert!(cluster.dropped() > 0);
+    let majority = ["r0", "r1", "r2", "r3"];
+    assert_eq!(
+        read_everywhere(&mut cluster, &majority, "a"),
+        vec!["majority"; 4]
+    );
+    assert!(cluster.data_of("r4").is_empty());
+}
+
+#[test]
+fn test_partitioned_replica_converges_after_heal() {
+    let mut cluster = recovering_cluster(3, 0);
+    cluster.partition(&names(&["r0", "r1"]), &names(&["r2"]));
+    for c in 0..3 {
+        cluster.client_request("r0", set(c, "k", &format!("v{}", c)));
+    }
+    cluster.run_until_quiet();
+    assert_eq!(acked(&mut cluster).len(), 3);
+
+    // r2 learns of the writes it missed as deps of its read, and recovers them
+    cluster.heal_partition();
+    let replicas = ["r2", "r0", "r1"];
+    assert_eq!(read_everywhere(&mut cluster, &replicas, "k"), vec!["v2"; 3]);
+    for replica in replicas {
+        assert_eq!(
+            cluster.data_of(replica),
+            cluster.data_of("r0"),
+            "{}",
+            replica
+        );
+    }
+}
+
+#[test]
+fn test_dropped_messages_are_recovered() {
+    let mut cluster = recovering_cluster(3, 7);
+    cluster.delay_messages(0, 20);
+    cluster.drop_messages_from("r0", "r2", 0.5);
+    cluster.drop_messages_from("r1", "r2", 0.5);
+    for c in 0..10 {
+        let replica = ["r0", "r1"][c % 2];
+        cluster.client_request(replica, set(c, "k", &format!("v{}", c)));
+    }
+    cluster.run_until_quiet();
+    assert!(cluster.dropped() > 0);
+    assert_eq!(acked(&mut cluster).len(), 10);
+
+    // Whatever r2 missed blocks its read until a tick recovers it
+    cluster.heal_partition();
+    let vals = read_everywhere(&mut cluster, &["r2", "r0", "r1"], "k");
+    assert_ne!(vals[0], "NONE");
+    assert!(vals.iter().all(|v| *v == vals[0]), "{:?}", vals);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_replica_restarted_from_snapshot_carries_on() {
+    let config = ProcessorConfig {
+        sparse_log: true,
+        ..ProcessorConfig::default()
+    };
+    let mut cluster = ClusterSimulator::with_config(3, config, 0);
+    for c in 0..3 {
+        let replica = ["r0", "r1", "r2"][c];
+        cluster.client_request(replica, set(c, &format!("k{}", c), "before"));
+    }
+    cluster.run_until_quiet();
+    assert_eq!(acked(&mut cluster).len(), 3);
+
+    // r1 comes back with its saved log and store, still keeping the log sparse
+    cluster.restart_from_snapshot("r1");
+    cluster.client_request("r1", set(3, "k1", "after"));
+    cluster.run_until_quiet();
+    assert_eq!(acked(&mut cluster), vec!["c3_0"]);
+
+    let replicas = ["r0", "r1", "r2"];
+    assert_eq!(
+        read_everywhere(&mut cluster, &replicas, "k1"),
+        vec!["after"; 3]
+    );
+}